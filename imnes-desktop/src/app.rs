@@ -118,7 +118,9 @@ impl App {
                     ..
                 } if !ui_want_text_input => {
                     // prevent joypad updates from colliding with text input fields in ui 
-                    emulator.update_joypad(physical_key, state);
+                    if !emulator.handle_hotkey(physical_key, state, &mut logger) {
+                        emulator.update_joypad(physical_key, state);
+                    }
                 },
                 winit::event::Event::WindowEvent {
                     event: winit::event::WindowEvent::Resized(new_size),
@@ -6,49 +6,73 @@ use std::{io, time::Duration};
 
 use imgui::Ui;
 use imgui_glium_renderer::Renderer;
-use winit::{event::ElementState, keyboard::PhysicalKey};
+use winit::{event::ElementState, keyboard::{KeyCode, PhysicalKey}};
 
-use nesemulib::{Apu2A03, Cpu6502, Ppu2C03, SystemControl, BASE_PPU_FREQUENCY};
+use nesemulib::{Region, RewindBuffer, SystemControl};
 
 use crate::{logger::Logger, rom::RomManager};
 
 use self::{audio::AudioPlayer, joypad::Joypad};
 pub use screen::Screen;
 
+const SAVE_STATE_KEY: KeyCode = KeyCode::F5;
+const LOAD_STATE_KEY: KeyCode = KeyCode::F7;
+const REWIND_KEY: KeyCode = KeyCode::Backspace;
+
+const DEFAULT_FRAMES_PER_SNAPSHOT: u64 = 2;
+const DEFAULT_REWIND_BUFFER_SIZE: usize = 32 << 20;
+
 pub struct Emulator {
-    pub cpu: Cpu6502,
-    pub ppu: Ppu2C03,
     pub audio_player: AudioPlayer,
     pub rom_manager: RomManager,
     pub joypad: Joypad,
     screen: Screen,
+    pub rewind: RewindBuffer,
 
     pub paused: bool,
     pub game_speed: f32,
-    pub total_cycles: u64,
+    pub skip_illegal_opcodes: bool,
+    pub rewind_enabled: bool,
+    pub rewind_button_held: bool,
+    rewind_key_held: bool,
+    /// Region to run every cartridge as, instead of the one given by its header
+    region_override: Option<Region>,
 }
 
 impl Emulator {
     pub fn new(screen: Screen) -> Self {
         let audio_player = AudioPlayer::new();
-        let apu = Apu2A03::new(audio_player.get_sample_rate());
 
         Self {
-            cpu: Cpu6502::new(apu),
-            ppu: Ppu2C03::new(),
             audio_player,
             screen,
             joypad: Joypad::new(),
             rom_manager: RomManager::new(),
+            rewind: RewindBuffer::new(DEFAULT_FRAMES_PER_SNAPSHOT, DEFAULT_REWIND_BUFFER_SIZE),
 
             game_speed: 1.0,
             paused: true,
-            total_cycles: 0,
+            skip_illegal_opcodes: false,
+            rewind_enabled: true,
+            rewind_button_held: false,
+            rewind_key_held: false,
+            region_override: None,
         }
     }
 
     pub fn load_ines_cartridge(&mut self, file_name: &str, logger: &mut Logger) -> Result<(), io::Error> {
-        self.rom_manager.load_ines_cartridge(file_name, logger)?;
+        self.rom_manager.load_ines_cartridge(file_name, self.audio_player.get_sample_rate(), logger)?;
+
+        if let Some(nes) = &mut self.rom_manager.nes {
+            nes.cpu.apu.adjust_cpu_clock_rate(self.game_speed);
+            nes.cpu.skip_illegal_opcodes = self.skip_illegal_opcodes;
+
+            if let Some(region) = self.region_override {
+                nes.set_region(region);
+            }
+        }
+
+        self.rewind.clear();
         self.reset();
         self.paused = false;
 
@@ -57,19 +81,43 @@ impl Emulator {
 
     pub fn unload_cartridge(&mut self, logger: &mut Logger) {
         self.rom_manager.unload_cartridge(logger);
+        self.rewind.clear();
         self.reset();
         self.paused = true;
     }
 
+    pub fn region_override(&self) -> Option<Region> {
+        self.region_override
+    }
+
+    /// Sets the region used by loaded cartridges (None to use the cartridge's own), restarting the current game
+    pub fn set_region_override(&mut self, region_override: Option<Region>) {
+        self.region_override = region_override;
+
+        if let Some(nes) = &mut self.rom_manager.nes {
+            let region = region_override
+                .unwrap_or_else(|| Region::from_timing(nes.bus.cartridge.header.timing));
+            nes.set_region(region);
+        }
+
+        self.reset();
+    }
+
     pub fn reset(&mut self) {
-        if let Some(bus) = &mut self.rom_manager.bus {
-            bus.reset();
-            self.cpu.reset(bus);
+        if let Some(nes) = &mut self.rom_manager.nes {
+            nes.reset();
         } 
         
         self.screen.reset();
-        self.ppu.reset();
-        self.total_cycles = 0;
+    }
+
+    /// Presses the console's reset button, which unlike `reset` lets multicarts move on to their next menu
+    pub fn soft_reset(&mut self) {
+        if let Some(nes) = &mut self.rom_manager.nes {
+            nes.soft_reset();
+        }
+
+        self.screen.reset();
     }
 
     pub fn run_for_duration(&mut self, duration: Duration, logger: &mut Logger) {
@@ -77,64 +125,98 @@ impl Emulator {
             return;
         }
 
-        if let Some(bus) = &mut self.rom_manager.bus {
-            let mut duration_cycles = duration.as_nanos() as u64 / (1e9 / (self.game_speed * BASE_PPU_FREQUENCY)) as u64;
-            while duration_cycles > 0 {
-                self.ppu.clock(bus);
-        
-                if self.total_cycles % 3 == 0 {
-                    // CPU clock
-                    if bus.dma_transferring {
-                        bus.dma_clock(self.total_cycles as u32);
-                    } else if bus.dmc_read_stall > 0 {
-                        bus.dmc_read_stall -= 1;
-                    } else {
-                        self.cpu.clock(bus);
-                    }
-        
-                    self.cpu.apu.cpu_clock(bus);
-        
-                    if let Some(sample) = self.cpu.apu.cpu_try_clock_sample() {
-                        self.audio_player.send_sample(sample)
-                    }
-                }
-        
-                if self.ppu.nmi_requested() {
-                    self.cpu.nmi(bus);
-                }
-        
-                if bus.irq_active() || self.cpu.apu.irq_active() {
-                    self.cpu.irq(bus);
-                }
-        
-                self.total_cycles += 1;
-                duration_cycles -= 1;
+        if self.is_rewinding() {
+            self.rewind_frame();
+            return;
+        }
+
+        if let Some(nes) = &mut self.rom_manager.nes {
+            let ppu_frequency = nes.region().ppu_frequency();
+            let duration_cycles = duration.as_nanos() as u64 / (1e9 / (self.game_speed * ppu_frequency)) as u64;
+            nes.run_for_cycles(duration_cycles);
+
+            for sample in nes.drain_samples() {
+                self.audio_player.send_sample(sample)
+            }
+
+            if nes.cpu.jammed {
+                logger.log_error("Unable to continue executing ROM as JAM was called");
+                self.reset();
+            } else if self.rewind_enabled {
+                self.rewind.try_capture(nes);
             }
         }
+    }
+
+    pub fn is_rewinding(&self) -> bool {
+        self.rewind_enabled && (self.rewind_key_held || self.rewind_button_held)
+    }
+
+    /// Steps back to the previous rewind snapshot. Audio is silenced while rewinding.
+    fn rewind_frame(&mut self) {
+        self.audio_player.silence();
 
-        if self.cpu.jammed {
-            logger.log_error("Unable to continue executing ROM as JAM was called");
-            self.reset();
+        if let Some(nes) = &mut self.rom_manager.nes {
+            if self.rewind.rewind(nes) {
+                // snapshots don't include the screen, so run forward a frame to redraw it
+                nes.run_frame();
+                nes.drain_samples();
+            }
         }
     }
  
     pub fn draw_screen(&mut self, renderer: &mut Renderer, ui: &mut Ui)  {    
-        self.screen.draw(self.ppu.try_get_frame(), renderer, ui, &self.rom_manager.cartridge_name)
+        let frame = match &mut self.rom_manager.nes {
+            Some(nes) => nes.ppu.try_get_frame(),
+            None => None,
+        };
+
+        self.screen.draw(frame, renderer, ui, &self.rom_manager.cartridge_name)
     }
 
     pub fn update_joypad(&mut self, physical_key: PhysicalKey, state: ElementState) {
-        if let Some(bus) = &mut self.rom_manager.bus {
+        if let Some(nes) = &mut self.rom_manager.nes {
 
             if self.joypad.update_joypad(physical_key, state) {
 
                 // future TODO: add support for second joypad
-                bus.update_joypad_state(self.joypad.get_key_state(), 0);
+                nes.bus.update_joypad_state(self.joypad.get_key_state(), 0);
             }
         } else {
             let _ = self.joypad.update_joypad(physical_key, state);
         }
     }
 
+    /// Handles emulator hotkeys; returns true if the key was used as a hotkey
+    pub fn handle_hotkey(&mut self, physical_key: PhysicalKey, state: ElementState, logger: &mut Logger) -> bool {
+        let key = match physical_key {
+            PhysicalKey::Code(key) if key == SAVE_STATE_KEY || key == LOAD_STATE_KEY || key == REWIND_KEY => key,
+            _ => return false,
+        };
+
+        if key == REWIND_KEY {
+            self.rewind_key_held = matches!(state, ElementState::Pressed);
+        } else if matches!(state, ElementState::Pressed) {
+            let slot = self.rom_manager.selected_slot;
+
+            if key == SAVE_STATE_KEY {
+                self.save_state(slot, logger);
+            } else {
+                self.load_state(slot, logger);
+            }
+        }
+
+        true
+    }
+
+    pub fn save_state(&mut self, slot: usize, logger: &mut Logger) {
+        self.rom_manager.write_state_to_slot(slot, self.screen.last_frame(), logger);
+    }
+
+    pub fn load_state(&mut self, slot: usize, logger: &mut Logger) {
+        self.rom_manager.load_state_from_slot(slot, logger);
+    }
+
     pub fn stop_emulation(&mut self, logger: &mut Logger, renderer: &mut Renderer) {
         self.unload_cartridge(logger);
         self.screen.clear_screen(renderer);
@@ -89,6 +89,12 @@ impl AudioPlayer {
         }
     }
 
+    /// Drops any samples that have not yet been sent to the output stream
+    pub fn silence(&mut self) {
+        self.audio_buffer.fill(0.0);
+        self.buffer_index = 0;
+    }
+
     pub fn get_sample_rate(&self) -> u32 {
         self.sample_rate
     }
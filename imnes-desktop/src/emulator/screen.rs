@@ -15,6 +15,7 @@ const FRAME_LENGTH: usize = DISPLAY_WIDTH * DISPLAY_HEIGHT * 4;
 
 pub struct Screen {
     screen_frame: PixelFrame,
+    last_frame: Vec<u8>,
 
     fps: f32,
     last_frame_update: Instant,
@@ -29,6 +30,7 @@ impl Screen {
 
         Self {
             screen_frame: PixelFrame::new(width, height, renderer, display),
+            last_frame: vec![0; FRAME_LENGTH],
             fps: 0.0,
             last_frame_update: Instant::now(),
             last_total_frames: 0,
@@ -47,6 +49,7 @@ impl Screen {
             }
             
             self.total_frames += 1;
+            self.last_frame = frame.to_vec();
             self.screen_frame.update_frame(frame.to_vec(),renderer);
         }
 
@@ -75,9 +78,15 @@ impl Screen {
     }
 
     pub fn clear_screen(&mut self, renderer: &mut Renderer) {
+        self.last_frame = vec![0; FRAME_LENGTH];
         self.screen_frame.update_frame(vec![0; FRAME_LENGTH], renderer);
     }
 
+    /// RGBA pixels of the most recent frame output by the PPU
+    pub fn last_frame(&self) -> &[u8] {
+        &self.last_frame
+    }
+
     pub fn reset(&mut self) {
         self.last_frame_update = Instant::now();
         self.last_total_frames = 0;
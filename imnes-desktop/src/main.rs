@@ -2,6 +2,7 @@ mod app;
 mod emulator;
 mod logger;
 mod rom;
+mod savestate;
 mod ui;
 
 use app::App;
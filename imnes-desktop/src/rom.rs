@@ -1,8 +1,8 @@
 use std::{fs, io, path::Path};
 
-use nesemulib::{CartridgeNes, SystemBus};
+use nesemulib::{CartridgeNes, Nes};
 
-use crate::logger::Logger;
+use crate::{logger::Logger, savestate::{crc32, SaveStateFile, SAVE_STATE_SLOTS}};
 
 
 const SAVE_FOLDER: &str = "saves/";
@@ -12,8 +12,12 @@ const ROMS_FOLDER: &str = "roms/";
 pub struct RomManager {
     pub auto_save: bool,
 
-    pub bus: Option<SystemBus>,
+    pub nes: Option<Nes>,
     pub cartridge_name: Option<String>,
+    rom_hash: u32,
+
+    pub save_states: Vec<Option<SaveStateFile>>,
+    pub selected_slot: usize,
 
     pub selected_file: usize,
     pub file_names: Vec<String>,
@@ -40,7 +44,10 @@ impl RomManager {
             selected_file,
             file_names,
             cartridge_name: None,
-            bus: None,
+            nes: None,
+            rom_hash: 0,
+            save_states: Vec::new(),
+            selected_slot: 0,
             save_folder,
             roms_folder,
         }
@@ -53,8 +60,9 @@ impl RomManager {
 
         self.write_save_to_file(logger);
 
-        self.bus = None;
+        self.nes = None;
         self.cartridge_name = None;
+        self.save_states.clear();
     }
 
     pub fn refresh_file_names(&mut self) {
@@ -67,14 +75,18 @@ impl RomManager {
         self.file_names.sort(); 
     }
 
-    pub fn load_ines_cartridge(&mut self, file_name: &str, logger: &mut Logger) -> Result<(), io::Error> {
-        let cartridge = CartridgeNes::from_ines_file(file_name)?;
-        let bus = SystemBus::new(cartridge);
+    pub fn load_ines_cartridge(&mut self, file_name: &str, sample_rate: u32, logger: &mut Logger) -> Result<(), io::Error> {
+        let data = fs::read(file_name)?;
+        let cartridge = CartridgeNes::from_ines_bytes(&data)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let nes = Nes::new(cartridge, sample_rate);
 
         self.cartridge_name = Some(String::from(file_name));
-        self.bus = Some(bus);
+        self.rom_hash = crc32(&data);
+        self.nes = Some(nes);
 
         self.load_save_from_file(file_name, logger);
+        self.refresh_save_states(file_name);
 
         logger.log_event(&format!("Loaded ROM cartridge: {}", file_name));
 
@@ -84,19 +96,19 @@ impl RomManager {
     fn load_save_from_file(&mut self, file_name: &str, logger: &mut Logger) {
         let save_path = self.get_save_path(file_name);
 
-        if let Some(bus) = &mut self.bus {
+        if let Some(nes) = &mut self.nes {
 
-            if !bus.cartridge.battery_backed {
+            if !nes.bus.cartridge.battery_backed {
                 return;
             }
 
             match fs::read(&save_path) {
-                Ok(save_ram) => if let Err(e) = bus.cartridge.load_save_ram(save_ram) {
+                Ok(save_ram) => if let Err(e) = nes.bus.cartridge.load_save_ram(save_ram) {
                     logger.log_event(&format!("Unable to load save RAM for {}:\n{}", file_name, e));
                 } else {
                     logger.log_event(&format!("Successfully loaded save RAM from: {}", save_path))
                 }
-                Err(e) if bus.cartridge.mapper.get_save_ram().is_some() => {
+                Err(e) if nes.bus.cartridge.mapper.get_save_ram().is_some() => {
                     logger.log_error(&format!("Failed to load save RAM for {}:\n{}", file_name, e));
                 }
                 _ => {}
@@ -112,13 +124,13 @@ impl RomManager {
 
         let save_path = self.get_save_path(file_name);
 
-        if let Some(bus) = &mut self.bus {
+        if let Some(nes) = &mut self.nes {
 
-            if !bus.cartridge.battery_backed {
+            if !nes.bus.cartridge.battery_backed {
                 return;
             }
 
-            if let Some(ram) = bus.cartridge.get_save_ram() {
+            if let Some(ram) = nes.bus.cartridge.get_save_ram() {
                 
                 if let Err(e) = fs::write(save_path.clone(), ram) {
                     logger.log_error(&format!("Failed to save RAM to {}:\n{}", save_path, e));
@@ -129,6 +141,56 @@ impl RomManager {
         }
     }
 
+    /// Reads the metadata and snapshots of every save state slot for the given ROM
+    fn refresh_save_states(&mut self, file_name: &str) {
+        self.save_states = (0..SAVE_STATE_SLOTS)
+            .map(|slot| SaveStateFile::read_from_file(&self.get_state_path(file_name, slot)).ok())
+            .collect();
+    }
+
+    /// Saves a snapshot of the running ROM to the given slot; frame is the RGBA screen used for its thumbnail
+    pub fn write_state_to_slot(&mut self, slot: usize, frame: &[u8], logger: &mut Logger) {
+        let file_name = match &self.cartridge_name {
+            Some(name) => name,
+            _ => return
+        };
+
+        let state_path = self.get_state_path(file_name, slot);
+
+        if let Some(nes) = &self.nes {
+            let save_state = SaveStateFile::new(self.rom_hash, frame, nes.snapshot());
+
+            if let Err(e) = save_state.write_to_file(&state_path) {
+                logger.log_error(&format!("Failed to save state to {}:\n{}", state_path, e));
+            } else {
+                logger.log_event(&format!("Saved state to slot {}: {}", slot + 1, state_path));
+                self.save_states[slot] = Some(save_state);
+            }
+        }
+    }
+
+    pub fn load_state_from_slot(&mut self, slot: usize, logger: &mut Logger) {
+        if let Some(nes) = &mut self.nes {
+            let save_state = match &self.save_states[slot] {
+                Some(save_state) => save_state,
+                None => {
+                    logger.log_error(&format!("Save state slot {} is empty", slot + 1));
+                    return;
+                }
+            };
+
+            if save_state.rom_hash != self.rom_hash {
+                logger.log_error(&format!("Save state in slot {} was made with a different ROM", slot + 1));
+                return;
+            }
+
+            match nes.restore_snapshot(&save_state.snapshot) {
+                Ok(_) => logger.log_event(&format!("Loaded state from slot {}", slot + 1)),
+                Err(e) => logger.log_error(&format!("Unable to load state from slot {}:\n{}", slot + 1, e)),
+            }
+        }
+    }
+
     pub fn do_auto_save(&mut self, logger: &mut Logger) {
         if !self.auto_save {
             return;
@@ -142,4 +204,10 @@ impl RomManager {
         let file_stem = Path::new(file_name).file_stem().unwrap().to_str().unwrap();
         format!("{}{}.sav", self.save_folder, file_stem)
     }
+
+    #[inline]
+    fn get_state_path(&self, file_name: &str, slot: usize) -> String {
+        let file_stem = Path::new(file_name).file_stem().unwrap().to_str().unwrap();
+        format!("{}{}.state{}", self.save_folder, file_stem, slot + 1)
+    }
 }
\ No newline at end of file
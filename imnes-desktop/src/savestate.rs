@@ -0,0 +1,114 @@
+use std::{fs, io};
+
+use chrono::{DateTime, Local, TimeZone};
+use nesemulib::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+
+pub const SAVE_STATE_SLOTS: usize = 8;
+
+pub const THUMBNAIL_WIDTH: usize = DISPLAY_WIDTH / 2;
+pub const THUMBNAIL_HEIGHT: usize = DISPLAY_HEIGHT / 2;
+const THUMBNAIL_LENGTH: usize = THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT * 4;
+
+// File layout: [ "IMNS" ][ ROM hash: u32 ][ timestamp: i64 ][ RGBA thumbnail ][ NES snapshot ... ]
+const SAVE_STATE_MAGIC: [u8; 4] = *b"IMNS";
+const HEADER_LENGTH: usize = 4 + 4 + 8;
+
+/// A save state slot on disk, which wraps a snapshot of the NES with details shown in the Ui
+pub struct SaveStateFile {
+    pub rom_hash: u32,
+    pub timestamp: DateTime<Local>,
+    pub thumbnail: Vec<u8>,
+    pub snapshot: Vec<u8>,
+}
+
+impl SaveStateFile {
+    /// Frame is expected to be the RGBA pixels of the screen at the time of saving
+    pub fn new(rom_hash: u32, frame: &[u8], snapshot: Vec<u8>) -> Self {
+        Self {
+            rom_hash,
+            timestamp: Local::now(),
+            thumbnail: make_thumbnail(frame),
+            snapshot,
+        }
+    }
+
+    pub fn read_from_file(path: &str) -> Result<Self, io::Error> {
+        let data = fs::read(path)?;
+
+        SaveStateFile::from_bytes(&data)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn write_to_file(&self, path: &str) -> Result<(), io::Error> {
+        fs::write(path, self.to_bytes())
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        if data.len() < HEADER_LENGTH + THUMBNAIL_LENGTH || data[0..4] != SAVE_STATE_MAGIC {
+            return Err(String::from("Not a save state file"));
+        }
+
+        let rom_hash = u32::from_le_bytes(data[4..8].try_into().unwrap());
+        let timestamp = i64::from_le_bytes(data[8..16].try_into().unwrap());
+        let timestamp = match Local.timestamp_opt(timestamp, 0).single() {
+            Some(timestamp) => timestamp,
+            None => return Err(String::from("Save state has an invalid timestamp")),
+        };
+
+        let thumbnail_end = HEADER_LENGTH + THUMBNAIL_LENGTH;
+
+        Ok(Self {
+            rom_hash,
+            timestamp,
+            thumbnail: data[HEADER_LENGTH..thumbnail_end].to_vec(),
+            snapshot: data[thumbnail_end..].to_vec(),
+        })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(HEADER_LENGTH + THUMBNAIL_LENGTH + self.snapshot.len());
+
+        data.extend_from_slice(&SAVE_STATE_MAGIC);
+        data.extend_from_slice(&self.rom_hash.to_le_bytes());
+        data.extend_from_slice(&self.timestamp.timestamp().to_le_bytes());
+        data.extend_from_slice(&self.thumbnail);
+        data.extend_from_slice(&self.snapshot);
+
+        data
+    }
+}
+
+/// Shrinks an RGBA screen frame to half its size by taking every other pixel
+fn make_thumbnail(frame: &[u8]) -> Vec<u8> {
+    let mut thumbnail = vec![0xFF; THUMBNAIL_LENGTH];
+
+    for y in 0..THUMBNAIL_HEIGHT {
+        for x in 0..THUMBNAIL_WIDTH {
+            let frame_i = 4 * ((2 * y) * DISPLAY_WIDTH + 2 * x);
+            let thumbnail_i = 4 * (y * THUMBNAIL_WIDTH + x);
+
+            thumbnail[thumbnail_i..thumbnail_i + 3].copy_from_slice(&frame[frame_i..frame_i + 3]);
+        }
+    }
+
+    thumbnail
+}
+
+/// CRC-32 (as used by zip and most ROM databases) of the given bytes
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFF;
+
+    for &byte in data {
+        crc ^= byte as u32;
+
+        for _ in 0..8 {
+            crc = if crc & 0x01 != 0 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
@@ -6,17 +6,21 @@ use glutin::surface::WindowSurface;
 use imgui_glium_renderer::{Renderer, Texture};
 use native_dialog::FileDialog;
 
-use nesemulib::{PATTERN_TABLE_LENGTH, PATTERN_TABLE_W_H};
-use crate::{emulator::Emulator, logger::Logger};
+use nesemulib::{Region, PATTERN_TABLE_LENGTH, PATTERN_TABLE_W_H};
+use crate::{emulator::Emulator, logger::Logger, savestate::{SAVE_STATE_SLOTS, THUMBNAIL_HEIGHT, THUMBNAIL_WIDTH}};
 
 
 pub struct EmulatorUi {
     cpu_window: bool,
     apu_window: bool,
     ppu_window: bool,
+    save_state_window: bool,
 
     pattern_table_frame: PixelFrame,
+    thumbnail_frame: PixelFrame,
     selected_palette: usize,
+    // Digits typed in for the Datach's barcode reader
+    barcode_input: String,
 }
 
 impl EmulatorUi {
@@ -25,9 +29,12 @@ impl EmulatorUi {
             cpu_window: true,
             apu_window: true,
             ppu_window: true,
+            save_state_window: false,
 
             pattern_table_frame: PixelFrame::new(2 * PATTERN_TABLE_W_H as u32, PATTERN_TABLE_W_H as u32, renderer, display),
+            thumbnail_frame: PixelFrame::new(THUMBNAIL_WIDTH as u32, THUMBNAIL_HEIGHT as u32, renderer, display),
             selected_palette: 0,
+            barcode_input: String::new(),
         }
     }
 
@@ -39,6 +46,10 @@ impl EmulatorUi {
 
         self.rom_window(ui, logger, emulator);
 
+        if self.save_state_window {
+            self.save_state_window(ui, emulator, logger, renderer);
+        }
+
         self.main_menu(emulator, ui);
     }
 
@@ -88,22 +99,77 @@ impl EmulatorUi {
                     }
                 }
 
-                if let Some(bus) = &emulator.rom_manager.bus {
+                if let Some(nes) = &emulator.rom_manager.nes {
+                    let bus = &nes.bus;
                     ui.separator();
 
-                    ui.text(format!("Mapper: {}", bus.cartridge.mapper_num));
+                    let header = &bus.cartridge.header;
+                    let kib = |size: usize| size as f32 / 1024.0;
+
+                    ui.text(format!("Mapper: {}.{}", bus.cartridge.mapper_num, bus.cartridge.submapper_num));
                     ui.same_line_with_spacing(10.0, 80.0);
 
-                    ui.text(format!("Mirroring: {:?}", 
-                        if let Some(mirroring) = bus.cartridge.mapper.get_updated_mirroring() {
-                            mirroring
-                        } else { 
-                            bus.cartridge.mirroring 
-                    }));
+                    ui.text(format!("Mirroring: {:?}", bus.cartridge.mirroring()));
 
-                    ui.text(format!("PRG-ROM banks: {}", bus.cartridge.prg_rom_banks));
-                    ui.text(format!("CHR-ROM banks: {}", bus.cartridge.chr_rom_banks));
+                    ui.text(format!("Format: {}", if header.nes2 { "NES 2.0" } else { "iNES" }));
+                    ui.same_line_with_spacing(10.0, 150.0);
                     ui.text(format!("Battery Backed: {}", bus.cartridge.battery_backed));
+                    ui.text(format!("Region: {:?}", nes.region()));
+
+                    if ui.collapsing_header("Header Details", imgui::TreeNodeFlags::empty()) {
+                        ui.text(format!("PRG-ROM: {}K ({} banks)", kib(header.prg_rom_size), bus.cartridge.prg_rom_banks));
+                        ui.text(format!("CHR-ROM: {}K ({} banks)", kib(header.chr_rom_size), bus.cartridge.chr_rom_banks));
+                        ui.text(format!("PRG-RAM: {}K", kib(header.prg_ram_size)));
+                        ui.same_line_with_spacing(10.0, 150.0);
+                        ui.text(format!("PRG-NVRAM: {}K", kib(header.prg_nvram_size)));
+                        ui.text(format!("CHR-RAM: {}K", kib(header.chr_ram_size)));
+                        ui.same_line_with_spacing(10.0, 150.0);
+                        ui.text(format!("CHR-NVRAM: {}K", kib(header.chr_nvram_size)));
+
+                        ui.text(format!("Timing: {:?}", header.timing));
+                        ui.same_line_with_spacing(10.0, 150.0);
+                        ui.text(format!("Console: {:?}", header.console_type));
+                        ui.text(format!("Expansion Device: {}", header.expansion_device_name()));
+                    }
+                }
+            });
+    }
+
+    fn save_state_window(&mut self, ui: &Ui, emulator: &mut Emulator, logger: &mut Logger, renderer: &mut Renderer) {
+        ui.window("Save States")
+            .size([300.0, 380.0], imgui::Condition::FirstUseEver)
+            .position([600.0, 220.0], imgui::Condition::FirstUseEver)
+            .build(|| {
+                if emulator.rom_manager.nes.is_none() {
+                    ui.text("(No currently running ROM)");
+                    return;
+                }
+
+                for slot in 0..SAVE_STATE_SLOTS {
+                    let label = match &emulator.rom_manager.save_states[slot] {
+                        Some(save_state) => format!("Slot {}: {}", slot + 1, save_state.timestamp.format("%Y-%m-%d %H:%M:%S")),
+                        None => format!("Slot {}: (empty)", slot + 1),
+                    };
+
+                    if ui.selectable_config(label).selected(emulator.rom_manager.selected_slot == slot).build() {
+                        emulator.rom_manager.selected_slot = slot;
+                    }
+                }
+
+                let slot = emulator.rom_manager.selected_slot;
+
+                ui.separator();
+                if ui.button("Save State (F5)") {
+                    emulator.save_state(slot, logger);
+                }
+                ui.same_line();
+                if ui.button("Load State (F7)") {
+                    emulator.load_state(slot, logger);
+                }
+
+                if let Some(save_state) = &emulator.rom_manager.save_states[slot] {
+                    self.thumbnail_frame.update_frame(save_state.thumbnail.clone(), renderer);
+                    self.thumbnail_frame.build_scaled(ui, 2.0);
                 }
             });
     }
@@ -113,9 +179,9 @@ impl EmulatorUi {
             .size([300.0, 350.0], imgui::Condition::FirstUseEver)
             .position([900.0, 370.0], imgui::Condition::FirstUseEver)
             .build(|| {
-                if let Some(bus) = &emulator.rom_manager.bus {
-                    let ppu = &emulator.ppu;
-                    let ppu_bus = &bus.ppu_bus;
+                if let Some(nes) = &emulator.rom_manager.nes {
+                    let ppu = &nes.ppu;
+                    let ppu_bus = &nes.bus.ppu_bus;
 
                     let register_label = |name: &str, value: &dyn std::fmt::Display| {
                         ui.label_text(format!("{}", value), format!("{}:", name));
@@ -141,7 +207,7 @@ impl EmulatorUi {
                             let mut frame = [0xFF; 2 * 4 * PATTERN_TABLE_LENGTH];
 
                             for table in 0..2 {
-                                let patt_table = emulator.ppu.get_pattern_table(&bus, table, self.selected_palette);
+                                let patt_table = nes.ppu.get_pattern_table(&nes.bus, table, self.selected_palette);
                                 let offset = PATTERN_TABLE_W_H * table;
 
                                 for y in 0..PATTERN_TABLE_W_H {
@@ -179,36 +245,45 @@ impl EmulatorUi {
 
     fn apu_state_window(&self, ui: &Ui, emulator: &mut Emulator) {
 
-        let channel_sound_plot = |channel_name: &str, enabled: &mut bool, samples: &[f32]| {
+        let channel_sound_plot = |channel_name: &str, enabled: &mut bool, samples: &[f32], scale_min: f32, scale_max: f32| {
             let _ = ui.checkbox(channel_name, enabled);
             ui.plot_lines(channel_name, samples)
-                .scale_min(0.0)
-                .scale_max(15.0)
+                .scale_min(scale_min)
+                .scale_max(scale_max)
                 .build();
         };
 
         ui.window("APU State")
-            .size([300.0, 380.0], imgui::Condition::Always)
-            .position([0.0, 420.0], imgui::Condition::Always)
+            .size([300.0, 300.0], imgui::Condition::Always)
+            .position([0.0, 500.0], imgui::Condition::Always)
             .build(|| {
-                if emulator.rom_manager.bus.is_some() {
+                if let Some(nes) = &mut emulator.rom_manager.nes {
                     ui.text("Master Volume");
                     let _ = ui.slider("##slider", 0.0, 1.0, &mut emulator.audio_player.master_volume);
                     ui.separator();
 
-                    let apu = &mut emulator.cpu.apu;
+                    let expansion_channels = nes.bus.cartridge.expansion_audio_channels();
+                    let apu = &mut nes.cpu.apu;
 
-                    channel_sound_plot("Pulse 1", &mut apu.pulse1_enabled, &apu.pulse1_samples);
-                    channel_sound_plot("Pulse 2", &mut apu.pulse2_enabled, &apu.pulse2_samples);
-                    channel_sound_plot("Triangle", &mut apu.triangle_enabled, &apu.triangle_samples);
-                    channel_sound_plot("Noise", &mut apu.noise_enabled, &apu.noise_samples);
-                    channel_sound_plot("DMC", &mut apu.dmc_enabled, &apu.dmc_samples);
+                    channel_sound_plot("Pulse 1", &mut apu.pulse1_enabled, &apu.pulse1_samples, 0.0, 15.0);
+                    channel_sound_plot("Pulse 2", &mut apu.pulse2_enabled, &apu.pulse2_samples, 0.0, 15.0);
+                    channel_sound_plot("Triangle", &mut apu.triangle_enabled, &apu.triangle_samples, 0.0, 15.0);
+                    channel_sound_plot("Noise", &mut apu.noise_enabled, &apu.noise_samples, 0.0, 15.0);
+                    channel_sound_plot("DMC", &mut apu.dmc_enabled, &apu.dmc_samples, 0.0, 15.0);
+
+                    let expansion = apu.expansion_enabled.iter_mut().zip(&apu.expansion_samples);
+                    for (channel, (enabled, samples)) in expansion_channels.iter().zip(expansion) {
+                        channel_sound_plot(channel.name, enabled, samples, channel.min_level, channel.max_level);
+                    }
 
                     apu.pulse1_samples.clear();
                     apu.pulse2_samples.clear();
                     apu.triangle_samples.clear();
                     apu.noise_samples.clear();
                     apu.dmc_samples.clear();
+                    for samples in &mut apu.expansion_samples {
+                        samples.clear();
+                    }
                 } else {
                     ui.text("(No currently running ROM)");
                 }
@@ -226,8 +301,8 @@ impl EmulatorUi {
             .size([320.0, 350.0], imgui::Condition::FirstUseEver)
             .position([900.0, 20.0], imgui::Condition::FirstUseEver)
             .build(|| {
-                if let Some(bus) = &mut emulator.rom_manager.bus {
-                    let cpu = &emulator.cpu;
+                if let Some(nes) = &mut emulator.rom_manager.nes {
+                    let cpu = &nes.cpu;
 
                     register_label(cpu.accumulator, "A");
                     ui.same_line();
@@ -245,7 +320,7 @@ impl EmulatorUi {
                     
                     ui.separator();
                     ui.separator();
-                    for instruction in emulator.cpu.get_disassembly(bus, 10) {
+                    for instruction in nes.cpu.get_disassembly(&mut nes.bus, 10) {
                         ui.text(instruction);
                     }
                     
@@ -257,15 +332,19 @@ impl EmulatorUi {
         style.end();
     }
 
-    pub fn show_options(&self, emulator: &mut Emulator, ui: &Ui, renderer: &mut Renderer, logger: &mut Logger) {
+    pub fn show_options(&mut self, emulator: &mut Emulator, ui: &Ui, renderer: &mut Renderer, logger: &mut Logger) {
         ui.window("Emulation Options")
-            .size([300.0, 200.0], imgui::Condition::Always)
+            .size([300.0, 280.0], imgui::Condition::Always)
             .position([0.0, 220.0], imgui::Condition::Always)
             .build(|| {
                 if ui.button(if emulator.paused {"Unpause"} else {"Pause"}) {
                     emulator.paused = !emulator.paused
                 }
                 ui.same_line();
+                if ui.button("Reset") {
+                    emulator.soft_reset();
+                }
+                ui.same_line();
                 if ui.button("Restart") {
                     emulator.reset();
                 }
@@ -277,15 +356,69 @@ impl EmulatorUi {
                 ui.separator();
 
                 if ui.slider("Game Speed", 0.5, 2.0, &mut emulator.game_speed) {
-                    emulator.cpu.apu.adjust_cpu_clock_rate(emulator.game_speed);
+                    if let Some(nes) = &mut emulator.rom_manager.nes {
+                        nes.cpu.apu.adjust_cpu_clock_rate(emulator.game_speed);
+                    }
                 }
 
                 ui.spacing();
 
                 if ui.button("Default Game Speed") {
                     emulator.game_speed = 1.0;
-                    emulator.cpu.apu.adjust_cpu_clock_rate(1.0);
+                    if let Some(nes) = &mut emulator.rom_manager.nes {
+                        nes.cpu.apu.adjust_cpu_clock_rate(1.0);
+                    }
+                }
+
+                let regions = [None, Some(Region::NTSC), Some(Region::PAL), Some(Region::DENDY)];
+                let mut region_index = regions.iter()
+                    .position(|&region| region == emulator.region_override())
+                    .unwrap_or(0);
+
+                if ui.combo_simple_string("Region", &mut region_index, &["Auto", "NTSC", "PAL", "Dendy"]) {
+                    emulator.set_region_override(regions[region_index]);
+                }
+
+                if let Some(nes) = &mut emulator.rom_manager.nes {
+                    let cartridge = &mut nes.bus.cartridge;
+
+                    // Multicarts read their DIP switches or solder pads to pick which menu to show
+                    if cartridge.dip_switch_settings() > 0 {
+                        let mut setting = cartridge.dip_switch() as i32;
+                        if ui.slider("DIP Switch", 0, cartridge.dip_switch_settings() as i32 - 1, &mut setting) {
+                            cartridge.set_dip_switch(setting as usize);
+                        }
+                    }
+
+                    if cartridge.has_barcode_reader() {
+                        ui.input_text("Barcode", &mut self.barcode_input).chars_decimal(true).build();
+                        ui.same_line();
+                        if ui.button("Scan") {
+                            match cartridge.scan_barcode(&self.barcode_input) {
+                                Ok(()) => logger.log_event(&format!("Scanned barcode: {}", self.barcode_input.trim())),
+                                Err(e) => logger.log_error(&format!("Unable to scan barcode:\n{}", e)),
+                            }
+                        }
+                    }
+                }
+
+                ui.separator();
+
+                if ui.checkbox("Enable Rewind", &mut emulator.rewind_enabled) && !emulator.rewind_enabled {
+                    emulator.rewind.clear();
                 }
+                ui.same_line();
+                ui.button("Rewind (Backspace)");
+                emulator.rewind_button_held = ui.is_item_active();
+
+                let mut buffer_size_mb = (emulator.rewind.memory_budget >> 20) as u32;
+                if ui.slider("Rewind Buffer (MB)", 4, 256, &mut buffer_size_mb) {
+                    emulator.rewind.memory_budget = (buffer_size_mb as usize) << 20;
+                }
+                ui.slider("Frames Per Snapshot", 1, 10, &mut emulator.rewind.frames_per_snapshot);
+
+                ui.text(format!("Rewind History: {} snapshots ({:.1} MB)", 
+                    emulator.rewind.len(), emulator.rewind.memory_used() as f32 / (1 << 20) as f32));
             });
     }
 
@@ -307,6 +440,10 @@ impl EmulatorUi {
 
                 if ui.menu_item("Show APU State") {
                     self.apu_window = !self.apu_window
+                }
+
+                if ui.menu_item("Show Save States") {
+                    self.save_state_window = !self.save_state_window
                 }          
             });
 
@@ -326,7 +463,11 @@ impl EmulatorUi {
 
                                 ui.checkbox("Enable Autosave", &mut emulator.rom_manager.auto_save);
 
-                                ui.checkbox("Skip Illegal CPU Opcodes", &mut emulator.cpu.skip_illegal_opcodes);
+                                if ui.checkbox("Skip Illegal CPU Opcodes", &mut emulator.skip_illegal_opcodes) {
+                                    if let Some(nes) = &mut emulator.rom_manager.nes {
+                                        nes.cpu.skip_illegal_opcodes = emulator.skip_illegal_opcodes;
+                                    }
+                                }
 
                                 ui.text(format!("Current ROMs Folder: {}", emulator.rom_manager.roms_folder));
                                 ui.same_line();
@@ -374,7 +515,10 @@ impl EmulatorUi {
                 if ui.button("Reset All Settings to Default") {
                     emulator.joypad.reset_keys();
                     emulator.rom_manager.auto_save = true;
-                    emulator.cpu.skip_illegal_opcodes = false;
+                    emulator.skip_illegal_opcodes = false;
+                    if let Some(nes) = &mut emulator.rom_manager.nes {
+                        nes.cpu.skip_illegal_opcodes = false;
+                    }
                 }
             });
     }
@@ -428,6 +572,14 @@ impl PixelFrame {
             .build(&ui);
     }
 
+    /// Draws the frame at a fixed multiple of its size instead of filling the window
+    pub fn build_scaled(&self, ui: &Ui, scale: f32) {
+        let size = [self.width as f32 * scale, self.height as f32 * scale];
+
+        Image::new(self.texture_id, size)
+            .build(&ui);
+    }
+
     pub fn update_frame(&mut self, frame: Vec<u8>, renderer: &mut Renderer) {
         let image = RawImage2d::from_raw_rgba(frame, (self.width, self.height));
 
@@ -83,60 +83,30 @@ fn main() -> Result<(), String> {
         Err(e) => panic!("Unable to load cartridge: {}", e)
     };
 
-    let mut total_cycles: u64 = 0;
     let mut joypad_state = 0;
-    let apu = Apu2A03::new(SAMPLING_RATE_HZ);
-    let mut cpu = Cpu6502::new(apu);
-    let mut ppu = Ppu2C03::new();
-    let mut bus = SystemBus::new(cartridge);
-
-    bus.reset(); 
-    cpu.reset(&mut bus);
-    ppu.reset();
+    let mut nes = Nes::new(cartridge, SAMPLING_RATE_HZ);
 
     let mut frame_count = 0;
     let mut last_fps_update = std::time::Instant::now();
     loop {
-        ppu.clock(&mut bus);
-
-        if total_cycles % 3 == 0 {
-            // CPU clock
-            if bus.dma_transferring {
-                bus.dma_clock(total_cycles as u32);
-            } else if bus.dmc_read_stall > 0 {
-                bus.dmc_read_stall -= 1;
-            } else {
-                cpu.clock(&mut bus);
-            }
-
-            cpu.apu.cpu_clock(&mut bus);
+        nes.run_frame();
 
-            match cpu.apu.cpu_try_clock_sample() {
-                Some(sample) => {
-                    audio_buffer[audio_buffer_size] = sample;
-                    audio_buffer_size += 1;
-    
-                    if audio_buffer_size == AUDIO_SAMPLES {
-                        audio_tx.send(audio_buffer).unwrap();
-                        audio_buffer_size = 0;
-                    }
-                }
-                None => {}
-            }
-        }
+        for sample in nes.drain_samples() {
+            audio_buffer[audio_buffer_size] = sample;
+            audio_buffer_size += 1;
 
-        if total_cycles % 8000 == 0 {
-            match get_events(&mut event_pump, &mut joypad_state) {
-                Ok(_) => bus.update_joypad_state(joypad_state, 0),
-                Err(e) => panic!("Emulator exited: {}", e)
+            if audio_buffer_size == AUDIO_SAMPLES {
+                audio_tx.send(audio_buffer).unwrap();
+                audio_buffer_size = 0;
             }
         }
 
-        if ppu.nmi_requested() {
-            cpu.nmi(&mut bus);
+        match get_events(&mut event_pump, &mut joypad_state) {
+            Ok(_) => nes.bus.update_joypad_state(joypad_state, 0),
+            Err(e) => panic!("Emulator exited: {}", e)
         }
 
-        match ppu.try_get_frame() {
+        match nes.ppu.try_get_frame() {
             Some(colour_frame) => {
                 for i in 0..DISPLAY_WIDTH*DISPLAY_HEIGHT {
                     frame[4 * i + 0] = colour_frame[i].2;
@@ -155,17 +125,11 @@ fn main() -> Result<(), String> {
             None => {}
         }
 
-        if bus.irq_active() || cpu.apu.irq_active() {
-            cpu.irq(&mut bus);
-        }
-
         if last_fps_update.elapsed() >= std::time::Duration::from_secs(1) {
             println!("FPS: {}", frame_count);
             frame_count = 0;
             last_fps_update = std::time::Instant::now();
         }
-
-        total_cycles += 1;
     }
 }
 
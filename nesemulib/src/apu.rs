@@ -13,12 +13,15 @@ use self::ch_noise::Noise;
 use self::frame_sequencer::FrameSequencer;
 use self::ch_triangle::Triangle;
 use self::ch_pulse::Pulse;
-use self::lookup::{PULSE_TABLE, TND_TABLE};
+
+pub(crate) use self::envelope::Envelope;
+pub(crate) use self::length_counter::LengthCounter;
+pub(crate) use self::lookup::{PULSE_TABLE, TND_TABLE};
 
 use crate::bus::SystemBus;
-use crate::{SystemControl, BASE_CPU_FREQUENCY, DEFAULT_TIME_PER_6502_CLOCK};
+use crate::{Region, SystemControl, StateReader, StateWriter, BASE_CPU_FREQUENCY, DEFAULT_TIME_PER_6502_CLOCK};
 
-const DUTY_SEQUENCES: [u8; 4] = [
+pub(crate) const DUTY_SEQUENCES: [u8; 4] = [
     0b01000000,
     0b01100000,
     0b01111000,
@@ -27,6 +30,8 @@ const DUTY_SEQUENCES: [u8; 4] = [
 
 
 pub struct Apu2A03 {
+    cpu_frequency: f32,
+    clock_speed: f32,
     time_per_6502_clock: f32,
     time_per_sample: f32,
     time_since_last_sample: f32,
@@ -43,6 +48,10 @@ pub struct Apu2A03 {
     triangle_sample: u8,
     noise_sample: u8,
     dmc_sample: u8,
+    // one level per expansion audio channel on the cartridge
+    expansion_levels: Vec<f32>,
+    // already mixed by the cartridge, so it's simply added to the APU's output
+    expansion_sample: f32,
 
     // for GUI
     pub pulse1_enabled: bool,
@@ -50,11 +59,13 @@ pub struct Apu2A03 {
     pub triangle_enabled: bool,
     pub noise_enabled: bool,
     pub dmc_enabled: bool,
+    pub expansion_enabled: Vec<bool>,
     pub pulse1_samples: Vec<f32>,
     pub pulse2_samples: Vec<f32>,
     pub triangle_samples: Vec<f32>,
     pub noise_samples: Vec<f32>,
     pub dmc_samples: Vec<f32>,
+    pub expansion_samples: Vec<Vec<f32>>,
 
     total_cycles: u32,
     interrupt_flag: bool,
@@ -76,11 +87,55 @@ impl SystemControl for Apu2A03 {
         self.total_cycles = 0;
         self.interrupt_flag = false;
     }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_f32(self.time_since_last_sample);
+
+        self.frame_sequencer.save_state(state);
+        self.pulse1.save_state(state);
+        self.pulse2.save_state(state);
+        self.triangle.save_state(state);
+        self.noise.save_state(state);
+        self.dmc.save_state(state);
+
+        state.write_u8(self.pulse1_sample);
+        state.write_u8(self.pulse2_sample);
+        state.write_u8(self.triangle_sample);
+        state.write_u8(self.noise_sample);
+        state.write_u8(self.dmc_sample);
+
+        state.write_u32(self.total_cycles);
+        state.write_bool(self.interrupt_flag);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.time_since_last_sample = state.read_f32()?;
+
+        self.frame_sequencer.load_state(state)?;
+        self.pulse1.load_state(state)?;
+        self.pulse2.load_state(state)?;
+        self.triangle.load_state(state)?;
+        self.noise.load_state(state)?;
+        self.dmc.load_state(state)?;
+
+        self.pulse1_sample = state.read_u8()?;
+        self.pulse2_sample = state.read_u8()?;
+        self.triangle_sample = state.read_u8()?;
+        self.noise_sample = state.read_u8()?;
+        self.dmc_sample = state.read_u8()?;
+
+        self.total_cycles = state.read_u32()?;
+        self.interrupt_flag = state.read_bool()?;
+
+        Ok(())
+    }
 }
 
 impl Apu2A03 {
     pub fn new(sample_rate: u32) -> Self {
         Self {
+            cpu_frequency: BASE_CPU_FREQUENCY,
+            clock_speed: 1.0,
             time_per_6502_clock: DEFAULT_TIME_PER_6502_CLOCK,
             time_per_sample: 1e9 / (sample_rate as f32),
             time_since_last_sample: 0.0,
@@ -97,17 +152,21 @@ impl Apu2A03 {
             triangle_sample: 0,
             noise_sample: 0,
             dmc_sample: 0,
+            expansion_levels: Vec::new(),
+            expansion_sample: 0.0,
 
             pulse1_enabled: true,
             pulse2_enabled: true,
             triangle_enabled: true,
             noise_enabled: true,
             dmc_enabled: true,
+            expansion_enabled: Vec::new(),
             pulse1_samples: Vec::new(),
             pulse2_samples: Vec::new(),
             triangle_samples: Vec::new(),
             noise_samples: Vec::new(),
             dmc_samples: Vec::new(),
+            expansion_samples: Vec::new(),
 
             total_cycles: 0,
             interrupt_flag: false,
@@ -118,7 +177,18 @@ impl Apu2A03 {
     /// If emulation is syncing to audio this effectively changes the emulation speed.
     pub fn adjust_cpu_clock_rate(&mut self, speed: f32) {
         assert!(0.0 < speed && speed <= 2.0, "speed is too big/small !!");  
-        self.time_per_6502_clock = 1e9 / (speed * BASE_CPU_FREQUENCY);
+        self.clock_speed = speed;
+        self.time_per_6502_clock = 1e9 / (speed * self.cpu_frequency);
+    }
+
+    /// Switches the CPU clock frequency along with the frame sequencer and period tables used by the APU
+    pub fn set_region(&mut self, region: Region) {
+        self.cpu_frequency = region.cpu_frequency();
+        self.adjust_cpu_clock_rate(self.clock_speed);
+
+        self.frame_sequencer.set_region(region);
+        self.noise.set_region(region);
+        self.dmc.set_region(region);
     }
 
     pub fn adjust_sample_rate(&mut self, sample_rate: u32) {
@@ -152,11 +222,14 @@ impl Apu2A03 {
         self.triangle_samples.push(self.triangle_sample as f32);
         self.noise_samples.push(self.noise_sample as f32);
         self.dmc_samples.push(self.dmc_sample as f32);
+        for (samples, level) in self.expansion_samples.iter_mut().zip(&self.expansion_levels) {
+            samples.push(*level);
+        }
 
         let pulse_out = PULSE_TABLE[(self.pulse1_sample + self.pulse2_sample) as usize];
         let tnd_out = TND_TABLE[(3 * self.triangle_sample + (self.noise_sample << 1) + self.dmc_sample) as usize];
 
-        Some(pulse_out + tnd_out)
+        Some(pulse_out + tnd_out + self.expansion_sample)
     }
 
 
@@ -191,6 +264,24 @@ impl Apu2A03 {
 
         self.triangle_sample = if self.triangle_enabled { self.triangle.clock() } else { 0 };
 
+        let expansion_channels = bus.cartridge.expansion_audio_channels().len();
+        if self.expansion_levels.len() != expansion_channels {
+            self.expansion_levels = vec![0.0; expansion_channels];
+            self.expansion_enabled = vec![true; expansion_channels];
+            self.expansion_samples = vec![Vec::new(); expansion_channels];
+        }
+
+        // expansion audio is always clocked so it stays in time, even while muted
+        bus.cartridge.clock_expansion_audio(&mut self.expansion_levels);
+
+        for (level, enabled) in self.expansion_levels.iter_mut().zip(&self.expansion_enabled) {
+            if !enabled {
+                *level = 0.0;
+            }
+        }
+
+        self.expansion_sample = bus.cartridge.mix_expansion_audio(&self.expansion_levels);
+
         if self.total_cycles % 2 == 0 {
             self.pulse1_sample = if self.pulse1_enabled { self.pulse1.clock() } else { 0 };
             self.pulse2_sample = if self.pulse2_enabled { self.pulse2.clock() } else { 0 };
@@ -361,6 +452,8 @@ impl Apu2A03 {
 impl Apu2A03 {
     pub fn test_new() -> Self {
         Apu2A03 {
+            cpu_frequency: BASE_CPU_FREQUENCY,
+            clock_speed: 1.0,
             time_per_6502_clock: DEFAULT_TIME_PER_6502_CLOCK,
             time_per_sample: 10000.0, 
             time_since_last_sample: 0.0,
@@ -377,17 +470,21 @@ impl Apu2A03 {
             triangle_sample: 0,
             noise_sample: 0,
             dmc_sample: 0,
+            expansion_levels: Vec::new(),
+            expansion_sample: 0.0,
 
             pulse1_enabled: true,
             pulse2_enabled: true,
             triangle_enabled: true,
             noise_enabled: true,
             dmc_enabled: true,
+            expansion_enabled: Vec::new(),
             pulse1_samples: Vec::new(),
             pulse2_samples: Vec::new(),
             triangle_samples: Vec::new(),
             noise_samples: Vec::new(),
             dmc_samples: Vec::new(),
+            expansion_samples: Vec::new(),
 
             total_cycles: 0,
             interrupt_flag: false,
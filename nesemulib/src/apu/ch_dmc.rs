@@ -1,8 +1,9 @@
-use crate::{bus::SystemBus, SystemControl};
+use crate::{bus::SystemBus, Region, SystemControl, StateReader, StateWriter};
 
 
 
 const PERIOD_LOOKUP: [u32; 0x10] = [214, 190, 170, 160, 143, 127, 113, 107, 95, 80, 71, 64, 53, 42, 36, 27];
+const PAL_PERIOD_LOOKUP: [u32; 0x10] = [199, 177, 158, 149, 138, 118, 105, 99, 88, 74, 66, 59, 49, 39, 33, 25];
 
 pub struct Dmc {
     pub irq_enabled_flag: bool,
@@ -23,6 +24,7 @@ pub struct Dmc {
 
     period: u32,
     cycles: u32,
+    period_lookup: &'static [u32; 0x10],
 }
 
 impl SystemControl for Dmc {
@@ -39,9 +41,47 @@ impl SystemControl for Dmc {
         self.shift_reg_index = 0;
         self.silence_flag = false;
         self.output_level = 0;
-        self.period = PERIOD_LOOKUP[0];
+        self.period = self.period_lookup[0];
         self.cycles = 0;
     }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.irq_enabled_flag);
+        state.write_bool(self.irq_flag);
+        state.write_bool(self.loop_flag);
+        state.write_usize(self.sample_address);
+        state.write_usize(self.address_counter);
+        state.write_usize(self.sample_length);
+        state.write_usize(self.bytes_left);
+        state.write_bool(self.sample_buffer.is_some());
+        state.write_u8(self.sample_buffer.unwrap_or_default());
+        state.write_u8(self.output_shift_reg);
+        state.write_usize(self.shift_reg_index);
+        state.write_bool(self.silence_flag);
+        state.write_u8(self.output_level);
+        state.write_u32(self.period);
+        state.write_u32(self.cycles);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.irq_enabled_flag = state.read_bool()?;
+        self.irq_flag = state.read_bool()?;
+        self.loop_flag = state.read_bool()?;
+        self.sample_address = state.read_usize()?;
+        self.address_counter = state.read_usize()?;
+        self.sample_length = state.read_usize()?;
+        self.bytes_left = state.read_usize()?;
+        let has_sample = state.read_bool()?;
+        let sample = state.read_u8()?;
+        self.sample_buffer = if has_sample { Some(sample) } else { None };
+        self.output_shift_reg = state.read_u8()?;
+        self.shift_reg_index = state.read_usize()?;
+        self.silence_flag = state.read_bool()?;
+        self.output_level = state.read_u8()?;
+        self.period = state.read_u32()?;
+        self.cycles = state.read_u32()?;
+        Ok(())
+    }
 }
 
 impl Dmc {
@@ -65,9 +105,14 @@ impl Dmc {
 
             period: PERIOD_LOOKUP[0],
             cycles: 0,
+            period_lookup: &PERIOD_LOOKUP,
         }
     }
 
+    pub fn set_region(&mut self, region: Region) {
+        self.period_lookup = if region.uses_pal_apu() { &PAL_PERIOD_LOOKUP } else { &PERIOD_LOOKUP };
+    }
+
     pub fn set_sample_address(&mut self, byte: u8) {
         self.sample_address = 0xC000 | ((byte as usize) << 6);
         self.address_counter = self.sample_address;
@@ -79,7 +124,7 @@ impl Dmc {
     }
 
     pub fn set_period(&mut self, period: usize) {
-        self.period = PERIOD_LOOKUP[period];
+        self.period = self.period_lookup[period];
     }
 
     pub fn write_status(&mut self, bit: bool) {
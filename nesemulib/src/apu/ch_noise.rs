@@ -1,8 +1,10 @@
-use crate::SystemControl;
+use crate::{Region, SystemControl, StateReader, StateWriter};
 
 use super::{envelope::Envelope, length_counter::LengthCounter};
 
 const PERIOD_LOOKUP: [u32; 0x10] = [2, 4, 8, 16, 32, 48, 64, 80, 101, 127, 190, 254, 381, 508, 1017, 2034];
+const PAL_PERIOD_LOOKUP: [u32; 0x10] = [2, 4, 7, 15, 30, 44, 59, 74, 94, 118, 177, 236, 354, 472, 945, 1889];
+
 pub struct Noise {
     pub length_counter: LengthCounter,
     pub envelope: Envelope,
@@ -11,6 +13,7 @@ pub struct Noise {
     period: u32,
     cycles: u32,
     shift_reg: u16,
+    period_lookup: &'static [u32; 0x10],
 }
 
 impl SystemControl for Noise {
@@ -18,10 +21,29 @@ impl SystemControl for Noise {
         self.length_counter.reset();
         self.envelope.reset();
         self.shift_mode = false;
-        self.period = PERIOD_LOOKUP[0];
+        self.period = self.period_lookup[0];
         self.cycles = 0;
         self.shift_reg = 1;
     }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.length_counter.save_state(state);
+        self.envelope.save_state(state);
+        state.write_bool(self.shift_mode);
+        state.write_u32(self.period);
+        state.write_u32(self.cycles);
+        state.write_u16(self.shift_reg);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.length_counter.load_state(state)?;
+        self.envelope.load_state(state)?;
+        self.shift_mode = state.read_bool()?;
+        self.period = state.read_u32()?;
+        self.cycles = state.read_u32()?;
+        self.shift_reg = state.read_u16()?;
+        Ok(())
+    }
 }
 
 impl Noise {
@@ -34,11 +56,16 @@ impl Noise {
             period: PERIOD_LOOKUP[0],
             cycles: 0,
             shift_reg: 1,
+            period_lookup: &PERIOD_LOOKUP,
         }
     }
 
+    pub fn set_region(&mut self, region: Region) {
+        self.period_lookup = if region.uses_pal_apu() { &PAL_PERIOD_LOOKUP } else { &PERIOD_LOOKUP };
+    }
+
     pub fn set_period(&mut self, period: usize) {
-        self.period = PERIOD_LOOKUP[period];
+        self.period = self.period_lookup[period];
     }
 
     pub fn clock(&mut self) -> u8 {
@@ -1,4 +1,4 @@
-use crate::SystemControl;
+use crate::{SystemControl, StateReader, StateWriter};
 
 use super::{envelope::Envelope, length_counter::LengthCounter, sweep::Sweep, DUTY_SEQUENCES};
 
@@ -23,6 +23,25 @@ impl SystemControl for Pulse {
         self.sweep.reset();
         self.cycles = 0;
     }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.duty_sequence);
+        state.write_u8(self.duty_step);
+        self.length_counter.save_state(state);
+        self.envelope.save_state(state);
+        self.sweep.save_state(state);
+        state.write_u32(self.cycles);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.duty_sequence = state.read_u8()?;
+        self.duty_step = state.read_u8()?;
+        self.length_counter.load_state(state)?;
+        self.envelope.load_state(state)?;
+        self.sweep.load_state(state)?;
+        self.cycles = state.read_u32()?;
+        Ok(())
+    }
 }
 
 impl Pulse {
@@ -1,4 +1,4 @@
-use crate::SystemControl;
+use crate::{SystemControl, StateReader, StateWriter};
 
 use super::length_counter::LengthCounter;
 
@@ -20,6 +20,23 @@ impl SystemControl for Triangle {
         self.duty_step = 0;
     }
 
+    fn save_state(&self, state: &mut StateWriter) {
+        self.length_counter.save_state(state);
+        self.linear_counter.save_state(state);
+        state.write_u32(self.period);
+        state.write_u32(self.cycles);
+        state.write_u8(self.duty_step);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.length_counter.load_state(state)?;
+        self.linear_counter.load_state(state)?;
+        self.period = state.read_u32()?;
+        self.cycles = state.read_u32()?;
+        self.duty_step = state.read_u8()?;
+        Ok(())
+    }
+
 }
 
 impl Triangle {
@@ -72,6 +89,21 @@ impl SystemControl for LinearCounter {
         self.reload = 0;
         self.reload_flag = false;
     }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.counter);
+        state.write_bool(self.control_flag);
+        state.write_u8(self.reload);
+        state.write_bool(self.reload_flag);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.counter = state.read_u8()?;
+        self.control_flag = state.read_bool()?;
+        self.reload = state.read_u8()?;
+        self.reload_flag = state.read_bool()?;
+        Ok(())
+    }
 }
 
 impl LinearCounter {
@@ -1,4 +1,4 @@
-use crate::SystemControl;
+use crate::{SystemControl, StateReader, StateWriter};
 
 pub struct Envelope {
     pub start_flag: bool,
@@ -22,6 +22,27 @@ impl SystemControl for Envelope {
         self.counter_period = 0;
         self.decay_counter = 0;
     }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.start_flag);
+        state.write_bool(self.loop_flag);
+        state.write_bool(self.constant_flag);
+        state.write_u8(self.constant_volume);
+        state.write_u8(self.counter);
+        state.write_u8(self.counter_period);
+        state.write_u8(self.decay_counter);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.start_flag = state.read_bool()?;
+        self.loop_flag = state.read_bool()?;
+        self.constant_flag = state.read_bool()?;
+        self.constant_volume = state.read_u8()? & 0x0F;
+        self.counter = state.read_u8()? & 0x0F;
+        self.counter_period = state.read_u8()? & 0x0F;
+        self.decay_counter = state.read_u8()? & 0x0F;
+        Ok(())
+    }
 }
 
 impl Envelope {
@@ -1,4 +1,8 @@
-use crate::SystemControl;
+use crate::{Region, SystemControl, StateReader, StateWriter};
+
+// APU cycles at which each step of the sequence is clocked
+const STEP_CYCLES: [u32; 5] = [3728, 7456, 11185, 14914, 18640];
+const PAL_STEP_CYCLES: [u32; 5] = [4156, 8313, 12469, 16626, 20782];
 
 pub struct FrameSequencer {
     pub mode: bool,
@@ -6,6 +10,7 @@ pub struct FrameSequencer {
 
     cycles: u32,
     skipped_cycle: bool,
+    step_cycles: &'static [u32; 5],
 }
 
 impl SystemControl for FrameSequencer {
@@ -15,6 +20,21 @@ impl SystemControl for FrameSequencer {
         self.skipped_cycle = false;
         self.irq_inhibit_flag = false;
     }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.mode);
+        state.write_bool(self.irq_inhibit_flag);
+        state.write_u32(self.cycles);
+        state.write_bool(self.skipped_cycle);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.mode = state.read_bool()?;
+        self.irq_inhibit_flag = state.read_bool()?;
+        self.cycles = state.read_u32()?;
+        self.skipped_cycle = state.read_bool()?;
+        Ok(())
+    }
 }
 
 impl FrameSequencer {
@@ -24,11 +44,18 @@ impl FrameSequencer {
             mode: false,
             skipped_cycle: false,
             irq_inhibit_flag: false,
+            step_cycles: &STEP_CYCLES,
         }
     }
 
+    pub fn set_region(&mut self, region: Region) {
+        self.step_cycles = if region.uses_pal_apu() { &PAL_STEP_CYCLES } else { &STEP_CYCLES };
+    }
+
     pub fn clock(&mut self, irq_flag: &mut bool) -> Option<(u8, u8)> {
-        if !self.mode && self.cycles >= 14914 {
+        let steps = self.step_cycles;
+
+        if !self.mode && self.cycles >= steps[3] {
             if !self.irq_inhibit_flag {
                 *irq_flag = true;
             }
@@ -37,29 +64,18 @@ impl FrameSequencer {
         if !self.skipped_cycle {
             self.skipped_cycle = true;
 
+            let step = steps.iter().position(|&cycles| cycles == self.cycles)? as u8 + 1;
+
             if self.mode {
-                match self.cycles {
-                    3728  => Some((0, 1)),
-                    7456  => Some((0, 2)),
-                    11185 => Some((0, 3)),
-                    14914 => Some((0, 4)),
-                    _ => None
-                }
+                if step <= 4 { Some((0, step)) } else { None }
             } else {
-                match self.cycles {
-                    3728  => Some((1, 1)),
-                    7456  => Some((1, 2)),
-                    11185 => Some((1, 3)),
-                    14914 => Some((1, 4)),
-                    18640 => Some((1, 5)),
-                    _ => None
-                }
+                Some((1, step))
             }
         } else {
             self.cycles += 1;
             self.skipped_cycle = false;
 
-            if (!self.mode && self.cycles == 14915) || (self.mode && self.cycles == 18641) {
+            if (!self.mode && self.cycles == steps[3] + 1) || (self.mode && self.cycles == steps[4] + 1) {
                 self.cycles = 0;
             }
 
@@ -67,4 +83,3 @@ impl FrameSequencer {
         }
     }
 }
-
@@ -1,4 +1,4 @@
-use crate::SystemControl;
+use crate::{SystemControl, StateReader, StateWriter};
 
 
 const LENGTH_LOOKUP: [u8; 0x20] = [
@@ -18,6 +18,19 @@ impl SystemControl for LengthCounter {
         self.enabled_flag = false;
         self.counter = 0;
     }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.halted);
+        state.write_bool(self.enabled_flag);
+        state.write_u8(self.counter);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.halted = state.read_bool()?;
+        self.enabled_flag = state.read_bool()?;
+        self.counter = state.read_u8()?;
+        Ok(())
+    }
 }
 
 impl LengthCounter {
@@ -1,4 +1,4 @@
-use crate::SystemControl;
+use crate::{SystemControl, StateReader, StateWriter};
 
 pub struct Sweep {
     pub period: u32,
@@ -26,6 +26,31 @@ impl SystemControl for Sweep {
         self.divider = 0;
         self.counter = 0;
     }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u32(self.period);
+        state.write_bool(self.muted);
+        state.write_u32(self.target_period);
+        state.write_u8(self.shift);
+        state.write_bool(self.negate_flag);
+        state.write_bool(self.enabled_flag);
+        state.write_bool(self.reload_flag);
+        state.write_u8(self.divider);
+        state.write_u8(self.counter);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.period = state.read_u32()?;
+        self.muted = state.read_bool()?;
+        self.target_period = state.read_u32()?;
+        self.shift = state.read_u8()?;
+        self.negate_flag = state.read_bool()?;
+        self.enabled_flag = state.read_bool()?;
+        self.reload_flag = state.read_bool()?;
+        self.divider = state.read_u8()?;
+        self.counter = state.read_u8()?;
+        Ok(())
+    }
 }
 
 impl Sweep {
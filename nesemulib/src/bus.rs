@@ -1,6 +1,6 @@
 use crate::cartridge::CartridgeNes;
 use crate::ppu::PpuBus;
-use crate::SystemControl;
+use crate::{SystemControl, StateReader, StateWriter};
 
 const CPU_RAM_START: usize = 0x0000;
 const CPU_RAM_END: usize = 0x1FFF;
@@ -34,6 +34,54 @@ pub struct SystemBus {
 impl SystemControl for SystemBus {
     fn reset(&mut self) {
         self.cartridge.reset();
+        self.reset_registers();
+    }
+
+    fn soft_reset(&mut self) {
+        self.cartridge.soft_reset();
+        self.reset_registers();
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.cartridge.save_state(state);
+        self.ppu_bus.save_state(state);
+
+        state.write_bytes(&self.cpu_ram);
+        state.write_bytes(&self.joypad_registers);
+        state.write_bytes(&self.joypad_state);
+
+        state.write_u8(self.dma_page);
+        state.write_u8(self.dma_addr);
+        state.write_u8(self.dma_data);
+        state.write_bool(self.dma_transferring);
+        state.write_bool(self.false_dma);
+
+        state.write_u8(self.dmc_read_stall);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.cartridge.load_state(state)?;
+        self.ppu_bus.load_state(state)?;
+
+        state.read_bytes(&mut self.cpu_ram)?;
+        state.read_bytes(&mut self.joypad_registers)?;
+        state.read_bytes(&mut self.joypad_state)?;
+
+        self.dma_page = state.read_u8()?;
+        self.dma_addr = state.read_u8()?;
+        self.dma_data = state.read_u8()?;
+        self.dma_transferring = state.read_bool()?;
+        self.false_dma = state.read_bool()?;
+
+        self.dmc_read_stall = state.read_u8()?;
+
+        Ok(())
+    }
+}
+
+impl SystemBus {
+    // Everything but the CPU's RAM and the cartridge, which both reset in their own way
+    fn reset_registers(&mut self) {
         self.ppu_bus.reset();
         self.joypad_registers = [0; 2];
         self.joypad_state = [0; 2];
@@ -43,9 +91,7 @@ impl SystemControl for SystemBus {
         self.dma_transferring = false;
         self.false_dma = true;
     }
-}
 
-impl SystemBus {
     pub fn new(cartridge: CartridgeNes) -> Self {
         Self {
             cartridge,
@@ -146,6 +192,17 @@ impl SystemBus {
         self.ppu_bus.ppu_read(addr, &self.cartridge)
     }
 
+    /// Reads made by the PPU while rendering, which the cartridge gets to see
+    pub fn ppu_fetch(&mut self, addr: usize) -> u8 {
+        let byte = self.ppu_bus.ppu_read(addr, &self.cartridge);
+
+        if self.ppu_bus.mask.show_bg() || self.ppu_bus.mask.show_spr() {
+            self.cartridge.notify_ppu_read(addr);
+        }
+
+        byte
+    }
+
     pub fn update_joypad_state(&mut self, joypad_state1: u8, joypad_state2: u8) {
         self.joypad_state[0] = joypad_state1;
         self.joypad_state[1] = joypad_state2;
@@ -1,6 +1,13 @@
+mod error;
+mod header;
+
 use std::{fs::read, io};
 
-use crate::{mapper::*, SystemControl};
+use crate::{mapper::*, SystemControl, StateReader, StateWriter};
+
+pub use self::error::CartridgeError;
+pub use self::header::{ConsoleType, InesHeader, TimingMode};
+use self::header::HEADER_SIZE;
 
 // The size of each PRG_ROM bank
 pub const PRG_ROM_SIZE: usize = 0x4000;
@@ -8,6 +15,50 @@ pub const PRG_ROM_SIZE: usize = 0x4000;
 // The size of each CHR_ROM bank
 pub const CHR_ROM_SIZE: usize = 0x2000;
 
+// The size of the optional trainer placed between the header and PRG-ROM
+const TRAINER_SIZE: usize = 0x200;
+
+// The trainer is copied into PRG-RAM at $7000-$71FF before the game starts
+const TRAINER_START: usize = 0x7000;
+
+// PRG-RAM provided by the cartridge itself, for mappers that don't map any at $6000-$7FFF
+const PRG_RAM_START: usize = 0x6000;
+const PRG_RAM_END: usize = 0x7FFF;
+const PRG_RAM_SIZE: usize = 0x2000;
+
+// The size of each nametable page, in both CIRAM and cartridge VRAM
+pub const NAME_TABLE_PAGE_SIZE: usize = 0x400;
+
+// Four-screen boards provide 2 KiB of VRAM for the two nametables the console doesn't have
+const FOUR_SCREEN_VRAM_SIZE: usize = 0x800;
+
+/// The 1 KiB page that a nametable slot ($2000, $2400, $2800 or $2C00) is mapped to
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NameTableSource {
+    /// One of the console's two internal nametables
+    CIRAM(u8),
+    /// A page of VRAM on the cartridge itself
+    CARTRIDGE(u8),
+}
+
+impl NameTableSource {
+    fn save_state(&self, state: &mut StateWriter) {
+        match self {
+            NameTableSource::CIRAM(page) => { state.write_u8(0); state.write_u8(*page); }
+            NameTableSource::CARTRIDGE(page) => { state.write_u8(1); state.write_u8(*page); }
+        }
+    }
+
+    fn load_state(state: &mut StateReader) -> Result<Self, String> {
+        match (state.read_u8()?, state.read_u8()?) {
+            (0, page) => Ok(NameTableSource::CIRAM(page & 0x01)),
+            (1, page) => Ok(NameTableSource::CARTRIDGE(page)),
+            (n, _) => Err(format!("Invalid nametable source {} in save state", n)),
+        }
+    }
+}
+
 #[allow(non_camel_case_types)]
 #[derive(Debug, Clone, Copy)]
 pub enum Mirroring {
@@ -16,24 +67,125 @@ pub enum Mirroring {
     ONESCREEN_LO,
     ONESCREEN_HI,
     FOUR_SCREEN,
+    /// Set by mappers that map each nametable slot individually
+    CUSTOM([NameTableSource; 4]),
+}
+
+impl Mirroring {
+    /// Resolves where each of the four nametable slots are mapped to
+    pub fn name_table_slots(&self) -> [NameTableSource; 4] {
+        use NameTableSource::*;
+
+        match self {
+            // [ A ]|[ a ]
+            // -----------
+            // [ B ]|[ b ]
+            Mirroring::HORIZONTAL   => [CIRAM(0), CIRAM(0), CIRAM(1), CIRAM(1)],
+            // [ A ]|[ B ]
+            // -----------
+            // [ a ]|[ b ]
+            Mirroring::VERTICAL     => [CIRAM(0), CIRAM(1), CIRAM(0), CIRAM(1)],
+            Mirroring::ONESCREEN_LO => [CIRAM(0); 4],
+            Mirroring::ONESCREEN_HI => [CIRAM(1); 4],
+            Mirroring::FOUR_SCREEN  => [CIRAM(0), CIRAM(1), CARTRIDGE(0), CARTRIDGE(1)],
+            Mirroring::CUSTOM(slots) => *slots,
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(match self {
+            Mirroring::HORIZONTAL   => 0,
+            Mirroring::VERTICAL     => 1,
+            Mirroring::ONESCREEN_LO => 2,
+            Mirroring::ONESCREEN_HI => 3,
+            Mirroring::FOUR_SCREEN  => 4,
+            Mirroring::CUSTOM(_)    => 5,
+        });
+
+        if let Mirroring::CUSTOM(slots) = self {
+            for slot in slots {
+                slot.save_state(state);
+            }
+        }
+    }
+
+    pub fn load_state(state: &mut StateReader) -> Result<Self, String> {
+        match state.read_u8()? {
+            0 => Ok(Mirroring::HORIZONTAL),
+            1 => Ok(Mirroring::VERTICAL),
+            2 => Ok(Mirroring::ONESCREEN_LO),
+            3 => Ok(Mirroring::ONESCREEN_HI),
+            4 => Ok(Mirroring::FOUR_SCREEN),
+            5 => {
+                let mut slots = [NameTableSource::CIRAM(0); 4];
+                for slot in &mut slots {
+                    *slot = NameTableSource::load_state(state)?;
+                }
+                Ok(Mirroring::CUSTOM(slots))
+            }
+            n => Err(format!("Invalid mirroring mode {} in save state", n)),
+        }
+    }
 }
 
 pub struct CartridgeNes {
+    pub header: InesHeader,
     pub mirroring: Mirroring,
-    pub mapper_num: u8,
+    pub mapper_num: u16,
+    pub submapper_num: u8,
     pub prg_rom_banks: usize,
     pub chr_rom_banks: usize,
     pub battery_backed: bool,
     pub mapper: Box<dyn Mapper>,
-    no_chr_rom: bool,
+    /// When set, `chr_rom` is CHR-RAM of the size given by the header, which is saved in save states
+    has_chr_ram: bool,
     prg_rom: Vec<u8>,
     chr_rom: Vec<u8>,
+    vram: Vec<u8>,
+    /// Only used when a trainer is loaded into a mapper without PRG-RAM of its own
+    prg_ram: Vec<u8>,
 }
 
 impl SystemControl for CartridgeNes {
     fn reset(&mut self) {
         self.mapper.reset()
     }
+
+    fn soft_reset(&mut self) {
+        self.mapper.soft_reset()
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.mapper.save_state(state);
+
+        if self.mapper.prg_rom_writable() {
+            state.write_bytes(&self.prg_rom);
+        }
+
+        if self.has_chr_ram {
+            state.write_bytes(&self.chr_rom);
+        }
+
+        state.write_bytes(&self.vram);
+        state.write_bytes(&self.prg_ram);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.mapper.load_state(state)?;
+
+        if self.mapper.prg_rom_writable() {
+            state.read_bytes(&mut self.prg_rom)?;
+        }
+
+        if self.has_chr_ram {
+            state.read_bytes(&mut self.chr_rom)?;
+        }
+
+        state.read_bytes(&mut self.vram)?;
+        state.read_bytes(&mut self.prg_ram)?;
+
+        Ok(())
+    }
 }
 
 impl CartridgeNes {
@@ -41,125 +193,321 @@ impl CartridgeNes {
         let data = read(file_path)?;
 
         CartridgeNes::from_ines_bytes(&data)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
     }
 
-    pub fn from_ines_bytes(data: &[u8]) -> Result<Self, String> {
-        // First three bytes must be "NES" in ASCII, followed by 0x1A
-        if &data[0..=3] != &[0x4E, 0x45, 0x53, 0x1A] {
-            return Err(String::from("Not a iNES file"));
-        }
-
-        let prg_rom_banks = data[4] as usize;
-
-        let chr_rom_banks = data[5] as usize;
-
-        if prg_rom_banks < 1 {
-            return Err(String::from("File must contain at least one PRG-ROM bank")); 
+    pub fn from_ines_bytes(data: &[u8]) -> Result<Self, CartridgeError> {
+        if data.len() < HEADER_SIZE {
+            if data.len() >= 4 && data[0..=3] != [0x4E, 0x45, 0x53, 0x1A] {
+                return Err(CartridgeError::BadMagic);
+            }
+            return Err(CartridgeError::TruncatedHeader { len: data.len() });
         }
 
-        let mut mirroring = if data[6] & 0x01 == 0 {
-            Mirroring::HORIZONTAL
-        } else {
-            Mirroring::VERTICAL
-        };
+        let mut header = InesHeader::from_bytes(data[0..HEADER_SIZE].try_into().unwrap())?;
 
-        if data[6] & 0b00001000 != 0 {
-            mirroring = Mirroring::FOUR_SCREEN;
+        if header.prg_rom_size == 0 {
+            return Err(CartridgeError::InconsistentSizes(String::from("File must contain at least one PRG-ROM bank")));
         }
 
-        let battery_backed = data[6] & 0x02 != 0;
+        // ROM sizes given in exponent form don't have to be a multiple of the bank size
+        let prg_rom_banks = header.prg_rom_size.div_ceil(PRG_ROM_SIZE);
+        let chr_rom_banks = header.chr_rom_size.div_ceil(CHR_ROM_SIZE);
 
-        let mapper_num = (data[7] & 0b11110000) | (data[6] >> 4);
+        let mapper_num = header.mapper_num;
 
-        // println!("Mapper:{} PRG-ROM banks:{} CHR-ROM banks:{} {:?} Trainer?:{} Battery?:{}", 
-        //     mapper_num, prg_rom_banks, chr_rom_banks, mirroring, data[6] & 0x04, battery_backed);
+        // Battery-backed and volatile PRG-RAM share the same address space
+        let prg_ram_size = header.prg_ram_size + header.prg_nvram_size;
 
         let mapper: Box<dyn Mapper> =  match mapper_num {
-            0  => Box::new(Mapper0::new(prg_rom_banks)),
-            1  => Box::new(Mapper1::new(prg_rom_banks)),
-            2  => Box::new(Mapper2::new(prg_rom_banks)),
+            0  => Box::new(Mapper0::new(prg_rom_banks, prg_ram_size)),
+            1  => Box::new(Mapper1::new(prg_rom_banks, prg_ram_size)),
+            2  => Box::new(Mapper2::new(UxromBoard::UXROM, prg_rom_banks)),
             3  => Box::new(Mapper3::new(prg_rom_banks)),
-            4  => Box::new(Mapper4::new(prg_rom_banks)),
+            4  => {
+                let board = match header.submapper_num {
+                    1 => Mmc3Board::MMC6,
+                    3 => Mmc3Board::MMC3_ACCLAIM,
+                    4 => Mmc3Board::MMC3_REV_A,
+                    _ => Mmc3Board::MMC3,
+                };
+                Box::new(Mapper4::new(board, prg_rom_banks, prg_ram_size))
+            }
+            5  => Box::new(Mapper5::new(prg_ram_size)),
             7  => Box::new(Mapper7::new()),
-            66 => Box::new(Mapper66::new()),
-            _ => return Err(format!("Unsupported iNES mapper {}", mapper_num))
+            9  => Box::new(Mapper9::new(LatchChip::MMC2, prg_rom_banks, prg_ram_size)),
+            10 => Box::new(Mapper9::new(LatchChip::MMC4, prg_rom_banks, prg_ram_size)),
+            11 => Box::new(Mapper66::new(LatchBoard::COLOR_DREAMS)),
+            13 => Box::new(Mapper13::new()),
+            15 => Box::new(Mapper15::new(prg_ram_size)),
+            16 => {
+                let board = match header.submapper_num {
+                    4 => FcgBoard::FCG,
+                    5 => FcgBoard::LZ93D50,
+                    _ => FcgBoard::FCG_OR_LZ93D50,
+                };
+                // Plain iNES files can only say that there's a save, which for these boards means a 24C02
+                let eeprom_size = match (header.nes2, header.battery_backed) {
+                    (true, _) => header.prg_nvram_size,
+                    (false, true) => 0x100,
+                    (false, false) => 0,
+                };
+                Box::new(Mapper16::new(board, prg_rom_banks, eeprom_size))
+            }
+            19 => Box::new(Mapper19::new(header.submapper_num, prg_rom_banks, prg_ram_size, header.battery_backed)),
+            21 | 22 | 23 | 25 => Box::new(Mapper21::new(mapper_num, header.submapper_num, prg_rom_banks, prg_ram_size)),
+            24 | 26 => Box::new(Mapper24::new(prg_rom_banks, prg_ram_size, mapper_num == 26)),
+            30 => {
+                // UNROM 512 marks its one-screen boards with the four-screen bit alone, keeping four-screen
+                // for when the vertical mirroring bit is set as well
+                if header.flags_6 & 0b00001001 == 0b00001000 {
+                    header.mirroring = Mirroring::ONESCREEN_LO;
+                }
+                Box::new(Mapper30::new(prg_rom_banks, header.mirroring, header.battery_backed))
+            }
+            34 => Box::new(Mapper34::new(header.submapper_num, chr_rom_banks)),
+            38 => Box::new(Mapper66::new(LatchBoard::BIT_CORP)),
+            57 => Box::new(Mapper225::new(MulticartBoard::GK_6_IN_1)),
+            58 => Box::new(Mapper225::new(MulticartBoard::GAME_STAR_68_IN_1)),
+            66 => Box::new(Mapper66::new(LatchBoard::GXROM)),
+            69 => Box::new(Mapper69::new(prg_rom_banks, prg_ram_size)),
+            70 => Box::new(Mapper2::new(UxromBoard::BANDAI, prg_rom_banks)),
+            71 => {
+                let board = if header.submapper_num == 1 { UxromBoard::CAMERICA_BF9097 } else { UxromBoard::CAMERICA };
+                Box::new(Mapper2::new(board, prg_rom_banks))
+            }
+            76 => Box::new(Mapper4::new(Mmc3Board::NAMCO_3446, prg_rom_banks, prg_ram_size)),
+            79 => Box::new(Mapper66::new(LatchBoard::NINA_03_06)),
+            85 => Box::new(Mapper85::new(header.submapper_num, prg_rom_banks, prg_ram_size)),
+            87 => Box::new(Mapper66::new(LatchBoard::JALECO_JF_87)),
+            88 => Box::new(Mapper4::new(Mmc3Board::NAMCO_3433, prg_rom_banks, prg_ram_size)),
+            95 => Box::new(Mapper4::new(Mmc3Board::NAMCO_3425, prg_rom_banks, prg_ram_size)),
+            118 => Box::new(Mapper4::new(Mmc3Board::TXSROM, prg_rom_banks, prg_ram_size)),
+            119 => Box::new(Mapper4::new(Mmc3Board::TQROM, prg_rom_banks, prg_ram_size)),
+            140 => Box::new(Mapper66::new(LatchBoard::JALECO_JF_11_14)),
+            152 => Box::new(Mapper2::new(UxromBoard::BANDAI_ONESCREEN, prg_rom_banks)),
+            153 => Box::new(Mapper16::new(FcgBoard::LZ93D50_SRAM, prg_rom_banks, 0)),
+            154 => Box::new(Mapper4::new(Mmc3Board::NAMCO_3453, prg_rom_banks, prg_ram_size)),
+            // Only the base unit's 24C02 is emulated. The 24C01 on some game carts (e.g. Battle Rush) isn't supported,
+            // so the header's NVRAM size is ignored and those games can't save
+            157 => Box::new(Mapper16::new(FcgBoard::DATACH, prg_rom_banks, 0x100)),
+            159 => Box::new(Mapper16::new(FcgBoard::LZ93D50, prg_rom_banks, 0x80)),
+            180 => Box::new(Mapper2::new(UxromBoard::UNROM_180, prg_rom_banks)),
+            206 => Box::new(Mapper4::new(Mmc3Board::NAMCO_108, prg_rom_banks, prg_ram_size)),
+            225 => Box::new(Mapper225::new(MulticartBoard::BMC_72_IN_1)),
+            226 => Box::new(Mapper225::new(MulticartBoard::BMC_76_IN_1)),
+            227 => Box::new(Mapper225::new(MulticartBoard::BMC_1200_IN_1)),
+            228 => Box::new(Mapper225::new(MulticartBoard::ACTION_52)),
+            232 => Box::new(Mapper232::new(header.submapper_num)),
+            233 => Box::new(Mapper225::new(MulticartBoard::BMC_42_IN_1)),
+            _ => return Err(CartridgeError::UnsupportedMapper { mapper_num, name: mapper_name(mapper_num) })
         };
 
-        let mut offset = 0x10;
+        let mut offset = HEADER_SIZE;
 
-        if data[6] & 0x04 != 0 {
-            offset += 0x200;
-        }
-
-        let prg_rom_size = prg_rom_banks * PRG_ROM_SIZE;
-        let mut prg_rom = vec![0; prg_rom_size];
-        prg_rom.copy_from_slice(&data[offset..offset + prg_rom_size]);
-        offset += prg_rom_size;
+        let trainer = if header.has_trainer {
+            Some(next_section(data, &mut offset, TRAINER_SIZE)
+                .map_err(|found| CartridgeError::TruncatedTrainer { expected: TRAINER_SIZE, found })?)
+        } else {
+            None
+        };
 
+        // The sizes are checked against the file before allocating, as exponent form headers can claim terabytes
+        let prg_rom_size = header.prg_rom_size;
+        let prg_rom_data = next_section(data, &mut offset, prg_rom_size)
+            .map_err(|found| CartridgeError::TruncatedPrgRom { expected: prg_rom_size, found })?;
+        let mut prg_rom = vec![0; prg_rom_banks * PRG_ROM_SIZE];
+        prg_rom[..prg_rom_size].copy_from_slice(prg_rom_data);
 
         let chr_rom = if chr_rom_banks > 0 {
+            let chr_rom_size = header.chr_rom_size;
+            let chr_rom_data = next_section(data, &mut offset, chr_rom_size)
+                .map_err(|found| CartridgeError::TruncatedChrRom { expected: chr_rom_size, found })?;
             let mut chr_rom = vec![0; chr_rom_banks * CHR_ROM_SIZE];
-            chr_rom.copy_from_slice(&data[offset..offset + chr_rom_banks * CHR_ROM_SIZE]);
+            chr_rom[..chr_rom_size].copy_from_slice(chr_rom_data);
             chr_rom
         } else {
-            vec![0; CHR_ROM_SIZE]
+            // Mappers without CHR banking index the whole pattern table directly, so there's always at least 8KB
+            vec![0; (header.chr_ram_size + header.chr_nvram_size).max(CHR_ROM_SIZE)]
         };
 
-        Ok(Self { 
-            mirroring,
+        let vram = match header.mirroring {
+            Mirroring::FOUR_SCREEN => vec![0; FOUR_SCREEN_VRAM_SIZE],
+            _ => Vec::new(),
+        };
+
+        let mut cartridge = Self { 
+            mirroring: header.mirroring,
             chr_rom_banks,
             prg_rom_banks,
             prg_rom,
             chr_rom,
-            no_chr_rom: chr_rom_banks == 0,
+            vram,
+            has_chr_ram: chr_rom_banks == 0,
             mapper_num,
+            submapper_num: header.submapper_num,
             mapper,
-            battery_backed,
-        })
+            battery_backed: header.battery_backed,
+            header,
+            prg_ram: Vec::new(),
+        };
+
+        if let Some(trainer) = trainer {
+            cartridge.load_trainer(trainer)?;
+        }
+
+        Ok(cartridge)
+    }
+
+    /// Copies the trainer into the mapper's PRG-RAM, or into PRG-RAM on the cartridge if the mapper has none
+    /// and nothing else at $7000-$71FF
+    fn load_trainer(&mut self, trainer: &[u8]) -> Result<(), CartridgeError> {
+        if self.mapper.load_trainer(trainer) {
+            return Ok(());
+        }
+
+        if self.mapper.registers_at_trainer() {
+            return Err(CartridgeError::UnmappableTrainer { mapper_num: self.mapper_num, name: mapper_name(self.mapper_num) });
+        }
+
+        if self.prg_ram.is_empty() {
+            self.prg_ram = vec![0; PRG_RAM_SIZE];
+        }
+
+        let start = TRAINER_START - PRG_RAM_START;
+        self.prg_ram[start..start + trainer.len()].copy_from_slice(trainer);
+        Ok(())
     }
 
     pub fn cpu_read(&mut self, addr: usize) -> Option<u8> {
-        self.mapper.mapped_cpu_read(&mut self.prg_rom, addr)
+        match self.mapper.mapped_cpu_read(&mut self.prg_rom, addr) {
+            Some(byte) => Some(byte),
+            None => match addr {
+                PRG_RAM_START..=PRG_RAM_END => self.prg_ram.get(addr - PRG_RAM_START).copied(),
+                _ => None,
+            }
+        }
     }
 
     pub fn cpu_write(&mut self, addr: usize, byte: u8) -> bool {
-        self.mapper.mapped_cpu_write(&mut self.prg_rom, addr, byte)
-    }
+        if self.mapper.mapped_cpu_write(&mut self.prg_rom, addr, byte) {
+            return true;
+        }
 
-    pub fn ppu_read(&self, addr: usize) -> u8 {
-        if self.no_chr_rom {
-            return self.chr_rom[addr];
+        match addr {
+            PRG_RAM_START..=PRG_RAM_END if !self.prg_ram.is_empty() => {
+                self.prg_ram[addr - PRG_RAM_START] = byte;
+                true
+            }
+            _ => false,
         }
+    }
 
+    pub fn ppu_read(&self, addr: usize) -> u8 {
         self.mapper.mapped_ppu_read(&self.chr_rom, addr)
     }
 
     pub fn ppu_write(&mut self, addr: usize, byte: u8) {
-        if self.no_chr_rom {
-            self.chr_rom[addr] = byte;
-            return;
+        if self.has_chr_ram {
+            self.mapper.mapped_ppu_write(&mut self.chr_rom, addr, byte)
+        } else {
+            self.mapper.mapped_chr_rom_write(addr, byte)
         }
-
-        self.mapper.mapped_ppu_write(&mut self.chr_rom, addr, byte)
     }
 
     pub fn mirroring(&self) -> Mirroring {
+        // Four-screen boards wire the nametables directly, ignoring the mapper's mirroring control
+        if let Mirroring::FOUR_SCREEN = self.mirroring {
+            return self.mirroring;
+        }
+
         match self.mapper.get_updated_mirroring() {
             Some(mirroring) => mirroring,
             None => self.mirroring,
         }
     }
 
-    pub fn notify_scanline(&mut self) {
-        self.mapper.notify_scanline()
+    pub fn name_table_read(&self, addr: usize) -> Option<u8> {
+        self.mapper.mapped_name_table_read(&self.chr_rom, addr)
+    }
+
+    pub fn name_table_write(&mut self, addr: usize, byte: u8) -> bool {
+        self.mapper.mapped_name_table_write(&mut self.chr_rom, addr, byte)
+    }
+
+    /// Reads from a page of the cartridge's own nametable VRAM, or open bus if it has none
+    pub fn vram_read(&self, page: u8, addr: usize) -> u8 {
+        let index = (page as usize * NAME_TABLE_PAGE_SIZE) | (addr & (NAME_TABLE_PAGE_SIZE - 1));
+
+        match self.vram.get(index) {
+            Some(byte) => *byte,
+            None => 0,
+        }
+    }
+
+    pub fn vram_write(&mut self, page: u8, addr: usize, byte: u8) {
+        let index = (page as usize * NAME_TABLE_PAGE_SIZE) | (addr & (NAME_TABLE_PAGE_SIZE - 1));
+
+        if let Some(b) = self.vram.get_mut(index) {
+            *b = byte;
+        }
+    }
+
+    pub fn notify_ppu_read(&mut self, addr: usize) {
+        self.mapper.notify_ppu_addr(addr);
+        self.mapper.notify_ppu_read(addr)
+    }
+
+    pub fn notify_ppu_addr(&mut self, addr: usize) {
+        self.mapper.notify_ppu_addr(addr)
+    }
+
+    pub fn cpu_clock(&mut self) {
+        self.mapper.cpu_clock()
+    }
+
+    pub fn expansion_audio_channels(&self) -> &'static [ExpansionChannel] {
+        self.mapper.expansion_audio_channels()
+    }
+
+    pub fn clock_expansion_audio(&mut self, levels: &mut [f32]) {
+        self.mapper.clock_expansion_audio(levels)
+    }
+
+    pub fn mix_expansion_audio(&self, levels: &[f32]) -> f32 {
+        self.mapper.mix_expansion_audio(levels)
     }
 
     pub fn irq_active(&mut self) -> bool {
         self.mapper.irq_active()
     }
 
+    pub fn has_barcode_reader(&self) -> bool {
+        self.mapper.has_barcode_reader()
+    }
+
+    pub fn scan_barcode(&mut self, barcode: &str) -> Result<(), String> {
+        self.mapper.scan_barcode(barcode)
+    }
+
+    pub fn dip_switch_settings(&self) -> usize {
+        self.mapper.dip_switch_settings()
+    }
+
+    pub fn dip_switch(&self) -> usize {
+        self.mapper.dip_switch()
+    }
+
+    /// Takes effect immediately, though most games only check the setting after a reset
+    pub fn set_dip_switch(&mut self, setting: usize) {
+        self.mapper.set_dip_switch(setting % self.mapper.dip_switch_settings().max(1))
+    }
+
     pub fn get_save_ram(&self) -> Option<Vec<u8>> {
+        if self.mapper.prg_rom_writable() {
+            return Some(self.prg_rom.clone());
+        }
+
         match self.mapper.get_save_ram() {
             Some(s) => Some(s.to_vec()),
             None => None
@@ -167,37 +515,263 @@ impl CartridgeNes {
     }
 
     pub fn load_save_ram(&mut self, save_ram: Vec<u8>) -> Result<(), String> {
-        match save_ram.try_into() {
-            Ok(s) => {
-                if self.mapper.load_save_ram(s) {
-                    Ok(())
-                } else {
-                    Err(String::from("Mapper does not have any save ram"))
-                }   
-            },
-            Err(_) => Err(String::from("Save size does not match")),
+        if self.mapper.prg_rom_writable() {
+            if save_ram.len() != self.prg_rom.len() {
+                return Err(format!("Saved flash size does not match (expected {} bytes, found {})", self.prg_rom.len(), save_ram.len()));
+            }
+
+            self.prg_rom = save_ram;
+            return Ok(());
+        }
+
+        let expected = match self.mapper.get_save_ram() {
+            Some(s) => s.len(),
+            None => return Err(String::from("Mapper does not have any save ram")),
+        };
+
+        if save_ram.len() != expected {
+            return Err(format!("Save size does not match (expected {} bytes, found {})", expected, save_ram.len()));
         }
+
+        self.mapper.load_save_ram(&save_ram);
+        Ok(())
     }
 }
 
+// Takes the next `len` bytes of the file, or returns how many bytes were left if there aren't enough
+fn next_section<'a>(data: &'a [u8], offset: &mut usize, len: usize) -> Result<&'a [u8], usize> {
+    let remaining = data.len().saturating_sub(*offset);
+
+    if len > remaining {
+        return Err(remaining);
+    }
+
+    let section = &data[*offset..*offset + len];
+    *offset += len;
+    Ok(section)
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{CartridgeNes, Mirroring};
-    use crate::mapper::TestMapper;
+    use std::fs::read;
+
+    use super::{CartridgeError, CartridgeNes, InesHeader, Mirroring, NameTableSource, HEADER_SIZE, TRAINER_SIZE};
+    use crate::SystemControl;
+    use crate::ppu::PpuBus;
+    use crate::mapper::{mapper_name, TestMapper, TestRom};
 
     impl CartridgeNes {
         pub fn test_new() -> Self {
+            let mut header = [0; HEADER_SIZE];
+            header[0..4].copy_from_slice(&[0x4E, 0x45, 0x53, 0x1A]);
+
             CartridgeNes {
+                header: InesHeader::from_bytes(&header).unwrap(),
                 prg_rom: vec![0; 0x10000],
                 chr_rom: vec![0; 0x2000],
+                vram: vec![0; 0x800],
+                prg_ram: Vec::new(),
                 prg_rom_banks: 0,
                 chr_rom_banks: 0,
-                no_chr_rom: true,
+                has_chr_ram: true,
                 mirroring: Mirroring::HORIZONTAL,
                 mapper_num: 0,
+                submapper_num: 0,
                 mapper: Box::new(TestMapper::new()),
                 battery_backed: false,
             }
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    pub fn test_malformed_files() {
+        let data = read("../roms/nestest.nes").unwrap();
+
+        let load = |data: &[u8]| CartridgeNes::from_ines_bytes(data).err();
+
+        assert_eq!(load(&data[..3]), Some(CartridgeError::TruncatedHeader { len: 3 }));
+        assert_eq!(load(b"not a rom file"), Some(CartridgeError::BadMagic));
+        assert_eq!(load(&data[..HEADER_SIZE + 100]), 
+            Some(CartridgeError::TruncatedPrgRom { expected: 0x4000, found: 100 }));
+        assert_eq!(load(&data[..data.len() - 1]), 
+            Some(CartridgeError::TruncatedChrRom { expected: 0x2000, found: 0x1FFF }));
+
+        let mut no_prg_rom = data.clone();
+        no_prg_rom[4] = 0;
+        assert!(matches!(load(&no_prg_rom), Some(CartridgeError::InconsistentSizes(_))));
+
+        let mut unsupported = data.clone();
+        unsupported[6] = 0x20;
+        unsupported[7] = 0x10;
+        assert_eq!(load(&unsupported), Some(CartridgeError::UnsupportedMapper { mapper_num: 18, name: "Jaleco SS88006" }));
+
+        let mut with_trainer = data.clone();
+        with_trainer[6] |= 0x04;
+        assert!(matches!(load(&with_trainer), Some(CartridgeError::TruncatedChrRom { .. })));
+
+        // NES 2.0 exponent form sizes are checked against the file before being allocated
+        let mut huge_prg_rom = [0; HEADER_SIZE + 48];
+        huge_prg_rom[0..4].copy_from_slice(&[0x4E, 0x45, 0x53, 0x1A]);
+        huge_prg_rom[4] = 0xA0;
+        huge_prg_rom[7] = 0x08;
+        huge_prg_rom[9] = 0x0F;
+        assert_eq!(load(&huge_prg_rom), Some(CartridgeError::TruncatedPrgRom { expected: 1 << 40, found: 48 }));
+
+        let mut huge_chr_rom = data.clone();
+        huge_chr_rom[5] = 0xA0;
+        huge_chr_rom[7] = 0x08;
+        huge_chr_rom[9] = 0xF0;
+        assert_eq!(load(&huge_chr_rom), Some(CartridgeError::TruncatedChrRom { expected: 1 << 40, found: 0x2000 }));
+    }
+
+    #[test]
+    pub fn test_trainer() {
+        let data = read("../roms/nestest.nes").unwrap();
+        let trainer: Vec<u8> = (0..TRAINER_SIZE).map(|i| i as u8 ^ 0x5A).collect();
+
+        // mapper 0 has its own PRG-RAM, while mapper 2 has to use the cartridge's
+        for flags in [0x04, 0x24] {
+            let mut with_trainer = data[..HEADER_SIZE].to_vec();
+            with_trainer[6] = flags;
+            with_trainer.extend_from_slice(&trainer);
+            with_trainer.extend_from_slice(&data[HEADER_SIZE..]);
+
+            let mut cartridge = CartridgeNes::from_ines_bytes(&with_trainer).unwrap();
+            cartridge.reset();
+
+            for (i, &byte) in trainer.iter().enumerate() {
+                assert_eq!(cartridge.cpu_read(0x7000 + i), Some(byte));
+            }
+            assert_eq!(cartridge.cpu_read(0x7200), Some(0));
+            assert_eq!(cartridge.cpu_read(0x8000), Some(data[HEADER_SIZE]));
+
+            assert!(cartridge.cpu_write(0x6000, 0x42));
+            assert_eq!(cartridge.cpu_read(0x6000), Some(0x42));
+        }
+    }
+
+    #[test]
+    pub fn test_trainer_bypasses_mapper_registers() {
+        let data = read("../roms/nestest.nes").unwrap();
+        let trainer: Vec<u8> = (0..TRAINER_SIZE).map(|i| i as u8 ^ 0x5A).collect();
+
+        let load = |flags_6: u8, flags_7: u8| {
+            let mut rom = data[..HEADER_SIZE].to_vec();
+            rom[6] = flags_6 | 0x04;
+            rom[7] = flags_7;
+            rom.extend_from_slice(&trainer);
+            rom.extend_from_slice(&data[HEADER_SIZE..]);
+
+            CartridgeNes::from_ines_bytes(&rom)
+        };
+
+        // MMC5's PRG-RAM is write protected at power-on, but still gets the trainer
+        let mut cartridge = load(0x50, 0x00).unwrap();
+        for (i, &byte) in trainer.iter().enumerate() {
+            assert_eq!(cartridge.cpu_read(0x7000 + i), Some(byte));
+        }
+        cartridge.cpu_write(0x7000, !trainer[0]);
+        assert_eq!(cartridge.cpu_read(0x7000), Some(trainer[0]));
+
+        // the LZ93D50 with SRAM has its PRG-RAM disabled until $800D bit 5 is set
+        let mut cartridge = load(0x90, 0x90).unwrap();
+        assert_eq!(cartridge.cpu_read(0x7000), None);
+        cartridge.cpu_write(0x800D, 0x20);
+        for (i, &byte) in trainer.iter().enumerate() {
+            assert_eq!(cartridge.cpu_read(0x7000 + i), Some(byte));
+        }
+
+        // the FCG-2 and JF-87 decode their registers at $6000-$7FFF, and have no PRG-RAM for the trainer to go in
+        for (flags_6, flags_7, mapper_num) in [(0x00, 0x10, 16), (0x70, 0x50, 87)] {
+            let name = mapper_name(mapper_num);
+            assert_eq!(load(flags_6, flags_7).err(), Some(CartridgeError::UnmappableTrainer { mapper_num, name }));
+        }
+    }
+
+    #[test]
+    pub fn test_small_chr_ram() {
+        // NES 2.0 headers can declare as little as 128 bytes of CHR-RAM
+        for mapper_num in [0, 2, 7, 9, 10, 70, 71, 152, 180, 232] {
+            let mut cartridge = TestRom::nes2(mapper_num, 0, 0x20000, 0).chr_ram(0x01).cartridge();
+
+            cartridge.ppu_write(0x0FFF, 0x42);
+            assert_eq!(cartridge.ppu_read(0x0FFF), 0x42, "mapper {}", mapper_num);
+        }
+    }
+
+    #[test]
+    pub fn test_banked_save_ram() {
+        // NES 2.0 SXROM board with 32KB of battery-backed PRG-RAM
+        let mut cartridge = TestRom::nes2(1, 0, 0x8000, 0).flags_6(0x02).prg_ram(0, 0x09).cartridge();
+        assert_eq!(cartridge.get_save_ram().map(|ram| ram.len()), Some(0x8000));
+
+        // MMC1 registers are written one bit at a time
+        let write_mmc1 = |cartridge: &mut CartridgeNes, addr: usize, value: u8| {
+            for bit in 0..5 {
+                cartridge.cpu_write(addr, (value >> bit) & 0x01);
+            }
+        };
+
+        for bank in 0..4 {
+            write_mmc1(&mut cartridge, 0xA000, bank << 2);
+            cartridge.cpu_write(0x6000, bank + 1);
+        }
+
+        let save_ram = cartridge.get_save_ram().unwrap();
+        for bank in 0..4 {
+            assert_eq!(save_ram[bank * 0x2000], bank as u8 + 1);
+        }
+
+        assert!(cartridge.load_save_ram(vec![0; 0x2000]).is_err());
+        assert!(cartridge.load_save_ram(vec![0xFF; 0x8000]).is_ok());
+        assert_eq!(cartridge.cpu_read(0x7FFF), Some(0xFF));
+    }
+
+    #[test]
+    pub fn test_four_screen_name_tables() {
+        let mut cartridge = CartridgeNes::test_new();
+        let mut ppu_bus = PpuBus::new();
+        cartridge.mirroring = Mirroring::FOUR_SCREEN;
+
+        for slot in 0..4 {
+            ppu_bus.ppu_write(0x2000 + slot * 0x400, slot as u8 + 1, &mut cartridge);
+        }
+
+        for slot in 0..4 {
+            assert_eq!(ppu_bus.ppu_read(0x2000 + slot * 0x400, &cartridge), slot as u8 + 1);
+            assert_eq!(ppu_bus.ppu_read(0x3000 + slot * 0x400, &cartridge), slot as u8 + 1);
+        }
+
+        // slots mapped to the same page share their contents
+        cartridge.mirroring = Mirroring::CUSTOM([
+            NameTableSource::CARTRIDGE(1), 
+            NameTableSource::CIRAM(0), 
+            NameTableSource::CARTRIDGE(1), 
+            NameTableSource::CIRAM(1),
+        ]);
+        assert_eq!(ppu_bus.ppu_read(0x2000, &cartridge), 4);
+        assert_eq!(ppu_bus.ppu_read(0x2400, &cartridge), 1);
+        assert_eq!(ppu_bus.ppu_read(0x2800, &cartridge), 4);
+        assert_eq!(ppu_bus.ppu_read(0x2C00, &cartridge), 2);
+    }
+
+    #[test]
+    pub fn test_mapper_name_tables() {
+        let mut cartridge = CartridgeNes::test_new();
+        let mut ppu_bus = PpuBus::new();
+        let mut mapper = TestMapper::new();
+        mapper.fill_name_table = Some(0xAA);
+        cartridge.mapper = Box::new(mapper);
+        cartridge.mirroring = Mirroring::VERTICAL;
+
+        for slot in 0..4 {
+            ppu_bus.ppu_write(0x2000 + slot * 0x400, slot as u8 + 1, &mut cartridge);
+        }
+
+        assert_eq!(ppu_bus.ppu_read(0x2000, &cartridge), 3);
+        assert_eq!(ppu_bus.ppu_read(0x2400, &cartridge), 2);
+        assert_eq!(ppu_bus.ppu_read(0x2800, &cartridge), 3);
+        assert_eq!(ppu_bus.ppu_read(0x2C00, &cartridge), 0xAA);
+        assert_eq!(ppu_bus.ppu_read(0x3C10, &cartridge), 0xAA);
+    }
+}
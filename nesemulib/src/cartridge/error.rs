@@ -0,0 +1,43 @@
+use std::{error::Error, fmt};
+
+/// Reasons an iNES file can fail to load as a cartridge
+#[derive(Debug, Clone, PartialEq)]
+pub enum CartridgeError {
+    /// The file does not start with "NES" followed by 0x1A
+    BadMagic,
+    /// The file is shorter than the 16 byte header
+    TruncatedHeader { len: usize },
+    TruncatedTrainer { expected: usize, found: usize },
+    TruncatedPrgRom { expected: usize, found: usize },
+    TruncatedChrRom { expected: usize, found: usize },
+    UnsupportedMapper { mapper_num: u16, name: &'static str },
+    /// The file has a trainer, but the board has registers where it goes and no PRG-RAM to load it into
+    UnmappableTrainer { mapper_num: u16, name: &'static str },
+    /// The header describes memory sizes that no cartridge could have
+    InconsistentSizes(String),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CartridgeError::BadMagic =>
+                write!(f, "Not a iNES file (missing \"NES\" header)"),
+            CartridgeError::TruncatedHeader { len } =>
+                write!(f, "File is only {} bytes long, which is too short for an iNES header", len),
+            CartridgeError::TruncatedTrainer { expected, found } =>
+                write!(f, "Trainer is truncated: expected {} bytes but found {}", expected, found),
+            CartridgeError::TruncatedPrgRom { expected, found } =>
+                write!(f, "PRG-ROM is truncated: expected {} bytes but found {}", expected, found),
+            CartridgeError::TruncatedChrRom { expected, found } =>
+                write!(f, "CHR-ROM is truncated: expected {} bytes but found {}", expected, found),
+            CartridgeError::UnsupportedMapper { mapper_num, name } =>
+                write!(f, "Unsupported iNES mapper {} ({})", mapper_num, name),
+            CartridgeError::UnmappableTrainer { mapper_num, name } =>
+                write!(f, "Trainer can't be loaded, as iNES mapper {} ({}) has registers at $7000-$71FF", mapper_num, name),
+            CartridgeError::InconsistentSizes(reason) =>
+                write!(f, "Inconsistent cartridge sizes: {}", reason),
+        }
+    }
+}
+
+impl Error for CartridgeError {}
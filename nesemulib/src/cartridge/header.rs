@@ -0,0 +1,305 @@
+use super::{CartridgeError, Mirroring, CHR_ROM_SIZE, PRG_ROM_SIZE};
+
+pub const HEADER_SIZE: usize = 0x10;
+
+// Default amount of PRG-RAM assumed for iNES 1.0 files, which cannot specify it reliably
+const INES_PRG_RAM_SIZE: usize = 0x2000;
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimingMode {
+    NTSC,
+    PAL,
+    MULTI_REGION,
+    DENDY,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConsoleType {
+    NES,
+    VS_SYSTEM { ppu_type: u8, hardware_type: u8 },
+    PLAYCHOICE_10,
+    EXTENDED(u8),
+}
+
+/// Everything described by an iNES or NES 2.0 file header.
+/// Fields that iNES 1.0 cannot express are filled in with the values most games expect.
+#[derive(Debug, Clone)]
+pub struct InesHeader {
+    pub nes2: bool,
+    pub mapper_num: u16,
+    pub submapper_num: u8,
+    pub mirroring: Mirroring,
+    pub battery_backed: bool,
+    pub has_trainer: bool,
+    /// Raw flags 6, for boards that give its mirroring bits their own meaning
+    pub flags_6: u8,
+
+    /// Sizes are all in bytes
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+
+    pub timing: TimingMode,
+    pub console_type: ConsoleType,
+    pub misc_rom_count: u8,
+    pub expansion_device: u8,
+}
+
+impl InesHeader {
+    pub fn from_bytes(header: &[u8; HEADER_SIZE]) -> Result<Self, CartridgeError> {
+        // First three bytes must be "NES" in ASCII, followed by 0x1A
+        if header[0..=3] != [0x4E, 0x45, 0x53, 0x1A] {
+            return Err(CartridgeError::BadMagic);
+        }
+
+        let mut mirroring = if header[6] & 0x01 == 0 {
+            Mirroring::HORIZONTAL
+        } else {
+            Mirroring::VERTICAL
+        };
+
+        if header[6] & 0b00001000 != 0 {
+            mirroring = Mirroring::FOUR_SCREEN;
+        }
+
+        let battery_backed = header[6] & 0x02 != 0;
+        let has_trainer = header[6] & 0x04 != 0;
+
+        if header[7] & 0b00001100 == 0b00001000 {
+            Ok(InesHeader::parse_nes2(header, mirroring, battery_backed, has_trainer))
+        } else {
+            Ok(InesHeader::parse_ines(header, mirroring, battery_backed, has_trainer))
+        }
+    }
+
+    fn parse_ines(header: &[u8; HEADER_SIZE], mirroring: Mirroring, battery_backed: bool, has_trainer: bool) -> Self {
+        // Old dumps often have garbage (like "DiskDude!") in bytes 7 to 15,
+        // in which case the upper nibble of the mapper number can't be trusted
+        let mapper_hi = if header[12..16].iter().all(|&b| b == 0) {
+            header[7] & 0b11110000
+        } else {
+            0
+        };
+
+        let chr_rom_size = header[5] as usize * CHR_ROM_SIZE;
+        let mapper_num = (mapper_hi | (header[6] >> 4)) as u16;
+        let prg_ram_size = ines_prg_ram_size(mapper_num);
+
+        Self {
+            nes2: false,
+            mapper_num,
+            submapper_num: 0,
+            mirroring,
+            battery_backed,
+            has_trainer,
+            flags_6: header[6],
+
+            prg_rom_size: header[4] as usize * PRG_ROM_SIZE,
+            chr_rom_size,
+            prg_ram_size: if battery_backed { 0 } else { prg_ram_size },
+            prg_nvram_size: if battery_backed { prg_ram_size } else { 0 },
+            chr_ram_size: if chr_rom_size == 0 { ines_chr_ram_size(mapper_num) } else { 0 },
+            chr_nvram_size: 0,
+
+            timing: TimingMode::NTSC,
+            console_type: ConsoleType::NES,
+            misc_rom_count: 0,
+            expansion_device: 0,
+        }
+    }
+
+    fn parse_nes2(header: &[u8; HEADER_SIZE], mirroring: Mirroring, battery_backed: bool, has_trainer: bool) -> Self {
+        let console_type = match header[7] & 0x03 {
+            0 => ConsoleType::NES,
+            1 => ConsoleType::VS_SYSTEM { ppu_type: header[13] & 0x0F, hardware_type: header[13] >> 4 },
+            2 => ConsoleType::PLAYCHOICE_10,
+            _ => ConsoleType::EXTENDED(header[13] & 0x0F),
+        };
+
+        let timing = match header[12] & 0x03 {
+            0 => TimingMode::NTSC,
+            1 => TimingMode::PAL,
+            2 => TimingMode::MULTI_REGION,
+            _ => TimingMode::DENDY,
+        };
+
+        Self {
+            nes2: true,
+            mapper_num: ((header[8] as u16 & 0x0F) << 8) | (header[7] & 0b11110000) as u16 | (header[6] >> 4) as u16,
+            submapper_num: header[8] >> 4,
+            mirroring,
+            battery_backed,
+            has_trainer,
+            flags_6: header[6],
+
+            prg_rom_size: nes2_rom_size(header[4], header[9] & 0x0F, PRG_ROM_SIZE),
+            chr_rom_size: nes2_rom_size(header[5], header[9] >> 4, CHR_ROM_SIZE),
+            prg_ram_size: nes2_ram_size(header[10] & 0x0F),
+            prg_nvram_size: nes2_ram_size(header[10] >> 4),
+            chr_ram_size: nes2_ram_size(header[11] & 0x0F),
+            chr_nvram_size: nes2_ram_size(header[11] >> 4),
+
+            timing,
+            console_type,
+            misc_rom_count: header[14] & 0x03,
+            expansion_device: header[15] & 0x3F,
+        }
+    }
+
+    /// Name of the default expansion device, as listed on the NESdev wiki
+    pub fn expansion_device_name(&self) -> &'static str {
+        match self.expansion_device {
+            0x00 => "Unspecified",
+            0x01 => "Standard Controllers",
+            0x02 => "Four Score",
+            0x03 => "Famicom Four Players Adapter",
+            0x04 => "Vs. System (1P via $4016)",
+            0x05 => "Vs. System (1P via $4017)",
+            0x07 => "Vs. Zapper",
+            0x08 => "Zapper",
+            0x09 => "Two Zappers",
+            0x0A => "Bandai Hyper Shot",
+            0x0B => "Power Pad Side A",
+            0x0C => "Power Pad Side B",
+            0x0D => "Family Trainer Side A",
+            0x0E => "Family Trainer Side B",
+            0x0F => "Arkanoid Vaus (NES)",
+            0x10 => "Arkanoid Vaus (Famicom)",
+            0x11 => "Two Vaus + Data Recorder",
+            0x12 => "Konami Hyper Shot",
+            0x13 => "Coconuts Pachinko",
+            0x14 => "Exciting Boxing Punching Bag",
+            0x15 => "Jissen Mahjong Controller",
+            0x16 => "Party Tap",
+            0x17 => "Oeka Kids Tablet",
+            0x18 => "Sunsoft Barcode Battler",
+            0x19 => "Miracle Piano Keyboard",
+            0x1A => "Pokkun Moguraa",
+            0x1B => "Top Rider",
+            0x1C => "Double-Fisted",
+            0x1D => "Famicom 3D System",
+            0x1E => "Doremikko Keyboard",
+            0x1F => "R.O.B. Gyro Set",
+            0x20 => "Famicom Data Recorder",
+            0x21 => "ASCII Turbo File",
+            0x22 => "IGS Storage Battle Box",
+            0x23 => "Family BASIC Keyboard",
+            0x24 => "Dongda PEC-586 Keyboard",
+            0x25 => "Bit Corp. Bit-79 Keyboard",
+            0x26 => "Subor Keyboard",
+            0x27 => "Subor Keyboard + Mouse",
+            0x28 => "Subor Keyboard + 24-bit Mouse",
+            0x29 => "SNES Mouse",
+            0x2A => "Multicart",
+            0x2B => "Two SNES Controllers",
+            0x2C => "RacerMate Bicycle",
+            0x2D => "U-Force",
+            0x2E => "R.O.B. Stack-Up",
+            0x2F => "City Patrolman Lightgun",
+            0x30 => "Sharp C1 Cassette Interface",
+            0x31 => "Standard Controller (Swapped)",
+            0x32 => "Excalibor Sudoku Pad",
+            0x33 => "ABL Pinball",
+            0x34 => "Golden Nugget Casino",
+            _ => "Unknown",
+        }
+    }
+}
+
+// iNES 1.0 files are assumed to have 8 KiB of PRG-RAM, except for boards that can address more or have none.
+// MMC5 games use at most 64 KiB, so that's enough for any of them, while VRC2a boards only have the microwire latch.
+fn ines_prg_ram_size(mapper_num: u16) -> usize {
+    match mapper_num {
+        5 => 0x10000,
+        22 => 0,
+        _ => INES_PRG_RAM_SIZE,
+    }
+}
+
+// iNES 1.0 files without CHR-ROM are assumed to have 8 KiB of CHR-RAM, except for boards known to have more
+fn ines_chr_ram_size(mapper_num: u16) -> usize {
+    match mapper_num {
+        13 => 0x4000,
+        30 => 0x8000,
+        _  => CHR_ROM_SIZE,
+    }
+}
+
+// ROM sizes are normally a count of banks with a 4-bit MSB stored in byte 9.
+// If the MSB is 0xF, the LSB instead encodes the size as 2^E * (MM * 2 + 1) from its bits EEEEEEMM.
+fn nes2_rom_size(lsb: u8, msb: u8, bank_size: usize) -> usize {
+    if msb == 0x0F {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0x03) as usize * 2 + 1;
+
+        2usize.saturating_pow(exponent).saturating_mul(multiplier)
+    } else {
+        (((msb as usize) << 8) | lsb as usize) * bank_size
+    }
+}
+
+// RAM sizes are stored as shift counts, where 0 means no RAM and otherwise the size is 64 << count
+fn nes2_ram_size(shift_count: u8) -> usize {
+    if shift_count == 0 {
+        0
+    } else {
+        64 << shift_count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConsoleType, InesHeader, TimingMode, HEADER_SIZE};
+
+    #[test]
+    pub fn test_nes2_header() {
+        let header: [u8; HEADER_SIZE] = [
+            0x4E, 0x45, 0x53, 0x1A,
+            0x02, 0x35,  // 2 PRG-ROM banks, CHR-ROM of 2^13 * 3 bytes
+            0x52,        // mapper lo nibble 5, battery, horizontal
+            0x48,        // mapper mid nibble 4, NES 2.0
+            0x31,        // submapper 3, mapper hi nibble 1
+            0xF0,        // CHR-ROM MSB 0xF (exponent form), PRG-ROM MSB 0
+            0x70,        // 8KiB PRG-NVRAM, no PRG-RAM
+            0x07,        // 8KiB CHR-RAM
+            0x03,        // Dendy
+            0x00, 0x00,
+            0x01,        // standard controllers
+        ];
+
+        let header = InesHeader::from_bytes(&header).unwrap();
+        assert!(header.nes2);
+        assert_eq!(header.mapper_num, 0x145);
+        assert_eq!(header.submapper_num, 3);
+        assert!(header.battery_backed);
+        assert_eq!(header.prg_rom_size, 0x8000);
+        assert_eq!(header.chr_rom_size, 0x6000); // 2^13 * 3
+        assert_eq!(header.prg_ram_size, 0);
+        assert_eq!(header.prg_nvram_size, 0x2000);
+        assert_eq!(header.chr_ram_size, 0x2000);
+        assert_eq!(header.timing, TimingMode::DENDY);
+        assert_eq!(header.console_type, ConsoleType::NES);
+        assert_eq!(header.expansion_device_name(), "Standard Controllers");
+    }
+
+    #[test]
+    pub fn test_ines_header_with_garbage() {
+        let mut header: [u8; HEADER_SIZE] = [0; HEADER_SIZE];
+        header[0..4].copy_from_slice(&[0x4E, 0x45, 0x53, 0x1A]);
+        header[4] = 0x08;
+        header[6] = 0x41;
+        header[7..16].copy_from_slice(b"DiskDude!");
+
+        let header = InesHeader::from_bytes(&header).unwrap();
+        assert!(!header.nes2);
+        assert_eq!(header.mapper_num, 4);
+        assert_eq!(header.flags_6, 0x41);
+        assert_eq!(header.prg_rom_size, 0x20000);
+        assert_eq!(header.chr_ram_size, 0x2000);
+    }
+}
@@ -4,7 +4,7 @@ use core::panic;
 use std::fs::OpenOptions;
 use std::io::prelude::*;
 
-use crate::{apu::Apu2A03, bus::SystemBus, SystemControl};
+use crate::{apu::Apu2A03, bus::SystemBus, SystemControl, StateReader, StateWriter};
 use self::opcode::{AddrMode, OPCODES_LOOKUP};
 
 const STACK_START: u16 = 0x100;
@@ -79,6 +79,12 @@ impl Cpu6502 {
         self.total_cycles += 1;
     }
 
+    /// Returns true if the next clock will start executing a new instruction
+    #[inline]
+    pub fn instruction_complete(&self) -> bool {
+        self.cycles == 0
+    }
+
     #[inline]
     fn execute_instruction(&mut self, bus: &mut SystemBus) {
         let opcode = self.advance_pc(bus);
@@ -118,6 +124,46 @@ impl Cpu6502 {
         self.apu.reset();
     }
 
+    /// Saves the CPU's registers and cycle counters, along with the state of its APU
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.accumulator);
+        state.write_u8(self.x_index_reg);
+        state.write_u8(self.y_index_reg);
+        state.write_u16(self.program_counter);
+        state.write_u8(self.stack_pointer);
+        state.write_u8(self.processor_status);
+
+        // the addressing mode is not saved as instructions are always executed in a single clock
+        state.write_u16(self.operand_addr);
+        state.write_u8(self.operand_data);
+        state.write_bool(self.page_crossed);
+
+        state.write_u32(self.cycles);
+        state.write_u64(self.total_cycles);
+        state.write_bool(self.jammed);
+
+        self.apu.save_state(state);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.accumulator = state.read_u8()?;
+        self.x_index_reg = state.read_u8()?;
+        self.y_index_reg = state.read_u8()?;
+        self.program_counter = state.read_u16()?;
+        self.stack_pointer = state.read_u8()?;
+        self.processor_status = state.read_u8()?;
+
+        self.operand_addr = state.read_u16()?;
+        self.operand_data = state.read_u8()?;
+        self.page_crossed = state.read_bool()?;
+
+        self.cycles = state.read_u32()?;
+        self.total_cycles = state.read_u64()?;
+        self.jammed = state.read_bool()?;
+
+        self.apu.load_state(state)
+    }
+
     pub fn irq(&mut self, bus: &mut SystemBus) {
         if self.get_flag(StatusFlag::I) {
             return;
@@ -10,11 +10,20 @@ mod cartridge;
 mod ppu;
 mod mapper;
 mod apu;
+mod nes;
+mod region;
+mod rewind;
+mod savestate;
 
 pub use apu::Apu2A03;
 pub use bus::SystemBus;
-pub use cartridge::CartridgeNes;
+pub use cartridge::{CartridgeError, CartridgeNes, ConsoleType, InesHeader, TimingMode};
 pub use cpu::Cpu6502;
+pub use mapper::ExpansionChannel;
+pub use nes::Nes;
+pub use region::Region;
+pub use rewind::RewindBuffer;
+pub use savestate::{StateReader, StateWriter, STATE_VERSION};
 pub use ppu::*;
 
 pub const DISPLAY_WIDTH: usize = 256;
@@ -26,5 +35,18 @@ pub const BASE_PPU_FREQUENCY: f32 = 3.0 * BASE_CPU_FREQUENCY;
 pub const DEFAULT_TIME_PER_6502_CLOCK: f32 = 1e9 / BASE_CPU_FREQUENCY;
 
 pub trait SystemControl {
+    /// Returns the component to its power-on state
     fn reset(&mut self);
+
+    /// Pressing the console's reset button. Most components can't tell this apart from power cycling,
+    /// so this is a full `reset` unless they override it
+    fn soft_reset(&mut self) {
+        self.reset()
+    }
+
+    /// Writes all internal state needed to later restore this component with `load_state`
+    fn save_state(&self, state: &mut StateWriter);
+
+    /// Restores internal state previously written by `save_state`
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String>;
 }
\ No newline at end of file
@@ -3,28 +3,62 @@ mod mapper1;
 mod mapper2;
 mod mapper3;
 mod mapper4;
+mod mapper5;
 mod mapper7;
+mod mapper9;
+mod mapper13;
+mod mapper15;
+mod mapper16;
+mod mapper19;
+mod mapper21;
+mod mapper225;
+mod mapper232;
+mod mapper30;
+mod mapper24;
+mod mapper34;
 mod mapper66;
+mod mapper69;
+mod mapper85;
 mod testmapper;
+#[cfg(test)]
+mod testrom;
+mod vrc_irq;
 
 use crate::cartridge::{Mirroring, PRG_ROM_SIZE};
 use crate::SystemControl;
 
 pub use self::mapper0::Mapper0;
 pub use self::mapper1::Mapper1;
-pub use self::mapper2::Mapper2;
+pub use self::mapper2::{Mapper2, UxromBoard};
 pub use self::mapper3::Mapper3;
-pub use self::mapper4::Mapper4;
+pub use self::mapper4::{Mapper4, Mmc3Board};
+pub use self::mapper5::Mapper5;
 pub use self::mapper7::Mapper7;
-pub use self::mapper66::Mapper66;
+pub use self::mapper9::{LatchChip, Mapper9};
+pub use self::mapper13::Mapper13;
+pub use self::mapper15::Mapper15;
+pub use self::mapper16::{FcgBoard, Mapper16};
+pub use self::mapper19::Mapper19;
+pub use self::mapper21::Mapper21;
+pub use self::mapper225::{Mapper225, MulticartBoard};
+pub use self::mapper232::Mapper232;
+pub use self::mapper30::Mapper30;
+pub use self::mapper24::Mapper24;
+pub use self::mapper34::Mapper34;
+pub use self::mapper66::{LatchBoard, Mapper66};
+pub use self::mapper69::Mapper69;
+pub use self::mapper85::Mapper85;
 
 #[cfg(test)]
 pub use self::testmapper::TestMapper;
+#[cfg(test)]
+pub use self::testrom::TestRom;
 
 
-const SAVE_RAM_START: usize = 0x6000;
-const SAVE_RAM_END: usize = 0x7FFF;
-const SAVE_RAM_SIZE: usize = 0x2000;
+const PRG_RAM_START: usize = 0x6000;
+const PRG_RAM_END: usize = 0x7FFF;
+// PRG-RAM larger than this is banked into $6000-$7FFF
+const PRG_RAM_BANK_SIZE: usize = 0x2000;
 
 const PRG_ROM_START: usize = 0x8000;
 const PRG_ROM_END: usize = 0xFFFF;
@@ -39,36 +73,203 @@ const CHR_ROM_LO_END: usize = 0x0FFF;
 const CHR_ROM_HI_START: usize = 0x1000;
 const CHR_ROM_HI_END: usize = 0x1FFF;
 
+// The trainer is loaded at $7000, which is this far into the PRG-RAM mapped at $6000
+const TRAINER_OFFSET: usize = 0x1000;
+
+/// For `Mapper::load_trainer()`: copies the trainer into `prg_ram`, which is mirrored if smaller than 8KB
+fn copy_trainer(prg_ram: &mut [u8], trainer: &[u8]) -> bool {
+    if prg_ram.is_empty() {
+        return false;
+    }
+
+    let len = prg_ram.len();
+    for (i, &byte) in trainer.iter().enumerate() {
+        prg_ram[(TRAINER_OFFSET + i) % len] = byte;
+    }
+
+    true
+}
+
+/// An expansion audio channel, as shown in the Ui
+pub struct ExpansionChannel {
+    pub name: &'static str,
+    /// Lowest and highest levels the channel outputs, used to scale its plot
+    pub min_level: f32,
+    pub max_level: f32,
+}
+
+/// Mappers save their bank registers and RAM through `SystemControl::save_state()` and `SystemControl::load_state()`
 pub trait Mapper: SystemControl {
 
     /// Some contains the successfully read byte; None means read is meant to be done from elsewhere...
-    fn mapped_cpu_read(&self, prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8>;
+    /// Takes `&mut self` as reading some mapper registers has side effects, like acknowledging an IRQ
+    fn mapped_cpu_read(&mut self, prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8>;
 
 
     /// Returns true if write was successful; false if write did nothing to the mapper...
     fn mapped_cpu_write(&mut self, prg_rom: &mut Vec<u8>, addr: usize, byte: u8) -> bool;
 
 
-    /// Returns the addressed pattern table byte (from PPU 0x0000 to 0x1FFF)
+    /// Returns the addressed pattern table byte (from PPU 0x0000 to 0x1FFF).
+    /// Debug views read through this as well, so mappers that react to PPU reads do so in `notify_ppu_read`
     fn mapped_ppu_read(&self, chr_rom: &Vec<u8>, addr: usize) -> u8;
     
 
-    /// not used by any mappers so far...
-    fn mapped_ppu_write(&mut self, _chr_rom: &mut Vec<u8>, _addr: usize, _byte: u8) {}
+    /// Only called for cartridges with CHR-RAM. Mappers that bank CHR-RAM must map the address 
+    /// the same way as `mapped_ppu_read`
+    fn mapped_ppu_write(&mut self, chr_ram: &mut Vec<u8>, addr: usize, byte: u8) {
+        chr_ram[addr] = byte;
+    }
+
+    /// Only called for cartridges with CHR-ROM, for boards that also have some CHR-RAM of their own
+    fn mapped_chr_rom_write(&mut self, _addr: usize, _byte: u8) {}
 
-    /// Some mappers can dynamically change mirroring mode during execution
+    /// Some mappers can dynamically change mirroring mode during execution, 
+    /// or map each nametable slot to CIRAM or cartridge VRAM with `Mirroring::CUSTOM`
     fn get_updated_mirroring(&self) -> Option<Mirroring> {
         None
     }
 
-    /// Some mappers require knowledge of when the PPU's scanline has been updated
-    fn notify_scanline(&mut self) {}
+    /// Lets mappers supply nametable bytes (PPU 0x2000 to 0x3EFF) from their own memory, such as ExRAM or CHR-ROM.
+    /// None means the read goes to CIRAM or cartridge VRAM according to the current `Mirroring`
+    fn mapped_name_table_read(&self, _chr_rom: &Vec<u8>, _addr: usize) -> Option<u8> { None }
+
+    /// Returns true if the mapper handled the nametable write; false lets it go through the current `Mirroring`
+    fn mapped_name_table_write(&mut self, _chr_rom: &mut Vec<u8>, _addr: usize, _byte: u8) -> bool { false }
+
+    /// Called after every pattern table and nametable fetch the PPU makes while rendering, and after
+    /// every PPUDATA read, for mappers that watch the PPU's address bus. Unlike `mapped_ppu_read`,
+    /// this is never called for reads made by debug views, so it's where mappers should update their state
+    fn notify_ppu_read(&mut self, _addr: usize) {}
+
+    /// Called whenever the PPU puts an address on its bus: for every `notify_ppu_read`, and when PPUADDR or PPUDATA
+    /// are written. This is for mappers that watch PPU A12 to count scanlines, like MMC3
+    fn notify_ppu_addr(&mut self, _addr: usize) {}
+
+    /// Called once per CPU cycle, for mappers with timers of their own
+    fn cpu_clock(&mut self) {}
+
+    /// The board's expansion audio channels, which is empty for boards without any
+    fn expansion_audio_channels(&self) -> &'static [ExpansionChannel] { &[] }
+
+    /// Clocks the board's expansion audio once per CPU cycle, writing each channel's current output level to `levels`
+    fn clock_expansion_audio(&mut self, _levels: &mut [f32]) {}
+
+    /// Mixes the channel levels (some of which may have been muted) on the same scale as the APU's own output
+    fn mix_expansion_audio(&self, _levels: &[f32]) -> f32 { 0.0 }
 
     /// Returns true if the mapper is sending an IRQ interrupt to the 6502
     fn irq_active(&mut self) -> bool { false }
 
+    /// Number of settings of the board's DIP switches or solder pads, which multicarts read to pick
+    /// which menu to show. Zero for boards without any
+    fn dip_switch_settings(&self) -> usize { 0 }
+
+    fn dip_switch(&self) -> usize { 0 }
+
+    /// `setting` is always less than `dip_switch_settings()`
+    fn set_dip_switch(&mut self, _setting: usize) {}
+
+
+    /// True for boards with a barcode reader, like the Datach Joint ROM System
+    fn has_barcode_reader(&self) -> bool { false }
 
-    fn get_save_ram(&self) -> Option<[u8; SAVE_RAM_SIZE]> { None }
+    /// Swipes a barcode through the board's reader, given as a string of its digits
+    fn scan_barcode(&mut self, _barcode: &str) -> Result<(), String> {
+        Err(String::from("Cartridge does not have a barcode reader"))
+    }
+
+    /// True for boards with flash memory that the game rewrites to save, in which case
+    /// the whole of PRG-ROM is kept in save states and in the save file instead of `get_save_ram()`
+    fn prg_rom_writable(&self) -> bool { false }
+
+    /// Copies the trainer into the PRG-RAM seen at $7000-$71FF at power-on, ignoring whatever enables, protects
+    /// or registers are mapped there. Returns false if the mapper has no PRG-RAM, so the cartridge provides some
+    fn load_trainer(&mut self, _trainer: &[u8]) -> bool { false }
+
+    /// True if the mapper decodes registers at $7000-$71FF, so without PRG-RAM there's nowhere to put a trainer
+    fn registers_at_trainer(&self) -> bool { false }
 
-    fn load_save_ram(&mut self, _save_ram: [u8; SAVE_RAM_SIZE]) -> bool { false }
+    /// The PRG-RAM to be kept in the save file, which is as big as the cartridge's header says
+    fn get_save_ram(&self) -> Option<&[u8]> { None }
+
+    /// Returns false if the mapper has no save RAM; `save_ram` is always the same size as `get_save_ram()`
+    fn load_save_ram(&mut self, _save_ram: &[u8]) -> bool { false }
+}
+
+/// Common name of the board or chip for an iNES mapper number, used in error messages and the Ui
+pub fn mapper_name(mapper_num: u16) -> &'static str {
+    match mapper_num {
+        0   => "NROM",
+        1   => "SxROM/MMC1",
+        2   => "UxROM",
+        3   => "CNROM",
+        4   => "TxROM/MMC3",
+        5   => "ExROM/MMC5",
+        7   => "AxROM",
+        9   => "PxROM/MMC2",
+        10  => "FxROM/MMC4",
+        11  => "Color Dreams",
+        13  => "CPROM",
+        15  => "100-in-1 Contra Function 16",
+        16  => "Bandai FCG",
+        18  => "Jaleco SS88006",
+        19  => "Namco 163",
+        21  => "VRC4a/VRC4c",
+        22  => "VRC2a",
+        23  => "VRC2b/VRC4e",
+        24  => "VRC6a",
+        25  => "VRC4b/VRC4d",
+        26  => "VRC6b",
+        28  => "Action 53",
+        30  => "UNROM 512",
+        32  => "Irem G-101",
+        33  => "Taito TC0190",
+        34  => "BNROM/NINA-001",
+        38  => "Bit Corp. PCI556",
+        48  => "Taito TC0690",
+        57  => "GK 6-in-1",
+        58  => "Multicart",
+        64  => "Tengen RAMBO-1",
+        65  => "Irem H3001",
+        66  => "GxROM",
+        67  => "Sunsoft-3",
+        68  => "Sunsoft-4",
+        69  => "Sunsoft FME-7/5B",
+        70  => "Bandai 74161/32",
+        71  => "Camerica/Codemasters",
+        73  => "VRC3",
+        75  => "VRC1",
+        76  => "Namco 3446",
+        78  => "Irem 74HC161/32",
+        79  => "NINA-03/NINA-06",
+        85  => "VRC7",
+        86  => "Jaleco JF-13",
+        87  => "Jaleco J87",
+        88  => "Namco 3433",
+        94  => "UN1ROM",
+        95  => "Namco 3425",
+        105 => "NES-EVENT",
+        118 => "TxSROM",
+        119 => "TQROM",
+        140 => "Jaleco JF-11/JF-14",
+        152 => "Bandai 74161/32 (one-screen)",
+        153 => "Bandai LZ93D50 + SRAM",
+        154 => "Namco 3453",
+        155 => "MMC1A",
+        157 => "Bandai Datach",
+        159 => "Bandai LZ93D50 + 24C01",
+        180 => "UNROM (Crazy Climber)",
+        184 => "Sunsoft-1",
+        185 => "CNROM with protection",
+        206 => "Namco 118/DxROM",
+        210 => "Namco 175/340",
+        225 => "72-in-1 Multicart",
+        226 => "76-in-1 Multicart",
+        227 => "1200-in-1 Multicart",
+        228 => "Action 52",
+        232 => "Camerica Quattro",
+        233 => "42-in-1 Multicart",
+        _   => "Unknown",
+    }
 }
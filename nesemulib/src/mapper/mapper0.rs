@@ -1,26 +1,31 @@
-use super::{Mapper, PRG_ROM_END, PRG_ROM_START};
-use crate::{cartridge::PRG_ROM_SIZE, SystemControl};
+use super::{copy_trainer, Mapper, PRG_RAM_END, PRG_RAM_START, PRG_ROM_END, PRG_ROM_START};
+use crate::{cartridge::PRG_ROM_SIZE, SystemControl, StateReader, StateWriter};
 
 
-const SAVE_RAM_START: usize = 0x4020;
-const SAVE_RAM_END: usize = 0x7FFF;
-
-const SAVE_RAM_SIZE: usize = 0x3FE0;
-
 pub struct Mapper0 {
-    save_ram: [u8; SAVE_RAM_SIZE],
+    prg_ram: Vec<u8>, // mirrored across 0x6000 - 0x7FFF when smaller than 8KB
     prg_rom_banks: usize, // 1 or 2 bank(s)
 }
 
 impl SystemControl for Mapper0 {
     fn reset(&mut self) { }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.read_bytes(&mut self.prg_ram)?;
+
+        Ok(())
+    }
 }
 
 impl Mapper for Mapper0 {
-    fn mapped_cpu_read(&self, prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8> {
+    fn mapped_cpu_read(&mut self, prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8> {
         match addr {
-            SAVE_RAM_START..=SAVE_RAM_END => {
-                Some(self.save_ram[addr - SAVE_RAM_START])
+            PRG_RAM_START..=PRG_RAM_END if !self.prg_ram.is_empty() => {
+                Some(self.prg_ram[(addr - PRG_RAM_START) % self.prg_ram.len()])
             }
             PRG_ROM_START..=PRG_ROM_END => {
                 let addr = addr - PRG_ROM_START;
@@ -35,30 +40,44 @@ impl Mapper for Mapper0 {
             _ => None
         }
     }
-    
+
     fn mapped_cpu_write(&mut self, _prg_rom: &mut Vec<u8>, addr: usize, byte: u8) -> bool {
         match addr {
-            SAVE_RAM_START..=SAVE_RAM_END => {
-                self.save_ram[addr - SAVE_RAM_START] = byte; 
+            PRG_RAM_START..=PRG_RAM_END if !self.prg_ram.is_empty() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - PRG_RAM_START) % len] = byte;
                 true
             }
             _ => false
         }
     }
-    
-    fn mapped_ppu_read(&self, chr_rom: &Vec<u8>, addr: usize) -> u8 { 
+
+    fn mapped_ppu_read(&self, chr_rom: &Vec<u8>, addr: usize) -> u8 {
         match addr {
             0x0000..=0x1FFF => chr_rom[addr],
             _ => unreachable!(),
         }
     }
+
+    fn load_trainer(&mut self, trainer: &[u8]) -> bool {
+        copy_trainer(&mut self.prg_ram, trainer)
+    }
+
+    fn get_save_ram(&self) -> Option<&[u8]> {
+        if self.prg_ram.is_empty() { None } else { Some(&self.prg_ram) }
+    }
+
+    fn load_save_ram(&mut self, save_ram: &[u8]) -> bool {
+        self.prg_ram.copy_from_slice(save_ram);
+        !self.prg_ram.is_empty()
+    }
 }
 
 impl Mapper0 {
-    pub fn new(prg_rom_banks: usize) -> Self {
+    pub fn new(prg_rom_banks: usize, prg_ram_size: usize) -> Self {
         Self {
-            save_ram: [0; SAVE_RAM_SIZE],
+            prg_ram: vec![0; prg_ram_size],
             prg_rom_banks,
         }
     }
-}
\ No newline at end of file
+}
@@ -1,9 +1,10 @@
-use crate::{cartridge::{Mirroring, CHR_ROM_SIZE, PRG_ROM_SIZE}, SystemControl};
+use crate::{cartridge::{Mirroring, CHR_ROM_SIZE, PRG_ROM_SIZE}, SystemControl, StateReader, StateWriter};
 
-use super::{Mapper, CHR_ROM_HI_END, CHR_ROM_HI_START, CHR_ROM_LO_END, CHR_ROM_LO_START, PRG_ROM_END, PRG_ROM_HI_END, PRG_ROM_HI_START, PRG_ROM_LO_END, PRG_ROM_LO_START, PRG_ROM_START, SAVE_RAM_END, SAVE_RAM_SIZE, SAVE_RAM_START};
+use super::{copy_trainer, Mapper, CHR_ROM_HI_END, CHR_ROM_HI_START, CHR_ROM_LO_END, CHR_ROM_LO_START, PRG_RAM_BANK_SIZE, PRG_RAM_END, PRG_RAM_START, PRG_ROM_END, PRG_ROM_HI_END, PRG_ROM_HI_START, PRG_ROM_LO_END, PRG_ROM_LO_START, PRG_ROM_START};
 
 pub struct Mapper1 {
-    save_ram: [u8; SAVE_RAM_SIZE],
+    prg_ram: Vec<u8>, // 8KB, or 16KB/32KB on SOROM/SXROM boards
+    prg_ram_bank: usize,
     mirroring: Mirroring,
     prg_rom_banks: usize,
     
@@ -25,6 +26,7 @@ impl SystemControl for Mapper1 {
         self.chr_bank_lo4 = 0;
         self.chr_bank_hi4 = 0;
         self.chr_bank_full8 = 0;
+        self.prg_ram_bank = 0;
 
         self.prg_bank_lo16 = 0;
         self.prg_bank_hi16 = self.prg_rom_banks - 1;
@@ -34,13 +36,52 @@ impl SystemControl for Mapper1 {
         self.control_reg = 0x1C;
         self.load_count = 0;
     }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+        state.write_usize(self.prg_ram_bank);
+        self.mirroring.save_state(state);
+
+        state.write_usize(self.chr_bank_lo4);
+        state.write_usize(self.chr_bank_hi4);
+        state.write_usize(self.chr_bank_full8);
+
+        state.write_usize(self.prg_bank_lo16);
+        state.write_usize(self.prg_bank_hi16);
+        state.write_usize(self.prg_bank_full32);
+
+        state.write_u8(self.load_reg);
+        state.write_u8(self.control_reg);
+        state.write_u8(self.load_count);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.read_bytes(&mut self.prg_ram)?;
+        self.prg_ram_bank = state.read_usize()? & 0x03;
+        self.mirroring = Mirroring::load_state(state)?;
+
+        self.chr_bank_lo4 = state.read_usize()? & 0x1F;
+        self.chr_bank_hi4 = state.read_usize()? & 0x1F;
+        self.chr_bank_full8 = state.read_usize()? & 0x1F;
+
+        // PRG-ROM is indexed directly, so the banks have to be within it
+        self.prg_bank_lo16 = state.read_usize()? % self.prg_rom_banks;
+        self.prg_bank_hi16 = state.read_usize()? % self.prg_rom_banks;
+        self.prg_bank_full32 = state.read_usize()? % self.prg_rom_banks.div_ceil(2);
+
+        self.load_reg = state.read_u8()? & 0x1F;
+        self.control_reg = state.read_u8()? & 0x1F;
+        self.load_count = state.read_u8()?.min(4);
+
+        Ok(())
+    }
 }
 
 impl Mapper for Mapper1 {
-    fn mapped_cpu_read(&self, prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8> {
+    fn mapped_cpu_read(&mut self, prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8> {
         match addr {
-            SAVE_RAM_START..=SAVE_RAM_END => {
-                Some(self.save_ram[addr & 0x1FFF])
+            PRG_RAM_START..=PRG_RAM_END if !self.prg_ram.is_empty() => {
+                Some(self.prg_ram[self.prg_ram_addr(addr)])
             },
             PRG_ROM_LO_START..=PRG_ROM_LO_END => {
                 if self.control_reg & 0b01000 != 0 {
@@ -62,8 +103,9 @@ impl Mapper for Mapper1 {
 
     fn mapped_cpu_write(&mut self, _prg_rom: &mut Vec<u8>, addr: usize, byte: u8) -> bool {
         match addr {
-            SAVE_RAM_START..=SAVE_RAM_END => {
-                self.save_ram[addr & 0x1FFF] = byte;
+            PRG_RAM_START..=PRG_RAM_END if !self.prg_ram.is_empty() => {
+                let prg_ram_addr = self.prg_ram_addr(addr);
+                self.prg_ram[prg_ram_addr] = byte;
                 true
             }
             PRG_ROM_START..=PRG_ROM_END => {
@@ -93,6 +135,13 @@ impl Mapper for Mapper1 {
                             }
                         },
                         1 => {
+                            self.prg_ram_bank = match self.prg_ram.len() / PRG_RAM_BANK_SIZE {
+                                // SOROM selects its PRG-RAM bank with bit 3, and SXROM with bits 2-3
+                                2 => ((self.load_reg >> 3) & 0b00000001) as usize,
+                                4 => ((self.load_reg >> 2) & 0b00000011) as usize,
+                                _ => 0,
+                            };
+
                             if self.control_reg & 0b10000 != 0 {
                                 self.chr_bank_lo4 = (self.load_reg & 0b00011111) as usize;
                             } else {
@@ -134,43 +183,37 @@ impl Mapper for Mapper1 {
     }
 
     fn mapped_ppu_read(&self, chr_rom: &Vec<u8>, addr: usize) -> u8 {
-        match addr {
-            CHR_ROM_LO_START..=CHR_ROM_LO_END => {
-                if self.control_reg & 0b10000 != 0 {
-                    chr_rom[self.chr_bank_lo4 * (CHR_ROM_SIZE >> 1) + (addr & 0x0FFF)]
-                } else {
-                    chr_rom[self.chr_bank_full8 * CHR_ROM_SIZE + (addr & 0x1FFF)]
-                }
-            },
-            CHR_ROM_HI_START..=CHR_ROM_HI_END => {
-                if self.control_reg & 0b10000 != 0 {
-                    chr_rom[self.chr_bank_hi4 * (CHR_ROM_SIZE >> 1) + (addr & 0x0FFF)]
-                } else {
-                    chr_rom[self.chr_bank_full8 * CHR_ROM_SIZE + (addr & 0x1FFF)]
-                }
-            },
-            _ => unreachable!("Tried to address: {:04X} in CHR-ROM, should be in 0x0000-0x1FFF", addr)
-        }
+        chr_rom[self.chr_addr(addr) % chr_rom.len()]
+    }
+
+    fn mapped_ppu_write(&mut self, chr_ram: &mut Vec<u8>, addr: usize, byte: u8) {
+        let len = chr_ram.len();
+        chr_ram[self.chr_addr(addr) % len] = byte;
     }
 
     fn get_updated_mirroring(&self) -> Option<Mirroring> {
         Some(self.mirroring)
     }
 
-    fn get_save_ram(&self) -> Option<[u8; SAVE_RAM_SIZE]> {
-        Some(self.save_ram)
+    fn load_trainer(&mut self, trainer: &[u8]) -> bool {
+        copy_trainer(&mut self.prg_ram, trainer)
+    }
+
+    fn get_save_ram(&self) -> Option<&[u8]> {
+        if self.prg_ram.is_empty() { None } else { Some(&self.prg_ram) }
     }
 
-    fn load_save_ram(&mut self, save_ram: [u8; SAVE_RAM_SIZE]) -> bool {
-        self.save_ram = save_ram;
-        true
+    fn load_save_ram(&mut self, save_ram: &[u8]) -> bool {
+        self.prg_ram.copy_from_slice(save_ram);
+        !self.prg_ram.is_empty()
     }
 }
 
 impl Mapper1 {
-    pub fn new(prg_rom_banks: usize) -> Self {
+    pub fn new(prg_rom_banks: usize, prg_ram_size: usize) -> Self {
         Self {
-            save_ram: [0; SAVE_RAM_SIZE],
+            prg_ram: vec![0; prg_ram_size],
+            prg_ram_bank: 0,
             mirroring: Mirroring::HORIZONTAL,
             prg_rom_banks,
 
@@ -187,4 +230,29 @@ impl Mapper1 {
             load_count: 0,
         }
     }
+
+    fn prg_ram_addr(&self, addr: usize) -> usize {
+        (self.prg_ram_bank * PRG_RAM_BANK_SIZE + (addr & 0x1FFF)) % self.prg_ram.len()
+    }
+
+    // CHR-RAM (and some CHR-ROMs) are smaller than the banks that can be selected, so callers wrap this address
+    fn chr_addr(&self, addr: usize) -> usize {
+        match addr {
+            CHR_ROM_LO_START..=CHR_ROM_LO_END => {
+                if self.control_reg & 0b10000 != 0 {
+                    self.chr_bank_lo4 * (CHR_ROM_SIZE >> 1) + (addr & 0x0FFF)
+                } else {
+                    self.chr_bank_full8 * CHR_ROM_SIZE + (addr & 0x1FFF)
+                }
+            },
+            CHR_ROM_HI_START..=CHR_ROM_HI_END => {
+                if self.control_reg & 0b10000 != 0 {
+                    self.chr_bank_hi4 * (CHR_ROM_SIZE >> 1) + (addr & 0x0FFF)
+                } else {
+                    self.chr_bank_full8 * CHR_ROM_SIZE + (addr & 0x1FFF)
+                }
+            },
+            _ => unreachable!("Tried to address: {:04X} in CHR-ROM, should be in 0x0000-0x1FFF", addr)
+        }
+    }
 }
\ No newline at end of file
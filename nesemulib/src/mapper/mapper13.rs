@@ -0,0 +1,95 @@
+use crate::{cartridge::CHR_ROM_SIZE, SystemControl, StateReader, StateWriter};
+
+use super::{Mapper, CHR_ROM_HI_START, PRG_ROM_END, PRG_ROM_START};
+
+// CPROM has 16KB of CHR-RAM, banked in 4KB pages
+const CHR_BANK_SIZE: usize = CHR_ROM_SIZE >> 1;
+
+pub struct Mapper13 {
+    chr_bank_hi: usize,
+}
+
+impl SystemControl for Mapper13 {
+    fn reset(&mut self) {
+        self.chr_bank_hi = 0;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_usize(self.chr_bank_hi);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.chr_bank_hi = state.read_usize()? & 0x03;
+
+        Ok(())
+    }
+}
+
+impl Mapper for Mapper13 {
+    fn mapped_cpu_read(&mut self, prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8> {
+        match addr {
+            PRG_ROM_START..=PRG_ROM_END => Some(prg_rom[(addr - PRG_ROM_START) % prg_rom.len()]),
+            _ => None
+        }
+    }
+
+    fn mapped_cpu_write(&mut self, _prg_rom: &mut Vec<u8>, addr: usize, byte: u8) -> bool {
+        match addr {
+            PRG_ROM_START..=PRG_ROM_END => {
+                self.chr_bank_hi = (byte & 0b00000011) as usize;
+                true
+            }
+            _ => false
+        }
+    }
+
+    fn mapped_ppu_read(&self, chr_rom: &Vec<u8>, addr: usize) -> u8 {
+        chr_rom[self.chr_addr(addr) % chr_rom.len()]
+    }
+
+    fn mapped_ppu_write(&mut self, chr_ram: &mut Vec<u8>, addr: usize, byte: u8) {
+        let len = chr_ram.len();
+        chr_ram[self.chr_addr(addr) % len] = byte;
+    }
+}
+
+impl Mapper13 {
+    pub fn new() -> Self {
+        Self {
+            chr_bank_hi: 0,
+        }
+    }
+
+    // 0x0000 - 0x0FFF is fixed to the first bank, while 0x1000 - 0x1FFF is switchable
+    fn chr_addr(&self, addr: usize) -> usize {
+        if addr < CHR_ROM_HI_START {
+            addr
+        } else {
+            self.chr_bank_hi * CHR_BANK_SIZE + (addr & 0x0FFF)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mapper::TestRom;
+
+    #[test]
+    pub fn test_chr_ram_banking() {
+        let mut cartridge = TestRom::ines(13, 0x8000, 0).cartridge();
+
+        // iNES files don't give a CHR-RAM size, so CPROM is assumed to have all 16KB
+        for bank in 0..4 {
+            cartridge.cpu_write(0x8000, bank);
+            cartridge.ppu_write(0x1000, bank + 1);
+        }
+
+        for bank in 0..4 {
+            cartridge.cpu_write(0x8000, bank);
+            assert_eq!(cartridge.ppu_read(0x1000), bank + 1);
+        }
+
+        // the lower pattern table is always the first bank
+        assert_eq!(cartridge.ppu_read(0x0000), 1);
+    }
+}
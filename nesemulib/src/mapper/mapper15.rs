@@ -0,0 +1,165 @@
+use crate::{cartridge::{Mirroring, PRG_ROM_SIZE}, SystemControl, StateReader, StateWriter};
+
+use super::{copy_trainer, Mapper, PRG_RAM_BANK_SIZE, PRG_RAM_END, PRG_RAM_START, PRG_ROM_END, PRG_ROM_START};
+
+/// K-1029 and K-1030P multicarts, with a single register at $8000-$FFFF whose address selects
+/// one of four banking modes that imitate NROM-256, UNROM, NROM-64 and NROM-128 boards
+pub struct Mapper15 {
+    prg_ram: Vec<u8>,
+    // Address bits 0-1 of the last register write
+    mode: usize,
+    // 16KB PRG-ROM bank, with bit 7 of the register selecting the 8KB half in NROM-64 mode
+    prg_bank: usize,
+    prg_half: usize,
+    mirroring: Mirroring,
+}
+
+impl SystemControl for Mapper15 {
+    fn reset(&mut self) {
+        self.mode = 0;
+        self.prg_bank = 0;
+        self.prg_half = 0;
+        self.mirroring = Mirroring::VERTICAL;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+        state.write_usize(self.mode);
+        state.write_usize(self.prg_bank);
+        state.write_usize(self.prg_half);
+        self.mirroring.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.read_bytes(&mut self.prg_ram)?;
+        self.mode = state.read_usize()? & 0x03;
+        self.prg_bank = state.read_usize()? & 0x3F;
+        self.prg_half = state.read_usize()? & 0x01;
+        self.mirroring = Mirroring::load_state(state)?;
+
+        Ok(())
+    }
+}
+
+impl Mapper for Mapper15 {
+    fn mapped_cpu_read(&mut self, prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8> {
+        match addr {
+            PRG_RAM_START..=PRG_RAM_END => Some(self.prg_ram[addr - PRG_RAM_START]),
+            PRG_ROM_START..=PRG_ROM_END => {
+                let hi = addr >= 0xC000;
+
+                // 8KB banks in NROM-64 mode, 16KB banks otherwise
+                let (bank, bank_size) = match (self.mode, hi) {
+                    (0, false) | (1, false) | (3, _) => (self.prg_bank, PRG_ROM_SIZE),
+                    (0, true) => (self.prg_bank | 0x01, PRG_ROM_SIZE),
+                    (1, true) => (self.prg_bank | 0x07, PRG_ROM_SIZE),
+                    _ => ((self.prg_bank << 1) | self.prg_half, PRG_ROM_SIZE >> 1),
+                };
+
+                Some(prg_rom[(bank * bank_size + (addr & (bank_size - 1))) % prg_rom.len()])
+            }
+            _ => None
+        }
+    }
+
+    fn mapped_cpu_write(&mut self, _prg_rom: &mut Vec<u8>, addr: usize, byte: u8) -> bool {
+        match addr {
+            PRG_RAM_START..=PRG_RAM_END => {
+                self.prg_ram[addr - PRG_RAM_START] = byte;
+                true
+            }
+            PRG_ROM_START..=PRG_ROM_END => {
+                self.mode = addr & 0x03;
+                self.prg_bank = (byte & 0b00111111) as usize;
+                self.prg_half = ((byte & 0b10000000) >> 7) as usize;
+                self.mirroring = if (byte & 0b01000000) != 0 { Mirroring::HORIZONTAL } else { Mirroring::VERTICAL };
+                true
+            }
+            _ => false
+        }
+    }
+
+    fn mapped_ppu_read(&self, chr_rom: &Vec<u8>, addr: usize) -> u8 {
+        chr_rom[addr % chr_rom.len()]
+    }
+
+    fn mapped_ppu_write(&mut self, chr_ram: &mut Vec<u8>, addr: usize, byte: u8) {
+        // CHR-RAM is write-protected in the NROM-256 and NROM-128 modes
+        if self.mode == 1 || self.mode == 2 {
+            let len = chr_ram.len();
+            chr_ram[addr % len] = byte;
+        }
+    }
+
+    fn get_updated_mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    fn load_trainer(&mut self, trainer: &[u8]) -> bool {
+        copy_trainer(&mut self.prg_ram, trainer)
+    }
+
+    fn get_save_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn load_save_ram(&mut self, save_ram: &[u8]) -> bool {
+        self.prg_ram.copy_from_slice(save_ram);
+        true
+    }
+}
+
+impl Mapper15 {
+    /// The board always has 8KB of PRG-RAM, even when the header doesn't say so
+    pub fn new(prg_ram_size: usize) -> Self {
+        Self {
+            prg_ram: vec![0; prg_ram_size.max(PRG_RAM_BANK_SIZE)],
+            mode: 0,
+            prg_bank: 0,
+            prg_half: 0,
+            mirroring: Mirroring::VERTICAL,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::Mirroring;
+    use crate::mapper::TestRom;
+
+    #[test]
+    pub fn test_mapper15_modes() {
+        let mut cartridge = TestRom::nes2(15, 0, 0x40000, 0).prg_banks(0x2000).prg_ram(0x07, 0).cartridge();
+
+        // (register address, register value, 8KB banks at $8000, $A000, $C000 and $E000)
+        let modes = [
+            (0x8000, 0x04, [8, 9, 10, 11]),
+            (0x8001, 0x04, [8, 9, 14, 15]),
+            (0x8002, 0x84, [9, 9, 9, 9]),
+            (0x8003, 0x05, [10, 11, 10, 11]),
+        ];
+
+        for (addr, byte, banks) in modes {
+            cartridge.cpu_write(addr, byte);
+
+            for (slot, bank) in banks.iter().enumerate() {
+                assert_eq!(cartridge.cpu_read(0x8000 + slot * 0x2000), Some(*bank), "mode {}", addr & 0x03);
+            }
+        }
+
+        cartridge.cpu_write(0x8000, 0x40);
+        assert!(matches!(cartridge.mirroring(), Mirroring::HORIZONTAL));
+    }
+
+    #[test]
+    pub fn test_mapper15_chr_ram_protect() {
+        let mut cartridge = TestRom::nes2(15, 0, 0x40000, 0).prg_banks(0x2000).prg_ram(0x07, 0).cartridge();
+
+        cartridge.ppu_write(0x0000, 0x12);
+        assert_eq!(cartridge.ppu_read(0x0000), 0x00);
+
+        cartridge.cpu_write(0x8001, 0x00);
+        cartridge.ppu_write(0x0000, 0x12);
+        assert_eq!(cartridge.ppu_read(0x0000), 0x12);
+    }
+}
@@ -0,0 +1,504 @@
+mod barcode;
+mod eeprom;
+
+use crate::{cartridge::{Mirroring, PRG_ROM_SIZE}, SystemControl, StateReader, StateWriter};
+
+use self::barcode::BarcodeReader;
+use self::eeprom::{EepromChip, I2cEeprom};
+use super::{copy_trainer, Mapper, PRG_RAM_BANK_SIZE, PRG_RAM_END, PRG_RAM_START, PRG_ROM_END, PRG_ROM_HI_START, PRG_ROM_START};
+
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// Bandai's FCG-1/FCG-2 and their successor, the LZ93D50, which share the same registers
+/// but differ in where they are, what the IRQ counter's registers write to, and what's used for saves
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FcgBoard {
+    /// Registers at $6000-$7FFF, with the IRQ counter written directly (mapper 16.4)
+    FCG,
+    /// Registers at $8000-$FFFF, with the IRQ counter reloaded from a latch, and saves on an optional
+    /// 24C02 (mapper 16.5) or a 24C01 (mapper 159)
+    LZ93D50,
+    /// Plain iNES files of mapper 16, which could be either, so registers are at both and
+    /// the IRQ counter's registers write to both the latch and the counter
+    FCG_OR_LZ93D50,
+    /// Registers at $8000-$FFFF with 8KB of PRG-RAM, and the CHR registers selecting the outer
+    /// 256KB PRG bank in place of any CHR banking (mapper 153)
+    LZ93D50_SRAM,
+    /// The Datach Joint ROM System, with a 24C02 and barcode reader on the base unit (mapper 157)
+    DATACH,
+}
+
+pub struct Mapper16 {
+    board: FcgBoard,
+    prg_rom_banks: usize,
+    prg_ram: Vec<u8>,
+    prg_ram_enabled: bool,
+    eeprom: Option<I2cEeprom>,
+    barcode_reader: Option<BarcodeReader>,
+
+    chr_banks: [usize; 8],
+    prg_bank: usize,
+    mirroring: Mirroring,
+
+    irq_enabled: bool,
+    irq_counter: u16,
+    irq_latch: u16,
+    irq_pending: bool,
+}
+
+impl SystemControl for Mapper16 {
+    fn reset(&mut self) {
+        self.prg_ram_enabled = false;
+        self.chr_banks = [0; 8];
+        self.prg_bank = 0;
+        self.mirroring = Mirroring::VERTICAL;
+        self.irq_enabled = false;
+        self.irq_counter = 0;
+        self.irq_latch = 0;
+        self.irq_pending = false;
+
+        if let Some(eeprom) = &mut self.eeprom {
+            eeprom.reset();
+        }
+        if let Some(barcode_reader) = &mut self.barcode_reader {
+            barcode_reader.reset();
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+        state.write_bool(self.prg_ram_enabled);
+        if let Some(eeprom) = &self.eeprom {
+            eeprom.save_state(state);
+        }
+        if let Some(barcode_reader) = &self.barcode_reader {
+            barcode_reader.save_state(state);
+        }
+
+        for bank in &self.chr_banks {
+            state.write_usize(*bank);
+        }
+        state.write_usize(self.prg_bank);
+        self.mirroring.save_state(state);
+
+        state.write_bool(self.irq_enabled);
+        state.write_u16(self.irq_counter);
+        state.write_u16(self.irq_latch);
+        state.write_bool(self.irq_pending);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.read_bytes(&mut self.prg_ram)?;
+        self.prg_ram_enabled = state.read_bool()?;
+        if let Some(eeprom) = &mut self.eeprom {
+            eeprom.load_state(state)?;
+        }
+        if let Some(barcode_reader) = &mut self.barcode_reader {
+            barcode_reader.load_state(state)?;
+        }
+
+        for bank in &mut self.chr_banks {
+            *bank = state.read_usize()? & 0xFF;
+        }
+        self.prg_bank = state.read_usize()? & 0x0F;
+        self.mirroring = Mirroring::load_state(state)?;
+
+        self.irq_enabled = state.read_bool()?;
+        self.irq_counter = state.read_u16()?;
+        self.irq_latch = state.read_u16()?;
+        self.irq_pending = state.read_bool()?;
+
+        Ok(())
+    }
+}
+
+impl Mapper for Mapper16 {
+    fn mapped_cpu_read(&mut self, prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8> {
+        match addr {
+            PRG_RAM_START..=PRG_RAM_END => {
+                if self.board == FcgBoard::LZ93D50_SRAM {
+                    return if self.prg_ram_enabled { Some(self.prg_ram[addr - PRG_RAM_START]) } else { None };
+                }
+
+                // Reads return the EEPROM's SDA in bit 4, and the barcode reader's data in bit 3
+                if self.eeprom.is_none() && self.barcode_reader.is_none() {
+                    return None;
+                }
+
+                let sda = self.eeprom.as_ref().is_some_and(|eeprom| eeprom.sda_out());
+                let barcode = self.barcode_reader.as_ref().is_some_and(|reader| reader.output());
+                Some(((sda as u8) << 4) | ((barcode as u8) << 3))
+            }
+            PRG_ROM_START..=PRG_ROM_END => {
+                // The last bank is fixed at $C000, and on LZ93D50 boards with PRG-RAM, the outer bank applies to both
+                let outer_bank = if self.board == FcgBoard::LZ93D50_SRAM {
+                    (self.chr_banks.iter().fold(0, |bank, chr_bank| bank | chr_bank) & 0x01) << 4
+                } else {
+                    0
+                };
+
+                let bank = if addr < PRG_ROM_HI_START {
+                    self.prg_bank
+                } else {
+                    (self.prg_rom_banks - 1) & 0x0F
+                };
+
+                Some(prg_rom[((outer_bank | bank) * PRG_ROM_SIZE + (addr & 0x3FFF)) % prg_rom.len()])
+            }
+            _ => None
+        }
+    }
+
+    fn mapped_cpu_write(&mut self, _prg_rom: &mut Vec<u8>, addr: usize, byte: u8) -> bool {
+        let is_register = match (self.board, addr) {
+            (FcgBoard::FCG | FcgBoard::FCG_OR_LZ93D50, PRG_RAM_START..=PRG_RAM_END) => true,
+            (FcgBoard::FCG, _) => false,
+            (_, PRG_ROM_START..=PRG_ROM_END) => true,
+            _ => false,
+        };
+
+        if !is_register {
+            return match addr {
+                PRG_RAM_START..=PRG_RAM_END if self.board == FcgBoard::LZ93D50_SRAM && self.prg_ram_enabled => {
+                    self.prg_ram[addr - PRG_RAM_START] = byte;
+                    true
+                }
+                _ => false
+            };
+        }
+
+        match addr & 0x000F {
+            0x0..=0x7 => self.chr_banks[addr & 0x07] = byte as usize,
+            0x8 => self.prg_bank = (byte & 0x0F) as usize,
+            0x9 => {
+                self.mirroring = match byte & 0x03 {
+                    0 => Mirroring::VERTICAL,
+                    1 => Mirroring::HORIZONTAL,
+                    2 => Mirroring::ONESCREEN_LO,
+                    _ => Mirroring::ONESCREEN_HI,
+                };
+            }
+            0xA => {
+                self.irq_enabled = (byte & 0x01) != 0;
+                self.irq_pending = false;
+                if self.board != FcgBoard::FCG {
+                    self.irq_counter = self.irq_latch;
+                }
+            }
+            0xB | 0xC => {
+                let shift = if addr & 0x000F == 0xB { 0 } else { 8 };
+                let set_byte = |value: u16| (value & !(0xFF << shift)) | ((byte as u16) << shift);
+
+                if self.board != FcgBoard::FCG {
+                    self.irq_latch = set_byte(self.irq_latch);
+                }
+                if matches!(self.board, FcgBoard::FCG | FcgBoard::FCG_OR_LZ93D50) {
+                    self.irq_counter = set_byte(self.irq_counter);
+                }
+            }
+            0xD => {
+                // SCL is bit 5 and SDA is bit 6, though LZ93D50 boards with PRG-RAM use bit 5 to enable it
+                if self.board == FcgBoard::LZ93D50_SRAM {
+                    self.prg_ram_enabled = (byte & 0x20) != 0;
+                } else if let Some(eeprom) = &mut self.eeprom {
+                    eeprom.write_lines((byte & 0x20) != 0, (byte & 0x40) != 0);
+                }
+            }
+            _ => {}
+        }
+
+        true
+    }
+
+    fn mapped_ppu_read(&self, chr_rom: &Vec<u8>, addr: usize) -> u8 {
+        match self.board {
+            // with only 8KB of CHR-RAM, the CHR registers go elsewhere
+            FcgBoard::LZ93D50_SRAM | FcgBoard::DATACH => chr_rom[addr % chr_rom.len()],
+            _ => {
+                let bank = self.chr_banks[addr / CHR_BANK_SIZE];
+                chr_rom[(bank * CHR_BANK_SIZE + (addr % CHR_BANK_SIZE)) % chr_rom.len()]
+            }
+        }
+    }
+
+    fn mapped_ppu_write(&mut self, chr_ram: &mut Vec<u8>, addr: usize, byte: u8) {
+        let len = chr_ram.len();
+        chr_ram[addr % len] = byte;
+    }
+
+    fn get_updated_mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    fn cpu_clock(&mut self) {
+        if let Some(barcode_reader) = &mut self.barcode_reader {
+            barcode_reader.clock();
+        }
+
+        if self.irq_enabled {
+            // Checked before decrementing, so the IRQ fires a cycle after the counter reaches 0
+            if self.irq_counter == 0 {
+                self.irq_pending = true;
+            }
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+        }
+    }
+
+    fn irq_active(&mut self) -> bool {
+        self.irq_pending
+    }
+
+    fn has_barcode_reader(&self) -> bool {
+        self.barcode_reader.is_some()
+    }
+
+    fn scan_barcode(&mut self, barcode: &str) -> Result<(), String> {
+        match &mut self.barcode_reader {
+            Some(barcode_reader) => barcode_reader.scan(barcode),
+            None => Err(String::from("Cartridge does not have a barcode reader")),
+        }
+    }
+
+    fn load_trainer(&mut self, trainer: &[u8]) -> bool {
+        copy_trainer(&mut self.prg_ram, trainer)
+    }
+
+    fn registers_at_trainer(&self) -> bool {
+        matches!(self.board, FcgBoard::FCG | FcgBoard::FCG_OR_LZ93D50)
+    }
+
+    fn get_save_ram(&self) -> Option<&[u8]> {
+        match &self.eeprom {
+            Some(eeprom) => Some(eeprom.data()),
+            None if !self.prg_ram.is_empty() => Some(&self.prg_ram),
+            None => None,
+        }
+    }
+
+    fn load_save_ram(&mut self, save_ram: &[u8]) -> bool {
+        match &mut self.eeprom {
+            Some(eeprom) => eeprom.load_data(save_ram),
+            None => self.prg_ram.copy_from_slice(save_ram),
+        }
+        self.get_save_ram().is_some()
+    }
+}
+
+impl Mapper16 {
+    /// `eeprom_size` is 128 for a 24C01 and 256 for a 24C02, or 0 for boards without an EEPROM.
+    /// The FCG never has one, and boards with PRG-RAM use that instead
+    pub fn new(board: FcgBoard, prg_rom_banks: usize, eeprom_size: usize) -> Self {
+        let eeprom = match (board, eeprom_size) {
+            (FcgBoard::FCG | FcgBoard::LZ93D50_SRAM, _) | (_, 0) => None,
+            (_, 0x80) => Some(I2cEeprom::new(EepromChip::X24C01)),
+            _ => Some(I2cEeprom::new(EepromChip::X24C02)),
+        };
+
+        let mut mapper = Self {
+            board,
+            prg_rom_banks,
+            prg_ram: if board == FcgBoard::LZ93D50_SRAM { vec![0; PRG_RAM_BANK_SIZE] } else { Vec::new() },
+            prg_ram_enabled: false,
+            eeprom,
+            barcode_reader: if board == FcgBoard::DATACH { Some(BarcodeReader::new()) } else { None },
+            chr_banks: [0; 8],
+            prg_bank: 0,
+            mirroring: Mirroring::VERTICAL,
+            irq_enabled: false,
+            irq_counter: 0,
+            irq_latch: 0,
+            irq_pending: false,
+        };
+
+        mapper.reset();
+        mapper
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::CartridgeNes;
+    use crate::mapper::TestRom;
+
+    fn bandai_cartridge(mapper_num: u16, submapper_num: u8, prg_rom_banks: usize, chr_rom_banks: usize, prg_nvram_shift: u8) -> CartridgeNes {
+        TestRom::nes2(mapper_num, submapper_num, prg_rom_banks * 0x4000, chr_rom_banks * 0x2000)
+            .prg_banks(0x4000)
+            .chr_banks(0x400)
+            .flags_6(0x02)
+            .prg_ram(0, prg_nvram_shift)
+            .cartridge()
+    }
+
+    // Drives SCL and SDA through $800D, leaving the other bits clear
+    fn i2c_lines(cartridge: &mut CartridgeNes, scl: bool, sda: bool) {
+        cartridge.cpu_write(0x800D, ((scl as u8) << 5) | ((sda as u8) << 6));
+    }
+
+    fn i2c_start(cartridge: &mut CartridgeNes) {
+        i2c_lines(cartridge, false, true);
+        i2c_lines(cartridge, true, true);
+        i2c_lines(cartridge, true, false);
+        i2c_lines(cartridge, false, false);
+    }
+
+    fn i2c_stop(cartridge: &mut CartridgeNes) {
+        i2c_lines(cartridge, false, false);
+        i2c_lines(cartridge, true, false);
+        i2c_lines(cartridge, true, true);
+    }
+
+    // Sends a byte MSB first, returning whether the EEPROM acknowledged it
+    fn i2c_send(cartridge: &mut CartridgeNes, byte: u8) -> bool {
+        for bit in (0..8).rev() {
+            let sda = (byte >> bit) & 0x01 != 0;
+            i2c_lines(cartridge, false, sda);
+            i2c_lines(cartridge, true, sda);
+            i2c_lines(cartridge, false, sda);
+        }
+
+        i2c_lines(cartridge, false, true);
+        i2c_lines(cartridge, true, true);
+        let ack = cartridge.cpu_read(0x6000).unwrap() & 0x10 == 0;
+        i2c_lines(cartridge, false, true);
+        ack
+    }
+
+    fn i2c_receive(cartridge: &mut CartridgeNes, ack: bool) -> u8 {
+        let mut byte = 0;
+        for _ in 0..8 {
+            i2c_lines(cartridge, false, true);
+            i2c_lines(cartridge, true, true);
+            byte = (byte << 1) | ((cartridge.cpu_read(0x6000).unwrap() >> 4) & 0x01);
+            i2c_lines(cartridge, false, true);
+        }
+
+        i2c_lines(cartridge, false, !ack);
+        i2c_lines(cartridge, true, !ack);
+        i2c_lines(cartridge, false, !ack);
+        byte
+    }
+
+    #[test]
+    pub fn test_fcg_banks() {
+        let mut cartridge = bandai_cartridge(16, 4, 16, 32, 0);
+
+        cartridge.cpu_write(0x6008, 0x05);
+        cartridge.cpu_write(0x6003, 0x21);
+        assert_eq!(cartridge.cpu_read(0x8000), Some(5));
+        assert_eq!(cartridge.cpu_read(0xC000), Some(15));
+        assert_eq!(cartridge.ppu_read(0x0C00), 0x21);
+
+        // the FCG doesn't decode $8000-$FFFF
+        assert!(!cartridge.cpu_write(0x8008, 0x02));
+        assert_eq!(cartridge.cpu_read(0x8000), Some(5));
+        assert!(cartridge.get_save_ram().is_none());
+    }
+
+    #[test]
+    pub fn test_fcg_irq() {
+        // the FCG's counter is written directly, while the LZ93D50's is reloaded from a latch by enabling it
+        for (mapper_num, submapper_num, base) in [(16, 4, 0x6000), (16, 5, 0x8000)] {
+            let mut cartridge = bandai_cartridge(mapper_num, submapper_num, 16, 32, 0);
+
+            cartridge.cpu_write(base + 0xB, 0x02);
+            cartridge.cpu_write(base + 0xC, 0x00);
+            cartridge.cpu_write(base + 0xA, 0x01);
+
+            for _ in 0..3 {
+                assert!(!cartridge.irq_active());
+                cartridge.cpu_clock();
+            }
+            assert!(cartridge.irq_active(), "mapper {}.{}", mapper_num, submapper_num);
+
+            cartridge.cpu_write(base + 0xA, 0x00);
+            assert!(!cartridge.irq_active());
+        }
+    }
+
+    #[test]
+    pub fn test_24c02() {
+        let mut cartridge = bandai_cartridge(16, 5, 16, 32, 2);
+        assert_eq!(cartridge.get_save_ram().unwrap().len(), 0x100);
+
+        // write two bytes starting at $40
+        i2c_start(&mut cartridge);
+        assert!(i2c_send(&mut cartridge, 0xA0));
+        assert!(i2c_send(&mut cartridge, 0x40));
+        assert!(i2c_send(&mut cartridge, 0x12));
+        assert!(i2c_send(&mut cartridge, 0x34));
+        i2c_stop(&mut cartridge);
+
+        // then read them back with a random read
+        i2c_start(&mut cartridge);
+        assert!(i2c_send(&mut cartridge, 0xA0));
+        assert!(i2c_send(&mut cartridge, 0x40));
+        i2c_start(&mut cartridge);
+        assert!(i2c_send(&mut cartridge, 0xA1));
+        assert_eq!(i2c_receive(&mut cartridge, true), 0x12);
+        assert_eq!(i2c_receive(&mut cartridge, false), 0x34);
+        i2c_stop(&mut cartridge);
+
+        let save = cartridge.get_save_ram().unwrap();
+        assert_eq!(&save[0x40..0x42], &[0x12, 0x34]);
+
+        // other device types aren't acknowledged
+        i2c_start(&mut cartridge);
+        assert!(!i2c_send(&mut cartridge, 0xB0));
+    }
+
+    #[test]
+    pub fn test_24c01() {
+        let mut cartridge = bandai_cartridge(159, 0, 16, 32, 0);
+        assert_eq!(cartridge.get_save_ram().unwrap().len(), 0x80);
+
+        // the address and read/write bit are sent together, LSB first
+        i2c_start(&mut cartridge);
+        assert!(i2c_send(&mut cartridge, 0x05u8.reverse_bits()));
+        assert!(i2c_send(&mut cartridge, 0xC3u8.reverse_bits()));
+        i2c_stop(&mut cartridge);
+
+        i2c_start(&mut cartridge);
+        assert!(i2c_send(&mut cartridge, 0x85u8.reverse_bits()));
+        assert_eq!(i2c_receive(&mut cartridge, false).reverse_bits(), 0xC3);
+        i2c_stop(&mut cartridge);
+    }
+
+    #[test]
+    pub fn test_lz93d50_sram() {
+        let mut cartridge = bandai_cartridge(153, 0, 32, 0, 0);
+
+        // bit 0 of the CHR registers picks the outer 256KB bank
+        cartridge.cpu_write(0x8008, 0x03);
+        cartridge.cpu_write(0x8000, 0x01);
+        assert_eq!(cartridge.cpu_read(0x8000), Some(19));
+        assert_eq!(cartridge.cpu_read(0xC000), Some(31));
+
+        assert_eq!(cartridge.cpu_read(0x6000), None);
+        cartridge.cpu_write(0x800D, 0x20);
+        cartridge.cpu_write(0x6000, 0x42);
+        assert_eq!(cartridge.cpu_read(0x6000), Some(0x42));
+        assert_eq!(cartridge.get_save_ram().unwrap().len(), 0x2000);
+    }
+
+    #[test]
+    pub fn test_datach_barcode() {
+        let mut cartridge = bandai_cartridge(157, 0, 16, 0, 0);
+
+        assert!(cartridge.scan_barcode("12345").is_err());
+        assert!(cartridge.scan_barcode("49012345A7890").is_err());
+        cartridge.scan_barcode("4901234567894").unwrap();
+
+        // the reader sends white space, then the start guard's bar-space-bar, one module every 1000 cycles
+        let mut modules = Vec::new();
+        for _ in 0..36 {
+            modules.push(cartridge.cpu_read(0x6000).unwrap() & 0x08 != 0);
+            for _ in 0..1000 {
+                cartridge.cpu_clock();
+            }
+        }
+
+        assert!(modules[..33].iter().all(|&space| space));
+        assert_eq!(&modules[33..], &[false, true, false]);
+    }
+}
@@ -0,0 +1,126 @@
+use crate::{SystemControl, StateReader, StateWriter};
+
+// How long the reader outputs each module (the width of the thinnest bar) of a swiped barcode
+const CPU_CYCLES_PER_MODULE: u32 = 1000;
+
+// White space sent before and after the barcode itself
+const LEADING_QUIET_ZONE: usize = 33;
+const TRAILING_QUIET_ZONE: usize = 32;
+
+// EAN encodings of each digit, with bars as 1s. Left hand digits use either odd (L) or even (G) parity,
+// while right hand digits (R) are the complement of L
+const EAN_L: [u8; 10] = [0x0D, 0x19, 0x13, 0x3D, 0x23, 0x31, 0x2F, 0x3B, 0x37, 0x0B];
+const EAN_G: [u8; 10] = [0x27, 0x33, 0x1B, 0x21, 0x1D, 0x39, 0x05, 0x11, 0x09, 0x17];
+
+// For EAN-13, the first digit isn't drawn, but picks which of the next six use even parity
+const EAN_13_PARITY: [u8; 10] = [0x00, 0x0B, 0x0D, 0x0E, 0x13, 0x19, 0x1C, 0x15, 0x16, 0x1A];
+
+/// The Datach Joint ROM System's barcode reader, which plays a swiped EAN-13 or EAN-8 barcode
+/// back to the CPU one module at a time
+pub struct BarcodeReader {
+    // Each module of the barcode being swiped, true for bars
+    modules: Vec<bool>,
+    position: usize,
+    cycles: u32,
+}
+
+impl SystemControl for BarcodeReader {
+    fn reset(&mut self) {
+        self.modules.clear();
+        self.position = 0;
+        self.cycles = 0;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_usize(self.modules.len());
+        for module in &self.modules {
+            state.write_bool(*module);
+        }
+        state.write_usize(self.position);
+        state.write_u32(self.cycles);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        let len = state.read_usize()?;
+        self.modules.clear();
+        for _ in 0..len {
+            self.modules.push(state.read_bool()?);
+        }
+        self.position = state.read_usize()?;
+        self.cycles = state.read_u32()? % CPU_CYCLES_PER_MODULE;
+
+        Ok(())
+    }
+}
+
+impl BarcodeReader {
+    pub fn new() -> Self {
+        Self {
+            modules: Vec::new(),
+            position: 0,
+            cycles: 0,
+        }
+    }
+
+    /// Starts swiping a barcode of 13 (EAN-13) or 8 (EAN-8) digits. The check digit isn't checked here,
+    /// as games check it themselves
+    pub fn scan(&mut self, barcode: &str) -> Result<(), String> {
+        let digits: Vec<usize> = barcode.trim().chars()
+            .map(|c| c.to_digit(10).map(|d| d as usize))
+            .collect::<Option<_>>()
+            .ok_or_else(|| format!("Barcode \"{}\" must only contain digits", barcode.trim()))?;
+
+        let (parity, left, right) = match digits.len() {
+            13 => (EAN_13_PARITY[digits[0]], &digits[1..7], &digits[7..13]),
+            8 => (0, &digits[0..4], &digits[4..8]),
+            n => return Err(format!("Barcode must have 13 or 8 digits, not {}", n)),
+        };
+
+        let mut modules = vec![false; LEADING_QUIET_ZONE];
+        let mut push_bits = |bits: u8, count: usize| {
+            for i in (0..count).rev() {
+                modules.push((bits >> i) & 0x01 != 0);
+            }
+        };
+
+        push_bits(0b101, 3);
+        for (i, &digit) in left.iter().enumerate() {
+            let even = (parity >> (left.len() - 1 - i)) & 0x01 != 0;
+            push_bits(if even { EAN_G[digit] } else { EAN_L[digit] }, 7);
+        }
+        push_bits(0b01010, 5);
+        for &digit in right {
+            push_bits(!EAN_L[digit] & 0x7F, 7);
+        }
+        push_bits(0b101, 3);
+
+        modules.extend([false; TRAILING_QUIET_ZONE]);
+
+        self.modules = modules;
+        self.position = 0;
+        self.cycles = 0;
+
+        Ok(())
+    }
+
+    pub fn clock(&mut self) {
+        if self.position >= self.modules.len() {
+            return;
+        }
+
+        self.cycles += 1;
+        if self.cycles == CPU_CYCLES_PER_MODULE {
+            self.cycles = 0;
+            self.position += 1;
+        }
+    }
+
+    /// The reader's data line, which is high for white space and low for bars. It stays low
+    /// when there's nothing being swiped
+    pub fn output(&self) -> bool {
+        match self.modules.get(self.position) {
+            Some(bar) => !bar,
+            None => false,
+        }
+    }
+}
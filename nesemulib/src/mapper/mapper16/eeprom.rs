@@ -0,0 +1,269 @@
+use crate::{SystemControl, StateReader, StateWriter};
+
+/// Serial EEPROMs that the CPU talks to over I2C by bit-banging SCL and SDA
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EepromChip {
+    /// 128 bytes. Xicor's older protocol has no device address, and sends the word address and data LSB first
+    X24C01,
+    /// 256 bytes, with standard I2C device addressing and everything sent MSB first
+    X24C02,
+}
+
+/// What the EEPROM expects next on the bus
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EepromState {
+    IDLE,
+    DEVICE_ADDRESS,
+    WORD_ADDRESS,
+    READ,
+    WRITE,
+    /// The EEPROM pulls SDA low for a clock to acknowledge a byte
+    SEND_ACK,
+    /// The CPU acknowledges a byte it read to keep reading
+    WAIT_ACK,
+}
+
+impl EepromState {
+    fn from_u8(n: u8) -> Result<Self, String> {
+        Ok(match n {
+            0 => EepromState::IDLE,
+            1 => EepromState::DEVICE_ADDRESS,
+            2 => EepromState::WORD_ADDRESS,
+            3 => EepromState::READ,
+            4 => EepromState::WRITE,
+            5 => EepromState::SEND_ACK,
+            6 => EepromState::WAIT_ACK,
+            n => return Err(format!("Invalid EEPROM state {} in save state", n)),
+        })
+    }
+}
+
+pub struct I2cEeprom {
+    chip: EepromChip,
+    data: Vec<u8>,
+
+    state: EepromState,
+    // The state to go to after the current acknowledge
+    next_state: EepromState,
+    // The byte being shifted in or out, and how many of its bits have been so far
+    shift: u8,
+    bit: u8,
+    address: usize,
+
+    scl: bool,
+    sda: bool,
+    // The EEPROM's side of SDA, which is open drain, so only pulls the line low
+    output: bool,
+}
+
+impl SystemControl for I2cEeprom {
+    fn reset(&mut self) {
+        self.state = EepromState::IDLE;
+        self.next_state = EepromState::IDLE;
+        self.shift = 0;
+        self.bit = 0;
+        self.address = 0;
+        self.scl = false;
+        self.sda = false;
+        self.output = true;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.data);
+        state.write_u8(self.state as u8);
+        state.write_u8(self.next_state as u8);
+        state.write_u8(self.shift);
+        state.write_u8(self.bit);
+        state.write_usize(self.address);
+        state.write_bool(self.scl);
+        state.write_bool(self.sda);
+        state.write_bool(self.output);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.read_bytes(&mut self.data)?;
+        self.state = EepromState::from_u8(state.read_u8()?)?;
+        self.next_state = EepromState::from_u8(state.read_u8()?)?;
+        self.shift = state.read_u8()?;
+        self.bit = state.read_u8()? & 0x0F;
+        self.address = state.read_usize()? % self.data.len();
+        self.scl = state.read_bool()?;
+        self.sda = state.read_bool()?;
+        self.output = state.read_bool()?;
+
+        Ok(())
+    }
+}
+
+impl I2cEeprom {
+    pub fn new(chip: EepromChip) -> Self {
+        let size = match chip {
+            EepromChip::X24C01 => 0x80,
+            EepromChip::X24C02 => 0x100,
+        };
+
+        let mut eeprom = Self {
+            chip,
+            data: vec![0xFF; size],
+            state: EepromState::IDLE,
+            next_state: EepromState::IDLE,
+            shift: 0,
+            bit: 0,
+            address: 0,
+            scl: false,
+            sda: false,
+            output: true,
+        };
+
+        eeprom.reset();
+        eeprom
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn load_data(&mut self, data: &[u8]) {
+        self.data.copy_from_slice(data);
+    }
+
+    /// The level of SDA as the CPU reads it
+    pub fn sda_out(&self) -> bool {
+        self.output
+    }
+
+    /// Sets the levels the CPU drives SCL and SDA to
+    pub fn write_lines(&mut self, scl: bool, sda: bool) {
+        let (prev_scl, prev_sda) = (self.scl, self.sda);
+        self.scl = scl;
+        self.sda = sda;
+
+        if prev_scl && scl && prev_sda != sda {
+            // SDA falling while SCL is high starts a command, and rising stops it
+            self.state = if sda {
+                EepromState::IDLE
+            } else {
+                match self.chip {
+                    EepromChip::X24C01 => EepromState::WORD_ADDRESS,
+                    EepromChip::X24C02 => EepromState::DEVICE_ADDRESS,
+                }
+            };
+            self.bit = 0;
+            self.output = true;
+        } else if !prev_scl && scl {
+            self.clock_rise(sda);
+        } else if prev_scl && !scl {
+            self.clock_fall();
+        }
+    }
+
+    // The CPU's data bits are sampled on the rising edge of SCL
+    fn clock_rise(&mut self, sda: bool) {
+        match self.state {
+            EepromState::DEVICE_ADDRESS | EepromState::WORD_ADDRESS | EepromState::WRITE => {
+                if self.bit < 8 {
+                    self.shift_in(sda);
+                }
+            }
+            EepromState::READ => {
+                if self.bit < 8 {
+                    let bit = match self.chip {
+                        EepromChip::X24C01 => self.bit,
+                        EepromChip::X24C02 => 7 - self.bit,
+                    };
+                    self.output = (self.shift >> bit) & 0x01 != 0;
+                    self.bit += 1;
+                }
+            }
+            EepromState::SEND_ACK => self.output = false,
+            EepromState::WAIT_ACK => {
+                // reading continues on to the next byte for as long as the CPU acknowledges
+                self.next_state = if sda { EepromState::IDLE } else { EepromState::READ };
+                self.shift = self.data[self.address];
+            }
+            EepromState::IDLE => {}
+        }
+    }
+
+    // The EEPROM acts on a completed byte on the falling edge after its last bit
+    fn clock_fall(&mut self) {
+        let size = self.data.len();
+
+        match self.state {
+            EepromState::DEVICE_ADDRESS if self.bit == 8 => {
+                // only answers to its own device type, %1010
+                if self.shift & 0xF0 != 0xA0 {
+                    self.state = EepromState::IDLE;
+                    return;
+                }
+
+                self.next_state = if self.shift & 0x01 != 0 {
+                    self.shift = self.data[self.address];
+                    EepromState::READ
+                } else {
+                    EepromState::WORD_ADDRESS
+                };
+                self.acknowledge();
+            }
+            EepromState::WORD_ADDRESS if self.bit == 8 => {
+                match self.chip {
+                    // the 24C01's last address bit says whether it's a read or a write
+                    EepromChip::X24C01 => {
+                        self.address = (self.shift & 0x7F) as usize;
+                        self.next_state = if self.shift & 0x80 != 0 {
+                            self.shift = self.data[self.address];
+                            EepromState::READ
+                        } else {
+                            EepromState::WRITE
+                        };
+                    }
+                    EepromChip::X24C02 => {
+                        self.address = self.shift as usize;
+                        self.next_state = EepromState::WRITE;
+                    }
+                }
+                self.acknowledge();
+            }
+            EepromState::WRITE if self.bit == 8 => {
+                self.data[self.address] = self.shift;
+
+                // writes wrap around within a page of 4 (24C01) or 8 (24C02) bytes
+                let page_mask = match self.chip {
+                    EepromChip::X24C01 => 0x03,
+                    EepromChip::X24C02 => 0x07,
+                };
+                self.address = (self.address & !page_mask) | ((self.address + 1) & page_mask);
+                self.next_state = EepromState::WRITE;
+                self.acknowledge();
+            }
+            EepromState::READ if self.bit == 8 => {
+                self.state = EepromState::WAIT_ACK;
+                self.address = (self.address + 1) % size;
+                self.output = true;
+            }
+            EepromState::SEND_ACK | EepromState::WAIT_ACK => {
+                self.state = self.next_state;
+                self.bit = 0;
+                self.output = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn acknowledge(&mut self) {
+        self.state = EepromState::SEND_ACK;
+        self.bit = 0;
+        self.output = true;
+    }
+
+    fn shift_in(&mut self, sda: bool) {
+        self.shift = match self.chip {
+            // the 24C01 is sent the word address and data LSB first
+            EepromChip::X24C01 => (self.shift >> 1) | ((sda as u8) << 7),
+            EepromChip::X24C02 => (self.shift << 1) | sda as u8,
+        };
+        self.bit += 1;
+    }
+}
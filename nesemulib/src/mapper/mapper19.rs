@@ -0,0 +1,474 @@
+mod audio;
+
+use crate::{cartridge::{Mirroring, NameTableSource, NAME_TABLE_PAGE_SIZE}, SystemControl, StateReader, StateWriter};
+
+use self::audio::{Namco163Audio, NAMCO_163_CHANNELS};
+use super::{ExpansionChannel, copy_trainer, Mapper, PRG_RAM_END, PRG_RAM_START, PRG_ROM_END, PRG_ROM_START};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+// The chip's own RAM, holding the wavetables and sound registers, which some games also save to
+const INTERNAL_RAM_SIZE: usize = 0x80;
+
+// CHR bank values from here up select a page of CIRAM instead
+const CIRAM_BANKS_START: usize = 0xE0;
+
+pub struct Mapper19 {
+    // PRG-RAM followed by the internal RAM, so both can be kept in the same save file
+    ram: Vec<u8>,
+    prg_ram_size: usize,
+    battery_backed: bool,
+    prg_rom_banks: usize,
+
+    prg_banks: [usize; 3],
+    // 8 pattern table banks, followed by the 4 nametable banks
+    chr_regs: [usize; 12],
+    sound_disabled: bool,
+
+    // $F800: both the internal RAM's address port, and the write protection for PRG-RAM
+    ram_address: u8,
+
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_pending: bool,
+
+    audio: Namco163Audio,
+}
+
+impl SystemControl for Mapper19 {
+    fn reset(&mut self) {
+        self.prg_banks = [0; 3];
+        self.chr_regs = [0; 12];
+        self.sound_disabled = false;
+        self.ram_address = 0;
+
+        self.irq_counter = 0;
+        self.irq_enabled = false;
+        self.irq_pending = false;
+
+        self.audio.reset();
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ram);
+
+        for value in &self.prg_banks {
+            state.write_usize(*value);
+        }
+        for value in &self.chr_regs {
+            state.write_usize(*value);
+        }
+        state.write_bool(self.sound_disabled);
+        state.write_u8(self.ram_address);
+
+        state.write_u16(self.irq_counter);
+        state.write_bool(self.irq_enabled);
+        state.write_bool(self.irq_pending);
+
+        self.audio.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.read_bytes(&mut self.ram)?;
+
+        for value in &mut self.prg_banks {
+            *value = state.read_usize()? & 0x3F;
+        }
+        for value in &mut self.chr_regs {
+            *value = state.read_usize()? & 0xFF;
+        }
+        self.sound_disabled = state.read_bool()?;
+        self.ram_address = state.read_u8()?;
+
+        self.irq_counter = state.read_u16()? & 0x7FFF;
+        self.irq_enabled = state.read_bool()?;
+        self.irq_pending = state.read_bool()?;
+
+        self.audio.load_state(state)?;
+
+        Ok(())
+    }
+}
+
+impl Mapper for Mapper19 {
+    fn mapped_cpu_read(&mut self, prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8> {
+        match addr {
+            0x4800..=0x4FFF => {
+                let byte = self.internal_ram()[(self.ram_address & 0x7F) as usize];
+                self.increment_ram_address();
+                Some(byte)
+            }
+            0x5000..=0x57FF => Some(self.irq_counter as u8),
+            0x5800..=0x5FFF => Some((self.irq_counter >> 8) as u8 | (self.irq_enabled as u8) << 7),
+            PRG_RAM_START..=PRG_RAM_END if self.prg_ram_size > 0 => {
+                Some(self.ram[(addr - PRG_RAM_START) % self.prg_ram_size])
+            }
+            PRG_ROM_START..=PRG_ROM_END => {
+                let bank = match addr {
+                    0xE000..=0xFFFF => self.prg_rom_banks * 2 - 1,
+                    _ => self.prg_banks[(addr - PRG_ROM_START) >> 13],
+                };
+
+                Some(prg_rom[(bank * PRG_BANK_SIZE + (addr & (PRG_BANK_SIZE - 1))) % prg_rom.len()])
+            }
+            _ => None
+        }
+    }
+
+    fn mapped_cpu_write(&mut self, _prg_rom: &mut Vec<u8>, addr: usize, byte: u8) -> bool {
+        match addr {
+            0x4800..=0x4FFF => {
+                let address = (self.ram_address & 0x7F) as usize;
+                self.internal_ram_mut()[address] = byte;
+                self.increment_ram_address();
+            }
+            // writing either half of the counter acknowledges the IRQ
+            0x5000..=0x57FF => {
+                self.irq_counter = (self.irq_counter & 0x7F00) | byte as u16;
+                self.irq_pending = false;
+            }
+            0x5800..=0x5FFF => {
+                self.irq_counter = (self.irq_counter & 0x00FF) | ((byte & 0x7F) as u16) << 8;
+                self.irq_enabled = (byte & 0b10000000) != 0;
+                self.irq_pending = false;
+            }
+            PRG_RAM_START..=PRG_RAM_END => {
+                if self.prg_ram_size == 0 {
+                    return false;
+                }
+
+                if self.prg_ram_writable(addr) {
+                    self.ram[(addr - PRG_RAM_START) % self.prg_ram_size] = byte;
+                }
+            }
+            0x8000..=0xDFFF => self.chr_regs[(addr - 0x8000) >> 11] = byte as usize,
+            0xE000..=0xE7FF => {
+                self.prg_banks[0] = (byte & 0x3F) as usize;
+                self.sound_disabled = (byte & 0b01000000) != 0;
+            }
+            // bits 6 and 7 stop either pattern table from using CIRAM, which isn't supported
+            0xE800..=0xEFFF => self.prg_banks[1] = (byte & 0x3F) as usize,
+            0xF000..=0xF7FF => self.prg_banks[2] = (byte & 0x3F) as usize,
+            0xF800..=0xFFFF => self.ram_address = byte,
+            _ => return false
+        }
+
+        true
+    }
+
+    // Pattern tables made from CIRAM aren't supported, as no known game uses them, so those banks read from CHR-ROM
+    fn mapped_ppu_read(&self, chr_rom: &Vec<u8>, addr: usize) -> u8 {
+        chr_rom[(self.chr_regs[addr >> 10] * CHR_BANK_SIZE + (addr & (CHR_BANK_SIZE - 1))) % chr_rom.len()]
+    }
+
+    fn mapped_ppu_write(&mut self, chr_ram: &mut Vec<u8>, addr: usize, byte: u8) {
+        let len = chr_ram.len();
+        chr_ram[(self.chr_regs[addr >> 10] * CHR_BANK_SIZE + (addr & (CHR_BANK_SIZE - 1))) % len] = byte;
+    }
+
+    // Nametable banks below $E0 come from CHR-ROM through `mapped_name_table_read`, so only the CIRAM pages matter here
+    fn get_updated_mirroring(&self) -> Option<Mirroring> {
+        let mut slots = [NameTableSource::CIRAM(0); 4];
+
+        for (slot, bank) in slots.iter_mut().zip(&self.chr_regs[8..]) {
+            *slot = NameTableSource::CIRAM((bank & 0x01) as u8);
+        }
+
+        Some(Mirroring::CUSTOM(slots))
+    }
+
+    fn mapped_name_table_read(&self, chr_rom: &Vec<u8>, addr: usize) -> Option<u8> {
+        let bank = self.chr_rom_name_table(addr)?;
+        Some(chr_rom[(bank * CHR_BANK_SIZE + (addr & (NAME_TABLE_PAGE_SIZE - 1))) % chr_rom.len()])
+    }
+
+    fn mapped_name_table_write(&mut self, _chr_rom: &mut Vec<u8>, addr: usize, _byte: u8) -> bool {
+        self.chr_rom_name_table(addr).is_some()
+    }
+
+    // The counter counts up every CPU cycle while enabled, and stops once it fires at $7FFF
+    fn cpu_clock(&mut self) {
+        if self.irq_enabled && self.irq_counter < 0x7FFF {
+            self.irq_counter += 1;
+
+            if self.irq_counter == 0x7FFF {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    fn expansion_audio_channels(&self) -> &'static [ExpansionChannel] {
+        NAMCO_163_CHANNELS
+    }
+
+    fn clock_expansion_audio(&mut self, levels: &mut [f32]) {
+        if self.sound_disabled {
+            levels.fill(0.0);
+            return;
+        }
+
+        let internal_ram = &mut self.ram[self.prg_ram_size..];
+        self.audio.clock(internal_ram, levels)
+    }
+
+    fn mix_expansion_audio(&self, levels: &[f32]) -> f32 {
+        self.audio.mix(levels)
+    }
+
+    fn irq_active(&mut self) -> bool {
+        self.irq_pending
+    }
+
+    fn load_trainer(&mut self, trainer: &[u8]) -> bool {
+        copy_trainer(&mut self.ram[..self.prg_ram_size], trainer)
+    }
+
+    fn get_save_ram(&self) -> Option<&[u8]> {
+        if self.battery_backed { Some(&self.ram) } else { None }
+    }
+
+    fn load_save_ram(&mut self, save_ram: &[u8]) -> bool {
+        self.ram.copy_from_slice(save_ram);
+        self.battery_backed
+    }
+}
+
+impl Mapper19 {
+    /// The submapper gives how loud the cartridge mixes its expansion audio, with submapper 2 having none.
+    /// Only battery-backed boards have a save, which holds both the PRG-RAM and the internal RAM
+    pub fn new(submapper_num: u8, prg_rom_banks: usize, prg_ram_size: usize, battery_backed: bool) -> Self {
+        let mix_level = match submapper_num {
+            2 => None,
+            4 => Some(16.5),
+            5 => Some(18.75),
+            _ => Some(12.0),
+        };
+
+        Self {
+            ram: vec![0; prg_ram_size + INTERNAL_RAM_SIZE],
+            prg_ram_size,
+            battery_backed,
+            prg_rom_banks,
+
+            prg_banks: [0; 3],
+            chr_regs: [0; 12],
+            sound_disabled: false,
+            ram_address: 0,
+
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+
+            audio: Namco163Audio::new(mix_level),
+        }
+    }
+
+    fn internal_ram(&self) -> &[u8] {
+        &self.ram[self.prg_ram_size..]
+    }
+
+    fn internal_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram[self.prg_ram_size..]
+    }
+
+    // Bit 7 of the address port makes it increment after every access through $4800
+    fn increment_ram_address(&mut self) {
+        if self.ram_address & 0x80 != 0 {
+            self.ram_address = 0x80 | (self.ram_address.wrapping_add(1) & 0x7F);
+        }
+    }
+
+    // Writes need the top nibble of $F800 to be $4, and the bit for the 2KB area being written to to be clear
+    fn prg_ram_writable(&self, addr: usize) -> bool {
+        let protect = self.ram_address;
+        protect & 0xF0 == 0x40 && protect & (1 << ((addr - PRG_RAM_START) >> 11)) == 0
+    }
+
+    fn chr_rom_name_table(&self, addr: usize) -> Option<usize> {
+        let bank = self.chr_regs[8 + ((addr >> 10) & 0x03)];
+        if bank < CIRAM_BANKS_START { Some(bank) } else { None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::CartridgeNes;
+    use crate::ppu::PpuBus;
+    use crate::mapper::TestRom;
+
+    #[test]
+    pub fn test_banking() {
+        let mut cartridge = TestRom::nes2(19, 0, 0x20000, 0x20000)
+            .prg_banks(0x2000)
+            .chr_banks(0x400)
+            .flags_6(0x02)
+            .prg_ram(0, 0x07)
+            .cartridge();
+        let mut ppu_bus = PpuBus::new();
+
+        cartridge.cpu_write(0xE000, 3);
+        cartridge.cpu_write(0xE800, 5);
+        cartridge.cpu_write(0xF000, 9);
+        assert_eq!(cartridge.cpu_read(0x8000), Some(3));
+        assert_eq!(cartridge.cpu_read(0xA000), Some(5));
+        assert_eq!(cartridge.cpu_read(0xC000), Some(9));
+        assert_eq!(cartridge.cpu_read(0xE000), Some(15));
+
+        for slot in 0..8 {
+            cartridge.cpu_write(0x8000 + slot * 0x800, 100 + slot as u8);
+        }
+        for slot in 0..8 {
+            assert_eq!(cartridge.ppu_read(slot * 0x400), 100 + slot as u8);
+        }
+
+        // the first two nametables from CHR-ROM, and the last two from CIRAM
+        cartridge.cpu_write(0xC000, 40);
+        cartridge.cpu_write(0xC800, 41);
+        cartridge.cpu_write(0xD000, 0xE1);
+        cartridge.cpu_write(0xD800, 0xE0);
+
+        ppu_bus.ppu_write(0x2000, 0xAA, &mut cartridge);
+        ppu_bus.ppu_write(0x2800, 0x11, &mut cartridge);
+        ppu_bus.ppu_write(0x2C00, 0x22, &mut cartridge);
+
+        assert_eq!(ppu_bus.ppu_read(0x2000, &cartridge), 40);
+        assert_eq!(ppu_bus.ppu_read(0x2400, &cartridge), 41);
+        assert_eq!(ppu_bus.ppu_read(0x2800, &cartridge), 0x11);
+        assert_eq!(ppu_bus.ppu_read(0x2C00, &cartridge), 0x22);
+
+        cartridge.cpu_write(0xC000, 0xE1);
+        assert_eq!(ppu_bus.ppu_read(0x2000, &cartridge), 0x11);
+    }
+
+    #[test]
+    pub fn test_prg_ram_protect() {
+        let mut cartridge = TestRom::nes2(19, 0, 0x20000, 0x20000)
+            .prg_banks(0x2000)
+            .chr_banks(0x400)
+            .flags_6(0x02)
+            .prg_ram(0, 0x07)
+            .cartridge();
+
+        cartridge.cpu_write(0x6000, 0x42);
+        assert_eq!(cartridge.cpu_read(0x6000), Some(0));
+
+        // unprotect everything but $6800-$6FFF
+        cartridge.cpu_write(0xF800, 0x42);
+        cartridge.cpu_write(0x6000, 0x42);
+        cartridge.cpu_write(0x6800, 0x42);
+        assert_eq!(cartridge.cpu_read(0x6000), Some(0x42));
+        assert_eq!(cartridge.cpu_read(0x6800), Some(0));
+    }
+
+    #[test]
+    pub fn test_internal_ram() {
+        let mut cartridge = TestRom::nes2(19, 0, 0x20000, 0x20000)
+            .prg_banks(0x2000)
+            .chr_banks(0x400)
+            .flags_6(0x02)
+            .prg_ram(0, 0x07)
+            .cartridge();
+
+        // write the last 3 bytes with auto increment, which wraps back around to the start
+        cartridge.cpu_write(0xF800, 0x80 | 0x7E);
+        for byte in [1, 2, 3] {
+            cartridge.cpu_write(0x4800, byte);
+        }
+
+        cartridge.cpu_write(0xF800, 0x80 | 0x7E);
+        assert_eq!(cartridge.cpu_read(0x4800), Some(1));
+        assert_eq!(cartridge.cpu_read(0x4800), Some(2));
+        assert_eq!(cartridge.cpu_read(0x4800), Some(3));
+
+        // without auto increment
+        cartridge.cpu_write(0xF800, 0x7E);
+        assert_eq!(cartridge.cpu_read(0x4800), Some(1));
+        assert_eq!(cartridge.cpu_read(0x4800), Some(1));
+
+        // the internal RAM is saved after the 8KB of PRG-RAM
+        let save_ram = cartridge.get_save_ram().unwrap();
+        assert_eq!(save_ram.len(), 0x2000 + 0x80);
+        assert_eq!(&save_ram[0x2000 + 0x7E..], &[1, 2]);
+        assert_eq!(save_ram[0x2000], 3);
+
+        // without a battery nothing is saved
+        assert!(TestRom::nes2(19, 0, 0x20000, 0x20000).prg_ram(0x07, 0).cartridge().get_save_ram().is_none());
+    }
+
+    #[test]
+    pub fn test_irq() {
+        let mut cartridge = TestRom::nes2(19, 0, 0x20000, 0x20000)
+            .prg_banks(0x2000)
+            .chr_banks(0x400)
+            .flags_6(0x02)
+            .prg_ram(0, 0x07)
+            .cartridge();
+
+        cartridge.cpu_write(0x5000, 0xF0);
+        cartridge.cpu_write(0x5800, 0x80 | 0x7F);
+
+        for _ in 0..14 {
+            cartridge.cpu_clock();
+            assert!(!cartridge.irq_active());
+        }
+
+        cartridge.cpu_clock();
+        assert!(cartridge.irq_active());
+        assert_eq!(cartridge.cpu_read(0x5000), Some(0xFF));
+
+        // the counter stops at $7FFF
+        cartridge.cpu_clock();
+        assert_eq!(cartridge.cpu_read(0x5800), Some(0xFF));
+
+        cartridge.cpu_write(0x5000, 0x00);
+        assert!(!cartridge.irq_active());
+    }
+
+    #[test]
+    pub fn test_audio() {
+        let mut cartridge = TestRom::nes2(19, 0, 0x20000, 0x20000)
+            .prg_banks(0x2000)
+            .chr_banks(0x400)
+            .flags_6(0x02)
+            .prg_ram(0, 0x07)
+            .cartridge();
+        let mut levels = [0.0; 8];
+
+        let write_ram = |cartridge: &mut CartridgeNes, address: u8, bytes: &[u8]| {
+            cartridge.cpu_write(0xF800, 0x80 | address);
+            for &byte in bytes {
+                cartridge.cpu_write(0x4800, byte);
+            }
+        };
+
+        // a 4 sample square wave at the start of RAM, played by the last channel one sample per update
+        write_ram(&mut cartridge, 0x00, &[0xF0, 0xF0]);
+        write_ram(&mut cartridge, 0x78, &[0x00, 0x00, 0x00, 0x00, 0xFD, 0x00, 0x00, 0x0F]);
+
+        let mut outputs = Vec::new();
+        for _ in 0..15 * 4 {
+            cartridge.clock_expansion_audio(&mut levels);
+            assert!(levels[1..].iter().all(|&level| level == 0.0));
+            outputs.push(levels[0]);
+        }
+        assert_eq!(outputs[14], 105.0);
+        assert_eq!(outputs[29], -120.0);
+        assert_eq!(outputs[44], 105.0);
+
+        // with two channels, they each get updated half as often. The second one holds the wave's first sample
+        write_ram(&mut cartridge, 0x70, &[0x00, 0x00, 0x00, 0x00, 0xFC, 0x00, 0x00, 0x0F]);
+        write_ram(&mut cartridge, 0x7F, &[0x1F]);
+        for _ in 0..15 * 2 {
+            cartridge.clock_expansion_audio(&mut levels);
+        }
+        assert_eq!(levels[1], -120.0);
+
+        // and the mix is halved as they share the output
+        let full = cartridge.mix_expansion_audio(&[120.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+        let single = TestRom::nes2(19, 0, 0x20000, 0x20000).cartridge().mix_expansion_audio(&[60.0; 1]);
+        assert!((full - single).abs() < 0.0001);
+
+        assert_eq!(TestRom::nes2(19, 2, 0x20000, 0x20000).cartridge().mix_expansion_audio(&[120.0]), 0.0);
+    }
+}
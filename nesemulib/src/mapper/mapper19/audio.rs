@@ -0,0 +1,134 @@
+use crate::apu::PULSE_TABLE;
+use crate::mapper::ExpansionChannel;
+use crate::{SystemControl, StateReader, StateWriter};
+
+/// Listed in the order the channels are enabled, from the one at $78-$7F down to the one at $40-$47
+pub const NAMCO_163_CHANNELS: &[ExpansionChannel] = &[
+    ExpansionChannel { name: "N163 Wave 1", min_level: -120.0, max_level: 120.0 },
+    ExpansionChannel { name: "N163 Wave 2", min_level: -120.0, max_level: 120.0 },
+    ExpansionChannel { name: "N163 Wave 3", min_level: -120.0, max_level: 120.0 },
+    ExpansionChannel { name: "N163 Wave 4", min_level: -120.0, max_level: 120.0 },
+    ExpansionChannel { name: "N163 Wave 5", min_level: -120.0, max_level: 120.0 },
+    ExpansionChannel { name: "N163 Wave 6", min_level: -120.0, max_level: 120.0 },
+    ExpansionChannel { name: "N163 Wave 7", min_level: -120.0, max_level: 120.0 },
+    ExpansionChannel { name: "N163 Wave 8", min_level: -120.0, max_level: 120.0 },
+];
+
+// Only one channel is updated (and output) at a time, each for 15 CPU cycles
+const CYCLES_PER_CHANNEL: u8 = 15;
+
+// Each channel's 8 registers are at the end of the internal RAM, with the last one at $78
+const CHANNEL_REGS_START: usize = 0x40;
+const LAST_CHANNEL: usize = 7;
+
+// The top nibble of the last register holds the number of enabled channels, minus 1
+const CHANNEL_COUNT_REG: usize = 0x7F;
+
+const MAX_OUTPUT: f32 = 120.0;
+
+/// Wavetable synth whose waveforms and channel registers all live in the mapper's 128 bytes of internal RAM
+pub struct Namco163Audio {
+    cycles: u8,
+    // how many channels have been updated since the first one
+    channel_step: usize,
+    channel_count: usize,
+    outputs: [i16; 8],
+    // how loud the expansion audio is mixed, which depends on the resistors on each cartridge
+    gain: f32,
+}
+
+impl SystemControl for Namco163Audio {
+    fn reset(&mut self) {
+        self.cycles = 0;
+        self.channel_step = 0;
+        self.channel_count = 1;
+        self.outputs = [0; 8];
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.cycles);
+        state.write_usize(self.channel_step);
+        state.write_usize(self.channel_count);
+        for output in &self.outputs {
+            state.write_u16(*output as u16);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.cycles = state.read_u8()? % CYCLES_PER_CHANNEL;
+        self.channel_step = state.read_usize()? & 0x07;
+        self.channel_count = (state.read_usize()? & 0x07).max(1);
+        for output in &mut self.outputs {
+            *output = state.read_u16()? as i16;
+        }
+        Ok(())
+    }
+}
+
+impl Namco163Audio {
+    /// `mix_level` is how much louder (in dB) a channel playing a full volume wave is than a full volume APU pulse,
+    /// or None for boards without any expansion audio
+    pub fn new(mix_level: Option<f32>) -> Self {
+        let gain = match mix_level {
+            Some(db) => 10f32.powf(db / 20.0) * PULSE_TABLE[15] / (2.0 * MAX_OUTPUT),
+            None => 0.0,
+        };
+
+        Self {
+            cycles: 0,
+            channel_step: 0,
+            channel_count: 1,
+            outputs: [0; 8],
+            gain,
+        }
+    }
+
+    /// Clocked once per CPU cycle, writing the output level of each channel in `NAMCO_163_CHANNELS` to `levels`.
+    /// Each channel's phase is kept in `ram`, just like on the real chip
+    pub fn clock(&mut self, ram: &mut [u8], levels: &mut [f32]) {
+        self.cycles += 1;
+
+        if self.cycles >= CYCLES_PER_CHANNEL {
+            self.cycles = 0;
+            self.channel_count = ((ram[CHANNEL_COUNT_REG] >> 4) & 0x07) as usize + 1;
+
+            if self.channel_step >= self.channel_count {
+                self.channel_step = 0;
+            }
+
+            self.outputs[self.channel_step] = Self::update_channel(ram, LAST_CHANNEL - self.channel_step);
+            self.channel_step += 1;
+        }
+
+        for (i, (level, output)) in levels.iter_mut().zip(&self.outputs).enumerate() {
+            *level = if i < self.channel_count { *output as f32 } else { 0.0 };
+        }
+    }
+
+    // The channels take turns being output, so each one is only heard for its share of the time
+    pub fn mix(&self, levels: &[f32]) -> f32 {
+        levels.iter().sum::<f32>() / self.channel_count as f32 * self.gain
+    }
+
+    // Advances the channel's 24-bit phase by its 18-bit frequency, and returns its next sample
+    fn update_channel(ram: &mut [u8], channel: usize) -> i16 {
+        let regs = CHANNEL_REGS_START + channel * 8;
+
+        let frequency = ram[regs] as u32 | (ram[regs + 2] as u32) << 8 | ((ram[regs + 4] & 0x03) as u32) << 16;
+        let mut phase = ram[regs + 1] as u32 | (ram[regs + 3] as u32) << 8 | (ram[regs + 5] as u32) << 16;
+        let length = 256 - (ram[regs + 4] & 0xFC) as u32;
+
+        phase = (phase + frequency) % (length << 16);
+
+        ram[regs + 1] = phase as u8;
+        ram[regs + 3] = (phase >> 8) as u8;
+        ram[regs + 5] = (phase >> 16) as u8;
+
+        // 4-bit samples are packed two to a byte, low nibble first
+        let address = ((phase >> 16) as usize + ram[regs + 6] as usize) & 0xFF;
+        let sample = (ram[address >> 1] >> ((address & 0x01) * 4)) & 0x0F;
+        let volume = ram[regs + 7] & 0x0F;
+
+        (sample as i16 - 8) * volume as i16
+    }
+}
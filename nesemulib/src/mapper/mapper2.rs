@@ -1,51 +1,200 @@
-use crate::{cartridge::PRG_ROM_SIZE, SystemControl};
+use crate::{cartridge::{Mirroring, CHR_ROM_SIZE, PRG_ROM_SIZE}, SystemControl, StateReader, StateWriter};
 
 use super::{Mapper, PRG_ROM_END, PRG_ROM_HI_END, PRG_ROM_HI_START, PRG_ROM_LO_END, PRG_ROM_LO_START, PRG_ROM_START};
 
+/// Boards that switch one 16KB PRG bank and fix the other, like UxROM
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UxromBoard {
+    /// Switchable bank at $8000, with the last bank fixed at $C000 (mapper 2)
+    UXROM,
+    /// Like UxROM, but with the bank register at $C000-$FFFF, and one-screen mirroring selected through
+    /// $9000-$9FFF, used only by Fire Hawk (mapper 71)
+    CAMERICA,
+    /// The BF9097 revision of the Camerica board, known for certain to control mirroring through $8000-$9FFF (mapper 71.1)
+    CAMERICA_BF9097,
+    /// Also switches an 8KB CHR bank (mapper 70)
+    BANDAI,
+    /// Also switches an 8KB CHR bank, and selects one-screen mirroring (mapper 152)
+    BANDAI_ONESCREEN,
+    /// The first bank is fixed at $8000, with the switchable bank at $C000 (mapper 180)
+    UNROM_180,
+}
+
 pub struct Mapper2 {
+    board: UxromBoard,
     prg_rom_banks: usize,
-    prg_bank_lo: usize,
+    prg_bank: usize,
+    chr_bank: usize,
+    // None until the game sets the mirroring, for boards that can
+    mirroring: Option<Mirroring>,
 }
 
 impl SystemControl for Mapper2 {
     fn reset(&mut self) {
-        self.prg_bank_lo = 0;
+        self.prg_bank = 0;
+        self.chr_bank = 0;
+        self.mirroring = None;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_usize(self.prg_bank);
+        state.write_usize(self.chr_bank);
+
+        state.write_bool(self.mirroring.is_some());
+        if let Some(mirroring) = &self.mirroring {
+            mirroring.save_state(state);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.prg_bank = state.read_usize()? & 0x0F;
+        self.chr_bank = state.read_usize()? & 0x0F;
+
+        self.mirroring = if state.read_bool()? {
+            Some(Mirroring::load_state(state)?)
+        } else {
+            None
+        };
+
+        Ok(())
     }
 }
 
 impl Mapper for Mapper2 {
-    fn mapped_cpu_read(&self, prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8> {
-        match addr {
-            PRG_ROM_LO_START..=PRG_ROM_LO_END => {
-                Some(prg_rom[self.prg_bank_lo * PRG_ROM_SIZE + (addr & 0x3FFF)])
-            },
-            PRG_ROM_HI_START..=PRG_ROM_HI_END => {
-                Some(prg_rom[(self.prg_rom_banks - 1) * PRG_ROM_SIZE + (addr & 0x3FFF)])
-            },
-            _ => None
-        }
+    fn mapped_cpu_read(&mut self, prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8> {
+        let bank = match (self.board, addr) {
+            (UxromBoard::UNROM_180, PRG_ROM_LO_START..=PRG_ROM_LO_END) => 0,
+            (UxromBoard::UNROM_180, PRG_ROM_HI_START..=PRG_ROM_HI_END) => self.prg_bank,
+            (_, PRG_ROM_LO_START..=PRG_ROM_LO_END) => self.prg_bank,
+            (_, PRG_ROM_HI_START..=PRG_ROM_HI_END) => self.prg_rom_banks - 1,
+            _ => return None
+        };
+
+        Some(prg_rom[(bank * PRG_ROM_SIZE + (addr & 0x3FFF)) % prg_rom.len()])
     }
 
     fn mapped_cpu_write(&mut self, _prg_rom: &mut Vec<u8>, addr: usize, byte: u8) -> bool {
-        match addr {
-            PRG_ROM_START..=PRG_ROM_END => {
-                self.prg_bank_lo = (byte & 0b00001111) as usize;
-                true
+        if !(PRG_ROM_START..=PRG_ROM_END).contains(&addr) {
+            return false;
+        }
+
+        let one_screen = |high: bool| Some(if high { Mirroring::ONESCREEN_HI } else { Mirroring::ONESCREEN_LO });
+
+        match (self.board, addr) {
+            (UxromBoard::UXROM, _) => self.prg_bank = (byte & 0b00001111) as usize,
+            (UxromBoard::CAMERICA | UxromBoard::CAMERICA_BF9097, 0xC000..=0xFFFF) => {
+                self.prg_bank = (byte & 0b00001111) as usize;
+            }
+            (UxromBoard::CAMERICA, 0x9000..=0x9FFF) | (UxromBoard::CAMERICA_BF9097, 0x8000..=0x9FFF) => {
+                self.mirroring = one_screen((byte & 0b00010000) != 0);
             }
-            _ => false
+            (UxromBoard::CAMERICA | UxromBoard::CAMERICA_BF9097, _) => {}
+            (UxromBoard::BANDAI, _) => {
+                self.prg_bank = ((byte & 0b11110000) >> 4) as usize;
+                self.chr_bank = (byte & 0b00001111) as usize;
+            }
+            (UxromBoard::BANDAI_ONESCREEN, _) => {
+                self.prg_bank = ((byte & 0b01110000) >> 4) as usize;
+                self.chr_bank = (byte & 0b00001111) as usize;
+                self.mirroring = one_screen((byte & 0b10000000) != 0);
+            }
+            (UxromBoard::UNROM_180, _) => self.prg_bank = (byte & 0b00000111) as usize,
         }
+
+        true
     }
 
     fn mapped_ppu_read(&self, chr_rom: &Vec<u8>, addr: usize) -> u8 {
-        chr_rom[addr]
+        chr_rom[(self.chr_bank * CHR_ROM_SIZE + addr) % chr_rom.len()]
+    }
+
+    fn get_updated_mirroring(&self) -> Option<Mirroring> {
+        self.mirroring
     }
 }
 
 impl Mapper2 {
-    pub fn new(prg_rom_banks: usize) -> Self {
+    pub fn new(board: UxromBoard, prg_rom_banks: usize) -> Self {
         Self {
+            board,
             prg_rom_banks,
-            prg_bank_lo: 0,
+            prg_bank: 0,
+            chr_bank: 0,
+            mirroring: None,
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::Mirroring;
+    use crate::mapper::TestRom;
+
+    fn is_one_screen_hi(mirroring: Mirroring) -> bool {
+        matches!(mirroring, Mirroring::ONESCREEN_HI)
+    }
+
+    #[test]
+    pub fn test_uxrom() {
+        let mut cartridge = TestRom::nes2(2, 0, 0x20000, 0).prg_banks(0x4000).cartridge();
+
+        cartridge.cpu_write(0x8000, 5);
+        assert_eq!(cartridge.cpu_read(0x8000), Some(5));
+        assert_eq!(cartridge.cpu_read(0xC000), Some(7));
+    }
+
+    #[test]
+    pub fn test_camerica() {
+        let mut cartridge = TestRom::nes2(71, 0, 0x20000, 0).prg_banks(0x4000).cartridge();
+
+        // writes below $C000 don't switch banks
+        cartridge.cpu_write(0xC000, 5);
+        cartridge.cpu_write(0xA000, 3);
+        assert_eq!(cartridge.cpu_read(0x8000), Some(5));
+        assert_eq!(cartridge.cpu_read(0xC000), Some(7));
+
+        // Fire Hawk's mirroring control, which the BF9097 also decodes at $8000
+        cartridge.cpu_write(0x9000, 0x10);
+        assert!(is_one_screen_hi(cartridge.mirroring()));
+        cartridge.cpu_write(0x8000, 0x00);
+        assert!(is_one_screen_hi(cartridge.mirroring()));
+
+        let mut cartridge = TestRom::nes2(71, 1, 0x20000, 0).prg_banks(0x4000).cartridge();
+        cartridge.cpu_write(0x9000, 0x10);
+        cartridge.cpu_write(0x8000, 0x00);
+        assert!(matches!(cartridge.mirroring(), Mirroring::ONESCREEN_LO));
+    }
+
+    #[test]
+    pub fn test_bandai() {
+        let mut cartridge = TestRom::nes2(70, 0, 0x20000, 0x20000)
+            .prg_banks(0x4000)
+            .chr_banks(0x2000)
+            .cartridge();
+
+        cartridge.cpu_write(0x8000, 0x5B);
+        assert_eq!(cartridge.cpu_read(0x8000), Some(5));
+        assert_eq!(cartridge.cpu_read(0xC000), Some(7));
+        assert_eq!(cartridge.ppu_read(0x0000), 11);
+
+        let mut cartridge = TestRom::nes2(152, 0, 0x20000, 0x20000)
+            .prg_banks(0x4000)
+            .chr_banks(0x2000)
+            .cartridge();
+
+        cartridge.cpu_write(0x8000, 0xD9);
+        assert_eq!(cartridge.cpu_read(0x8000), Some(5));
+        assert_eq!(cartridge.ppu_read(0x0000), 9);
+        assert!(is_one_screen_hi(cartridge.mirroring()));
+    }
+
+    #[test]
+    pub fn test_unrom_180() {
+        let mut cartridge = TestRom::nes2(180, 0, 0x20000, 0).prg_banks(0x4000).cartridge();
+
+        cartridge.cpu_write(0x8000, 5);
+        assert_eq!(cartridge.cpu_read(0x8000), Some(0));
+        assert_eq!(cartridge.cpu_read(0xC000), Some(5));
+    }
+}
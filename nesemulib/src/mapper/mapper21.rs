@@ -0,0 +1,382 @@
+use crate::{cartridge::Mirroring, SystemControl, StateReader, StateWriter};
+
+use super::vrc_irq::VrcIrq;
+use super::{copy_trainer, Mapper, PRG_RAM_END, PRG_RAM_START, PRG_ROM_END, PRG_ROM_START};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+// The microwire latch (VRC2 boards without PRG-RAM) only answers in the first half of $6000-$7FFF
+const MICROWIRE_END: usize = 0x6FFF;
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum VrcChip {
+    /// Fixed PRG layout, single-bit mirroring and a one-bit microwire latch in place of PRG-RAM
+    VRC2,
+    /// Adds the PRG swap mode, one-screen mirroring and the IRQ counter
+    VRC4,
+}
+
+/// Each board connects two of the CPU's address lines to the chip's register select pins,
+/// given here as masks so that boards of unknown wiring can listen on both candidates at once
+struct Wiring {
+    chip: VrcChip,
+    a0: usize,
+    a1: usize,
+    // VRC2a leaves out CHR A10, so its 1KB bank numbers are really 2KB bank numbers
+    chr_shift: usize,
+}
+
+impl Wiring {
+    /// Picks the wiring from the NES 2.0 submapper. Plain iNES files (submapper 0) use every
+    /// address line the mapper number could stand for, as no known game writes to the other's registers
+    fn from_header(mapper_num: u16, submapper_num: u8) -> Self {
+        let (chip, a0, a1) = match (mapper_num, submapper_num) {
+            (21, 1) => (VrcChip::VRC4, 1 << 1, 1 << 2), // VRC4a
+            (21, 2) => (VrcChip::VRC4, 1 << 6, 1 << 7), // VRC4c
+            (21, _) => (VrcChip::VRC4, (1 << 1) | (1 << 6), (1 << 2) | (1 << 7)),
+            (22, _) => (VrcChip::VRC2, 1 << 1, 1 << 0), // VRC2a
+            (23, 1) => (VrcChip::VRC4, 1 << 0, 1 << 1), // VRC4f
+            (23, 2) => (VrcChip::VRC4, 1 << 2, 1 << 3), // VRC4e
+            (23, 3) => (VrcChip::VRC2, 1 << 0, 1 << 1), // VRC2b
+            (23, _) => (VrcChip::VRC4, (1 << 0) | (1 << 2), (1 << 1) | (1 << 3)),
+            (25, 1) => (VrcChip::VRC4, 1 << 1, 1 << 0), // VRC4b
+            (25, 2) => (VrcChip::VRC4, 1 << 3, 1 << 2), // VRC4d
+            (25, 3) => (VrcChip::VRC2, 1 << 1, 1 << 0), // VRC2c
+            (_, _)  => (VrcChip::VRC4, (1 << 1) | (1 << 3), (1 << 0) | (1 << 2)),
+        };
+
+        Self { chip, a0, a1, chr_shift: (mapper_num == 22) as usize }
+    }
+
+    // Turns a CPU address into its register number, $x000 to $x003
+    fn register(&self, addr: usize) -> usize {
+        let a0 = ((addr & self.a0) != 0) as usize;
+        let a1 = ((addr & self.a1) != 0) as usize;
+
+        (addr & 0xF000) | (a1 << 1) | a0
+    }
+}
+
+pub struct Mapper21 {
+    wiring: Wiring,
+    prg_ram: Vec<u8>,
+    prg_rom_banks: usize,
+
+    prg_banks: [usize; 2],
+    // VRC4 only: swaps the $8000 bank with the fixed second-last bank at $C000
+    prg_swap_mode: bool,
+    // 1KB CHR banks, each written to a nibble at a time
+    chr_regs: [usize; 8],
+    mirroring: Mirroring,
+    microwire_latch: u8,
+
+    irq: VrcIrq,
+}
+
+impl SystemControl for Mapper21 {
+    fn reset(&mut self) {
+        self.prg_banks = [0; 2];
+        self.prg_swap_mode = false;
+        self.chr_regs = [0; 8];
+        self.mirroring = Mirroring::VERTICAL;
+        self.microwire_latch = 0;
+
+        self.irq.reset();
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+
+        for value in &self.prg_banks {
+            state.write_usize(*value);
+        }
+        state.write_bool(self.prg_swap_mode);
+        for value in &self.chr_regs {
+            state.write_usize(*value);
+        }
+        self.mirroring.save_state(state);
+        state.write_u8(self.microwire_latch);
+
+        self.irq.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.read_bytes(&mut self.prg_ram)?;
+
+        for value in &mut self.prg_banks {
+            *value = state.read_usize()? & 0x1F;
+        }
+        self.prg_swap_mode = state.read_bool()?;
+        for value in &mut self.chr_regs {
+            *value = state.read_usize()? & 0x1FF;
+        }
+        self.mirroring = Mirroring::load_state(state)?;
+        self.microwire_latch = state.read_u8()? & 0x01;
+
+        self.irq.load_state(state)?;
+
+        Ok(())
+    }
+}
+
+impl Mapper for Mapper21 {
+    fn mapped_cpu_read(&mut self, prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8> {
+        match addr {
+            PRG_RAM_START..=PRG_RAM_END if !self.prg_ram.is_empty() => {
+                Some(self.prg_ram[(addr - PRG_RAM_START) % self.prg_ram.len()])
+            }
+            // only bit 0 is driven, the rest is open bus (usually the high byte of the address)
+            PRG_RAM_START..=MICROWIRE_END if self.wiring.chip == VrcChip::VRC2 => {
+                Some(((addr >> 8) as u8 & 0xFE) | self.microwire_latch)
+            }
+            PRG_ROM_START..=PRG_ROM_END => {
+                let second_last = self.prg_rom_banks * 2 - 2;
+
+                let bank = match (self.prg_swap_mode, addr) {
+                    (false, 0x8000..=0x9FFF) | (true, 0xC000..=0xDFFF) => self.prg_banks[0],
+                    (_, 0xA000..=0xBFFF) => self.prg_banks[1],
+                    (_, 0xE000..=0xFFFF) => second_last + 1,
+                    (_, _) => second_last,
+                };
+
+                Some(prg_rom[(bank * PRG_BANK_SIZE + (addr & (PRG_BANK_SIZE - 1))) % prg_rom.len()])
+            }
+            _ => None
+        }
+    }
+
+    fn mapped_cpu_write(&mut self, _prg_rom: &mut Vec<u8>, addr: usize, byte: u8) -> bool {
+        match addr {
+            PRG_RAM_START..=PRG_RAM_END if !self.prg_ram.is_empty() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - PRG_RAM_START) % len] = byte;
+                return true;
+            }
+            PRG_RAM_START..=MICROWIRE_END if self.wiring.chip == VrcChip::VRC2 => {
+                self.microwire_latch = byte & 0x01;
+                return true;
+            }
+            PRG_ROM_START..=PRG_ROM_END => {}
+            _ => return false
+        }
+
+        let vrc4 = self.wiring.chip == VrcChip::VRC4;
+
+        match self.wiring.register(addr) {
+            0x8000..=0x8003 => self.prg_banks[0] = (byte & 0x1F) as usize,
+            0x9000..=0x9001 if vrc4 => {
+                self.mirroring = match byte & 0x03 {
+                    0 => Mirroring::VERTICAL,
+                    1 => Mirroring::HORIZONTAL,
+                    2 => Mirroring::ONESCREEN_LO,
+                    _ => Mirroring::ONESCREEN_HI,
+                };
+            }
+            0x9002..=0x9003 if vrc4 => self.prg_swap_mode = (byte & 0b10) != 0,
+            0x9000..=0x9003 => {
+                self.mirroring = if byte & 0x01 == 0 {
+                    Mirroring::VERTICAL
+                } else {
+                    Mirroring::HORIZONTAL
+                };
+            }
+            0xA000..=0xA003 => self.prg_banks[1] = (byte & 0x1F) as usize,
+            reg @ 0xB000..=0xEFFF => {
+                // $B000/$B001 hold the low and high nibbles of the first bank, $B002/$B003 the second, and so on
+                let index = ((reg - 0xB000) >> 11) | ((reg >> 1) & 0x01);
+                let value = &mut self.chr_regs[index];
+
+                if reg & 0x01 == 0 {
+                    *value = (*value & !0x0F) | (byte & 0x0F) as usize;
+                } else {
+                    *value = (*value & 0x0F) | ((byte & 0x1F) as usize) << 4;
+                }
+            }
+            0xF000 if vrc4 => self.irq.latch = (self.irq.latch & 0xF0) | (byte & 0x0F),
+            0xF001 if vrc4 => self.irq.latch = (self.irq.latch & 0x0F) | (byte << 4),
+            0xF002 if vrc4 => self.irq.write_control(byte),
+            0xF003 if vrc4 => self.irq.acknowledge(),
+            _ => {}
+        }
+
+        true
+    }
+
+    fn mapped_ppu_read(&self, chr_rom: &Vec<u8>, addr: usize) -> u8 {
+        chr_rom[self.chr_addr(addr) % chr_rom.len()]
+    }
+
+    fn mapped_ppu_write(&mut self, chr_ram: &mut Vec<u8>, addr: usize, byte: u8) {
+        let len = chr_ram.len();
+        chr_ram[self.chr_addr(addr) % len] = byte;
+    }
+
+    fn get_updated_mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    fn cpu_clock(&mut self) {
+        self.irq.clock();
+    }
+
+    fn irq_active(&mut self) -> bool {
+        self.irq.active()
+    }
+
+    fn load_trainer(&mut self, trainer: &[u8]) -> bool {
+        copy_trainer(&mut self.prg_ram, trainer)
+    }
+
+    fn get_save_ram(&self) -> Option<&[u8]> {
+        if self.prg_ram.is_empty() { None } else { Some(&self.prg_ram) }
+    }
+
+    fn load_save_ram(&mut self, save_ram: &[u8]) -> bool {
+        self.prg_ram.copy_from_slice(save_ram);
+        !self.prg_ram.is_empty()
+    }
+}
+
+impl Mapper21 {
+    /// Mappers 21, 22, 23 and 25 cover VRC2a/b/c and VRC4a-f, told apart by their submapper
+    pub fn new(mapper_num: u16, submapper_num: u8, prg_rom_banks: usize, prg_ram_size: usize) -> Self {
+        Self {
+            wiring: Wiring::from_header(mapper_num, submapper_num),
+            prg_ram: vec![0; prg_ram_size],
+            prg_rom_banks,
+
+            prg_banks: [0; 2],
+            prg_swap_mode: false,
+            chr_regs: [0; 8],
+            mirroring: Mirroring::VERTICAL,
+            microwire_latch: 0,
+
+            irq: VrcIrq::new(),
+        }
+    }
+
+    fn chr_addr(&self, addr: usize) -> usize {
+        let bank = self.chr_regs[(addr >> 10) & 0x07] >> self.wiring.chr_shift;
+        bank * CHR_BANK_SIZE + (addr & (CHR_BANK_SIZE - 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mapper::TestRom;
+
+    #[test]
+    pub fn test_wiring() {
+        // (mapper, submapper, the address lines for register A0 and A1)
+        let boards = [
+            (21, 1, 0x002, 0x004), (21, 2, 0x040, 0x080), (21, 0, 0x040, 0x004),
+            (22, 0, 0x002, 0x001),
+            (23, 1, 0x001, 0x002), (23, 2, 0x004, 0x008), (23, 3, 0x001, 0x002), (23, 0, 0x004, 0x002),
+            (25, 1, 0x002, 0x001), (25, 2, 0x008, 0x004), (25, 3, 0x002, 0x001), (25, 0, 0x008, 0x001),
+        ];
+
+        for (mapper_num, submapper_num, a0, a1) in boards {
+            let mut cartridge = TestRom::nes2(mapper_num, submapper_num, 0x20000, 0x20000)
+                .prg_banks(0x2000)
+                .chr_banks(0x400)
+                .prg_ram(0x07, 0)
+                .cartridge();
+            let chr_scale = if mapper_num == 22 { 2 } else { 1 };
+
+            cartridge.cpu_write(0x8000, 3);
+            cartridge.cpu_write(0xA000, 9);
+            assert_eq!(cartridge.cpu_read(0x8000), Some(3));
+            assert_eq!(cartridge.cpu_read(0xA000), Some(9));
+            assert_eq!(cartridge.cpu_read(0xC000), Some(14));
+            assert_eq!(cartridge.cpu_read(0xE000), Some(15));
+
+            // $D002 and $D003 hold the low and high nibbles of the bank at $1400
+            cartridge.cpu_write(0xD000 | a1, (0x34 * chr_scale) & 0x0F);
+            cartridge.cpu_write(0xD000 | a1 | a0, (0x34 * chr_scale) >> 4);
+            assert_eq!(cartridge.ppu_read(0x1400), 0x34, "mapper {}.{}", mapper_num, submapper_num);
+            assert_eq!(cartridge.ppu_read(0x1000), 0);
+        }
+    }
+
+    #[test]
+    pub fn test_vrc4_prg_swap_mode() {
+        let mut cartridge = TestRom::nes2(21, 1, 0x20000, 0x20000)
+            .prg_banks(0x2000)
+            .chr_banks(0x400)
+            .prg_ram(0x07, 0)
+            .cartridge();
+
+        cartridge.cpu_write(0x8000, 3);
+        cartridge.cpu_write(0x9004, 0x02);
+        assert_eq!(cartridge.cpu_read(0x8000), Some(14));
+        assert_eq!(cartridge.cpu_read(0xC000), Some(3));
+        assert_eq!(cartridge.cpu_read(0xE000), Some(15));
+
+        cartridge.cpu_write(0x6000, 0x42);
+        assert_eq!(cartridge.cpu_read(0x6000), Some(0x42));
+    }
+
+    #[test]
+    pub fn test_vrc4_irq() {
+        let mut cartridge = TestRom::nes2(25, 1, 0x20000, 0x20000)
+            .prg_banks(0x2000)
+            .chr_banks(0x400)
+            .prg_ram(0x07, 0)
+            .cartridge();
+
+        // the latch is written a nibble at a time, here set to 0xF0 in cycle mode
+        cartridge.cpu_write(0xF000, 0x00);
+        cartridge.cpu_write(0xF002, 0x0F);
+        cartridge.cpu_write(0xF001, 0b111);
+
+        for _ in 0..15 {
+            cartridge.cpu_clock();
+            assert!(!cartridge.irq_active());
+        }
+
+        cartridge.cpu_clock();
+        assert!(cartridge.irq_active());
+
+        cartridge.cpu_write(0xF003, 0);
+        assert!(!cartridge.irq_active());
+    }
+
+    #[test]
+    pub fn test_vrc2_microwire_latch() {
+        let mut cartridge = TestRom::nes2(23, 3, 0x20000, 0x20000)
+            .prg_banks(0x2000)
+            .chr_banks(0x400)
+            .cartridge();
+
+        cartridge.cpu_write(0x6000, 0xFF);
+        assert_eq!(cartridge.cpu_read(0x6000).map(|byte| byte & 0x01), Some(1));
+        cartridge.cpu_write(0x6000, 0xFE);
+        assert_eq!(cartridge.cpu_read(0x6000).map(|byte| byte & 0x01), Some(0));
+        assert_eq!(cartridge.cpu_read(0x7000), None);
+        assert_eq!(cartridge.get_save_ram(), None);
+
+        // VRC2 has no IRQ counter
+        cartridge.cpu_write(0xF000, 0x0F);
+        cartridge.cpu_write(0xF001, 0x0F);
+        cartridge.cpu_write(0xF002, 0b111);
+        for _ in 0..0x200 {
+            cartridge.cpu_clock();
+        }
+        assert!(!cartridge.irq_active());
+    }
+
+    #[test]
+    pub fn test_ines_vrc2_microwire_latch() {
+        // plain iNES files can't say there's no PRG-RAM, but mapper 22 boards never have any
+        let mut cartridge = TestRom::ines(22, 0x20000, 0x20000).cartridge();
+
+        cartridge.cpu_write(0x6000, 0x01);
+        assert_eq!(cartridge.cpu_read(0x6000), Some(0x61));
+        cartridge.cpu_write(0x6000, 0x00);
+        assert_eq!(cartridge.cpu_read(0x6000), Some(0x60));
+        assert_eq!(cartridge.cpu_read(0x7000), None);
+        assert_eq!(cartridge.get_save_ram(), None);
+    }
+}
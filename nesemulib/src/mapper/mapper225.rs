@@ -0,0 +1,344 @@
+use crate::{cartridge::{Mirroring, NameTableSource, CHR_ROM_SIZE, PRG_ROM_SIZE}, SystemControl, StateReader, StateWriter};
+
+use super::{Mapper, PRG_ROM_END, PRG_ROM_START};
+
+/// Multicarts that switch the whole of PRG-ROM and CHR between games, with an outer bank selecting
+/// a block of the ROM and an inner bank selecting the game within it. Most latch the address of
+/// the write rather than the byte written, and pick between a 16KB or 32KB PRG bank for each game
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MulticartBoard {
+    /// Registers at $8000 (.H.. .CCC) and $8800 (PPPO MCCC), with DIP switches read at $6000 (mapper 57)
+    GK_6_IN_1,
+    /// Address latch: A~[.... .... MOCC CPPP] (mapper 58)
+    GAME_STAR_68_IN_1,
+    /// Address latch: A~[.HMO PPPP PPCC CCCC], with four nibbles of RAM at $5800-$5FFF (mapper 225)
+    BMC_72_IN_1,
+    /// Registers at $8000 (PMOP PPPp) and $8001 (.... ...P) (mapper 226)
+    BMC_76_IN_1,
+    /// Address latch: A~[.... ..LP OPPP PPMS], with UNROM-like modes (mapper 227)
+    BMC_1200_IN_1,
+    /// Address latch: A~[..MH HPPP PPO. CCCC] plus two more CHR bits from the data, with four
+    /// nibbles of RAM at $4020-$5FFF (mapper 228)
+    ACTION_52,
+    /// Latch: MMOP PPPP, with a flip-flop toggled by every press of reset that selects the ROM's half (mapper 233)
+    BMC_42_IN_1,
+}
+
+pub struct Mapper225 {
+    board: MulticartBoard,
+    // The last value written to each of the board's registers, which is the address for address latches
+    registers: [usize; 2],
+    // Action 52's CHR bank also takes the low bits of the byte written
+    latch_data: usize,
+    // The 16KB PRG-ROM banks at $8000 and $C000 and the 8KB CHR bank, as decoded from the registers
+    prg_banks: [usize; 2],
+    chr_bank: usize,
+    mirroring: Mirroring,
+    nibble_ram: [u8; 4],
+    reset_flip_flop: bool,
+    dip_switch: usize,
+}
+
+impl SystemControl for Mapper225 {
+    fn reset(&mut self) {
+        self.registers = [0; 2];
+        self.latch_data = 0;
+        self.reset_flip_flop = false;
+        self.update_banks();
+    }
+
+    fn soft_reset(&mut self) {
+        let reset_flip_flop = !self.reset_flip_flop;
+        self.reset();
+
+        if self.board == MulticartBoard::BMC_42_IN_1 {
+            self.reset_flip_flop = reset_flip_flop;
+            self.update_banks();
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        for register in &self.registers {
+            state.write_usize(*register);
+        }
+        state.write_usize(self.latch_data);
+        state.write_bytes(&self.nibble_ram);
+        state.write_bool(self.reset_flip_flop);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        for register in &mut self.registers {
+            *register = state.read_usize()? & 0xFFFF;
+        }
+        self.latch_data = state.read_usize()? & 0xFF;
+        state.read_bytes(&mut self.nibble_ram)?;
+        self.reset_flip_flop = state.read_bool()?;
+        self.update_banks();
+
+        Ok(())
+    }
+}
+
+impl Mapper for Mapper225 {
+    fn mapped_cpu_read(&mut self, prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8> {
+        match (self.board, addr) {
+            (MulticartBoard::GK_6_IN_1, 0x6000) => Some(self.dip_switch as u8),
+            (MulticartBoard::BMC_72_IN_1, 0x5800..=0x5FFF) | (MulticartBoard::ACTION_52, 0x4020..=0x5FFF) => {
+                Some(self.nibble_ram[addr & 0x03])
+            }
+            (_, PRG_ROM_START..=PRG_ROM_END) => {
+                let bank = self.prg_banks[(addr >> 14) & 0x01];
+                Some(prg_rom[(bank * PRG_ROM_SIZE + (addr & 0x3FFF)) % prg_rom.len()])
+            }
+            _ => None
+        }
+    }
+
+    fn mapped_cpu_write(&mut self, _prg_rom: &mut Vec<u8>, addr: usize, byte: u8) -> bool {
+        match (self.board, addr) {
+            (MulticartBoard::BMC_72_IN_1, 0x5800..=0x5FFF) | (MulticartBoard::ACTION_52, 0x4020..=0x5FFF) => {
+                self.nibble_ram[addr & 0x03] = byte & 0x0F;
+                return true;
+            }
+            (_, PRG_ROM_START..=PRG_ROM_END) => {}
+            _ => return false
+        }
+
+        match self.board {
+            MulticartBoard::GK_6_IN_1 => self.registers[(addr & 0x0800) >> 11] = byte as usize,
+            MulticartBoard::BMC_76_IN_1 => self.registers[addr & 0x01] = byte as usize,
+            MulticartBoard::BMC_42_IN_1 => self.registers[0] = byte as usize,
+            MulticartBoard::ACTION_52 => {
+                self.registers[0] = addr;
+                self.latch_data = byte as usize;
+            }
+            _ => self.registers[0] = addr,
+        }
+
+        self.update_banks();
+        true
+    }
+
+    fn mapped_ppu_read(&self, chr_rom: &Vec<u8>, addr: usize) -> u8 {
+        chr_rom[(self.chr_bank * CHR_ROM_SIZE + addr) % chr_rom.len()]
+    }
+
+    fn mapped_ppu_write(&mut self, chr_ram: &mut Vec<u8>, addr: usize, byte: u8) {
+        let len = chr_ram.len();
+        chr_ram[(self.chr_bank * CHR_ROM_SIZE + addr) % len] = byte;
+    }
+
+    fn get_updated_mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    fn dip_switch_settings(&self) -> usize {
+        match self.board {
+            MulticartBoard::GK_6_IN_1 => 4,
+            _ => 0,
+        }
+    }
+
+    fn dip_switch(&self) -> usize {
+        self.dip_switch
+    }
+
+    fn set_dip_switch(&mut self, setting: usize) {
+        self.dip_switch = setting;
+    }
+}
+
+impl Mapper225 {
+    pub fn new(board: MulticartBoard) -> Self {
+        let mut mapper = Self {
+            board,
+            registers: [0; 2],
+            latch_data: 0,
+            prg_banks: [0, 1],
+            chr_bank: 0,
+            mirroring: Mirroring::VERTICAL,
+            nibble_ram: [0; 4],
+            reset_flip_flop: false,
+            dip_switch: 0,
+        };
+
+        mapper.update_banks();
+        mapper
+    }
+
+    fn update_banks(&mut self) {
+        let [r0, r1] = self.registers;
+
+        // (16KB PRG bank, whether it's a 16KB game rather than a 32KB one, CHR bank, horizontal mirroring)
+        let (prg_bank, nrom_128, chr_bank, horizontal) = match self.board {
+            MulticartBoard::GK_6_IN_1 => {
+                let chr_bank = ((r0 & 0x40) >> 3) | ((r0 | r1) & 0x07);
+                (r1 >> 5, (r1 & 0x10) == 0, chr_bank, (r1 & 0x08) != 0)
+            }
+            MulticartBoard::GAME_STAR_68_IN_1 => {
+                (r0 & 0x07, (r0 & 0x40) != 0, (r0 >> 3) & 0x07, (r0 & 0x80) != 0)
+            }
+            MulticartBoard::BMC_72_IN_1 => {
+                // A14 is the outer bank for both PRG and CHR
+                let outer_bank = (r0 >> 8) & 0x40;
+                (((r0 >> 6) & 0x3F) | outer_bank, (r0 & 0x1000) != 0, (r0 & 0x3F) | outer_bank, (r0 & 0x2000) != 0)
+            }
+            MulticartBoard::BMC_76_IN_1 => {
+                let prg_bank = (r0 & 0x1F) | ((r0 >> 2) & 0x20) | ((r1 & 0x01) << 6);
+                (prg_bank, (r0 & 0x20) != 0, 0, (r0 & 0x40) == 0)
+            }
+            MulticartBoard::BMC_1200_IN_1 => return self.update_1200_in_1_banks(),
+            MulticartBoard::ACTION_52 => {
+                // Action 52 only has three 512KB PRG chips, with the third selected as chip 3
+                let chip = match (r0 >> 11) & 0x03 {
+                    3 => 2,
+                    chip => chip,
+                };
+                let chr_bank = ((r0 & 0x0F) << 2) | (self.latch_data & 0x03);
+                (((r0 >> 6) & 0x1F) | (chip << 5), (r0 & 0x20) != 0, chr_bank, (r0 & 0x2000) != 0)
+            }
+            MulticartBoard::BMC_42_IN_1 => {
+                let prg_bank = (r0 & 0x1F) | ((self.reset_flip_flop as usize) << 5);
+
+                self.mirroring = match r0 >> 6 {
+                    0 => Mirroring::CUSTOM([
+                        NameTableSource::CIRAM(0), NameTableSource::CIRAM(0),
+                        NameTableSource::CIRAM(0), NameTableSource::CIRAM(1),
+                    ]),
+                    1 => Mirroring::VERTICAL,
+                    2 => Mirroring::HORIZONTAL,
+                    _ => Mirroring::ONESCREEN_HI,
+                };
+                self.set_banks(prg_bank, (r0 & 0x20) != 0, 0);
+                return;
+            }
+        };
+
+        self.mirroring = if horizontal { Mirroring::HORIZONTAL } else { Mirroring::VERTICAL };
+        self.set_banks(prg_bank, nrom_128, chr_bank);
+    }
+
+    // Maps a 16KB bank to both halves for NROM-128 games, or the 32KB bank it's in for NROM-256 ones
+    fn set_banks(&mut self, prg_bank: usize, nrom_128: bool, chr_bank: usize) {
+        self.prg_banks = if nrom_128 { [prg_bank; 2] } else { [prg_bank & !0x01, prg_bank | 0x01] };
+        self.chr_bank = chr_bank;
+    }
+
+    // The 1200-in-1 can also act like UNROM, with either the first or last bank of a 128KB block fixed at $C000
+    fn update_1200_in_1_banks(&mut self) {
+        let r0 = self.registers[0];
+        let prg_bank = ((r0 >> 2) & 0x1F) | ((r0 & 0x100) >> 3);
+        let nrom = (r0 & 0x80) != 0;
+        let size_32k = (r0 & 0x01) != 0;
+        let last_bank = (r0 & 0x200) != 0;
+
+        self.prg_banks = match (nrom, size_32k) {
+            (true, true) => [prg_bank & !0x01, prg_bank | 0x01],
+            (true, false) => [prg_bank; 2],
+            (false, _) => {
+                let first = if size_32k { prg_bank & !0x01 } else { prg_bank };
+                [first, if last_bank { prg_bank | 0x07 } else { prg_bank & !0x07 }]
+            }
+        };
+        self.chr_bank = 0;
+        self.mirroring = if (r0 & 0x02) != 0 { Mirroring::HORIZONTAL } else { Mirroring::VERTICAL };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cartridge::{CartridgeNes, Mirroring}, SystemControl};
+    use crate::mapper::TestRom;
+
+    fn banks(cartridge: &mut CartridgeNes) -> (u8, u8, u8) {
+        (cartridge.cpu_read(0x8000).unwrap(), cartridge.cpu_read(0xC000).unwrap(), cartridge.ppu_read(0x0000))
+    }
+
+    #[test]
+    pub fn test_address_latches() {
+        // (mapper, write address, written byte, PRG banks and CHR bank)
+        let writes = [
+            (58, 0x80DD, 0x00, (5, 5, 3)),
+            (58, 0x801D, 0x00, (4, 5, 3)),
+            (225, 0xD0C5, 0x00, (67, 67, 69)),
+            (225, 0x8085, 0x00, (2, 3, 5)),
+            (228, 0x9885, 0x03, (66, 67, 23)),
+            (228, 0x80A0, 0x00, (2, 2, 0)),
+        ];
+
+        for (mapper_num, addr, byte, expected) in writes {
+            let mut cartridge = TestRom::nes2(mapper_num, 0, 0x200000, 0x100000)
+                .prg_banks(0x4000)
+                .chr_banks(0x2000)
+                .cartridge();
+
+            cartridge.cpu_write(addr, byte);
+            assert_eq!(banks(&mut cartridge), expected, "mapper {} write to ${:04X}", mapper_num, addr);
+        }
+    }
+
+    #[test]
+    pub fn test_register_multicarts() {
+        let mut cartridge = TestRom::nes2(57, 0, 0x20000, 0x20000)
+            .prg_banks(0x4000)
+            .chr_banks(0x2000)
+            .cartridge();
+        cartridge.cpu_write(0x8000, 0x42);
+        cartridge.cpu_write(0x8800, 0x99);
+        assert_eq!(banks(&mut cartridge), (4, 5, 11));
+        assert!(matches!(cartridge.mirroring(), Mirroring::HORIZONTAL));
+
+        // the 76-in-1 spreads its bank number across both registers
+        let mut cartridge = TestRom::nes2(226, 0, 0x200000, 0).prg_banks(0x4000).cartridge();
+        cartridge.cpu_write(0x8000, 0xA3);
+        cartridge.cpu_write(0x8001, 0x01);
+        assert_eq!(banks(&mut cartridge), (99, 99, 0));
+        assert!(matches!(cartridge.mirroring(), Mirroring::HORIZONTAL));
+
+        // UNROM mode on the 1200-in-1, with the last bank of the 128KB block fixed
+        let mut cartridge = TestRom::nes2(227, 0, 0x100000, 0).prg_banks(0x4000).cartridge();
+        cartridge.cpu_write(0x8208, 0x00);
+        assert_eq!(banks(&mut cartridge), (2, 7, 0));
+        cartridge.cpu_write(0x8008, 0x00);
+        assert_eq!(banks(&mut cartridge), (2, 0, 0));
+    }
+
+    #[test]
+    pub fn test_multicart_ram_and_dip_switch() {
+        let mut cartridge = TestRom::nes2(225, 0, 0x20000, 0x10000)
+            .prg_banks(0x4000)
+            .chr_banks(0x2000)
+            .cartridge();
+        cartridge.cpu_write(0x5801, 0xAB);
+        assert_eq!(cartridge.cpu_read(0x5FFD), Some(0x0B));
+
+        let mut cartridge = TestRom::nes2(57, 0, 0x20000, 0x10000)
+            .prg_banks(0x4000)
+            .chr_banks(0x2000)
+            .cartridge();
+        assert_eq!(cartridge.dip_switch_settings(), 4);
+        cartridge.set_dip_switch(6);
+        assert_eq!(cartridge.cpu_read(0x6000), Some(2));
+    }
+
+    #[test]
+    pub fn test_reset_flip_flop() {
+        let mut cartridge = TestRom::nes2(233, 0, 0x100000, 0).prg_banks(0x4000).cartridge();
+
+        cartridge.cpu_write(0x8000, 0x62);
+        assert_eq!(banks(&mut cartridge), (2, 2, 0));
+        assert!(matches!(cartridge.mirroring(), Mirroring::VERTICAL));
+
+        // every press of reset switches to the other half of the ROM, while power cycling goes back to the first
+        cartridge.soft_reset();
+        assert_eq!(banks(&mut cartridge), (32, 33, 0));
+        cartridge.cpu_write(0x8000, 0x22);
+        assert_eq!(banks(&mut cartridge), (34, 34, 0));
+        cartridge.soft_reset();
+        assert_eq!(banks(&mut cartridge), (0, 1, 0));
+        cartridge.soft_reset();
+        cartridge.reset();
+        assert_eq!(banks(&mut cartridge), (0, 1, 0));
+    }
+}
@@ -0,0 +1,108 @@
+use crate::{cartridge::PRG_ROM_SIZE, SystemControl, StateReader, StateWriter};
+
+use super::{Mapper, PRG_ROM_HI_END, PRG_ROM_HI_START, PRG_ROM_LO_END, PRG_ROM_LO_START};
+
+// Each of the four 64KB blocks is laid out like a small UxROM game
+const BANKS_PER_BLOCK: usize = 4;
+
+/// Camerica's Quattro multicarts, where $8000-$BFFF selects a 64KB block and $C000-$FFFF selects
+/// a 16KB bank within it, with the block's last bank fixed at $C000
+pub struct Mapper232 {
+    // The Aladdin Deck Enhancer's version has the two block bits the other way round
+    aladdin: bool,
+    block_select: usize,
+    prg_rom_select: usize,
+}
+
+impl SystemControl for Mapper232 {
+    fn reset(&mut self) {
+        self.block_select = 0;
+        self.prg_rom_select = 0;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_usize(self.block_select);
+        state.write_usize(self.prg_rom_select);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.block_select = state.read_usize()? & 0x03;
+        self.prg_rom_select = state.read_usize()? & 0x03;
+
+        Ok(())
+    }
+}
+
+impl Mapper for Mapper232 {
+    fn mapped_cpu_read(&mut self, prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8> {
+        let bank = match addr {
+            PRG_ROM_LO_START..=PRG_ROM_LO_END => self.prg_rom_select,
+            PRG_ROM_HI_START..=PRG_ROM_HI_END => BANKS_PER_BLOCK - 1,
+            _ => return None
+        };
+
+        let bank = self.block_select * BANKS_PER_BLOCK + bank;
+        Some(prg_rom[(bank * PRG_ROM_SIZE + (addr & 0x3FFF)) % prg_rom.len()])
+    }
+
+    fn mapped_cpu_write(&mut self, _prg_rom: &mut Vec<u8>, addr: usize, byte: u8) -> bool {
+        match addr {
+            PRG_ROM_LO_START..=0xBFFF => {
+                let block = ((byte & 0b00011000) >> 3) as usize;
+
+                self.block_select = if self.aladdin {
+                    ((block & 0b01) << 1) | ((block & 0b10) >> 1)
+                } else {
+                    block
+                };
+                true
+            }
+            PRG_ROM_HI_START..=PRG_ROM_HI_END => {
+                self.prg_rom_select = (byte & 0b00000011) as usize;
+                true
+            }
+            _ => false
+        }
+    }
+
+    fn mapped_ppu_read(&self, chr_rom: &Vec<u8>, addr: usize) -> u8 {
+        chr_rom[addr]
+    }
+}
+
+impl Mapper232 {
+    /// Submapper 1 is the Aladdin Deck Enhancer
+    pub fn new(submapper_num: u8) -> Self {
+        Self {
+            aladdin: submapper_num == 1,
+            block_select: 0,
+            prg_rom_select: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mapper::TestRom;
+
+    #[test]
+    pub fn test_quattro() {
+        let mut cartridge = TestRom::nes2(232, 0, 0x40000, 0).prg_banks(0x4000).cartridge();
+
+        assert_eq!(cartridge.cpu_read(0x8000), Some(0));
+        assert_eq!(cartridge.cpu_read(0xC000), Some(3));
+
+        cartridge.cpu_write(0x8000, 0x08);
+        cartridge.cpu_write(0xC000, 0x02);
+        assert_eq!(cartridge.cpu_read(0x8000), Some(6));
+        assert_eq!(cartridge.cpu_read(0xC000), Some(7));
+
+        // the Aladdin version swaps the block bits
+        let mut cartridge = TestRom::nes2(232, 1, 0x40000, 0).prg_banks(0x4000).cartridge();
+
+        cartridge.cpu_write(0x9000, 0x08);
+        cartridge.cpu_write(0xFFFF, 0x01);
+        assert_eq!(cartridge.cpu_read(0x8000), Some(9));
+        assert_eq!(cartridge.cpu_read(0xC000), Some(11));
+    }
+}
@@ -0,0 +1,366 @@
+mod audio;
+
+use crate::{cartridge::{Mirroring, NAME_TABLE_PAGE_SIZE}, SystemControl, StateReader, StateWriter};
+
+use self::audio::{Vrc6Audio, VRC6_CHANNELS};
+use super::vrc_irq::VrcIrq;
+use super::{ExpansionChannel, copy_trainer, Mapper, PRG_RAM_END, PRG_RAM_START, PRG_ROM_END, PRG_ROM_START};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+pub struct Mapper24 {
+    prg_ram: Vec<u8>,
+    prg_rom_banks: usize,
+    // VRC6b (mapper 26) has the A0 and A1 lines swapped compared to VRC6a (mapper 24)
+    swapped_lines: bool,
+
+    prg_bank_16k: usize,
+    prg_bank_8k: usize,
+    chr_regs: [usize; 8],
+    // $B003: W.PN MMDD (PRG-RAM enable, CHR A10 source, nametables from CHR-ROM, mirroring, CHR mode)
+    ppu_banking: u8,
+
+    irq: VrcIrq,
+    audio: Vrc6Audio,
+}
+
+impl SystemControl for Mapper24 {
+    fn reset(&mut self) {
+        self.prg_bank_16k = 0;
+        self.prg_bank_8k = 0;
+        self.chr_regs = [0; 8];
+        self.ppu_banking = 0;
+
+        self.irq.reset();
+        self.audio.reset();
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+
+        state.write_usize(self.prg_bank_16k);
+        state.write_usize(self.prg_bank_8k);
+        for value in &self.chr_regs {
+            state.write_usize(*value);
+        }
+        state.write_u8(self.ppu_banking);
+
+        self.irq.save_state(state);
+        self.audio.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.read_bytes(&mut self.prg_ram)?;
+
+        self.prg_bank_16k = state.read_usize()? & 0x0F;
+        self.prg_bank_8k = state.read_usize()? & 0x1F;
+        for value in &mut self.chr_regs {
+            *value = state.read_usize()? & 0xFF;
+        }
+        self.ppu_banking = state.read_u8()?;
+
+        self.irq.load_state(state)?;
+        self.audio.load_state(state)?;
+
+        Ok(())
+    }
+}
+
+impl Mapper for Mapper24 {
+    fn mapped_cpu_read(&mut self, prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8> {
+        match addr {
+            PRG_RAM_START..=PRG_RAM_END if self.prg_ram_enabled() => {
+                Some(self.prg_ram[(addr - PRG_RAM_START) % self.prg_ram.len()])
+            }
+            PRG_ROM_START..=PRG_ROM_END => {
+                let bank = match addr {
+                    0x8000..=0xBFFF => self.prg_bank_16k * 2 + ((addr >> 13) & 0x01),
+                    0xC000..=0xDFFF => self.prg_bank_8k,
+                    _ => self.prg_rom_banks * 2 - 1,
+                };
+
+                Some(prg_rom[(bank * PRG_BANK_SIZE + (addr & (PRG_BANK_SIZE - 1))) % prg_rom.len()])
+            }
+            _ => None
+        }
+    }
+
+    fn mapped_cpu_write(&mut self, _prg_rom: &mut Vec<u8>, addr: usize, byte: u8) -> bool {
+        if let PRG_RAM_START..=PRG_RAM_END = addr {
+            if self.prg_ram_enabled() {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - PRG_RAM_START) % len] = byte;
+            }
+            return !self.prg_ram.is_empty();
+        }
+
+        let reg = if self.swapped_lines {
+            (addr & 0xF000) | ((addr & 0x01) << 1) | ((addr & 0x02) >> 1)
+        } else {
+            addr & 0xF003
+        };
+
+        match reg {
+            0x8000..=0x8003 => self.prg_bank_16k = (byte & 0x0F) as usize,
+            0x9000..=0x9003 | 0xA000..=0xA002 | 0xB000..=0xB002 => self.audio.write_register(reg, byte),
+            0xB003 => self.ppu_banking = byte,
+            0xC000..=0xC003 => self.prg_bank_8k = (byte & 0x1F) as usize,
+            0xD000..=0xD003 => self.chr_regs[reg & 0x03] = byte as usize,
+            0xE000..=0xE003 => self.chr_regs[4 + (reg & 0x03)] = byte as usize,
+            0xF000 => self.irq.latch = byte,
+            0xF001 => self.irq.write_control(byte),
+            0xF002 => self.irq.acknowledge(),
+            _ => return false
+        }
+
+        true
+    }
+
+    fn mapped_ppu_read(&self, chr_rom: &Vec<u8>, addr: usize) -> u8 {
+        chr_rom[(self.chr_bank(addr) * CHR_BANK_SIZE + (addr & (CHR_BANK_SIZE - 1))) % chr_rom.len()]
+    }
+
+    fn mapped_ppu_write(&mut self, chr_ram: &mut Vec<u8>, addr: usize, byte: u8) {
+        let len = chr_ram.len();
+        chr_ram[(self.chr_bank(addr) * CHR_BANK_SIZE + (addr & (CHR_BANK_SIZE - 1))) % len] = byte;
+    }
+
+    // Only the mirroring used by mode 0 is supported when nametables come from CIRAM,
+    // which is the only mode games use
+    fn get_updated_mirroring(&self) -> Option<Mirroring> {
+        Some(match (self.ppu_banking >> 2) & 0x03 {
+            0 => Mirroring::VERTICAL,
+            1 => Mirroring::HORIZONTAL,
+            2 => Mirroring::ONESCREEN_LO,
+            _ => Mirroring::ONESCREEN_HI,
+        })
+    }
+
+    fn mapped_name_table_read(&self, chr_rom: &Vec<u8>, addr: usize) -> Option<u8> {
+        let reg = self.chr_rom_name_table(addr)?;
+        Some(chr_rom[(self.chr_regs[reg] * CHR_BANK_SIZE + (addr & (NAME_TABLE_PAGE_SIZE - 1))) % chr_rom.len()])
+    }
+
+    fn mapped_name_table_write(&mut self, _chr_rom: &mut Vec<u8>, addr: usize, _byte: u8) -> bool {
+        self.chr_rom_name_table(addr).is_some()
+    }
+
+    fn cpu_clock(&mut self) {
+        self.irq.clock();
+    }
+
+    fn expansion_audio_channels(&self) -> &'static [ExpansionChannel] {
+        VRC6_CHANNELS
+    }
+
+    fn clock_expansion_audio(&mut self, levels: &mut [f32]) {
+        self.audio.clock(levels)
+    }
+
+    fn mix_expansion_audio(&self, levels: &[f32]) -> f32 {
+        Vrc6Audio::mix(levels)
+    }
+
+    fn irq_active(&mut self) -> bool {
+        self.irq.active()
+    }
+
+    fn load_trainer(&mut self, trainer: &[u8]) -> bool {
+        copy_trainer(&mut self.prg_ram, trainer)
+    }
+
+    fn get_save_ram(&self) -> Option<&[u8]> {
+        if self.prg_ram.is_empty() { None } else { Some(&self.prg_ram) }
+    }
+
+    fn load_save_ram(&mut self, save_ram: &[u8]) -> bool {
+        self.prg_ram.copy_from_slice(save_ram);
+        !self.prg_ram.is_empty()
+    }
+}
+
+impl Mapper24 {
+    pub fn new(prg_rom_banks: usize, prg_ram_size: usize, swapped_lines: bool) -> Self {
+        Self {
+            prg_ram: vec![0; prg_ram_size],
+            prg_rom_banks,
+            swapped_lines,
+
+            prg_bank_16k: 0,
+            prg_bank_8k: 0,
+            chr_regs: [0; 8],
+            ppu_banking: 0,
+
+            irq: VrcIrq::new(),
+            audio: Vrc6Audio::new(),
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        !self.prg_ram.is_empty() && (self.ppu_banking & 0b10000000) != 0
+    }
+
+    // Returns the 1KB CHR bank for the address, in each of the four CHR modes
+    fn chr_bank(&self, addr: usize) -> usize {
+        // in 2KB banks, A10 either comes from the PPU or from bit 0 of the register
+        let bank_2k = |reg: usize| {
+            if self.ppu_banking & 0b00100000 != 0 {
+                (reg & !0x01) | ((addr >> 10) & 0x01)
+            } else {
+                reg
+            }
+        };
+
+        match (self.ppu_banking & 0x03, addr) {
+            (0, _) => self.chr_regs[addr >> 10],
+            (1, _) => bank_2k(self.chr_regs[addr >> 11]),
+            (_, 0x0000..=0x0FFF) => self.chr_regs[addr >> 10],
+            (_, _) => bank_2k(self.chr_regs[4 + ((addr >> 11) & 0x01)]),
+        }
+    }
+
+    // When enabled, nametables are read from the CHR-ROM banks in R6 and R7
+    fn chr_rom_name_table(&self, addr: usize) -> Option<usize> {
+        if self.ppu_banking & 0b00010000 == 0 {
+            return None;
+        }
+
+        let slot = (addr >> 10) & 0x03;
+
+        Some(match (self.ppu_banking >> 2) & 0x03 {
+            0 => 6 + (slot & 0x01),
+            1 => 6 + (slot >> 1),
+            2 => 6,
+            _ => 7,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mapper::TestRom;
+
+    #[test]
+    pub fn test_banking() {
+        for mapper_num in [24, 26] {
+            let mut cartridge = TestRom::ines(mapper_num, 0x20000, 0x8000)
+                .prg_banks(0x2000)
+                .chr_banks(0x400)
+                .cartridge();
+
+            // registers $x001 and $x002 trade places on VRC6b
+            let reg = |addr: usize| if mapper_num == 26 { (addr & !0x03) | ((addr & 0x01) << 1) | ((addr & 0x02) >> 1) } else { addr };
+
+            cartridge.cpu_write(0x8000, 3);
+            cartridge.cpu_write(0xC000, 9);
+            assert_eq!(cartridge.cpu_read(0x8000), Some(6));
+            assert_eq!(cartridge.cpu_read(0xA000), Some(7));
+            assert_eq!(cartridge.cpu_read(0xC000), Some(9));
+            assert_eq!(cartridge.cpu_read(0xE000), Some(15));
+
+            for i in 0..4 {
+                cartridge.cpu_write(reg(0xD000 + i), 20 + i as u8);
+                cartridge.cpu_write(reg(0xE000 + i), 24 + i as u8);
+            }
+            for slot in 0..8 {
+                assert_eq!(cartridge.ppu_read(slot * 0x400), 20 + slot as u8);
+            }
+
+            // 2KB banks still use 1KB bank numbers, with A10 from the PPU
+            cartridge.cpu_write(reg(0xB003), 0x21);
+            assert_eq!(cartridge.ppu_read(0x0800), 20);
+            assert_eq!(cartridge.ppu_read(0x0C00), 21);
+            assert_eq!(cartridge.ppu_read(0x1800), 22);
+        }
+    }
+
+    #[test]
+    pub fn test_prg_ram() {
+        let mut cartridge = TestRom::ines(24, 0x20000, 0x8000).prg_banks(0x2000).chr_banks(0x400).cartridge();
+
+        cartridge.cpu_write(0x6000, 0x42);
+        assert_ne!(cartridge.cpu_read(0x6000), Some(0x42));
+
+        cartridge.cpu_write(0xB003, 0x80);
+        cartridge.cpu_write(0x6000, 0x42);
+        assert_eq!(cartridge.cpu_read(0x6000), Some(0x42));
+    }
+
+    #[test]
+    pub fn test_cycle_irq() {
+        let mut cartridge = TestRom::ines(24, 0x20000, 0x8000).prg_banks(0x2000).chr_banks(0x400).cartridge();
+
+        // cycle mode, firing 16 cycles after being enabled
+        cartridge.cpu_write(0xF000, 0xF0);
+        cartridge.cpu_write(0xF001, 0b111);
+
+        for _ in 0..15 {
+            cartridge.cpu_clock();
+            assert!(!cartridge.irq_active());
+        }
+
+        cartridge.cpu_clock();
+        assert!(cartridge.irq_active());
+
+        // acknowledging keeps the IRQ enabled, and the counter is reloaded from the latch
+        cartridge.cpu_write(0xF002, 0);
+        assert!(!cartridge.irq_active());
+
+        for _ in 0..16 {
+            cartridge.cpu_clock();
+        }
+        assert!(cartridge.irq_active());
+    }
+
+    #[test]
+    pub fn test_scanline_irq() {
+        let mut cartridge = TestRom::ines(24, 0x20000, 0x8000).prg_banks(0x2000).chr_banks(0x400).cartridge();
+
+        // scanline mode, firing after 2 scanlines (of 341 / 3 CPU cycles each)
+        cartridge.cpu_write(0xF000, 0xFE);
+        cartridge.cpu_write(0xF001, 0b010);
+
+        for _ in 0..227 {
+            cartridge.cpu_clock();
+        }
+        assert!(!cartridge.irq_active());
+
+        cartridge.cpu_clock();
+        assert!(cartridge.irq_active());
+    }
+
+    #[test]
+    pub fn test_audio() {
+        let mut cartridge = TestRom::ines(24, 0x20000, 0x8000).prg_banks(0x2000).chr_banks(0x400).cartridge();
+        let mut levels = [0.0; 3];
+
+        // pulse 1 at volume 10 with a 50% duty cycle, and the sawtooth at its highest rate without overflowing
+        cartridge.cpu_write(0x9000, 0x7A);
+        cartridge.cpu_write(0x9001, 0x0F);
+        cartridge.cpu_write(0x9002, 0x80);
+        cartridge.cpu_write(0xB000, 42);
+        cartridge.cpu_write(0xB001, 0x00);
+        cartridge.cpu_write(0xB002, 0x80);
+
+        let mut pulse = Vec::new();
+        let mut sawtooth = Vec::new();
+
+        for _ in 0..0x200 {
+            cartridge.clock_expansion_audio(&mut levels);
+            pulse.push(levels[0]);
+            sawtooth.push(levels[2]);
+        }
+
+        assert!(pulse.iter().all(|&level| level == 0.0 || level == 10.0));
+        assert_eq!(pulse.iter().filter(|&&level| level == 10.0).count(), 0x100);
+        assert_eq!(sawtooth.iter().cloned().fold(0.0, f32::max), 31.0);
+
+        // halting freezes every channel where it is
+        cartridge.cpu_write(0x9003, 0x01);
+        cartridge.clock_expansion_audio(&mut levels);
+        let halted = levels;
+        cartridge.clock_expansion_audio(&mut levels);
+        assert_eq!(halted, levels);
+    }
+}
@@ -0,0 +1,303 @@
+use crate::apu::PULSE_TABLE;
+use crate::mapper::ExpansionChannel;
+use crate::{SystemControl, StateReader, StateWriter};
+
+pub const VRC6_CHANNELS: &[ExpansionChannel] = &[
+    ExpansionChannel { name: "VRC6 Pulse 1", min_level: 0.0, max_level: 15.0 },
+    ExpansionChannel { name: "VRC6 Pulse 2", min_level: 0.0, max_level: 15.0 },
+    ExpansionChannel { name: "VRC6 Sawtooth", min_level: 0.0, max_level: 31.0 },
+];
+
+// The sawtooth's accumulator resets after being clocked this many times
+const SAW_STEPS: u8 = 14;
+
+/// Pulse with a 16 step sequencer and 8 possible duty cycles
+struct PulseVrc6 {
+    volume: u8,
+    duty: u8,
+    // ignores the duty cycle and outputs its volume constantly, for playing samples
+    digitized: bool,
+    enabled: bool,
+
+    period: u16,
+    cycles: u16,
+    duty_step: u8,
+}
+
+impl SystemControl for PulseVrc6 {
+    fn reset(&mut self) {
+        self.volume = 0;
+        self.duty = 0;
+        self.digitized = false;
+        self.enabled = false;
+        self.period = 0;
+        self.cycles = 0;
+        self.duty_step = 0;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.volume);
+        state.write_u8(self.duty);
+        state.write_bool(self.digitized);
+        state.write_bool(self.enabled);
+        state.write_u16(self.period);
+        state.write_u16(self.cycles);
+        state.write_u8(self.duty_step);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.volume = state.read_u8()? & 0x0F;
+        self.duty = state.read_u8()? & 0x07;
+        self.digitized = state.read_bool()?;
+        self.enabled = state.read_bool()?;
+        self.period = state.read_u16()? & 0x0FFF;
+        self.cycles = state.read_u16()? & 0x0FFF;
+        self.duty_step = state.read_u8()? & 0x0F;
+        Ok(())
+    }
+}
+
+impl PulseVrc6 {
+    fn new() -> Self {
+        Self {
+            volume: 0,
+            duty: 0,
+            digitized: false,
+            enabled: false,
+
+            period: 0,
+            cycles: 0,
+            duty_step: 0,
+        }
+    }
+
+    fn clock(&mut self, period_shift: u8) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.cycles == 0 {
+            self.cycles = self.period >> period_shift;
+            self.duty_step = (self.duty_step + 1) & 0x0F;
+        } else {
+            self.cycles -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.digitized || self.duty_step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+
+    fn write_register(&mut self, reg: usize, byte: u8) {
+        match reg {
+            0 => {
+                self.digitized = (byte & 0b10000000) != 0;
+                self.duty = (byte & 0b01110000) >> 4;
+                self.volume = byte & 0b00001111;
+            }
+            1 => self.period = (self.period & 0x0F00) | byte as u16,
+            2 => {
+                self.period = (self.period & 0x00FF) | (((byte & 0x0F) as u16) << 8);
+                self.enabled = (byte & 0b10000000) != 0;
+
+                if !self.enabled {
+                    self.duty_step = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Sawtooth made by repeatedly adding a rate to an accumulator, whose top 5 bits are output
+struct SawtoothVrc6 {
+    rate: u8,
+    enabled: bool,
+
+    period: u16,
+    cycles: u16,
+    step: u8,
+    accumulator: u8,
+}
+
+impl SystemControl for SawtoothVrc6 {
+    fn reset(&mut self) {
+        self.rate = 0;
+        self.enabled = false;
+        self.period = 0;
+        self.cycles = 0;
+        self.step = 0;
+        self.accumulator = 0;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.rate);
+        state.write_bool(self.enabled);
+        state.write_u16(self.period);
+        state.write_u16(self.cycles);
+        state.write_u8(self.step);
+        state.write_u8(self.accumulator);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.rate = state.read_u8()? & 0x3F;
+        self.enabled = state.read_bool()?;
+        self.period = state.read_u16()? & 0x0FFF;
+        self.cycles = state.read_u16()? & 0x0FFF;
+        self.step = state.read_u8()? % SAW_STEPS;
+        self.accumulator = state.read_u8()?;
+        Ok(())
+    }
+}
+
+impl SawtoothVrc6 {
+    fn new() -> Self {
+        Self {
+            rate: 0,
+            enabled: false,
+
+            period: 0,
+            cycles: 0,
+            step: 0,
+            accumulator: 0,
+        }
+    }
+
+    // the rate is only added on every other step, and the accumulator resets in place of the 7th addition
+    fn clock(&mut self, period_shift: u8) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.cycles == 0 {
+            self.cycles = self.period >> period_shift;
+
+            self.step += 1;
+
+            if self.step == SAW_STEPS {
+                self.step = 0;
+                self.accumulator = 0;
+            } else if self.step & 0x01 == 0 {
+                self.accumulator = self.accumulator.wrapping_add(self.rate);
+            }
+        } else {
+            self.cycles -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled { self.accumulator >> 3 } else { 0 }
+    }
+
+    fn write_register(&mut self, reg: usize, byte: u8) {
+        match reg {
+            0 => self.rate = byte & 0b00111111,
+            1 => self.period = (self.period & 0x0F00) | byte as u16,
+            2 => {
+                self.period = (self.period & 0x00FF) | (((byte & 0x0F) as u16) << 8);
+                self.enabled = (byte & 0b10000000) != 0;
+
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// VRC6's expansion audio: two pulse channels and a sawtooth, all clocked directly by the CPU
+pub struct Vrc6Audio {
+    pulse1: PulseVrc6,
+    pulse2: PulseVrc6,
+    sawtooth: SawtoothVrc6,
+
+    halted: bool,
+    period_shift: u8,
+}
+
+impl SystemControl for Vrc6Audio {
+    fn reset(&mut self) {
+        self.pulse1.reset();
+        self.pulse2.reset();
+        self.sawtooth.reset();
+        self.halted = false;
+        self.period_shift = 0;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.pulse1.save_state(state);
+        self.pulse2.save_state(state);
+        self.sawtooth.save_state(state);
+        state.write_bool(self.halted);
+        state.write_u8(self.period_shift);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.pulse1.load_state(state)?;
+        self.pulse2.load_state(state)?;
+        self.sawtooth.load_state(state)?;
+        self.halted = state.read_bool()?;
+        self.period_shift = match state.read_u8()? {
+            shift @ (0 | 4 | 8) => shift,
+            n => return Err(format!("Invalid VRC6 period shift {} in save state", n)),
+        };
+        Ok(())
+    }
+}
+
+impl Vrc6Audio {
+    pub fn new() -> Self {
+        Self {
+            pulse1: PulseVrc6::new(),
+            pulse2: PulseVrc6::new(),
+            sawtooth: SawtoothVrc6::new(),
+
+            halted: false,
+            period_shift: 0,
+        }
+    }
+
+    /// Clocked once per CPU cycle, writing the output level of each channel in `VRC6_CHANNELS` to `levels`
+    pub fn clock(&mut self, levels: &mut [f32]) {
+        if !self.halted {
+            self.pulse1.clock(self.period_shift);
+            self.pulse2.clock(self.period_shift);
+            self.sawtooth.clock(self.period_shift);
+        }
+
+        levels[0] = self.pulse1.output() as f32;
+        levels[1] = self.pulse2.output() as f32;
+        levels[2] = self.sawtooth.output() as f32;
+    }
+
+    // VRC6 mixes its channels linearly, with a full volume pulse about as loud as one of the APU's
+    pub fn mix(levels: &[f32]) -> f32 {
+        (levels[0] + levels[1] + levels[2]) * PULSE_TABLE[15] / 15.0
+    }
+
+    /// `reg` is the register's address after any address line swapping for VRC6b
+    pub fn write_register(&mut self, reg: usize, byte: u8) {
+        match reg {
+            0x9000..=0x9002 => self.pulse1.write_register(reg & 0x03, byte),
+            0x9003 => {
+                self.halted = (byte & 0b001) != 0;
+                self.period_shift = if byte & 0b100 != 0 {
+                    8
+                } else if byte & 0b010 != 0 {
+                    4
+                } else {
+                    0
+                };
+            }
+            0xA000..=0xA002 => self.pulse2.write_register(reg & 0x03, byte),
+            0xB000..=0xB002 => self.sawtooth.write_register(reg & 0x03, byte),
+            _ => {}
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{cartridge::{CHR_ROM_SIZE, PRG_ROM_SIZE}, SystemControl};
+use crate::{cartridge::{CHR_ROM_SIZE, PRG_ROM_SIZE}, SystemControl, StateReader, StateWriter};
 
 use super::{Mapper, PRG_ROM_END, PRG_ROM_START};
 
@@ -11,10 +11,20 @@ impl SystemControl for Mapper3 {
     fn reset(&mut self) { 
         self.chr_bank_select = 0;
     }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_usize(self.chr_bank_select);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.chr_bank_select = state.read_usize()? & 0x07;
+
+        Ok(())
+    }
 }
 
 impl Mapper for Mapper3 {
-    fn mapped_cpu_read(&self, prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8> {
+    fn mapped_cpu_read(&mut self, prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8> {
         match addr {
             PRG_ROM_START..=PRG_ROM_END => {
                 let addr = addr - PRG_ROM_START;
@@ -41,7 +51,12 @@ impl Mapper for Mapper3 {
     }
 
     fn mapped_ppu_read(&self, chr_rom: &Vec<u8>, addr: usize) -> u8 {
-        chr_rom[self.chr_bank_select * CHR_ROM_SIZE + addr]
+        chr_rom[(self.chr_bank_select * CHR_ROM_SIZE + addr) % chr_rom.len()]
+    }
+
+    fn mapped_ppu_write(&mut self, chr_ram: &mut Vec<u8>, addr: usize, byte: u8) {
+        let len = chr_ram.len();
+        chr_ram[(self.chr_bank_select * CHR_ROM_SIZE + addr) % len] = byte;
     }
 }
 
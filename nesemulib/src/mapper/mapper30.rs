@@ -0,0 +1,199 @@
+mod flash;
+
+use crate::{cartridge::{Mirroring, CHR_ROM_SIZE, PRG_ROM_SIZE}, SystemControl, StateReader, StateWriter};
+
+use self::flash::Sst39sf040;
+use super::{Mapper, PRG_ROM_END, PRG_ROM_HI_END, PRG_ROM_HI_START, PRG_ROM_LO_END, PRG_ROM_LO_START, PRG_ROM_START};
+
+/// RetroUSB's UNROM 512, used by most homebrew released on cartridge: UxROM-style PRG banking
+/// with a bank register of MCCP PPPP, switching 4 banks of CHR-RAM and, optionally, one-screen mirroring.
+/// The flashable version lets games save by rewriting their own PRG-ROM
+pub struct Mapper30 {
+    prg_rom_banks: usize,
+    prg_bank: usize,
+    chr_bank: usize,
+    // None unless the board has one-screen mirroring
+    mirroring: Option<Mirroring>,
+    // Writes to $8000-$BFFF go to the flash on flashable boards, with the bank register only at $C000-$FFFF
+    flash: Option<Sst39sf040>,
+}
+
+impl SystemControl for Mapper30 {
+    fn reset(&mut self) {
+        self.prg_bank = 0;
+        self.chr_bank = 0;
+        if self.mirroring.is_some() {
+            self.mirroring = Some(Mirroring::ONESCREEN_LO);
+        }
+        if let Some(flash) = &mut self.flash {
+            flash.reset();
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_usize(self.prg_bank);
+        state.write_usize(self.chr_bank);
+        if let Some(mirroring) = &self.mirroring {
+            mirroring.save_state(state);
+        }
+        if let Some(flash) = &self.flash {
+            flash.save_state(state);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.prg_bank = state.read_usize()? & 0x1F;
+        self.chr_bank = state.read_usize()? & 0x03;
+        if self.mirroring.is_some() {
+            self.mirroring = Some(Mirroring::load_state(state)?);
+        }
+        if let Some(flash) = &mut self.flash {
+            flash.load_state(state)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Mapper for Mapper30 {
+    fn mapped_cpu_read(&mut self, prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8> {
+        let bank = match addr {
+            PRG_ROM_LO_START..=PRG_ROM_LO_END => self.prg_bank,
+            PRG_ROM_HI_START..=PRG_ROM_HI_END => self.prg_rom_banks - 1,
+            _ => return None
+        };
+
+        let addr = bank * PRG_ROM_SIZE + (addr & 0x3FFF);
+
+        Some(match &self.flash {
+            Some(flash) => flash.read(prg_rom, addr),
+            None => prg_rom[addr % prg_rom.len()],
+        })
+    }
+
+    fn mapped_cpu_write(&mut self, prg_rom: &mut Vec<u8>, addr: usize, byte: u8) -> bool {
+        if !(PRG_ROM_START..=PRG_ROM_END).contains(&addr) {
+            return false;
+        }
+
+        if let (Some(flash), PRG_ROM_LO_START..=PRG_ROM_LO_END) = (&mut self.flash, addr) {
+            flash.write(prg_rom, self.prg_bank * PRG_ROM_SIZE + (addr & 0x3FFF), byte);
+            return true;
+        }
+
+        self.prg_bank = (byte & 0b00011111) as usize;
+        self.chr_bank = ((byte & 0b01100000) >> 5) as usize;
+        if self.mirroring.is_some() {
+            self.mirroring = Some(if (byte & 0b10000000) != 0 { Mirroring::ONESCREEN_HI } else { Mirroring::ONESCREEN_LO });
+        }
+
+        true
+    }
+
+    fn mapped_ppu_read(&self, chr_rom: &Vec<u8>, addr: usize) -> u8 {
+        chr_rom[(self.chr_bank * CHR_ROM_SIZE + addr) % chr_rom.len()]
+    }
+
+    fn mapped_ppu_write(&mut self, chr_ram: &mut Vec<u8>, addr: usize, byte: u8) {
+        let len = chr_ram.len();
+        chr_ram[(self.chr_bank * CHR_ROM_SIZE + addr) % len] = byte;
+    }
+
+    fn get_updated_mirroring(&self) -> Option<Mirroring> {
+        self.mirroring
+    }
+
+    fn prg_rom_writable(&self) -> bool {
+        self.flash.is_some()
+    }
+}
+
+impl Mapper30 {
+    /// Boards with one-screen mirroring are given `Mirroring::ONESCREEN_LO` by the cartridge,
+    /// and flashable boards are marked as battery-backed
+    pub fn new(prg_rom_banks: usize, mirroring: Mirroring, flashable: bool) -> Self {
+        Self {
+            prg_rom_banks,
+            prg_bank: 0,
+            chr_bank: 0,
+            mirroring: match mirroring {
+                Mirroring::ONESCREEN_LO => Some(Mirroring::ONESCREEN_LO),
+                _ => None,
+            },
+            flash: if flashable { Some(Sst39sf040::new()) } else { None },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::{CartridgeNes, Mirroring};
+    use crate::mapper::TestRom;
+
+    // Commands are written to $5555 and $2AAA in the flash, through banks 1 and 0 at $8000-$BFFF
+    fn flash_command(cartridge: &mut CartridgeNes, command: u8) {
+        for (bank, addr, byte) in [(1, 0x9555, 0xAA), (0, 0xAAAA, 0x55), (1, 0x9555, command)] {
+            cartridge.cpu_write(0xC000, bank);
+            cartridge.cpu_write(addr, byte);
+        }
+    }
+
+    #[test]
+    pub fn test_unrom_512_banks() {
+        let mut cartridge = TestRom::ines(30, 0x80000, 0).prg_banks(0x4000).flags_6(0x08).cartridge();
+
+        cartridge.cpu_write(0x8000, 0xE5);
+        assert_eq!(cartridge.cpu_read(0x8000), Some(5));
+        assert_eq!(cartridge.cpu_read(0xC000), Some(31));
+        assert!(matches!(cartridge.mirroring(), Mirroring::ONESCREEN_HI));
+
+        // 32KB of CHR-RAM in four banks
+        cartridge.ppu_write(0x0000, 0x42);
+        cartridge.cpu_write(0x8000, 0x00);
+        assert_eq!(cartridge.ppu_read(0x0000), 0x00);
+        assert!(matches!(cartridge.mirroring(), Mirroring::ONESCREEN_LO));
+        cartridge.cpu_write(0x8000, 0x60);
+        assert_eq!(cartridge.ppu_read(0x0000), 0x42);
+
+        // without the flash, the whole of $8000-$FFFF is the bank register
+        assert!(cartridge.get_save_ram().is_none());
+    }
+
+    #[test]
+    pub fn test_unrom_512_flash() {
+        let mut cartridge = TestRom::ines(30, 0x80000, 0).prg_banks(0x4000)
+            .flags_6(0x0B)
+            .cartridge();
+        assert!(matches!(cartridge.mirroring(), Mirroring::FOUR_SCREEN));
+
+        flash_command(&mut cartridge, 0x90);
+        assert_eq!(cartridge.cpu_read(0x8000), Some(0xBF));
+        assert_eq!(cartridge.cpu_read(0x8001), Some(0xB7));
+        cartridge.cpu_write(0x8000, 0xF0);
+
+        // erase the sector at the start of bank 3, then program a byte in it
+        flash_command(&mut cartridge, 0x80);
+        cartridge.cpu_write(0xC000, 1);
+        cartridge.cpu_write(0x9555, 0xAA);
+        cartridge.cpu_write(0xC000, 0);
+        cartridge.cpu_write(0xAAAA, 0x55);
+        cartridge.cpu_write(0xC000, 3);
+        cartridge.cpu_write(0x8000, 0x30);
+        assert_eq!(cartridge.cpu_read(0x8000), Some(0xFF));
+        assert_eq!(cartridge.cpu_read(0x9000), Some(0x00));
+
+        flash_command(&mut cartridge, 0xA0);
+        cartridge.cpu_write(0xC000, 3);
+        cartridge.cpu_write(0x8000, 0x5A);
+        assert_eq!(cartridge.cpu_read(0x8000), Some(0x5A));
+
+        // writes outside of a command don't change the flash
+        cartridge.cpu_write(0x8000, 0x00);
+        assert_eq!(cartridge.cpu_read(0x8000), Some(0x5A));
+
+        // the flash is saved as the whole of PRG-ROM
+        let save = cartridge.get_save_ram().unwrap();
+        assert_eq!(save.len(), 0x80000);
+        assert_eq!(save[3 * 0x4000], 0x5A);
+    }
+}
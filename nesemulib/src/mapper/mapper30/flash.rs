@@ -0,0 +1,116 @@
+use crate::{SystemControl, StateReader, StateWriter};
+
+// Commands are only accepted after writing $AA to $5555 then $55 to $2AAA
+const UNLOCK_ADDR_1: usize = 0x5555;
+const UNLOCK_ADDR_2: usize = 0x2AAA;
+
+const SECTOR_SIZE: usize = 0x1000;
+
+const MANUFACTURER_ID: u8 = 0xBF;
+const DEVICE_ID: u8 = 0xB7;
+
+/// Progress through a command sequence
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FlashState {
+    READ,
+    UNLOCK_1,
+    UNLOCK_2,
+    /// The next write programs a byte
+    PROGRAM,
+    ERASE,
+    ERASE_UNLOCK_1,
+    ERASE_UNLOCK_2,
+}
+
+/// The SST39SF040 flash chip, which holds up to 512KB of PRG-ROM that the game can rewrite itself.
+/// Programming and erasing finish instantly, so polling for completion always succeeds straight away
+pub struct Sst39sf040 {
+    state: FlashState,
+    // Reads return the chip's ID instead of its contents
+    software_id: bool,
+}
+
+impl SystemControl for Sst39sf040 {
+    fn reset(&mut self) {
+        self.state = FlashState::READ;
+        self.software_id = false;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.state as u8);
+        state.write_bool(self.software_id);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.state = match state.read_u8()? {
+            0 => FlashState::READ,
+            1 => FlashState::UNLOCK_1,
+            2 => FlashState::UNLOCK_2,
+            3 => FlashState::PROGRAM,
+            4 => FlashState::ERASE,
+            5 => FlashState::ERASE_UNLOCK_1,
+            6 => FlashState::ERASE_UNLOCK_2,
+            n => return Err(format!("Invalid flash state {} in save state", n)),
+        };
+        self.software_id = state.read_bool()?;
+
+        Ok(())
+    }
+}
+
+impl Sst39sf040 {
+    pub fn new() -> Self {
+        Self {
+            state: FlashState::READ,
+            software_id: false,
+        }
+    }
+
+    /// `addr` is the address within the whole chip
+    pub fn read(&self, data: &[u8], addr: usize) -> u8 {
+        if self.software_id {
+            return if addr & 0x01 == 0 { MANUFACTURER_ID } else { DEVICE_ID };
+        }
+
+        data[addr % data.len()]
+    }
+
+    /// Programming can only clear bits, so erasing (which sets every bit) must come first
+    pub fn write(&mut self, data: &mut [u8], addr: usize, byte: u8) {
+        let command_addr = addr & 0x7FFF;
+
+        self.state = match (self.state, command_addr, byte) {
+            (FlashState::PROGRAM, _, _) => {
+                let len = data.len();
+                data[addr % len] &= byte;
+                FlashState::READ
+            }
+            // $F0 leaves software ID mode, and aborts any command
+            (_, _, 0xF0) => {
+                self.software_id = false;
+                FlashState::READ
+            }
+            (FlashState::READ, UNLOCK_ADDR_1, 0xAA) => FlashState::UNLOCK_1,
+            (FlashState::UNLOCK_1, UNLOCK_ADDR_2, 0x55) => FlashState::UNLOCK_2,
+            (FlashState::UNLOCK_2, UNLOCK_ADDR_1, 0xA0) => FlashState::PROGRAM,
+            (FlashState::UNLOCK_2, UNLOCK_ADDR_1, 0x80) => FlashState::ERASE,
+            (FlashState::UNLOCK_2, UNLOCK_ADDR_1, 0x90) => {
+                self.software_id = true;
+                FlashState::READ
+            }
+            (FlashState::ERASE, UNLOCK_ADDR_1, 0xAA) => FlashState::ERASE_UNLOCK_1,
+            (FlashState::ERASE_UNLOCK_1, UNLOCK_ADDR_2, 0x55) => FlashState::ERASE_UNLOCK_2,
+            (FlashState::ERASE_UNLOCK_2, UNLOCK_ADDR_1, 0x10) => {
+                data.fill(0xFF);
+                FlashState::READ
+            }
+            (FlashState::ERASE_UNLOCK_2, _, 0x30) => {
+                let sector = (addr % data.len()) & !(SECTOR_SIZE - 1);
+                data[sector..sector + SECTOR_SIZE].fill(0xFF);
+                FlashState::READ
+            }
+            _ => FlashState::READ
+        };
+    }
+}
@@ -0,0 +1,178 @@
+use crate::{cartridge::{CHR_ROM_SIZE, PRG_ROM_SIZE}, SystemControl, StateReader, StateWriter};
+
+use super::{copy_trainer, Mapper, CHR_ROM_HI_START, CHR_ROM_LO_END, CHR_ROM_LO_START, PRG_RAM_BANK_SIZE, PRG_RAM_END, PRG_RAM_START, PRG_ROM_END, PRG_ROM_START};
+
+// NINA-001's registers overlap the last three bytes of its PRG-RAM
+const NINA_PRG_SELECT: usize = 0x7FFD;
+const NINA_CHR_LO_SELECT: usize = 0x7FFE;
+const NINA_CHR_HI_SELECT: usize = 0x7FFF;
+
+/// Two unrelated boards that share mapper 34: BNROM, which only switches 32KB of PRG-ROM,
+/// and NINA-001, which also switches two 4KB CHR-ROM banks and has 8KB of PRG-RAM
+pub struct Mapper34 {
+    nina_001: bool,
+    prg_ram: Vec<u8>,
+    prg_rom_select: usize,
+    chr_rom_select: [usize; 2],
+}
+
+impl SystemControl for Mapper34 {
+    fn reset(&mut self) {
+        self.prg_rom_select = 0;
+        self.chr_rom_select = [0, 1];
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+        state.write_usize(self.prg_rom_select);
+        for bank in &self.chr_rom_select {
+            state.write_usize(*bank);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.read_bytes(&mut self.prg_ram)?;
+        self.prg_rom_select = state.read_usize()? & 0xFF;
+        for bank in &mut self.chr_rom_select {
+            *bank = state.read_usize()? & 0x0F;
+        }
+
+        Ok(())
+    }
+}
+
+impl Mapper for Mapper34 {
+    fn mapped_cpu_read(&mut self, prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8> {
+        match addr {
+            PRG_RAM_START..=PRG_RAM_END if !self.prg_ram.is_empty() => {
+                Some(self.prg_ram[addr - PRG_RAM_START])
+            }
+            PRG_ROM_START..=PRG_ROM_END => {
+                Some(prg_rom[(self.prg_rom_select * (PRG_ROM_SIZE << 1) + (addr & 0x7FFF)) % prg_rom.len()])
+            }
+            _ => None
+        }
+    }
+
+    fn mapped_cpu_write(&mut self, _prg_rom: &mut Vec<u8>, addr: usize, byte: u8) -> bool {
+        if !self.nina_001 {
+            return match addr {
+                PRG_ROM_START..=PRG_ROM_END => {
+                    self.prg_rom_select = byte as usize;
+                    true
+                }
+                _ => false
+            };
+        }
+
+        match addr {
+            PRG_RAM_START..=PRG_RAM_END => {
+                // the registers are written through to the RAM underneath them
+                self.prg_ram[addr - PRG_RAM_START] = byte;
+
+                match addr {
+                    NINA_PRG_SELECT => self.prg_rom_select = (byte & 0b00000001) as usize,
+                    NINA_CHR_LO_SELECT => self.chr_rom_select[0] = (byte & 0b00001111) as usize,
+                    NINA_CHR_HI_SELECT => self.chr_rom_select[1] = (byte & 0b00001111) as usize,
+                    _ => {}
+                }
+                true
+            }
+            _ => false
+        }
+    }
+
+    fn mapped_ppu_read(&self, chr_rom: &Vec<u8>, addr: usize) -> u8 {
+        if !self.nina_001 {
+            return chr_rom[addr % chr_rom.len()];
+        }
+
+        let bank = match addr {
+            CHR_ROM_LO_START..=CHR_ROM_LO_END => self.chr_rom_select[0],
+            _ => self.chr_rom_select[1],
+        };
+
+        chr_rom[(bank * (CHR_ROM_SIZE >> 1) + (addr & (CHR_ROM_HI_START - 1))) % chr_rom.len()]
+    }
+
+    fn mapped_ppu_write(&mut self, chr_ram: &mut Vec<u8>, addr: usize, byte: u8) {
+        let len = chr_ram.len();
+        chr_ram[addr % len] = byte;
+    }
+
+    fn load_trainer(&mut self, trainer: &[u8]) -> bool {
+        copy_trainer(&mut self.prg_ram, trainer)
+    }
+
+    fn get_save_ram(&self) -> Option<&[u8]> {
+        if self.prg_ram.is_empty() { None } else { Some(&self.prg_ram) }
+    }
+
+    fn load_save_ram(&mut self, save_ram: &[u8]) -> bool {
+        self.prg_ram.copy_from_slice(save_ram);
+        !self.prg_ram.is_empty()
+    }
+}
+
+impl Mapper34 {
+    /// Submapper 1 is NINA-001 and submapper 2 is BNROM; otherwise, only NINA-001 has more than 8KB of CHR-ROM
+    pub fn new(submapper_num: u8, chr_rom_banks: usize) -> Self {
+        let nina_001 = match submapper_num {
+            1 => true,
+            2 => false,
+            _ => chr_rom_banks > 1,
+        };
+
+        Self {
+            nina_001,
+            prg_ram: if nina_001 { vec![0; PRG_RAM_BANK_SIZE] } else { Vec::new() },
+            prg_rom_select: 0,
+            chr_rom_select: [0, 1],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mapper::TestRom;
+
+    #[test]
+    pub fn test_bnrom() {
+        let mut cartridge = TestRom::nes2(34, 0, 0x20000, 0).prg_banks(0x8000).cartridge();
+
+        assert!(!cartridge.cpu_write(0x7FFD, 1));
+        cartridge.cpu_write(0x8000, 3);
+        assert_eq!(cartridge.cpu_read(0x8000), Some(3));
+
+        cartridge.ppu_write(0x1000, 0xAB);
+        assert_eq!(cartridge.ppu_read(0x1000), 0xAB);
+    }
+
+    #[test]
+    pub fn test_nina_001() {
+        let mut cartridge = TestRom::nes2(34, 0, 0x20000, 0x8000)
+            .prg_banks(0x8000)
+            .chr_banks(0x1000)
+            .cartridge();
+
+        // both CHR banks start out mapped in order
+        assert_eq!(cartridge.ppu_read(0x0000), 0);
+        assert_eq!(cartridge.ppu_read(0x1000), 1);
+
+        cartridge.cpu_write(0x7FFD, 1);
+        cartridge.cpu_write(0x7FFE, 5);
+        cartridge.cpu_write(0x7FFF, 2);
+        assert_eq!(cartridge.cpu_read(0x8000), Some(1));
+        assert_eq!(cartridge.ppu_read(0x0000), 5);
+        assert_eq!(cartridge.ppu_read(0x1000), 2);
+
+        // the registers don't hide the PRG-RAM under them
+        assert_eq!(cartridge.cpu_read(0x7FFE), Some(5));
+        cartridge.cpu_write(0x6000, 0x42);
+        assert_eq!(cartridge.cpu_read(0x6000), Some(0x42));
+
+        // writes to ROM don't switch banks
+        assert!(!cartridge.cpu_write(0x8000, 0));
+        assert_eq!(cartridge.cpu_read(0x8000), Some(1));
+    }
+}
@@ -1,9 +1,56 @@
-use crate::{cartridge::{Mirroring, CHR_ROM_SIZE, PRG_ROM_SIZE}, SystemControl};
+use crate::{cartridge::{Mirroring, NameTableSource, CHR_ROM_SIZE, PRG_ROM_SIZE}, SystemControl, StateReader, StateWriter};
 
-use super::{Mapper, PRG_ROM_END, PRG_ROM_HI_END, PRG_ROM_HI_START, PRG_ROM_LO_END, PRG_ROM_LO_START, PRG_ROM_START, SAVE_RAM_END, SAVE_RAM_SIZE, SAVE_RAM_START};
+use super::{copy_trainer, Mapper, PRG_ROM_END, PRG_ROM_HI_END, PRG_ROM_HI_START, PRG_ROM_LO_END, PRG_ROM_LO_START, PRG_ROM_START, PRG_RAM_END, PRG_RAM_START};
+
+/// MMC3 and its predecessor, the Namco 108, along with the boards that wire their CHR lines differently.
+/// The Namco 108 only has the bank select and bank data registers at $8000-$9FFF, without PRG mode or CHR inversion
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mmc3Board {
+    /// TxROM (mapper 4)
+    MMC3,
+    /// The MMC3A, whose IRQ counter only fires when it's decremented to 0 or reloaded by a write to $C001 (mapper 4.4)
+    MMC3_REV_A,
+    /// Has 1KB of internal PRG-RAM at $7000-$7FFF, whose two halves are protected separately,
+    /// and the same IRQ counter as the MMC3A (mapper 4.1)
+    MMC6,
+    /// Acclaim's MC-ACC, whose IRQ counter is clocked by every 8th fall of A12 instead of by its filtered rises (mapper 4.3)
+    MMC3_ACCLAIM,
+    /// Bit 7 of the CHR bank mapped into each 1KB of $0000-$0FFF selects the CIRAM page of the matching nametable (mapper 118)
+    TXSROM,
+    /// Bit 6 of each CHR bank selects 8KB of CHR-RAM instead of CHR-ROM (mapper 119)
+    TQROM,
+    /// Namco 108 on DxROM and Namco 118 boards (mapper 206)
+    NAMCO_108,
+    /// Uses R2-R5 as four 2KB CHR banks, for up to 128KB of CHR-ROM (mapper 76)
+    NAMCO_3446,
+    /// R0 and R1 can only reach the first 64KB of CHR-ROM, and R2-R5 only the second (mapper 88)
+    NAMCO_3433,
+    /// Bit 5 of R0 and R1 selects the CIRAM page of the top and bottom nametables (mapper 95)
+    NAMCO_3425,
+    /// Like Namco 3433, but bit 6 of any write to $8000-$FFFF selects the one-screen page (mapper 154)
+    NAMCO_3453,
+}
+
+// The MMC6's PRG-RAM is mirrored across $7000-$7FFF, with $6000-$6FFF left as open bus
+const MMC6_PRG_RAM_SIZE: usize = 0x400;
+const MMC6_PRG_RAM_START: usize = 0x7000;
+
+// A12 has to stay low for this many CPU cycles before it rises again to clock the IRQ counter. This filters out
+// the rises between background tiles fetched from $1000-$1FFF, which are at most 9 dots (3 CPU cycles) apart
+const A12_FILTER_CYCLES: u8 = 4;
+
+// The MC-ACC divides the falls of A12 by 8, so the 8 sprites fetched from $1000 each scanline clock the counter once
+const ACCLAIM_A12_FALLS: u8 = 8;
+
+const TQROM_CHR_RAM_SIZE: usize = 0x2000;
+const TQROM_CHR_RAM_BANK: usize = 0b01000000;
 
 pub struct Mapper4 {
-    save_ram: [u8; SAVE_RAM_SIZE],
+    board: Mmc3Board,
+    prg_ram: Vec<u8>,
+    // only TQROM has CHR-RAM alongside its CHR-ROM
+    chr_ram: Vec<u8>,
     mirroring: Mirroring,
     prg_rom_banks: usize,
 
@@ -20,11 +67,22 @@ pub struct Mapper4 {
     irq_active: bool,
     irq_enable: bool,
     irq_update: bool,
+    a12_high: bool,
+    a12_low_cycles: u8,
+    // MC-ACC only: falls of A12 since the IRQ counter was last clocked
+    a12_falls: u8,
+
+    // MMC6 only: PRG-RAM is enabled through bit 5 of $8000, then each half is protected through $A001
+    prg_ram_enable: bool,
+    prg_ram_protect: u8,
 }
 
 impl SystemControl for Mapper4 {
     fn reset(&mut self) {
-        self.mirroring = Mirroring::HORIZONTAL;
+        self.mirroring = match self.board {
+            Mmc3Board::NAMCO_3453 => Mirroring::ONESCREEN_LO,
+            _ => Mirroring::HORIZONTAL,
+        };
 
         self.prg_bank_offset[0] = 0;
         self.prg_bank_offset[1] = PRG_ROM_SIZE >> 1;
@@ -36,24 +94,86 @@ impl SystemControl for Mapper4 {
         self.chr_inversion = false;
         self.registers = [0; 8];
         self.target_register = 0;
+        self.update_banks();
 
         self.irq_counter = 0;
         self.irq_reload = 0;
         self.irq_active = false;
         self.irq_enable = false;
         self.irq_update = false;
+        self.a12_high = false;
+        self.a12_low_cycles = 0;
+        self.a12_falls = 0;
+
+        self.prg_ram_enable = false;
+        self.prg_ram_protect = 0;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+        state.write_bytes(&self.chr_ram);
+        self.mirroring.save_state(state);
+
+        state.write_bool(self.prg_mode);
+        state.write_bool(self.chr_inversion);
+        for value in &self.registers {
+            state.write_usize(*value);
+        }
+        state.write_usize(self.target_register);
+
+        state.write_u16(self.irq_counter);
+        state.write_u16(self.irq_reload);
+        state.write_bool(self.irq_active);
+        state.write_bool(self.irq_enable);
+        state.write_bool(self.irq_update);
+        state.write_bool(self.a12_high);
+        state.write_u8(self.a12_low_cycles);
+        state.write_u8(self.a12_falls);
+
+        state.write_bool(self.prg_ram_enable);
+        state.write_u8(self.prg_ram_protect);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.read_bytes(&mut self.prg_ram)?;
+        state.read_bytes(&mut self.chr_ram)?;
+        self.mirroring = Mirroring::load_state(state)?;
+
+        // the bank offsets aren't saved, as they're worked out from the registers
+        self.prg_mode = state.read_bool()?;
+        self.chr_inversion = state.read_bool()?;
+        for value in &mut self.registers {
+            *value = state.read_usize()? & 0xFF;
+        }
+        self.target_register = state.read_usize()? & 0x07;
+        self.update_banks();
+
+        self.irq_counter = state.read_u16()?;
+        self.irq_reload = state.read_u16()?;
+        self.irq_active = state.read_bool()?;
+        self.irq_enable = state.read_bool()?;
+        self.irq_update = state.read_bool()?;
+        self.a12_high = state.read_bool()?;
+        self.a12_low_cycles = state.read_u8()?;
+        self.a12_falls = state.read_u8()? % ACCLAIM_A12_FALLS;
+
+        self.prg_ram_enable = state.read_bool()?;
+        self.prg_ram_protect = state.read_u8()?;
+
+        Ok(())
     }
 }
 
 impl Mapper for Mapper4 {
-    fn mapped_cpu_read(&self, prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8> {
+    fn mapped_cpu_read(&mut self, prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8> {
         match addr {
-            SAVE_RAM_START..=SAVE_RAM_END => {
-                Some(self.save_ram[addr & 0x1FFF])
+            PRG_RAM_START..=PRG_RAM_END if self.board == Mmc3Board::MMC6 => self.mmc6_ram_read(addr),
+            PRG_RAM_START..=PRG_RAM_END if !self.prg_ram.is_empty() => {
+                Some(self.prg_ram[(addr & 0x1FFF) % self.prg_ram.len()])
             },
             PRG_ROM_START..=PRG_ROM_END => {
                 let bank_index = (addr & 0x6000) >> 13;
-                Some(prg_rom[self.prg_bank_offset[bank_index] + (addr & 0x1FFF)])
+                Some(prg_rom[(self.prg_bank_offset[bank_index] + (addr & 0x1FFF)) % prg_rom.len()])
             },
             _ => None
         }
@@ -61,8 +181,37 @@ impl Mapper for Mapper4 {
 
     fn mapped_cpu_write(&mut self, _prg_rom: &mut Vec<u8>, addr: usize, byte: u8) -> bool {
         match addr {
-            SAVE_RAM_START..=SAVE_RAM_END => {
-                self.save_ram[addr & 0x1FFF] = byte;
+            PRG_RAM_START..=PRG_RAM_END if self.board == Mmc3Board::MMC6 => {
+                self.mmc6_ram_write(addr, byte);
+                true
+            },
+            PRG_RAM_START..=PRG_RAM_END if !self.prg_ram.is_empty() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr & 0x1FFF) % len] = byte;
+                true
+            },
+            PRG_ROM_START..=PRG_ROM_END if self.is_namco_108() => {
+                if self.board == Mmc3Board::NAMCO_3453 {
+                    self.mirroring = if byte & 0b01000000 != 0 {
+                        Mirroring::ONESCREEN_HI
+                    } else {
+                        Mirroring::ONESCREEN_LO
+                    };
+                }
+
+                // the Namco 108 has no PRG mode or CHR inversion, and only decodes $8000-$9FFF
+                if addr < (PRG_ROM_LO_START + (PRG_ROM_SIZE >> 1)) {
+                    if addr & 0x01 == 0 {
+                        self.target_register = (byte & 0b00000111) as usize;
+                    } else {
+                        self.registers[self.target_register] = match self.target_register {
+                            6 | 7 => byte & 0b00001111,
+                            _ => byte & 0b00111111,
+                        } as usize;
+                        self.update_banks();
+                    }
+                }
+
                 true
             },
             PRG_ROM_LO_START..=PRG_ROM_LO_END => {    
@@ -72,6 +221,13 @@ impl Mapper for Mapper4 {
                         self.target_register = (byte & 0b00000111) as usize;
                         self.prg_mode = byte & 0b01000000 != 0;
                         self.chr_inversion = byte & 0b10000000 != 0;
+
+                        if self.board == Mmc3Board::MMC6 {
+                            self.prg_ram_enable = byte & 0b00100000 != 0;
+                            if !self.prg_ram_enable {
+                                self.prg_ram_protect = 0;
+                            }
+                        }
                         
                     } else {
                         let mut byte = byte;
@@ -79,40 +235,8 @@ impl Mapper for Mapper4 {
                             byte &= 0b00111111;
                         }
                         self.registers[self.target_register] = byte as usize;
-
-
-                        let prg_half_bank_size = PRG_ROM_SIZE >> 1;
-
-                        if self.prg_mode {
-                            self.prg_bank_offset[2] = self.registers[6] * prg_half_bank_size;
-                            self.prg_bank_offset[0] = ((self.prg_rom_banks << 1) - 2) * prg_half_bank_size;
-                        } else {
-                            self.prg_bank_offset[0] = self.registers[6] * (prg_half_bank_size);
-                            self.prg_bank_offset[2] = ((self.prg_rom_banks << 1) - 2) * prg_half_bank_size;
-                        }
-                        self.prg_bank_offset[1] = self.registers[7] * (prg_half_bank_size);
-                        self.prg_bank_offset[3] = ((self.prg_rom_banks << 1) - 1) * prg_half_bank_size;
-
-
-                        let chr_half_bank_size = CHR_ROM_SIZE >> 3;
-                        
-                        let r0lo = (self.registers[0] & 0xFE) * chr_half_bank_size;
-                        let r0hi = (self.registers[0] + 1) * chr_half_bank_size;
-                        let r1lo = (self.registers[1] & 0xFE) * chr_half_bank_size;
-                        let r1hi = (self.registers[1] + 1) * chr_half_bank_size;
-                        let r2 = self.registers[2] * chr_half_bank_size;
-                        let r3 = self.registers[3] * chr_half_bank_size;
-                        let r4 = self.registers[4] * chr_half_bank_size;
-                        let r5 = self.registers[5] * chr_half_bank_size;
-
-                        self.chr_bank_offset = [r0lo, r0hi, r1lo, r1hi, r2, r3, r4, r5];
-                        if self.chr_inversion {
-                            self.chr_bank_offset.swap(0, 4);
-                            self.chr_bank_offset.swap(1, 5);
-                            self.chr_bank_offset.swap(2, 6);
-                            self.chr_bank_offset.swap(3, 7);
-                        }
                     }
+                    self.update_banks();
             
                 } else {
                     if addr & 0x01 == 0 {
@@ -121,6 +245,11 @@ impl Mapper for Mapper4 {
                         } else {
                             Mirroring::VERTICAL
                         };
+                    } else if self.board == Mmc3Board::MMC6 {
+                        // the protect bits can only be changed while PRG-RAM is enabled
+                        if self.prg_ram_enable {
+                            self.prg_ram_protect = byte & 0b11110000;
+                        }
                     } else {
                         // TODO: add implementation for PRG-RAM protect register
                     }
@@ -134,6 +263,8 @@ impl Mapper for Mapper4 {
                         self.irq_reload = byte as u16;
                     } else {
                         self.irq_counter = 0x0000;
+                        self.irq_update = true;
+                        self.a12_falls = 0;
                     }
                 } else {
                     if addr & 0x01 == 0 {
@@ -152,45 +283,110 @@ impl Mapper for Mapper4 {
 
     fn mapped_ppu_read(&self, chr_rom: &Vec<u8>, addr: usize) -> u8 {
         let bank_index = (addr & 0x1C00) >> 10;
-        chr_rom[self.chr_bank_offset[bank_index] + (addr & 0x03FF)]
+
+        match self.tqrom_chr_ram_addr(bank_index, addr) {
+            Some(ram_addr) => self.chr_ram[ram_addr],
+            None => chr_rom[(self.chr_bank_offset[bank_index] + (addr & 0x03FF)) % chr_rom.len()],
+        }
     }
 
-    fn notify_scanline(&mut self) {
-        if self.irq_counter == 0 {
-            self.irq_counter = self.irq_reload;
-        } else {
-            self.irq_counter -= 1;
+    fn mapped_ppu_write(&mut self, chr_ram: &mut Vec<u8>, addr: usize, byte: u8) {
+        let bank_index = (addr & 0x1C00) >> 10;
+        let len = chr_ram.len();
+        chr_ram[(self.chr_bank_offset[bank_index] + (addr & 0x03FF)) % len] = byte;
+    }
+
+    fn mapped_chr_rom_write(&mut self, addr: usize, byte: u8) {
+        let bank_index = (addr & 0x1C00) >> 10;
+
+        if let Some(ram_addr) = self.tqrom_chr_ram_addr(bank_index, addr) {
+            self.chr_ram[ram_addr] = byte;
         }
+    }
 
-        if self.irq_counter == 0 && self.irq_enable {
-            self.irq_active = true;
+    fn notify_ppu_addr(&mut self, addr: usize) {
+        let a12_high = addr & 0x1000 != 0;
+
+        if self.board == Mmc3Board::MMC3_ACCLAIM {
+            // the falls aren't filtered, so background tiles fetched from $1000 count as well
+            if !a12_high && self.a12_high {
+                self.a12_falls = (self.a12_falls + 1) % ACCLAIM_A12_FALLS;
+                if self.a12_falls == 0 {
+                    self.clock_irq_counter();
+                }
+            }
+        } else if a12_high && !self.a12_high && self.a12_low_cycles >= A12_FILTER_CYCLES {
+            self.clock_irq_counter();
+        }
+        if !a12_high && self.a12_high {
+            self.a12_low_cycles = 0;
+        }
+
+        self.a12_high = a12_high;
+    }
+
+    fn cpu_clock(&mut self) {
+        if !self.a12_high {
+            self.a12_low_cycles = self.a12_low_cycles.saturating_add(1);
         }
     }
 
     fn irq_active(&mut self) -> bool {
-        let ret = self.irq_active;
-        self.irq_active = false;
-        ret
+        self.irq_active
     }
 
     fn get_updated_mirroring(&self) -> Option<Mirroring> {
-        Some(self.mirroring)
+        match self.board {
+            Mmc3Board::TXSROM => {
+                let slots = [0, 1, 2, 3].map(|slot| {
+                    let bank = self.chr_bank_offset[slot] / (CHR_ROM_SIZE >> 3);
+                    NameTableSource::CIRAM(((bank & 0b10000000) >> 7) as u8)
+                });
+
+                Some(Mirroring::CUSTOM(slots))
+            }
+            Mmc3Board::NAMCO_3425 => {
+                let top = NameTableSource::CIRAM(((self.registers[0] & 0b00100000) >> 5) as u8);
+                let bottom = NameTableSource::CIRAM(((self.registers[1] & 0b00100000) >> 5) as u8);
+
+                Some(Mirroring::CUSTOM([top, top, bottom, bottom]))
+            }
+            // the other Namco boards have their mirroring soldered
+            Mmc3Board::NAMCO_108 | Mmc3Board::NAMCO_3446 | Mmc3Board::NAMCO_3433 => None,
+            _ => Some(self.mirroring),
+        }
+    }
+
+    fn load_trainer(&mut self, trainer: &[u8]) -> bool {
+        copy_trainer(&mut self.prg_ram, trainer)
     }
 
-    fn get_save_ram(&self) -> Option<[u8; SAVE_RAM_SIZE]> {
-        Some(self.save_ram)
+    fn get_save_ram(&self) -> Option<&[u8]> {
+        if self.prg_ram.is_empty() { None } else { Some(&self.prg_ram) }
     }
 
-    fn load_save_ram(&mut self, save_ram: [u8; SAVE_RAM_SIZE]) -> bool {
-        self.save_ram = save_ram;
-        true
+    fn load_save_ram(&mut self, save_ram: &[u8]) -> bool {
+        self.prg_ram.copy_from_slice(save_ram);
+        !self.prg_ram.is_empty()
     }
 }
 
 impl Mapper4 {
-    pub fn new(prg_rom_banks: usize) -> Self {
-        Self {
-            save_ram: [0; SAVE_RAM_SIZE],
+    pub fn new(board: Mmc3Board, prg_rom_banks: usize, prg_ram_size: usize) -> Self {
+        let prg_ram_size = match board {
+            Mmc3Board::MMC6 => MMC6_PRG_RAM_SIZE,
+            // none of the Namco 108 boards have PRG-RAM
+            Mmc3Board::NAMCO_108 | Mmc3Board::NAMCO_3446 | Mmc3Board::NAMCO_3433
+            | Mmc3Board::NAMCO_3425 | Mmc3Board::NAMCO_3453 => 0,
+            _ => prg_ram_size,
+        };
+
+        let chr_ram_size = if board == Mmc3Board::TQROM { TQROM_CHR_RAM_SIZE } else { 0 };
+
+        let mut mapper = Self {
+            board,
+            prg_ram: vec![0; prg_ram_size],
+            chr_ram: vec![0; chr_ram_size],
             mirroring: Mirroring::HORIZONTAL,
             prg_rom_banks,
 
@@ -207,6 +403,460 @@ impl Mapper4 {
             irq_active: false,
             irq_enable: false,
             irq_update: false,
+            a12_high: false,
+            a12_low_cycles: 0,
+            a12_falls: 0,
+
+            prg_ram_enable: false,
+            prg_ram_protect: 0,
+        };
+
+        mapper.reset();
+        mapper
+    }
+
+    // Clocked by each filtered rise of PPU A12 (or every 8th fall on the MC-ACC), which normally happens once per scanline
+    fn clock_irq_counter(&mut self) {
+        let zero_before = self.irq_counter == 0;
+
+        if self.irq_counter == 0 || self.irq_update {
+            self.irq_counter = self.irq_reload;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        // The new behaviour fires every time the counter is 0, even if it was just reloaded with 0.
+        // The old behaviour only fires when it gets there, by decrementing or through a write to $C001
+        let fire = match self.board {
+            Mmc3Board::MMC3_REV_A | Mmc3Board::MMC6 => !zero_before || self.irq_update,
+            _ => true,
+        };
+        self.irq_update = false;
+
+        if self.irq_counter == 0 && fire && self.irq_enable {
+            self.irq_active = true;
+        }
+    }
+
+    fn is_namco_108(&self) -> bool {
+        matches!(self.board, Mmc3Board::NAMCO_108 | Mmc3Board::NAMCO_3446 | Mmc3Board::NAMCO_3433
+            | Mmc3Board::NAMCO_3425 | Mmc3Board::NAMCO_3453)
+    }
+
+    // Bit 7 of $A001 allows writes to the upper 512 bytes and bit 6 allows reads, with bits 5 and 4 doing the same
+    // for the lower half. Reading a half that isn't readable gives 0, unless neither half is, which gives open bus
+    fn mmc6_ram_read(&self, addr: usize) -> Option<u8> {
+        let readable = (self.prg_ram_protect >> 4) & 0b0101;
+
+        if addr < MMC6_PRG_RAM_START || !self.prg_ram_enable || readable == 0 {
+            return None;
+        }
+
+        let index = addr & (MMC6_PRG_RAM_SIZE - 1);
+        let read_bit = if index & 0x200 != 0 { 0b01000000 } else { 0b00010000 };
+
+        Some(if self.prg_ram_protect & read_bit != 0 { self.prg_ram[index] } else { 0 })
+    }
+
+    fn mmc6_ram_write(&mut self, addr: usize, byte: u8) {
+        let index = addr & (MMC6_PRG_RAM_SIZE - 1);
+        let protect_bits = if index & 0x200 != 0 { 0b11000000 } else { 0b00110000 };
+
+        // a half can only be written while it can also be read
+        if addr >= MMC6_PRG_RAM_START && self.prg_ram_enable && self.prg_ram_protect & protect_bits == protect_bits {
+            self.prg_ram[index] = byte;
+        }
+    }
+
+    fn tqrom_chr_ram_addr(&self, bank_index: usize, addr: usize) -> Option<usize> {
+        let bank = self.chr_bank_offset[bank_index] / (CHR_ROM_SIZE >> 3);
+
+        if self.board == Mmc3Board::TQROM && bank & TQROM_CHR_RAM_BANK != 0 {
+            Some(((bank & 0b00000111) << 10) | (addr & 0x03FF))
+        } else {
+            None
+        }
+    }
+
+    fn update_banks(&mut self) {
+        let prg_half_bank_size = PRG_ROM_SIZE >> 1;
+
+        if self.prg_mode {
+            self.prg_bank_offset[2] = self.registers[6] * prg_half_bank_size;
+            self.prg_bank_offset[0] = ((self.prg_rom_banks << 1) - 2) * prg_half_bank_size;
+        } else {
+            self.prg_bank_offset[0] = self.registers[6] * (prg_half_bank_size);
+            self.prg_bank_offset[2] = ((self.prg_rom_banks << 1) - 2) * prg_half_bank_size;
+        }
+        self.prg_bank_offset[1] = self.registers[7] * (prg_half_bank_size);
+        self.prg_bank_offset[3] = ((self.prg_rom_banks << 1) - 1) * prg_half_bank_size;
+
+
+        let chr_half_bank_size = CHR_ROM_SIZE >> 3;
+
+        let mut registers = self.registers;
+        match self.board {
+            Mmc3Board::NAMCO_3446 => {
+                // R2-R5 are 2KB banks in place of R0 and R1's
+                self.chr_bank_offset = [
+                    registers[2] * 2, registers[2] * 2 + 1, registers[3] * 2, registers[3] * 2 + 1,
+                    registers[4] * 2, registers[4] * 2 + 1, registers[5] * 2, registers[5] * 2 + 1,
+                ].map(|bank| bank * chr_half_bank_size);
+                return;
+            }
+            Mmc3Board::NAMCO_3433 | Mmc3Board::NAMCO_3453 => {
+                // CHR A16 comes from whichever half of the pattern tables is being read
+                for register in &mut registers[2..=5] {
+                    *register |= 0b01000000;
+                }
+            }
+            Mmc3Board::NAMCO_3425 => {
+                registers[0] &= 0b00011111;
+                registers[1] &= 0b00011111;
+            }
+            _ => {}
+        }
+
+        let r0lo = (registers[0] & 0xFE) * chr_half_bank_size;
+        let r0hi = ((registers[0] & 0xFE) + 1) * chr_half_bank_size;
+        let r1lo = (registers[1] & 0xFE) * chr_half_bank_size;
+        let r1hi = ((registers[1] & 0xFE) + 1) * chr_half_bank_size;
+        let r2 = registers[2] * chr_half_bank_size;
+        let r3 = registers[3] * chr_half_bank_size;
+        let r4 = registers[4] * chr_half_bank_size;
+        let r5 = registers[5] * chr_half_bank_size;
+
+        self.chr_bank_offset = [r0lo, r0hi, r1lo, r1hi, r2, r3, r4, r5];
+        if self.chr_inversion {
+            self.chr_bank_offset.swap(0, 4);
+            self.chr_bank_offset.swap(1, 5);
+            self.chr_bank_offset.swap(2, 6);
+            self.chr_bank_offset.swap(3, 7);
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::{CartridgeNes, Mirroring, NameTableSource};
+    use crate::mapper::TestRom;
+
+    fn write_register(cartridge: &mut CartridgeNes, register: u8, byte: u8) {
+        cartridge.cpu_write(0x8000, register);
+        cartridge.cpu_write(0x8001, byte);
+    }
+
+    fn chr_banks(cartridge: &CartridgeNes) -> [u8; 8] {
+        [0, 1, 2, 3, 4, 5, 6, 7].map(|slot| cartridge.ppu_read(slot * 0x0400))
+    }
+
+    #[test]
+    pub fn test_mmc3_banks() {
+        let mut cartridge = TestRom::nes2(4, 0, 0x20000, 0x20000)
+            .prg_banks(0x2000)
+            .chr_banks(0x0400)
+            .prg_ram(0x07, 0)
+            .cartridge();
+
+        for (register, byte) in [(0, 9), (1, 20), (2, 30), (3, 31), (4, 32), (5, 33), (6, 3), (7, 4)] {
+            write_register(&mut cartridge, register, byte);
+        }
+
+        // R0 and R1 ignore their lowest bit
+        assert_eq!(chr_banks(&cartridge), [8, 9, 20, 21, 30, 31, 32, 33]);
+        assert_eq!([0x8000, 0xA000, 0xC000, 0xE000].map(|addr| cartridge.cpu_read(addr).unwrap()), [3, 4, 14, 15]);
+
+        // PRG mode and CHR inversion take effect as soon as they're selected
+        cartridge.cpu_write(0x8000, 0xC0);
+        assert_eq!(chr_banks(&cartridge), [30, 31, 32, 33, 8, 9, 20, 21]);
+        assert_eq!([0x8000, 0xA000, 0xC000, 0xE000].map(|addr| cartridge.cpu_read(addr).unwrap()), [14, 4, 3, 15]);
+    }
+
+    #[test]
+    pub fn test_namco_108() {
+        let mut cartridge = TestRom::nes2(206, 0, 0x20000, 0x20000)
+            .prg_banks(0x2000)
+            .chr_banks(0x0400)
+            .prg_ram(0x07, 0)
+            .cartridge();
+
+        for (register, byte) in [(0, 2), (1, 4), (2, 6), (3, 7), (4, 8), (5, 9), (6, 1), (7, 2)] {
+            write_register(&mut cartridge, register, byte);
+        }
+
+        // the mode bits and the registers above $9FFF don't exist
+        cartridge.cpu_write(0x8000, 0xC0);
+        cartridge.cpu_write(0xA000, 0x01);
+        assert_eq!(chr_banks(&cartridge), [2, 3, 4, 5, 6, 7, 8, 9]);
+        assert_eq!([0x8000, 0xA000, 0xC000, 0xE000].map(|addr| cartridge.cpu_read(addr).unwrap()), [1, 2, 14, 15]);
+        assert!(matches!(cartridge.mirroring(), Mirroring::HORIZONTAL));
+    }
+
+    #[test]
+    pub fn test_namco_chr_variants() {
+        let mut cartridge = TestRom::nes2(76, 0, 0x20000, 0x20000)
+            .prg_banks(0x2000)
+            .chr_banks(0x0400)
+            .prg_ram(0x07, 0)
+            .cartridge();
+        for (register, byte) in [(2, 10), (3, 11), (4, 40), (5, 63)] {
+            write_register(&mut cartridge, register, byte);
+        }
+        assert_eq!(chr_banks(&cartridge), [20, 21, 22, 23, 80, 81, 126, 127]);
+
+        for mapper_num in [88, 154] {
+            let mut cartridge = TestRom::nes2(mapper_num, 0, 0x20000, 0x20000)
+                .prg_banks(0x2000)
+                .chr_banks(0x0400)
+                .prg_ram(0x07, 0)
+                .cartridge();
+            for (register, byte) in [(0, 0x42), (1, 4), (2, 0), (3, 1), (4, 2), (5, 3)] {
+                write_register(&mut cartridge, register, byte);
+            }
+            assert_eq!(chr_banks(&cartridge), [2, 3, 4, 5, 64, 65, 66, 67]);
+        }
+    }
+
+    #[test]
+    pub fn test_namco_mirroring() {
+        let mut cartridge = TestRom::nes2(154, 0, 0x20000, 0x20000)
+            .prg_banks(0x2000)
+            .chr_banks(0x0400)
+            .prg_ram(0x07, 0)
+            .cartridge();
+        assert!(matches!(cartridge.mirroring(), Mirroring::ONESCREEN_LO));
+        cartridge.cpu_write(0xE000, 0x40);
+        assert!(matches!(cartridge.mirroring(), Mirroring::ONESCREEN_HI));
+
+        let mut cartridge = TestRom::nes2(95, 0, 0x20000, 0x20000)
+            .prg_banks(0x2000)
+            .chr_banks(0x0400)
+            .prg_ram(0x07, 0)
+            .cartridge();
+        write_register(&mut cartridge, 0, 0x24);
+        write_register(&mut cartridge, 1, 0x02);
+        assert_eq!(chr_banks(&cartridge)[0..4], [4, 5, 2, 3]);
+
+        let slots = cartridge.mirroring().name_table_slots();
+        assert!(matches!(slots, [NameTableSource::CIRAM(1), NameTableSource::CIRAM(1), NameTableSource::CIRAM(0), NameTableSource::CIRAM(0)]));
+    }
+
+    #[test]
+    pub fn test_txsrom_mirroring() {
+        let mut cartridge = TestRom::nes2(118, 0, 0x20000, 0x20000)
+            .prg_banks(0x2000)
+            .chr_banks(0x0400)
+            .prg_ram(0x07, 0)
+            .cartridge();
+
+        write_register(&mut cartridge, 0, 0x80);
+        write_register(&mut cartridge, 1, 0x02);
+        cartridge.cpu_write(0xA000, 0x01);
+
+        let slots = cartridge.mirroring().name_table_slots();
+        assert!(matches!(slots, [NameTableSource::CIRAM(1), NameTableSource::CIRAM(1), NameTableSource::CIRAM(0), NameTableSource::CIRAM(0)]));
+
+        // with CHR inversion, the nametables follow R2-R5 instead
+        for (register, byte) in [(2, 0x00), (3, 0x81), (4, 0x82), (5, 0x03)] {
+            write_register(&mut cartridge, register, byte);
+        }
+        cartridge.cpu_write(0x8000, 0x80);
+
+        let slots = cartridge.mirroring().name_table_slots();
+        assert!(matches!(slots, [NameTableSource::CIRAM(0), NameTableSource::CIRAM(1), NameTableSource::CIRAM(1), NameTableSource::CIRAM(0)]));
+    }
+
+    #[test]
+    pub fn test_tqrom_chr_ram() {
+        let mut cartridge = TestRom::nes2(119, 0, 0x20000, 0x20000)
+            .prg_banks(0x2000)
+            .chr_banks(0x0400)
+            .prg_ram(0x07, 0)
+            .cartridge();
+
+        write_register(&mut cartridge, 2, 0x05);
+        write_register(&mut cartridge, 3, 0x41);
+        write_register(&mut cartridge, 4, 0x41);
+
+        // writes to CHR-ROM are ignored, while CHR-RAM banks can be mapped more than once
+        cartridge.ppu_write(0x1000, 0xAA);
+        cartridge.ppu_write(0x1400, 0xBB);
+        assert_eq!(cartridge.ppu_read(0x1000), 5);
+        assert_eq!(cartridge.ppu_read(0x1400), 0xBB);
+        assert_eq!(cartridge.ppu_read(0x1800), 0xBB);
+    }
+
+    #[test]
+    pub fn test_mmc6_prg_ram() {
+        let mut cartridge = TestRom::nes2(4, 1, 0x20000, 0x20000)
+            .prg_banks(0x2000)
+            .chr_banks(0x0400)
+            .prg_ram(0x07, 0)
+            .cartridge();
+
+        // open bus until PRG-RAM is enabled and a half is readable
+        cartridge.cpu_write(0x7000, 0x11);
+        assert_eq!(cartridge.cpu_read(0x7000), None);
+        cartridge.cpu_write(0xA001, 0xF0);
+        cartridge.cpu_write(0x8000, 0x20);
+        assert_eq!(cartridge.cpu_read(0x7000), None);
+
+        cartridge.cpu_write(0xA001, 0xF0);
+        cartridge.cpu_write(0x7000, 0x11);
+        cartridge.cpu_write(0x7200, 0x22);
+        assert_eq!(cartridge.cpu_read(0x7400), Some(0x11));
+        assert_eq!(cartridge.cpu_read(0x7600), Some(0x22));
+        assert_eq!(cartridge.cpu_read(0x6000), None);
+
+        // the upper half is readable but not writable, and the lower half reads as 0
+        cartridge.cpu_write(0xA001, 0x40);
+        cartridge.cpu_write(0x7200, 0x33);
+        assert_eq!(cartridge.cpu_read(0x7200), Some(0x22));
+        assert_eq!(cartridge.cpu_read(0x7000), Some(0));
+
+        assert_eq!(cartridge.get_save_ram().map(|ram| ram.len()), Some(0x400));
+    }
+
+    // What the PPU does to A12 once per scanline, with sprites at $1000 and the background at $0000
+    fn end_scanline(cartridge: &mut CartridgeNes) {
+        cartridge.notify_ppu_read(0x0FF0);
+        for _ in 0..20 {
+            cartridge.cpu_clock();
+        }
+        cartridge.notify_ppu_read(0x1FF0);
+    }
+
+    fn acknowledge_irq(cartridge: &mut CartridgeNes) {
+        cartridge.cpu_write(0xE000, 0);
+        cartridge.cpu_write(0xE001, 0);
+    }
+
+    #[test]
+    pub fn test_irq_revisions() {
+        // (submapper, fires with a reload value of 0)
+        for (submapper_num, fires) in [(0, true), (4, false), (1, false)] {
+            let mut cartridge = TestRom::nes2(4, submapper_num, 0x20000, 0x20000)
+                .prg_banks(0x2000)
+                .chr_banks(0x0400)
+                .prg_ram(0x07, 0)
+                .cartridge();
+
+            cartridge.cpu_write(0xC000, 0);
+            cartridge.cpu_write(0xC001, 0);
+            cartridge.cpu_write(0xE001, 0);
+
+            // the write to $C001 makes both revisions fire on the next scanline
+            end_scanline(&mut cartridge);
+            assert!(cartridge.irq_active());
+            acknowledge_irq(&mut cartridge);
+
+            end_scanline(&mut cartridge);
+            assert_eq!(cartridge.irq_active(), fires, "submapper {}", submapper_num);
+        }
+
+        // both count down the same way
+        for submapper_num in [0, 4] {
+            let mut cartridge = TestRom::nes2(4, submapper_num, 0x20000, 0x20000)
+                .prg_banks(0x2000)
+                .chr_banks(0x0400)
+                .prg_ram(0x07, 0)
+                .cartridge();
+
+            cartridge.cpu_write(0xC000, 2);
+            cartridge.cpu_write(0xC001, 0);
+            cartridge.cpu_write(0xE001, 0);
+
+            let irqs: Vec<bool> = (0..6).map(|_| {
+                end_scanline(&mut cartridge);
+                let irq = cartridge.irq_active();
+                acknowledge_irq(&mut cartridge);
+                irq
+            }).collect();
+            assert_eq!(irqs, [false, false, true, false, false, true]);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    pub fn test_acclaim_a12_clocking() {
+        let mut cartridge = TestRom::nes2(4, 3, 0x20000, 0x20000)
+            .prg_banks(0x2000)
+            .chr_banks(0x0400)
+            .prg_ram(0x07, 0)
+            .cartridge();
+
+        cartridge.cpu_write(0xC000, 0);
+        cartridge.cpu_write(0xC001, 0);
+        cartridge.cpu_write(0xE001, 0);
+
+        // filtered rises don't clock the counter, but each one is followed by a fall at the start of the next scanline
+        for _ in 0..8 {
+            end_scanline(&mut cartridge);
+            assert!(!cartridge.irq_active());
+        }
+        cartridge.notify_ppu_read(0x0FF0);
+        assert!(cartridge.irq_active());
+        acknowledge_irq(&mut cartridge);
+
+        // a scanline of sprites fetched from $1000 falls 8 times, no matter how close together
+        let sprite_fetches = |cartridge: &mut CartridgeNes, count: usize| {
+            for _ in 0..count {
+                cartridge.notify_ppu_read(0x1FF0);
+                cartridge.notify_ppu_read(0x2000);
+            }
+        };
+        sprite_fetches(&mut cartridge, 7);
+        assert!(!cartridge.irq_active());
+        sprite_fetches(&mut cartridge, 1);
+        assert!(cartridge.irq_active());
+        acknowledge_irq(&mut cartridge);
+
+        // writing $C001 also starts counting the falls again
+        sprite_fetches(&mut cartridge, 4);
+        cartridge.cpu_write(0xC001, 0);
+        sprite_fetches(&mut cartridge, 7);
+        assert!(!cartridge.irq_active());
+        sprite_fetches(&mut cartridge, 1);
+        assert!(cartridge.irq_active());
+    }
+
+    #[test]
+    pub fn test_a12_clocking() {
+        let mut cartridge = TestRom::nes2(4, 0, 0x20000, 0x20000)
+            .prg_banks(0x2000)
+            .chr_banks(0x0400)
+            .prg_ram(0x07, 0)
+            .cartridge();
+
+        cartridge.cpu_write(0xC000, 1);
+        cartridge.cpu_write(0xC001, 0);
+        cartridge.cpu_write(0xE001, 0);
+
+        // the first rise reloads the counter, then the IRQ stays asserted until it's acknowledged
+        end_scanline(&mut cartridge);
+        end_scanline(&mut cartridge);
+        assert!(cartridge.irq_active());
+        cartridge.notify_ppu_read(0x0000);
+        assert!(cartridge.irq_active());
+        acknowledge_irq(&mut cartridge);
+        assert!(!cartridge.irq_active());
+
+        // rises after A12 was low for 3 CPU cycles or less are filtered out, like those between background tiles at $1000
+        for _ in 0..8 {
+            cartridge.notify_ppu_read(0x2000);
+            for _ in 0..3 {
+                cartridge.cpu_clock();
+            }
+            cartridge.notify_ppu_read(0x1000);
+        }
+        assert!(!cartridge.irq_active());
+
+        // A12 also follows addresses set through PPUADDR and PPUDATA
+        for _ in 0..2 {
+            cartridge.notify_ppu_addr(0x0000);
+            for _ in 0..4 {
+                cartridge.cpu_clock();
+            }
+            cartridge.notify_ppu_addr(0x1000);
+        }
+        assert!(cartridge.irq_active());
+    }
+}
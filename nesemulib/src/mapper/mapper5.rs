@@ -0,0 +1,741 @@
+mod audio;
+
+use crate::{cartridge::{Mirroring, NameTableSource, NAME_TABLE_PAGE_SIZE}, SystemControl, StateReader, StateWriter};
+
+use self::audio::{Mmc5Audio, MMC5_CHANNELS};
+use super::{ExpansionChannel, copy_trainer, Mapper, PRG_RAM_BANK_SIZE, PRG_RAM_END, PRG_RAM_START, PRG_ROM_END, PRG_ROM_START};
+
+// All PRG banking is done in multiples of 8KB, and CHR banking in multiples of 1KB
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+const AUDIO_START: usize = 0x5000;
+const AUDIO_END: usize = 0x5015;
+
+const EXRAM_START: usize = 0x5C00;
+const EXRAM_END: usize = 0x5FFF;
+const EXRAM_SIZE: usize = 0x0400;
+
+// Offset of the attribute table within each nametable
+const ATTR_TABLE_OFFSET: usize = 0x03C0;
+
+// The "in frame" flag is cleared once the PPU has fetched every visible scanline
+const VISIBLE_SCANLINES: u8 = 240;
+
+#[allow(non_camel_case_types)]
+enum PrgTarget {
+    ROM(usize),
+    RAM(usize),
+}
+
+pub struct Mapper5 {
+    prg_ram: Vec<u8>,
+    exram: [u8; EXRAM_SIZE],
+
+    prg_mode: u8,
+    chr_mode: u8,
+    prg_ram_protect: [u8; 2],
+    exram_mode: u8,
+    name_table_mapping: u8,
+    fill_tile: u8,
+    fill_attr: u8,
+
+    // $5113 to $5117
+    prg_regs: [u8; 5],
+    // set A ($5120 - $5127) is used for sprites and set B ($5128 - $512B) for the background in 8x16 sprite mode,
+    // otherwise whichever set was last written to is used for everything
+    chr_regs_a: [usize; 8],
+    chr_regs_b: [usize; 4],
+    chr_upper: usize,
+    last_chr_set_b: bool,
+
+    irq_compare: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    in_frame: bool,
+    scanline: u8,
+
+    multiplicand: u8,
+    multiplier: u8,
+
+    // snooped from writes to PPUCTRL
+    sprite_8x16: bool,
+
+    // scanlines are detected by watching the PPU's fetches, as MMC5 isn't connected to PPU A12 or /IRQ
+    last_fetch_addr: usize,
+    fetch_repeats: u8,
+    fetching_sprites: bool,
+    // ExRAM byte for the tile being fetched, when ExRAM is used for extended attributes
+    ex_attr: u8,
+
+    audio: Mmc5Audio,
+}
+
+impl SystemControl for Mapper5 {
+    fn reset(&mut self) {
+        self.prg_mode = 3;
+        self.chr_mode = 0;
+        self.prg_ram_protect = [0; 2];
+        self.exram_mode = 0;
+        self.name_table_mapping = 0;
+        self.fill_tile = 0;
+        self.fill_attr = 0;
+
+        self.prg_regs = [0, 0, 0, 0, 0xFF];
+        self.chr_regs_a = [0; 8];
+        self.chr_regs_b = [0; 4];
+        self.chr_upper = 0;
+        self.last_chr_set_b = false;
+
+        self.irq_compare = 0;
+        self.irq_enabled = false;
+        self.irq_pending = false;
+        self.in_frame = false;
+        self.scanline = 0;
+
+        self.multiplicand = 0xFF;
+        self.multiplier = 0xFF;
+
+        self.sprite_8x16 = false;
+
+        self.last_fetch_addr = 0;
+        self.fetch_repeats = 0;
+        self.fetching_sprites = false;
+        self.ex_attr = 0;
+
+        self.audio.reset();
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+        state.write_bytes(&self.exram);
+
+        state.write_u8(self.prg_mode);
+        state.write_u8(self.chr_mode);
+        state.write_bytes(&self.prg_ram_protect);
+        state.write_u8(self.exram_mode);
+        state.write_u8(self.name_table_mapping);
+        state.write_u8(self.fill_tile);
+        state.write_u8(self.fill_attr);
+
+        state.write_bytes(&self.prg_regs);
+        for value in &self.chr_regs_a {
+            state.write_usize(*value);
+        }
+        for value in &self.chr_regs_b {
+            state.write_usize(*value);
+        }
+        state.write_usize(self.chr_upper);
+        state.write_bool(self.last_chr_set_b);
+
+        state.write_u8(self.irq_compare);
+        state.write_bool(self.irq_enabled);
+        state.write_bool(self.irq_pending);
+        state.write_bool(self.in_frame);
+        state.write_u8(self.scanline);
+
+        state.write_u8(self.multiplicand);
+        state.write_u8(self.multiplier);
+
+        state.write_bool(self.sprite_8x16);
+
+        state.write_usize(self.last_fetch_addr);
+        state.write_u8(self.fetch_repeats);
+        state.write_bool(self.fetching_sprites);
+        state.write_u8(self.ex_attr);
+
+        self.audio.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.read_bytes(&mut self.prg_ram)?;
+        state.read_bytes(&mut self.exram)?;
+
+        self.prg_mode = state.read_u8()? & 0x03;
+        self.chr_mode = state.read_u8()? & 0x03;
+        state.read_bytes(&mut self.prg_ram_protect)?;
+        self.exram_mode = state.read_u8()? & 0x03;
+        self.name_table_mapping = state.read_u8()?;
+        self.fill_tile = state.read_u8()?;
+        self.fill_attr = state.read_u8()?;
+
+        state.read_bytes(&mut self.prg_regs)?;
+        for value in &mut self.chr_regs_a {
+            *value = state.read_usize()? & 0x3FF;
+        }
+        for value in &mut self.chr_regs_b {
+            *value = state.read_usize()? & 0x3FF;
+        }
+        self.chr_upper = state.read_usize()? & 0x03;
+        self.last_chr_set_b = state.read_bool()?;
+
+        self.irq_compare = state.read_u8()?;
+        self.irq_enabled = state.read_bool()?;
+        self.irq_pending = state.read_bool()?;
+        self.in_frame = state.read_bool()?;
+        self.scanline = state.read_u8()?.min(VISIBLE_SCANLINES);
+
+        self.multiplicand = state.read_u8()?;
+        self.multiplier = state.read_u8()?;
+
+        self.sprite_8x16 = state.read_bool()?;
+
+        self.last_fetch_addr = state.read_usize()?;
+        self.fetch_repeats = state.read_u8()?;
+        self.fetching_sprites = state.read_bool()?;
+        self.ex_attr = state.read_u8()?;
+
+        self.audio.load_state(state)?;
+
+        Ok(())
+    }
+}
+
+impl Mapper for Mapper5 {
+    fn mapped_cpu_read(&mut self, prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8> {
+        match addr {
+            AUDIO_START..=AUDIO_END => self.audio.read_register(addr),
+            0x5204 => {
+                let byte = ((self.irq_pending as u8) << 7) | ((self.in_frame as u8) << 6);
+                self.irq_pending = false;
+                Some(byte)
+            }
+            0x5205 => Some(self.product() as u8),
+            0x5206 => Some((self.product() >> 8) as u8),
+            EXRAM_START..=EXRAM_END => match self.exram_mode {
+                // ExRAM can only be read by the CPU in modes 2 and 3
+                0 | 1 => None,
+                _ => Some(self.exram[addr - EXRAM_START]),
+            },
+            PRG_RAM_START..=PRG_ROM_END => match self.prg_target(addr) {
+                PrgTarget::ROM(offset) => Some(prg_rom[offset % prg_rom.len()]),
+                PrgTarget::RAM(offset) if !self.prg_ram.is_empty() => Some(self.prg_ram[offset % self.prg_ram.len()]),
+                PrgTarget::RAM(_) => None,
+            },
+            _ => None
+        }
+    }
+
+    fn mapped_cpu_write(&mut self, _prg_rom: &mut Vec<u8>, addr: usize, byte: u8) -> bool {
+        match addr {
+            // MMC5 watches writes to the PPU's registers, but the PPU still needs to receive them
+            0x2000..=0x3FFF => {
+                match addr & 0x2007 {
+                    0x2000 => self.sprite_8x16 = (byte & 0b00100000) != 0,
+                    0x2001 if (byte & 0b00011000) == 0 => self.in_frame = false,
+                    _ => {}
+                }
+                return false;
+            }
+            AUDIO_START..=AUDIO_END => self.audio.write_register(addr, byte),
+            0x5100 => self.prg_mode = byte & 0x03,
+            0x5101 => self.chr_mode = byte & 0x03,
+            0x5102 => self.prg_ram_protect[0] = byte & 0x03,
+            0x5103 => self.prg_ram_protect[1] = byte & 0x03,
+            0x5104 => self.exram_mode = byte & 0x03,
+            0x5105 => self.name_table_mapping = byte,
+            0x5106 => self.fill_tile = byte,
+            0x5107 => self.fill_attr = byte & 0x03,
+            0x5113..=0x5117 => self.prg_regs[addr - 0x5113] = byte,
+            0x5120..=0x5127 => {
+                self.chr_regs_a[addr - 0x5120] = (self.chr_upper << 8) | byte as usize;
+                self.last_chr_set_b = false;
+            }
+            0x5128..=0x512B => {
+                self.chr_regs_b[addr - 0x5128] = (self.chr_upper << 8) | byte as usize;
+                self.last_chr_set_b = true;
+            }
+            0x5130 => self.chr_upper = (byte & 0x03) as usize,
+            0x5200..=0x5202 => {} // vertical split mode is not supported
+            0x5203 => self.irq_compare = byte,
+            0x5204 => self.irq_enabled = (byte & 0x80) != 0,
+            0x5205 => self.multiplicand = byte,
+            0x5206 => self.multiplier = byte,
+            EXRAM_START..=EXRAM_END => match self.exram_mode {
+                // while used as a nametable, ExRAM can only be written to during rendering
+                0 | 1 => self.exram[addr - EXRAM_START] = if self.in_frame { byte } else { 0 },
+                2 => self.exram[addr - EXRAM_START] = byte,
+                _ => {}
+            },
+            PRG_RAM_START..=PRG_ROM_END => {
+                if let PrgTarget::RAM(offset) = self.prg_target(addr) {
+                    if self.prg_ram_writable() && !self.prg_ram.is_empty() {
+                        let len = self.prg_ram.len();
+                        self.prg_ram[offset % len] = byte;
+                    }
+                }
+            }
+            _ => return false
+        }
+
+        true
+    }
+
+    fn mapped_ppu_read(&self, chr_rom: &Vec<u8>, addr: usize) -> u8 {
+        chr_rom[self.chr_addr(addr) % chr_rom.len()]
+    }
+
+    fn mapped_ppu_write(&mut self, chr_ram: &mut Vec<u8>, addr: usize, byte: u8) {
+        let len = chr_ram.len();
+        chr_ram[self.chr_addr(addr) % len] = byte;
+    }
+
+    // Slots mapped to ExRAM or fill mode are handled in `mapped_name_table_read`,
+    // so it doesn't matter which CIRAM page they are given here
+    fn get_updated_mirroring(&self) -> Option<Mirroring> {
+        let mut slots = [NameTableSource::CIRAM(0); 4];
+
+        for (i, slot) in slots.iter_mut().enumerate() {
+            if self.name_table_slot(i) == 1 {
+                *slot = NameTableSource::CIRAM(1);
+            }
+        }
+
+        Some(Mirroring::CUSTOM(slots))
+    }
+
+    fn mapped_name_table_read(&self, _chr_rom: &Vec<u8>, addr: usize) -> Option<u8> {
+        let offset = addr & (NAME_TABLE_PAGE_SIZE - 1);
+
+        // extended attributes give every tile its own palette, no matter which nametable it's from
+        if self.extended_attributes() && offset >= ATTR_TABLE_OFFSET {
+            return Some(((self.ex_attr >> 6) & 0x03) * 0x55);
+        }
+
+        match self.name_table_slot((addr >> 10) & 0x03) {
+            2 => Some(if self.exram_mode <= 1 { self.exram[offset] } else { 0 }),
+            3 => Some(if offset < ATTR_TABLE_OFFSET { self.fill_tile } else { self.fill_attr * 0x55 }),
+            _ => None,
+        }
+    }
+
+    fn mapped_name_table_write(&mut self, _chr_rom: &mut Vec<u8>, addr: usize, byte: u8) -> bool {
+        let offset = addr & (NAME_TABLE_PAGE_SIZE - 1);
+
+        match self.name_table_slot((addr >> 10) & 0x03) {
+            2 => {
+                if self.exram_mode <= 1 {
+                    self.exram[offset] = byte;
+                }
+                true
+            }
+            3 => true,
+            _ => false,
+        }
+    }
+
+    // The PPU fetches the same nametable byte twice in a row only at the end of each scanline,
+    // which is what MMC5 uses to count scanlines
+    fn notify_ppu_read(&mut self, addr: usize) {
+        if addr == self.last_fetch_addr {
+            self.fetch_repeats = self.fetch_repeats.saturating_add(1);
+        } else {
+            self.fetch_repeats = 0;
+        }
+        self.last_fetch_addr = addr;
+
+        if !(0x2000..=0x2FFF).contains(&addr) {
+            return;
+        }
+
+        // Background tiles are always fetched starting with their nametable byte, while each sprite starts with
+        // two garbage fetches of the same nametable byte. A third one only happens at the end of each scanline
+        match self.fetch_repeats {
+            0 => {
+                self.fetching_sprites = false;
+
+                let offset = addr & (NAME_TABLE_PAGE_SIZE - 1);
+                if offset < ATTR_TABLE_OFFSET {
+                    self.ex_attr = self.exram[offset];
+                }
+            }
+            1 => self.fetching_sprites = self.in_frame,
+            2 => self.detect_scanline(),
+            _ => {}
+        }
+    }
+
+    fn expansion_audio_channels(&self) -> &'static [ExpansionChannel] {
+        MMC5_CHANNELS
+    }
+
+    fn clock_expansion_audio(&mut self, levels: &mut [f32]) {
+        self.audio.clock(levels)
+    }
+
+    fn mix_expansion_audio(&self, levels: &[f32]) -> f32 {
+        Mmc5Audio::mix(levels)
+    }
+
+    fn irq_active(&mut self) -> bool {
+        self.irq_enabled && self.irq_pending
+    }
+
+    fn load_trainer(&mut self, trainer: &[u8]) -> bool {
+        copy_trainer(&mut self.prg_ram, trainer)
+    }
+
+    fn get_save_ram(&self) -> Option<&[u8]> {
+        if self.prg_ram.is_empty() { None } else { Some(&self.prg_ram) }
+    }
+
+    fn load_save_ram(&mut self, save_ram: &[u8]) -> bool {
+        self.prg_ram.copy_from_slice(save_ram);
+        !self.prg_ram.is_empty()
+    }
+}
+
+impl Mapper5 {
+    pub fn new(prg_ram_size: usize) -> Self {
+        let mut mapper = Self {
+            prg_ram: vec![0; prg_ram_size],
+            exram: [0; EXRAM_SIZE],
+
+            prg_mode: 3,
+            chr_mode: 0,
+            prg_ram_protect: [0; 2],
+            exram_mode: 0,
+            name_table_mapping: 0,
+            fill_tile: 0,
+            fill_attr: 0,
+
+            prg_regs: [0; 5],
+            chr_regs_a: [0; 8],
+            chr_regs_b: [0; 4],
+            chr_upper: 0,
+            last_chr_set_b: false,
+
+            irq_compare: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            in_frame: false,
+            scanline: 0,
+
+            multiplicand: 0,
+            multiplier: 0,
+
+            sprite_8x16: false,
+
+            last_fetch_addr: 0,
+            fetch_repeats: 0,
+            fetching_sprites: false,
+            ex_attr: 0,
+
+            audio: Mmc5Audio::new(),
+        };
+
+        mapper.reset();
+        mapper
+    }
+
+    fn product(&self) -> u16 {
+        self.multiplicand as u16 * self.multiplier as u16
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [0b10, 0b01]
+    }
+
+    fn name_table_slot(&self, slot: usize) -> u8 {
+        (self.name_table_mapping >> (slot * 2)) & 0x03
+    }
+
+    fn extended_attributes(&self) -> bool {
+        self.exram_mode == 1 && self.in_frame && !self.fetching_sprites
+    }
+
+    fn detect_scanline(&mut self) {
+        if self.in_frame {
+            self.scanline += 1;
+
+            if self.scanline == self.irq_compare {
+                self.irq_pending = true;
+            }
+
+            if self.scanline >= VISIBLE_SCANLINES {
+                self.in_frame = false;
+            }
+        } else {
+            self.in_frame = true;
+            self.scanline = 0;
+            self.irq_pending = false;
+        }
+
+        self.fetching_sprites = false;
+    }
+
+    // $6000 - $7FFF is always RAM, and $E000 - $FFFF is always ROM.
+    // Banks bigger than 8KB ignore the low bits of their bank number.
+    fn prg_target(&self, addr: usize) -> PrgTarget {
+        let (reg, bank_size) = match (self.prg_mode, addr) {
+            (_, PRG_RAM_START..=PRG_RAM_END) => {
+                let bank = (self.prg_regs[0] & 0x0F) as usize;
+                return PrgTarget::RAM(bank * PRG_RAM_BANK_SIZE + (addr & (PRG_RAM_BANK_SIZE - 1)));
+            }
+            (0, _)               => (4, 0x8000),
+            (1, 0x8000..=0xBFFF) => (2, 0x4000),
+            (1, _)               => (4, 0x4000),
+            (2, 0x8000..=0xBFFF) => (2, 0x4000),
+            (2, 0xC000..=0xDFFF) => (3, 0x2000),
+            (2, _)               => (4, 0x2000),
+            (_, _)               => (1 + ((addr - PRG_ROM_START) >> 13), 0x2000),
+        };
+
+        let value = self.prg_regs[reg] as usize;
+        let bank = value & !(bank_size / PRG_BANK_SIZE - 1);
+        let offset = addr & (bank_size - 1);
+
+        if reg == 4 || value & 0x80 != 0 {
+            PrgTarget::ROM((bank & 0x7F) * PRG_BANK_SIZE + offset)
+        } else {
+            PrgTarget::RAM((bank & 0x0F) * PRG_RAM_BANK_SIZE + offset)
+        }
+    }
+
+    fn chr_addr(&self, addr: usize) -> usize {
+        // extended attributes also select a 4KB CHR bank for each background tile
+        if self.extended_attributes() {
+            let bank = (self.chr_upper << 6) | (self.ex_attr & 0x3F) as usize;
+            return bank * (CHR_BANK_SIZE << 2) + (addr & 0x0FFF);
+        }
+
+        let use_set_b = if self.sprite_8x16 && self.in_frame {
+            !self.fetching_sprites
+        } else {
+            self.last_chr_set_b
+        };
+
+        // mode 0 uses 8KB banks, down to 1KB banks in mode 3
+        let bank_size = (CHR_BANK_SIZE << 3) >> self.chr_mode;
+        let slot = addr / bank_size;
+
+        // bigger banks are selected by the last register of the ones they cover
+        let reg = (slot + 1) * (8 >> self.chr_mode) - 1;
+
+        let bank = if use_set_b {
+            self.chr_regs_b[reg & 0x03]
+        } else {
+            self.chr_regs_a[reg]
+        };
+
+        bank * bank_size + (addr & (bank_size - 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::CartridgeNes;
+    use crate::ppu::PpuBus;
+    use crate::mapper::TestRom;
+
+    const PRG_BANKS_8K: usize = 16;
+    const CHR_BANKS_1K: usize = 32;
+
+    #[test]
+    pub fn test_prg_banking() {
+        let mut cartridge = TestRom::ines(5, PRG_BANKS_8K * 0x2000, CHR_BANKS_1K * 0x400)
+            .prg_banks(0x2000)
+            .chr_banks(0x400)
+            .cartridge();
+
+        // powers on in mode 3, with the last bank at $E000
+        assert_eq!(cartridge.cpu_read(0xE000), Some(PRG_BANKS_8K as u8 - 1));
+
+        cartridge.cpu_write(0x5114, 0x83);
+        cartridge.cpu_write(0x5115, 0x85);
+        cartridge.cpu_write(0x5116, 0x87);
+        cartridge.cpu_write(0x5117, 0x09);
+        assert_eq!(cartridge.cpu_read(0x8000), Some(3));
+        assert_eq!(cartridge.cpu_read(0xA000), Some(5));
+        assert_eq!(cartridge.cpu_read(0xC000), Some(7));
+        assert_eq!(cartridge.cpu_read(0xE000), Some(9));
+
+        // 16KB banks ignore bit 0
+        cartridge.cpu_write(0x5100, 1);
+        assert_eq!(cartridge.cpu_read(0x8000), Some(4));
+        assert_eq!(cartridge.cpu_read(0xA000), Some(5));
+        assert_eq!(cartridge.cpu_read(0xC000), Some(8));
+        assert_eq!(cartridge.cpu_read(0xE000), Some(9));
+
+        cartridge.cpu_write(0x5100, 0);
+        assert_eq!(cartridge.cpu_read(0x8000), Some(8));
+        assert_eq!(cartridge.cpu_read(0xE000), Some(11));
+    }
+
+    #[test]
+    pub fn test_prg_ram() {
+        let mut cartridge = TestRom::ines(5, PRG_BANKS_8K * 0x2000, CHR_BANKS_1K * 0x400)
+            .prg_banks(0x2000)
+            .chr_banks(0x400)
+            .cartridge();
+
+        // writes are ignored until both protect registers are set
+        cartridge.cpu_write(0x6000, 0xAA);
+        assert_eq!(cartridge.cpu_read(0x6000), Some(0));
+
+        cartridge.cpu_write(0x5102, 0x02);
+        cartridge.cpu_write(0x5103, 0x01);
+
+        for bank in 0..4 {
+            cartridge.cpu_write(0x5113, bank);
+            cartridge.cpu_write(0x6000, bank + 1);
+        }
+
+        cartridge.cpu_write(0x5113, 2);
+        assert_eq!(cartridge.cpu_read(0x6000), Some(3));
+
+        // RAM can be also banked into $8000 - $DFFF
+        cartridge.cpu_write(0x5114, 0x01);
+        assert_eq!(cartridge.cpu_read(0x8000), Some(2));
+        cartridge.cpu_write(0x8000, 0x55);
+        cartridge.cpu_write(0x5113, 1);
+        assert_eq!(cartridge.cpu_read(0x6000), Some(0x55));
+
+        // iNES files are given 64KB so they work with any MMC5 game
+        assert_eq!(cartridge.get_save_ram().unwrap().len(), 0x10000);
+    }
+
+    #[test]
+    pub fn test_chr_banking() {
+        let mut cartridge = TestRom::ines(5, PRG_BANKS_8K * 0x2000, CHR_BANKS_1K * 0x400)
+            .prg_banks(0x2000)
+            .chr_banks(0x400)
+            .cartridge();
+
+        cartridge.cpu_write(0x5101, 3);
+        for reg in 0..8 {
+            cartridge.cpu_write(0x5120 + reg, 31 - reg as u8);
+        }
+        for slot in 0..8 {
+            assert_eq!(cartridge.ppu_read(slot * 0x400), 31 - slot as u8);
+        }
+
+        // writing to set B switches everything over to it, with each register mirrored across both halves
+        cartridge.cpu_write(0x5128, 4);
+        cartridge.cpu_write(0x512B, 7);
+        assert_eq!(cartridge.ppu_read(0x0000), 4);
+        assert_eq!(cartridge.ppu_read(0x1000), 4);
+        assert_eq!(cartridge.ppu_read(0x1C00), 7);
+
+        // 2KB banks use the odd registers
+        cartridge.cpu_write(0x5101, 2);
+        cartridge.cpu_write(0x5123, 3);
+        assert_eq!(cartridge.ppu_read(0x0800), 6);
+        assert_eq!(cartridge.ppu_read(0x0C00), 7);
+    }
+
+    #[test]
+    pub fn test_multiplier() {
+        let mut cartridge = TestRom::ines(5, PRG_BANKS_8K * 0x2000, CHR_BANKS_1K * 0x400)
+            .prg_banks(0x2000)
+            .chr_banks(0x400)
+            .cartridge();
+
+        cartridge.cpu_write(0x5205, 200);
+        cartridge.cpu_write(0x5206, 123);
+        assert_eq!(cartridge.cpu_read(0x5205), Some((24600 & 0xFF) as u8));
+        assert_eq!(cartridge.cpu_read(0x5206), Some((24600 >> 8) as u8));
+    }
+
+    #[test]
+    pub fn test_name_tables() {
+        let mut cartridge = TestRom::ines(5, PRG_BANKS_8K * 0x2000, CHR_BANKS_1K * 0x400)
+            .prg_banks(0x2000)
+            .chr_banks(0x400)
+            .cartridge();
+        let mut ppu_bus = PpuBus::new();
+
+        // CIRAM 0, CIRAM 1, ExRAM, fill mode
+        cartridge.cpu_write(0x5105, 0b11100100);
+        cartridge.cpu_write(0x5106, 0x42);
+        cartridge.cpu_write(0x5107, 0x02);
+
+        ppu_bus.ppu_write(0x2000, 1, &mut cartridge);
+        ppu_bus.ppu_write(0x2400, 2, &mut cartridge);
+        ppu_bus.ppu_write(0x2800, 3, &mut cartridge);
+        ppu_bus.ppu_write(0x2C00, 4, &mut cartridge);
+
+        assert_eq!(ppu_bus.ppu_read(0x2000, &cartridge), 1);
+        assert_eq!(ppu_bus.ppu_read(0x2400, &cartridge), 2);
+        assert_eq!(ppu_bus.ppu_read(0x2800, &cartridge), 3);
+        assert_eq!(ppu_bus.ppu_read(0x2C00, &cartridge), 0x42);
+        assert_eq!(ppu_bus.ppu_read(0x2FC0, &cartridge), 0xAA);
+
+        // ExRAM can only be read back by the CPU in mode 2 or 3
+        assert_eq!(cartridge.cpu_read(0x5C00), None);
+        cartridge.cpu_write(0x5104, 2);
+        assert_eq!(cartridge.cpu_read(0x5C00), Some(3));
+        assert_eq!(ppu_bus.ppu_read(0x2800, &cartridge), 0);
+    }
+
+    #[test]
+    pub fn test_scanline_irq() {
+        let mut cartridge = TestRom::ines(5, PRG_BANKS_8K * 0x2000, CHR_BANKS_1K * 0x400)
+            .prg_banks(0x2000)
+            .chr_banks(0x400)
+            .cartridge();
+
+        cartridge.cpu_write(0x5203, 3);
+        cartridge.cpu_write(0x5204, 0x80);
+
+        // end of each scanline: one normal tile fetch followed by three identical nametable fetches
+        let end_scanline = |cartridge: &mut CartridgeNes| {
+            cartridge.notify_ppu_read(0x0010);
+            cartridge.notify_ppu_read(0x2000);
+            cartridge.notify_ppu_read(0x2000);
+            cartridge.notify_ppu_read(0x2000);
+        };
+
+        // the pre-render scanline only starts the frame
+        end_scanline(&mut cartridge);
+        assert_eq!(cartridge.cpu_read(0x5204), Some(0x40));
+
+        for _ in 0..2 {
+            end_scanline(&mut cartridge);
+            assert!(!cartridge.irq_active());
+        }
+
+        end_scanline(&mut cartridge);
+        assert!(cartridge.irq_active());
+
+        // reading the status acknowledges the IRQ
+        assert_eq!(cartridge.cpu_read(0x5204), Some(0xC0));
+        assert!(!cartridge.irq_active());
+
+        // the frame ends after the last visible scanline
+        for _ in 3..240 {
+            end_scanline(&mut cartridge);
+        }
+        assert_eq!(cartridge.cpu_read(0x5204), Some(0x00));
+    }
+
+    #[test]
+    pub fn test_pulse_audio() {
+        let mut cartridge = TestRom::ines(5, PRG_BANKS_8K * 0x2000, CHR_BANKS_1K * 0x400)
+            .prg_banks(0x2000)
+            .chr_banks(0x400)
+            .cartridge();
+
+        let mut levels = [0.0; 3];
+        cartridge.clock_expansion_audio(&mut levels);
+        assert_eq!(cartridge.mix_expansion_audio(&levels), 0.0);
+
+        // constant volume 15, 50% duty and a length counter that won't run out
+        cartridge.cpu_write(0x5015, 0x01);
+        cartridge.cpu_write(0x5000, 0b10111111);
+        cartridge.cpu_write(0x5002, 0x10);
+        cartridge.cpu_write(0x5003, 0x08);
+        assert_eq!(cartridge.cpu_read(0x5015), Some(0x01));
+
+        let samples: Vec<f32> = (0..0x400).map(|_| {
+            cartridge.clock_expansion_audio(&mut levels);
+            levels[0]
+        }).collect();
+        assert!(samples.iter().any(|&s| s == 15.0));
+        assert!(samples.iter().any(|&s| s == 0.0));
+        assert!(cartridge.mix_expansion_audio(&[15.0, 0.0, 0.0]) > 0.0);
+
+        cartridge.cpu_write(0x5015, 0x00);
+        assert_eq!(cartridge.cpu_read(0x5015), Some(0x00));
+    }
+}
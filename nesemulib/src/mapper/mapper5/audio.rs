@@ -0,0 +1,247 @@
+use crate::apu::{Envelope, LengthCounter, DUTY_SEQUENCES, PULSE_TABLE, TND_TABLE};
+use crate::mapper::ExpansionChannel;
+use crate::{SystemControl, StateReader, StateWriter};
+
+pub const MMC5_CHANNELS: &[ExpansionChannel] = &[
+    ExpansionChannel { name: "MMC5 Pulse 1", min_level: 0.0, max_level: 15.0 },
+    ExpansionChannel { name: "MMC5 Pulse 2", min_level: 0.0, max_level: 15.0 },
+    ExpansionChannel { name: "MMC5 PCM", min_level: 0.0, max_level: 255.0 },
+];
+
+// MMC5 has no frame sequencer; its envelopes and length counters are clocked at a fixed ~240Hz
+const FRAME_PERIOD: u32 = 7457;
+
+/// Same as the APU's pulse channels, but without a sweep unit
+struct PulseMmc5 {
+    duty_sequence: u8,
+    duty_step: u8,
+
+    length_counter: LengthCounter,
+    envelope: Envelope,
+
+    period: u32,
+    cycles: u32,
+}
+
+impl SystemControl for PulseMmc5 {
+    fn reset(&mut self) {
+        self.duty_sequence = DUTY_SEQUENCES[0];
+        self.duty_step = 0;
+        self.length_counter.reset();
+        self.envelope.reset();
+        self.period = 0;
+        self.cycles = 0;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.duty_sequence);
+        state.write_u8(self.duty_step);
+        self.length_counter.save_state(state);
+        self.envelope.save_state(state);
+        state.write_u32(self.period);
+        state.write_u32(self.cycles);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.duty_sequence = state.read_u8()?;
+        self.duty_step = state.read_u8()? & 0x07;
+        self.length_counter.load_state(state)?;
+        self.envelope.load_state(state)?;
+        self.period = state.read_u32()? & 0x7FF;
+        self.cycles = state.read_u32()? & 0x7FF;
+        Ok(())
+    }
+}
+
+impl PulseMmc5 {
+    fn new() -> Self {
+        Self {
+            duty_sequence: DUTY_SEQUENCES[0],
+            duty_step: 0,
+
+            length_counter: LengthCounter::new(),
+            envelope: Envelope::new(),
+
+            period: 0,
+            cycles: 0,
+        }
+    }
+
+    // Unlike the APU's pulses, low periods aren't muted, so these can reach ultrasonic frequencies
+    fn clock(&mut self) -> u8 {
+        if self.cycles == 0 {
+            self.cycles = self.period;
+            self.duty_step = (self.duty_step + 1) & 0x07;
+        } else {
+            self.cycles -= 1;
+        }
+
+        if self.length_counter.silenced() {
+            return 0;
+        }
+
+        ((self.duty_sequence & (1 << self.duty_step)) != 0) as u8 * self.envelope.output_volume()
+    }
+
+    fn write_register(&mut self, reg: usize, byte: u8) {
+        match reg {
+            0 => {
+                self.duty_sequence = DUTY_SEQUENCES[((byte & 0b11000000) >> 6) as usize];
+
+                self.envelope.loop_flag = (byte & 0b00100000) != 0;
+                self.length_counter.halted = (byte & 0b00100000) != 0;
+
+                self.envelope.constant_flag = (byte & 0b00010000) != 0;
+
+                self.envelope.set_volume(byte & 0b00001111);
+            },
+            2 => {
+                self.period &= 0b11100000000;
+                self.period |= byte as u32;
+            },
+            3 => {
+                self.length_counter.load_counter((byte & 0b11111000) >> 3);
+                self.envelope.start_flag = true;
+
+                self.period &= 0b00011111111;
+                self.period |= ((byte as u32) & 0b00000111) << 8;
+
+                self.duty_step = 0;
+            },
+            _ => {} // no sweep unit
+        }
+    }
+}
+
+/// MMC5's expansion audio: two pulse channels and an 8-bit PCM channel
+pub struct Mmc5Audio {
+    pulse1: PulseMmc5,
+    pulse2: PulseMmc5,
+
+    pcm_output: u8,
+    // read mode plays back bytes the CPU reads from $8000-$BFFF, which no game uses
+    pcm_read_mode: bool,
+
+    pulse1_sample: u8,
+    pulse2_sample: u8,
+
+    frame_cycles: u32,
+    total_cycles: u32,
+}
+
+impl SystemControl for Mmc5Audio {
+    fn reset(&mut self) {
+        self.pulse1.reset();
+        self.pulse2.reset();
+        self.pcm_output = 0;
+        self.pcm_read_mode = false;
+        self.pulse1_sample = 0;
+        self.pulse2_sample = 0;
+        self.frame_cycles = 0;
+        self.total_cycles = 0;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.pulse1.save_state(state);
+        self.pulse2.save_state(state);
+        state.write_u8(self.pcm_output);
+        state.write_bool(self.pcm_read_mode);
+        state.write_u8(self.pulse1_sample);
+        state.write_u8(self.pulse2_sample);
+        state.write_u32(self.frame_cycles);
+        state.write_u32(self.total_cycles);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.pulse1.load_state(state)?;
+        self.pulse2.load_state(state)?;
+        self.pcm_output = state.read_u8()?;
+        self.pcm_read_mode = state.read_bool()?;
+        self.pulse1_sample = state.read_u8()? & 0x0F;
+        self.pulse2_sample = state.read_u8()? & 0x0F;
+        self.frame_cycles = state.read_u32()? % FRAME_PERIOD;
+        self.total_cycles = state.read_u32()? % 2;
+        Ok(())
+    }
+}
+
+impl Mmc5Audio {
+    pub fn new() -> Self {
+        Self {
+            pulse1: PulseMmc5::new(),
+            pulse2: PulseMmc5::new(),
+
+            pcm_output: 0,
+            pcm_read_mode: false,
+
+            pulse1_sample: 0,
+            pulse2_sample: 0,
+
+            frame_cycles: 0,
+            total_cycles: 0,
+        }
+    }
+
+    /// Clocked once per CPU cycle, writing the output level of each channel in `MMC5_CHANNELS` to `levels`
+    pub fn clock(&mut self, levels: &mut [f32]) {
+        self.frame_cycles += 1;
+
+        if self.frame_cycles >= FRAME_PERIOD {
+            self.frame_cycles = 0;
+
+            self.pulse1.envelope.clock();
+            self.pulse2.envelope.clock();
+            self.pulse1.length_counter.clock();
+            self.pulse2.length_counter.clock();
+        }
+
+        self.total_cycles += 1;
+
+        if self.total_cycles % 2 == 0 {
+            self.pulse1_sample = self.pulse1.clock();
+            self.pulse2_sample = self.pulse2.clock();
+        }
+
+        levels[0] = self.pulse1_sample as f32;
+        levels[1] = self.pulse2_sample as f32;
+        levels[2] = self.pcm_output as f32;
+    }
+
+    // The pulses are mixed just like the APU's, and the PCM channel is about as loud as the DMC at the same level
+    pub fn mix(levels: &[f32]) -> f32 {
+        PULSE_TABLE[(levels[0] + levels[1]) as usize] + TND_TABLE[levels[2] as usize >> 1]
+    }
+
+    pub fn read_register(&mut self, addr: usize) -> Option<u8> {
+        match addr {
+            0x5015 => {
+                let mut byte = 0;
+
+                if self.pulse1.length_counter.counter > 0 { byte |= 1 << 0; }
+                if self.pulse2.length_counter.counter > 0 { byte |= 1 << 1; }
+
+                Some(byte)
+            }
+            _ => None
+        }
+    }
+
+    pub fn write_register(&mut self, addr: usize, byte: u8) {
+        match addr {
+            0x5000..=0x5003 => self.pulse1.write_register(addr & 0x03, byte),
+            0x5004..=0x5007 => self.pulse2.write_register(addr & 0x03, byte),
+            0x5010 => self.pcm_read_mode = (byte & 0x01) != 0,
+            0x5011 => {
+                // writing 0 has no effect, as it's used to mark the end of a sample in read mode
+                if !self.pcm_read_mode && byte != 0 {
+                    self.pcm_output = byte;
+                }
+            }
+            0x5015 => {
+                self.pulse1.length_counter.set_enabled_flag((byte & 0x01) != 0);
+                self.pulse2.length_counter.set_enabled_flag((byte & 0x02) != 0);
+            }
+            _ => {}
+        }
+    }
+}
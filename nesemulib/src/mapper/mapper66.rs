@@ -1,8 +1,28 @@
-use crate::{cartridge::{CHR_ROM_SIZE, PRG_ROM_SIZE}, SystemControl};
+use crate::{cartridge::{CHR_ROM_SIZE, PRG_ROM_SIZE}, SystemControl, StateReader, StateWriter};
 
 use super::{Mapper, PRG_ROM_END, PRG_ROM_START};
 
+/// Boards with a single latch that selects a 32KB PRG bank and an 8KB CHR bank, like GxROM.
+/// They only differ in where the latch is and which bits go where
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LatchBoard {
+    /// Latch at $8000-$FFFF: ..PP ..CC (mapper 66)
+    GXROM,
+    /// Latch at $8000-$FFFF: CCCC ..PP (mapper 11)
+    COLOR_DREAMS,
+    /// Latch at $7000-$7FFF: .... CCPP (mapper 38)
+    BIT_CORP,
+    /// Latch at $4100-$5FFF wherever A8 is set: .... PCCC (mapper 79)
+    NINA_03_06,
+    /// Latch at $6000-$7FFF: .... ..LH, with the two CHR bits swapped and no PRG banking (mapper 87)
+    JALECO_JF_87,
+    /// Latch at $6000-$7FFF: ..PP CCCC (mapper 140)
+    JALECO_JF_11_14,
+}
+
 pub struct Mapper66 {
+    board: LatchBoard,
     prg_rom_select: usize,
     chr_rom_select: usize,
 }
@@ -12,40 +32,113 @@ impl SystemControl for Mapper66 {
         self.prg_rom_select = 0;
         self.chr_rom_select = 0;
     }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_usize(self.prg_rom_select);
+        state.write_usize(self.chr_rom_select);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.prg_rom_select = state.read_usize()? & 0x03;
+        self.chr_rom_select = state.read_usize()? & 0x0F;
+
+        Ok(())
+    }
 }
 
 impl Mapper for Mapper66 {
-    fn mapped_cpu_read(&self, prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8> {
+    fn mapped_cpu_read(&mut self, prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8> {
         match addr {
             PRG_ROM_START..=PRG_ROM_END => {
-                Some(prg_rom[self.prg_rom_select * (PRG_ROM_SIZE << 1) + (addr & 0x7FFF)])
+                Some(prg_rom[(self.prg_rom_select * (PRG_ROM_SIZE << 1) + (addr & 0x7FFF)) % prg_rom.len()])
             }
             _ => None
         }
     }
 
     fn mapped_cpu_write(&mut self, _prg_rom: &mut Vec<u8>, addr: usize, byte: u8) -> bool {
-        match addr {
-            PRG_ROM_START..=PRG_ROM_END => {
-                self.chr_rom_select = (byte & 0b00000011) as usize;
-                self.prg_rom_select = ((byte & 0b00110000) >> 4) as usize;
+        let byte = byte as usize;
 
-                true
-            }
-            _ => false
-        }
+        let (prg_rom_select, chr_rom_select) = match (self.board, addr) {
+            (LatchBoard::GXROM, PRG_ROM_START..=PRG_ROM_END) => ((byte & 0b00110000) >> 4, byte & 0b00000011),
+            (LatchBoard::COLOR_DREAMS, PRG_ROM_START..=PRG_ROM_END) => (byte & 0b00000011, (byte & 0b11110000) >> 4),
+            (LatchBoard::BIT_CORP, 0x7000..=0x7FFF) => (byte & 0b00000011, (byte & 0b00001100) >> 2),
+            (LatchBoard::NINA_03_06, 0x4100..=0x5FFF) if addr & 0x0100 != 0 => ((byte & 0b00001000) >> 3, byte & 0b00000111),
+            (LatchBoard::JALECO_JF_87, 0x6000..=0x7FFF) => (0, ((byte & 0b01) << 1) | ((byte & 0b10) >> 1)),
+            (LatchBoard::JALECO_JF_11_14, 0x6000..=0x7FFF) => ((byte & 0b00110000) >> 4, byte & 0b00001111),
+            _ => return false
+        };
+
+        self.prg_rom_select = prg_rom_select;
+        self.chr_rom_select = chr_rom_select;
+
+        true
     }
 
     fn mapped_ppu_read(&self, chr_rom: &Vec<u8>, addr: usize) -> u8 {
-        chr_rom[self.chr_rom_select * CHR_ROM_SIZE + addr]
+        chr_rom[(self.chr_rom_select * CHR_ROM_SIZE + addr) % chr_rom.len()]
+    }
+
+    fn mapped_ppu_write(&mut self, chr_ram: &mut Vec<u8>, addr: usize, byte: u8) {
+        let len = chr_ram.len();
+        chr_ram[(self.chr_rom_select * CHR_ROM_SIZE + addr) % len] = byte;
+    }
+
+    fn registers_at_trainer(&self) -> bool {
+        matches!(self.board, LatchBoard::BIT_CORP | LatchBoard::JALECO_JF_87 | LatchBoard::JALECO_JF_11_14)
     }
 }
 
 impl Mapper66 {
-    pub fn new() -> Self {
+    pub fn new(board: LatchBoard) -> Self {
         Self {
+            board,
             prg_rom_select: 0,
             chr_rom_select: 0,
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mapper::TestRom;
+
+    #[test]
+    pub fn test_latches() {
+        // (mapper, latch address, latch value, PRG bank, CHR bank)
+        let boards = [
+            (66, 0x8000, 0x21, 2, 1),
+            (11, 0xFFFF, 0x72, 2, 7),
+            (38, 0x7000, 0x0B, 3, 2),
+            (79, 0x4100, 0x0D, 1, 5),
+            (79, 0x5F00, 0x0D, 1, 5),
+            (87, 0x6000, 0x01, 0, 2),
+            (140, 0x7FFF, 0x3C, 3, 12),
+        ];
+
+        for (mapper_num, addr, byte, prg_bank, chr_bank) in boards {
+            let mut cartridge = TestRom::ines(mapper_num, 0x20000, 0x20000)
+                .prg_banks(0x8000)
+                .chr_banks(0x2000)
+                .cartridge();
+
+            assert!(cartridge.cpu_write(addr, byte));
+            assert_eq!(cartridge.cpu_read(0x8000), Some(prg_bank), "mapper {}", mapper_num);
+            assert_eq!(cartridge.ppu_read(0x0000), chr_bank, "mapper {}", mapper_num);
+        }
+    }
+
+    #[test]
+    pub fn test_latch_addresses() {
+        // NINA-03/06 only decodes addresses with A8 set
+        let mut cartridge = TestRom::ines(79, 0x20000, 0x20000).prg_banks(0x8000).chr_banks(0x2000).cartridge();
+        assert!(!cartridge.cpu_write(0x4000, 0x0F));
+        assert!(!cartridge.cpu_write(0x4200, 0x0F));
+        assert_eq!(cartridge.ppu_read(0x0000), 0);
+
+        // Bit Corp's latch is only in the upper half of $6000-$7FFF
+        let mut cartridge = TestRom::ines(38, 0x20000, 0x20000).prg_banks(0x8000).chr_banks(0x2000).cartridge();
+        assert!(!cartridge.cpu_write(0x6000, 0x0F));
+        assert_eq!(cartridge.cpu_read(0x8000), Some(0));
+    }
+}
@@ -0,0 +1,363 @@
+mod audio;
+
+use crate::{cartridge::Mirroring, SystemControl, StateReader, StateWriter};
+
+use self::audio::{Sunsoft5bAudio, SUNSOFT_5B_CHANNELS};
+use super::{ExpansionChannel, copy_trainer, Mapper, PRG_RAM_END, PRG_RAM_START, PRG_ROM_END, PRG_ROM_START};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// FME-7, and the Sunsoft 5B which adds expansion audio to it. Plain FME-7 boards ignore writes to the
+/// audio registers, so both are treated as a 5B
+pub struct Mapper69 {
+    prg_ram: Vec<u8>,
+    prg_rom_banks: usize,
+
+    command: u8,
+    chr_regs: [usize; 8],
+    // $6000 bank: ER.B BBBB (RAM enable, RAM instead of ROM, bank)
+    prg_bank_6000: u8,
+    prg_banks: [usize; 3],
+    mirroring: Mirroring,
+
+    irq_enabled: bool,
+    irq_counter_enabled: bool,
+    irq_counter: u16,
+    irq_pending: bool,
+
+    audio: Sunsoft5bAudio,
+}
+
+impl SystemControl for Mapper69 {
+    fn reset(&mut self) {
+        self.command = 0;
+        self.chr_regs = [0; 8];
+        self.prg_bank_6000 = 0;
+        self.prg_banks = [0; 3];
+        self.mirroring = Mirroring::VERTICAL;
+
+        self.irq_enabled = false;
+        self.irq_counter_enabled = false;
+        self.irq_counter = 0;
+        self.irq_pending = false;
+
+        self.audio.reset();
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+
+        state.write_u8(self.command);
+        for value in &self.chr_regs {
+            state.write_usize(*value);
+        }
+        state.write_u8(self.prg_bank_6000);
+        for value in &self.prg_banks {
+            state.write_usize(*value);
+        }
+        self.mirroring.save_state(state);
+
+        state.write_bool(self.irq_enabled);
+        state.write_bool(self.irq_counter_enabled);
+        state.write_u16(self.irq_counter);
+        state.write_bool(self.irq_pending);
+
+        self.audio.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.read_bytes(&mut self.prg_ram)?;
+
+        self.command = state.read_u8()? & 0x0F;
+        for value in &mut self.chr_regs {
+            *value = state.read_usize()? & 0xFF;
+        }
+        self.prg_bank_6000 = state.read_u8()?;
+        for value in &mut self.prg_banks {
+            *value = state.read_usize()? & 0x3F;
+        }
+        self.mirroring = Mirroring::load_state(state)?;
+
+        self.irq_enabled = state.read_bool()?;
+        self.irq_counter_enabled = state.read_bool()?;
+        self.irq_counter = state.read_u16()?;
+        self.irq_pending = state.read_bool()?;
+
+        self.audio.load_state(state)?;
+
+        Ok(())
+    }
+}
+
+impl Mapper for Mapper69 {
+    fn mapped_cpu_read(&mut self, prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8> {
+        let bank = match addr {
+            PRG_RAM_START..=PRG_RAM_END if self.prg_bank_6000 & 0b01000000 != 0 => {
+                // disabled RAM is open bus
+                if !self.prg_ram_enabled() {
+                    return None;
+                }
+
+                let index = (self.prg_bank_6000 & 0x3F) as usize * PRG_BANK_SIZE + (addr - PRG_RAM_START);
+                return Some(self.prg_ram[index % self.prg_ram.len()]);
+            }
+            PRG_RAM_START..=PRG_RAM_END => (self.prg_bank_6000 & 0x3F) as usize,
+            0xE000..=PRG_ROM_END => self.prg_rom_banks * 2 - 1,
+            PRG_ROM_START..=0xDFFF => self.prg_banks[(addr - PRG_ROM_START) >> 13],
+            _ => return None
+        };
+
+        Some(prg_rom[(bank * PRG_BANK_SIZE + (addr & (PRG_BANK_SIZE - 1))) % prg_rom.len()])
+    }
+
+    fn mapped_cpu_write(&mut self, _prg_rom: &mut Vec<u8>, addr: usize, byte: u8) -> bool {
+        match addr {
+            PRG_RAM_START..=PRG_RAM_END => {
+                if self.prg_ram_enabled() && self.prg_bank_6000 & 0b01000000 != 0 {
+                    let len = self.prg_ram.len();
+                    let index = (self.prg_bank_6000 & 0x3F) as usize * PRG_BANK_SIZE + (addr - PRG_RAM_START);
+                    self.prg_ram[index % len] = byte;
+                }
+                return !self.prg_ram.is_empty();
+            }
+            0x8000..=0x9FFF => self.command = byte & 0x0F,
+            0xA000..=0xBFFF => self.write_parameter(byte),
+            0xC000..=0xDFFF => self.audio.select_register(byte),
+            0xE000..=0xFFFF => self.audio.write_register(byte),
+            _ => return false
+        }
+
+        true
+    }
+
+    fn mapped_ppu_read(&self, chr_rom: &Vec<u8>, addr: usize) -> u8 {
+        chr_rom[(self.chr_regs[addr >> 10] * CHR_BANK_SIZE + (addr & (CHR_BANK_SIZE - 1))) % chr_rom.len()]
+    }
+
+    fn mapped_ppu_write(&mut self, chr_ram: &mut Vec<u8>, addr: usize, byte: u8) {
+        let len = chr_ram.len();
+        chr_ram[(self.chr_regs[addr >> 10] * CHR_BANK_SIZE + (addr & (CHR_BANK_SIZE - 1))) % len] = byte;
+    }
+
+    fn get_updated_mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    // The counter decrements every CPU cycle while enabled, firing when it wraps around from 0 to $FFFF
+    fn cpu_clock(&mut self) {
+        if !self.irq_counter_enabled {
+            return;
+        }
+
+        self.irq_counter = self.irq_counter.wrapping_sub(1);
+
+        if self.irq_counter == 0xFFFF && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+
+    fn expansion_audio_channels(&self) -> &'static [ExpansionChannel] {
+        SUNSOFT_5B_CHANNELS
+    }
+
+    fn clock_expansion_audio(&mut self, levels: &mut [f32]) {
+        self.audio.clock(levels)
+    }
+
+    fn mix_expansion_audio(&self, levels: &[f32]) -> f32 {
+        Sunsoft5bAudio::mix(levels)
+    }
+
+    fn irq_active(&mut self) -> bool {
+        self.irq_pending
+    }
+
+    fn load_trainer(&mut self, trainer: &[u8]) -> bool {
+        copy_trainer(&mut self.prg_ram, trainer)
+    }
+
+    fn get_save_ram(&self) -> Option<&[u8]> {
+        if self.prg_ram.is_empty() { None } else { Some(&self.prg_ram) }
+    }
+
+    fn load_save_ram(&mut self, save_ram: &[u8]) -> bool {
+        self.prg_ram.copy_from_slice(save_ram);
+        !self.prg_ram.is_empty()
+    }
+}
+
+impl Mapper69 {
+    pub fn new(prg_rom_banks: usize, prg_ram_size: usize) -> Self {
+        Self {
+            prg_ram: vec![0; prg_ram_size],
+            prg_rom_banks,
+
+            command: 0,
+            chr_regs: [0; 8],
+            prg_bank_6000: 0,
+            prg_banks: [0; 3],
+            mirroring: Mirroring::VERTICAL,
+
+            irq_enabled: false,
+            irq_counter_enabled: false,
+            irq_counter: 0,
+            irq_pending: false,
+
+            audio: Sunsoft5bAudio::new(),
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        !self.prg_ram.is_empty() && (self.prg_bank_6000 & 0b10000000) != 0
+    }
+
+    // Writes to $A000-$BFFF go to whichever register the last write to $8000-$9FFF selected
+    fn write_parameter(&mut self, byte: u8) {
+        match self.command {
+            0x0..=0x7 => self.chr_regs[self.command as usize] = byte as usize,
+            0x8 => self.prg_bank_6000 = byte,
+            0x9..=0xB => self.prg_banks[(self.command - 0x9) as usize] = (byte & 0x3F) as usize,
+            0xC => {
+                self.mirroring = match byte & 0x03 {
+                    0 => Mirroring::VERTICAL,
+                    1 => Mirroring::HORIZONTAL,
+                    2 => Mirroring::ONESCREEN_LO,
+                    _ => Mirroring::ONESCREEN_HI,
+                };
+            }
+            // writing the IRQ control register also acknowledges the IRQ
+            0xD => {
+                self.irq_enabled = (byte & 0b00000001) != 0;
+                self.irq_counter_enabled = (byte & 0b10000000) != 0;
+                self.irq_pending = false;
+            }
+            0xE => self.irq_counter = (self.irq_counter & 0xFF00) | byte as u16,
+            _ => self.irq_counter = (self.irq_counter & 0x00FF) | (byte as u16) << 8,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::CartridgeNes;
+    use crate::mapper::TestRom;
+
+    fn write_command(cartridge: &mut CartridgeNes, command: u8, parameter: u8) {
+        cartridge.cpu_write(0x8000, command);
+        cartridge.cpu_write(0xA000, parameter);
+    }
+
+    #[test]
+    pub fn test_banking() {
+        let mut cartridge = TestRom::ines(69, 0x20000, 0x8000)
+            .prg_banks(0x2000)
+            .chr_banks(0x400)
+            .flags_6(0x02)
+            .cartridge();
+
+        write_command(&mut cartridge, 0x9, 3);
+        write_command(&mut cartridge, 0xA, 5);
+        write_command(&mut cartridge, 0xB, 9);
+        assert_eq!(cartridge.cpu_read(0x8000), Some(3));
+        assert_eq!(cartridge.cpu_read(0xA000), Some(5));
+        assert_eq!(cartridge.cpu_read(0xC000), Some(9));
+        assert_eq!(cartridge.cpu_read(0xE000), Some(15));
+
+        for slot in 0..8 {
+            write_command(&mut cartridge, slot, 31 - slot);
+        }
+        for slot in 0..8 {
+            assert_eq!(cartridge.ppu_read(slot as usize * 0x400), 31 - slot);
+        }
+    }
+
+    #[test]
+    pub fn test_prg_ram() {
+        let mut cartridge = TestRom::ines(69, 0x20000, 0x8000)
+            .prg_banks(0x2000)
+            .chr_banks(0x400)
+            .flags_6(0x02)
+            .cartridge();
+
+        // ROM at $6000
+        write_command(&mut cartridge, 0x8, 7);
+        assert_eq!(cartridge.cpu_read(0x6000), Some(7));
+
+        // RAM at $6000, but not enabled
+        write_command(&mut cartridge, 0x8, 0x40);
+        cartridge.cpu_write(0x6000, 0x42);
+        assert_eq!(cartridge.cpu_read(0x6000), None);
+
+        write_command(&mut cartridge, 0x8, 0xC0);
+        cartridge.cpu_write(0x6000, 0x42);
+        assert_eq!(cartridge.cpu_read(0x6000), Some(0x42));
+        assert_eq!(cartridge.get_save_ram().unwrap()[0], 0x42);
+    }
+
+    #[test]
+    pub fn test_irq() {
+        let mut cartridge = TestRom::ines(69, 0x20000, 0x8000)
+            .prg_banks(0x2000)
+            .chr_banks(0x400)
+            .flags_6(0x02)
+            .cartridge();
+
+        write_command(&mut cartridge, 0xE, 0x10);
+        write_command(&mut cartridge, 0xF, 0x00);
+        write_command(&mut cartridge, 0xD, 0x81);
+
+        // fires on the 17th cycle, once the counter wraps from 0 to $FFFF
+        for _ in 0..16 {
+            cartridge.cpu_clock();
+            assert!(!cartridge.irq_active());
+        }
+
+        cartridge.cpu_clock();
+        assert!(cartridge.irq_active());
+
+        // the counter keeps going after being acknowledged
+        write_command(&mut cartridge, 0xD, 0x80);
+        assert!(!cartridge.irq_active());
+        cartridge.cpu_clock();
+        assert!(!cartridge.irq_active());
+    }
+
+    #[test]
+    pub fn test_audio() {
+        let mut cartridge = TestRom::ines(69, 0x20000, 0x8000)
+            .prg_banks(0x2000)
+            .chr_banks(0x400)
+            .flags_6(0x02)
+            .cartridge();
+        let mut levels = [0.0; 3];
+
+        let write_audio = |cartridge: &mut CartridgeNes, reg: u8, byte: u8| {
+            cartridge.cpu_write(0xC000, reg);
+            cartridge.cpu_write(0xE000, byte);
+        };
+
+        // square A with a period of 4 (128 CPU cycles) at volume 12, and square C held high at volume 5
+        write_audio(&mut cartridge, 0x00, 0x04);
+        write_audio(&mut cartridge, 0x01, 0x00);
+        write_audio(&mut cartridge, 0x08, 0x0C);
+        write_audio(&mut cartridge, 0x07, 0b100);
+        write_audio(&mut cartridge, 0x0A, 0x05);
+
+        let mut square_a = Vec::new();
+        for _ in 0..0x400 {
+            cartridge.clock_expansion_audio(&mut levels);
+            square_a.push(levels[0]);
+            assert_eq!(levels[1], 0.0);
+            assert_eq!(levels[2], 5.0);
+        }
+
+        assert!(square_a.iter().all(|&level| level == 0.0 || level == 12.0));
+        assert_eq!(square_a.iter().filter(|&&level| level == 12.0).count(), 0x200);
+
+        // every 2 volume steps halves the amplitude
+        let mix = |level: f32| cartridge.mix_expansion_audio(&[level, 0.0, 0.0]);
+        assert!((mix(13.0) / mix(15.0) - 0.5).abs() < 0.01);
+        assert_eq!(mix(0.0), 0.0);
+    }
+}
@@ -0,0 +1,174 @@
+use crate::apu::PULSE_TABLE;
+use crate::mapper::ExpansionChannel;
+use crate::{SystemControl, StateReader, StateWriter};
+
+pub const SUNSOFT_5B_CHANNELS: &[ExpansionChannel] = &[
+    ExpansionChannel { name: "5B Square A", min_level: 0.0, max_level: 15.0 },
+    ExpansionChannel { name: "5B Square B", min_level: 0.0, max_level: 15.0 },
+    ExpansionChannel { name: "5B Square C", min_level: 0.0, max_level: 15.0 },
+];
+
+// The tone counters are clocked every 16 CPU cycles, and flip their output each time they reach their period
+const TONE_DIVIDER: u8 = 16;
+
+// Each volume step is 3dB, with volume 0 being silent
+const VOLUME_TABLE: [f32; 16] = [
+    0.0, 0.0079, 0.0112, 0.0158, 0.0224, 0.0316, 0.0447, 0.0631,
+    0.0891, 0.1259, 0.1778, 0.2512, 0.3548, 0.5012, 0.7079, 1.0,
+];
+
+/// One of the AY-3-8910's square wave tone generators
+struct Square {
+    period: u16,
+    counter: u16,
+    high: bool,
+    volume: u8,
+    // a disabled tone holds its output high, which games use to play samples through the volume
+    tone_disabled: bool,
+}
+
+impl SystemControl for Square {
+    fn reset(&mut self) {
+        self.period = 0;
+        self.counter = 0;
+        self.high = false;
+        self.volume = 0;
+        self.tone_disabled = false;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.period);
+        state.write_u16(self.counter);
+        state.write_bool(self.high);
+        state.write_u8(self.volume);
+        state.write_bool(self.tone_disabled);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.period = state.read_u16()? & 0x0FFF;
+        self.counter = state.read_u16()? & 0x0FFF;
+        self.high = state.read_bool()?;
+        self.volume = state.read_u8()? & 0x0F;
+        self.tone_disabled = state.read_bool()?;
+        Ok(())
+    }
+}
+
+impl Square {
+    fn new() -> Self {
+        Self {
+            period: 0,
+            counter: 0,
+            high: false,
+            volume: 0,
+            tone_disabled: false,
+        }
+    }
+
+    fn clock(&mut self) {
+        self.counter += 1;
+
+        // a period of 0 acts the same as 1
+        if self.counter >= self.period.max(1) {
+            self.counter = 0;
+            self.high = !self.high;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.high || self.tone_disabled { self.volume } else { 0 }
+    }
+}
+
+/// The Sunsoft 5B's expansion audio: an AY-3-8910 clone, of which only the three square channels are
+/// emulated, as its noise and envelope generators go unused by Gimmick!, the only game with the chip
+pub struct Sunsoft5bAudio {
+    squares: [Square; 3],
+    register_select: u8,
+    cycles: u8,
+}
+
+impl SystemControl for Sunsoft5bAudio {
+    fn reset(&mut self) {
+        for square in &mut self.squares {
+            square.reset();
+        }
+        self.register_select = 0;
+        self.cycles = 0;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        for square in &self.squares {
+            square.save_state(state);
+        }
+        state.write_u8(self.register_select);
+        state.write_u8(self.cycles);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        for square in &mut self.squares {
+            square.load_state(state)?;
+        }
+        self.register_select = state.read_u8()? & 0x0F;
+        self.cycles = state.read_u8()? % TONE_DIVIDER;
+        Ok(())
+    }
+}
+
+impl Sunsoft5bAudio {
+    pub fn new() -> Self {
+        Self {
+            squares: [Square::new(), Square::new(), Square::new()],
+            register_select: 0,
+            cycles: 0,
+        }
+    }
+
+    /// Clocked once per CPU cycle, writing the output level of each channel in `SUNSOFT_5B_CHANNELS` to `levels`
+    pub fn clock(&mut self, levels: &mut [f32]) {
+        self.cycles += 1;
+
+        if self.cycles >= TONE_DIVIDER {
+            self.cycles = 0;
+
+            for square in &mut self.squares {
+                square.clock();
+            }
+        }
+
+        for (level, square) in levels.iter_mut().zip(&self.squares) {
+            *level = square.output() as f32;
+        }
+    }
+
+    // Volumes are logarithmic, and a full volume square is about as loud as a full volume APU pulse
+    pub fn mix(levels: &[f32]) -> f32 {
+        levels.iter().map(|&level| VOLUME_TABLE[level as usize]).sum::<f32>() * PULSE_TABLE[15]
+    }
+
+    /// Writes to $C000-$DFFF select the register that writes to $E000-$FFFF go to
+    pub fn select_register(&mut self, byte: u8) {
+        self.register_select = byte;
+    }
+
+    pub fn write_register(&mut self, byte: u8) {
+        match self.register_select {
+            reg @ 0x00..=0x05 => {
+                let square = &mut self.squares[(reg >> 1) as usize];
+
+                if reg & 0x01 == 0 {
+                    square.period = (square.period & 0x0F00) | byte as u16;
+                } else {
+                    square.period = (square.period & 0x00FF) | ((byte & 0x0F) as u16) << 8;
+                }
+            }
+            0x07 => {
+                for (i, square) in self.squares.iter_mut().enumerate() {
+                    square.tone_disabled = (byte & (1 << i)) != 0;
+                }
+            }
+            reg @ 0x08..=0x0A => self.squares[(reg - 0x08) as usize].volume = byte & 0x0F,
+            _ => {} // noise and envelope
+        }
+    }
+}
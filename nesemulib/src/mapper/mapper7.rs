@@ -1,24 +1,38 @@
-use crate::{cartridge::{CHR_ROM_SIZE, PRG_ROM_SIZE}, SystemControl};
+use crate::{cartridge::{Mirroring, PRG_ROM_SIZE}, SystemControl, StateReader, StateWriter};
 
 use super::{Mapper, PRG_ROM_END, PRG_ROM_START};
 
 pub struct Mapper7 {
     prg_rom_select: usize,
-    chr_rom_1kb: usize,
+    // AxROM boards select which CIRAM page is used for single-screen mirroring
+    name_table_page: usize,
 }
 
 impl SystemControl for Mapper7 {
     fn reset(&mut self) {
         self.prg_rom_select = 0;
-        self.chr_rom_1kb = 0;
+        self.name_table_page = 0;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_usize(self.prg_rom_select);
+        state.write_usize(self.name_table_page);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.prg_rom_select = state.read_usize()? & 0x07;
+        self.name_table_page = state.read_usize()? & 0x01;
+
+        Ok(())
     }
 }
 
 impl Mapper for Mapper7 {
-    fn mapped_cpu_read(&self, prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8> {
+    fn mapped_cpu_read(&mut self, prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8> {
         match addr {
             PRG_ROM_START..=PRG_ROM_END => {
-                Some(prg_rom[self.prg_rom_select * (PRG_ROM_SIZE << 1) + (addr & 0x7FFF)])
+                let addr = self.prg_rom_select * (PRG_ROM_SIZE << 1) + (addr & 0x7FFF);
+                Some(prg_rom[addr % prg_rom.len()])
             }
             _ => None
         }
@@ -27,7 +41,7 @@ impl Mapper for Mapper7 {
     fn mapped_cpu_write(&mut self, _prg_rom: &mut Vec<u8>, addr: usize, byte: u8) -> bool {
         match addr {
             PRG_ROM_START..=PRG_ROM_END => {
-                self.chr_rom_1kb = ((byte & 0b00010000) != 0) as usize;
+                self.name_table_page = ((byte & 0b00010000) != 0) as usize;
                 self.prg_rom_select = (byte & 0b00000111) as usize;
                 true
             }
@@ -36,7 +50,15 @@ impl Mapper for Mapper7 {
     }
 
     fn mapped_ppu_read(&self, chr_rom: &Vec<u8>, addr: usize) -> u8 {
-        chr_rom[self.chr_rom_1kb * (CHR_ROM_SIZE >> 1) + (addr & 0x0FFF)]
+        chr_rom[addr]
+    }
+
+    fn get_updated_mirroring(&self) -> Option<Mirroring> {
+        Some(if self.name_table_page == 0 {
+            Mirroring::ONESCREEN_LO
+        } else {
+            Mirroring::ONESCREEN_HI
+        })
     }
 }
 
@@ -44,7 +66,7 @@ impl Mapper7 {
     pub fn new() -> Self {
         Self {
             prg_rom_select: 0,
-            chr_rom_1kb: 0,
+            name_table_page: 0,
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,298 @@
+mod audio;
+
+use crate::{cartridge::Mirroring, SystemControl, StateReader, StateWriter};
+
+use self::audio::{Vrc7Audio, VRC7_CHANNELS};
+use super::vrc_irq::VrcIrq;
+use super::{ExpansionChannel, copy_trainer, Mapper, PRG_RAM_END, PRG_RAM_START, PRG_ROM_END, PRG_ROM_START};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+pub struct Mapper85 {
+    prg_ram: Vec<u8>,
+    prg_rom_banks: usize,
+    // VRC7a (Lagrange Point) selects between register pairs with A4, and VRC7b (Tiny Toon Adventures 2) with A3
+    pair_lines: usize,
+
+    prg_banks: [usize; 3],
+    chr_regs: [usize; 8],
+    // $E000: RS.. ..MM (PRG-RAM enable, expansion sound reset, mirroring)
+    control: u8,
+
+    irq: VrcIrq,
+    audio: Vrc7Audio,
+}
+
+impl SystemControl for Mapper85 {
+    fn reset(&mut self) {
+        self.prg_banks = [0; 3];
+        self.chr_regs = [0; 8];
+        self.control = 0;
+
+        self.irq.reset();
+        self.audio.reset();
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+
+        for value in &self.prg_banks {
+            state.write_usize(*value);
+        }
+        for value in &self.chr_regs {
+            state.write_usize(*value);
+        }
+        state.write_u8(self.control);
+
+        self.irq.save_state(state);
+        self.audio.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.read_bytes(&mut self.prg_ram)?;
+
+        for value in &mut self.prg_banks {
+            *value = state.read_usize()? & 0x3F;
+        }
+        for value in &mut self.chr_regs {
+            *value = state.read_usize()? & 0xFF;
+        }
+        self.control = state.read_u8()?;
+
+        self.irq.load_state(state)?;
+        self.audio.load_state(state)?;
+
+        Ok(())
+    }
+}
+
+impl Mapper for Mapper85 {
+    fn mapped_cpu_read(&mut self, prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8> {
+        match addr {
+            PRG_RAM_START..=PRG_RAM_END if self.prg_ram_enabled() => {
+                Some(self.prg_ram[(addr - PRG_RAM_START) % self.prg_ram.len()])
+            }
+            PRG_ROM_START..=PRG_ROM_END => {
+                let bank = match addr {
+                    0xE000..=0xFFFF => self.prg_rom_banks * 2 - 1,
+                    _ => self.prg_banks[(addr - PRG_ROM_START) >> 13],
+                };
+
+                Some(prg_rom[(bank * PRG_BANK_SIZE + (addr & (PRG_BANK_SIZE - 1))) % prg_rom.len()])
+            }
+            _ => None
+        }
+    }
+
+    fn mapped_cpu_write(&mut self, _prg_rom: &mut Vec<u8>, addr: usize, byte: u8) -> bool {
+        match addr {
+            PRG_RAM_START..=PRG_RAM_END => {
+                if self.prg_ram_enabled() {
+                    let len = self.prg_ram.len();
+                    self.prg_ram[(addr - PRG_RAM_START) % len] = byte;
+                }
+                return !self.prg_ram.is_empty();
+            }
+            PRG_ROM_START..=PRG_ROM_END => {}
+            _ => return false
+        }
+
+        let reg = (addr & 0xF000) | if addr & self.pair_lines != 0 { 0x10 } else { 0 };
+
+        match reg {
+            0x8000 => self.prg_banks[0] = (byte & 0x3F) as usize,
+            0x8010 => self.prg_banks[1] = (byte & 0x3F) as usize,
+            0x9000 => self.prg_banks[2] = (byte & 0x3F) as usize,
+            // the synth's address and data ports are told apart by A5, on VRC7a only
+            0x9010 if addr & 0x20 == 0 => self.audio.select_register(byte),
+            0x9010 => self.audio.write_register(byte),
+            0xA000..=0xDFFF => self.chr_regs[((reg - 0xA000) >> 11) | ((reg >> 4) & 0x01)] = byte as usize,
+            0xE000 => {
+                self.control = byte;
+                self.audio.set_silenced((byte & 0b01000000) != 0);
+            }
+            0xE010 => self.irq.latch = byte,
+            0xF000 => self.irq.write_control(byte),
+            0xF010 => self.irq.acknowledge(),
+            _ => {}
+        }
+
+        true
+    }
+
+    fn mapped_ppu_read(&self, chr_rom: &Vec<u8>, addr: usize) -> u8 {
+        chr_rom[(self.chr_regs[addr >> 10] * CHR_BANK_SIZE + (addr & (CHR_BANK_SIZE - 1))) % chr_rom.len()]
+    }
+
+    fn mapped_ppu_write(&mut self, chr_ram: &mut Vec<u8>, addr: usize, byte: u8) {
+        let len = chr_ram.len();
+        chr_ram[(self.chr_regs[addr >> 10] * CHR_BANK_SIZE + (addr & (CHR_BANK_SIZE - 1))) % len] = byte;
+    }
+
+    fn get_updated_mirroring(&self) -> Option<Mirroring> {
+        Some(match self.control & 0x03 {
+            0 => Mirroring::VERTICAL,
+            1 => Mirroring::HORIZONTAL,
+            2 => Mirroring::ONESCREEN_LO,
+            _ => Mirroring::ONESCREEN_HI,
+        })
+    }
+
+    fn cpu_clock(&mut self) {
+        self.irq.clock();
+    }
+
+    fn expansion_audio_channels(&self) -> &'static [ExpansionChannel] {
+        VRC7_CHANNELS
+    }
+
+    fn clock_expansion_audio(&mut self, levels: &mut [f32]) {
+        self.audio.clock(levels)
+    }
+
+    fn mix_expansion_audio(&self, levels: &[f32]) -> f32 {
+        Vrc7Audio::mix(levels)
+    }
+
+    fn irq_active(&mut self) -> bool {
+        self.irq.active()
+    }
+
+    fn load_trainer(&mut self, trainer: &[u8]) -> bool {
+        copy_trainer(&mut self.prg_ram, trainer)
+    }
+
+    fn get_save_ram(&self) -> Option<&[u8]> {
+        if self.prg_ram.is_empty() { None } else { Some(&self.prg_ram) }
+    }
+
+    fn load_save_ram(&mut self, save_ram: &[u8]) -> bool {
+        self.prg_ram.copy_from_slice(save_ram);
+        !self.prg_ram.is_empty()
+    }
+}
+
+impl Mapper85 {
+    /// Submapper 1 is VRC7b and submapper 2 is VRC7a. Plain iNES files listen on both lines
+    pub fn new(submapper_num: u8, prg_rom_banks: usize, prg_ram_size: usize) -> Self {
+        let pair_lines = match submapper_num {
+            1 => 1 << 3,
+            2 => 1 << 4,
+            _ => (1 << 3) | (1 << 4),
+        };
+
+        Self {
+            prg_ram: vec![0; prg_ram_size],
+            prg_rom_banks,
+            pair_lines,
+
+            prg_banks: [0; 3],
+            chr_regs: [0; 8],
+            control: 0,
+
+            irq: VrcIrq::new(),
+            audio: Vrc7Audio::new(),
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        !self.prg_ram.is_empty() && (self.control & 0b10000000) != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mapper::TestRom;
+
+    #[test]
+    pub fn test_banking() {
+        for (submapper_num, pair) in [(1, 0x08), (2, 0x10), (0, 0x10)] {
+            let mut cartridge = TestRom::nes2(85, submapper_num, 0x20000, 0x8000)
+                .prg_banks(0x2000)
+                .chr_banks(0x400)
+                .flags_6(0x02)
+                .prg_ram(0x07, 0)
+                .cartridge();
+
+            cartridge.cpu_write(0x8000, 3);
+            cartridge.cpu_write(0x8000 | pair, 5);
+            cartridge.cpu_write(0x9000, 9);
+            assert_eq!(cartridge.cpu_read(0x8000), Some(3));
+            assert_eq!(cartridge.cpu_read(0xA000), Some(5));
+            assert_eq!(cartridge.cpu_read(0xC000), Some(9));
+            assert_eq!(cartridge.cpu_read(0xE000), Some(15));
+
+            for (slot, base) in [0xA000, 0xB000, 0xC000, 0xD000].into_iter().enumerate() {
+                cartridge.cpu_write(base, 20 + slot as u8 * 2);
+                cartridge.cpu_write(base | pair, 21 + slot as u8 * 2);
+            }
+            for slot in 0..8 {
+                assert_eq!(cartridge.ppu_read(slot * 0x400), 20 + slot as u8);
+            }
+
+            cartridge.cpu_write(0x6000, 0x42);
+            assert_ne!(cartridge.cpu_read(0x6000), Some(0x42));
+            cartridge.cpu_write(0xE000, 0x80);
+            cartridge.cpu_write(0x6000, 0x42);
+            assert_eq!(cartridge.cpu_read(0x6000), Some(0x42));
+        }
+    }
+
+    #[test]
+    pub fn test_irq() {
+        let mut cartridge = TestRom::nes2(85, 2, 0x20000, 0x8000)
+            .prg_banks(0x2000)
+            .chr_banks(0x400)
+            .flags_6(0x02)
+            .prg_ram(0x07, 0)
+            .cartridge();
+
+        cartridge.cpu_write(0xE010, 0xF0);
+        cartridge.cpu_write(0xF000, 0b111);
+
+        for _ in 0..15 {
+            cartridge.cpu_clock();
+            assert!(!cartridge.irq_active());
+        }
+
+        cartridge.cpu_clock();
+        assert!(cartridge.irq_active());
+
+        cartridge.cpu_write(0xF010, 0);
+        assert!(!cartridge.irq_active());
+    }
+
+    #[test]
+    pub fn test_audio() {
+        let mut cartridge = TestRom::nes2(85, 2, 0x20000, 0x8000)
+            .prg_banks(0x2000)
+            .chr_banks(0x400)
+            .flags_6(0x02)
+            .prg_ram(0x07, 0)
+            .cartridge();
+        let mut levels = [0.0; 6];
+
+        // the built-in organ on the third channel
+        for (reg, byte) in [(0x12, 0xAC), (0x32, 0x80), (0x22, 0b00011000)] {
+            cartridge.cpu_write(0x9010, reg);
+            cartridge.cpu_write(0x9030, byte);
+        }
+
+        let mut peak = 0.0f32;
+        for _ in 0..36 * 2000 {
+            cartridge.clock_expansion_audio(&mut levels);
+            peak = peak.max(levels[2].abs());
+            assert_eq!(levels[0], 0.0);
+        }
+        assert!(peak > 0.0);
+
+        // holding the synth in reset silences it, and it stays silent once released
+        cartridge.cpu_write(0xE000, 0x40);
+        cartridge.cpu_write(0xE000, 0x00);
+        for _ in 0..36 * 2 {
+            cartridge.clock_expansion_audio(&mut levels);
+        }
+        assert!(levels.iter().all(|&level| level == 0.0));
+    }
+}
@@ -0,0 +1,676 @@
+use std::f64::consts::PI;
+
+use crate::apu::PULSE_TABLE;
+use crate::mapper::ExpansionChannel;
+use crate::{SystemControl, StateReader, StateWriter};
+
+pub const VRC7_CHANNELS: &[ExpansionChannel] = &[
+    ExpansionChannel { name: "VRC7 FM 1", min_level: -2047.0, max_level: 2047.0 },
+    ExpansionChannel { name: "VRC7 FM 2", min_level: -2047.0, max_level: 2047.0 },
+    ExpansionChannel { name: "VRC7 FM 3", min_level: -2047.0, max_level: 2047.0 },
+    ExpansionChannel { name: "VRC7 FM 4", min_level: -2047.0, max_level: 2047.0 },
+    ExpansionChannel { name: "VRC7 FM 5", min_level: -2047.0, max_level: 2047.0 },
+    ExpansionChannel { name: "VRC7 FM 6", min_level: -2047.0, max_level: 2047.0 },
+];
+
+// The synth's 3.58MHz clock is divided by 72 for each sample, which is every 36 CPU cycles
+const CPU_CYCLES_PER_SAMPLE: u8 = 36;
+
+/// The 15 built-in instruments, as dumped from the chip itself. Instrument 0 is the custom one in registers $00-$07
+const BUILTIN_PATCHES: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27], // Buzzy Bell
+    [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12], // Guitar
+    [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12], // Wurly
+    [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27], // Flute
+    [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28], // Clarinet
+    [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4], // Synth
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07], // Trumpet
+    [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17], // Organ
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01], // Bells
+    [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02], // Vibes
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12], // Vibraphone
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16], // Tutti
+    [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02], // Fretless
+    [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6], // Synth Bass
+    [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06], // Sweep
+];
+
+// Twice each frequency multiplier, as a multiplier of 0 means one half
+const MULTIPLIERS: [u32; 16] = [1, 2, 4, 6, 8, 10, 12, 14, 16, 18, 20, 20, 24, 24, 30, 30];
+
+// Key scale level attenuation of the top 4 F-Number bits in octave 7, for 6dB per octave
+const KEY_SCALE_LEVELS: [u32; 16] = [0, 24, 32, 37, 40, 43, 45, 47, 48, 50, 51, 52, 53, 54, 55, 56];
+
+// All attenuation is measured in steps of 0.375dB, and the envelope's 7 bits can't go past 127 (about 48dB)
+const ENV_BITS: u32 = 16;
+const ENV_MAX: u32 = 127 << ENV_BITS;
+const ATTENUATION_STEPS: usize = 256;
+
+const SINE_STEPS: usize = 1024;
+const MAX_OUTPUT: f64 = 2047.0;
+
+// The tremolo (3.7Hz, up to 4.8dB) and vibrato (6.4Hz, about 7 cents) LFOs, in samples
+const AM_PERIOD: u32 = 13437;
+const AM_DEPTH: u32 = 13;
+const PM_PERIOD: u32 = 7768;
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EnvelopeState {
+    ATTACK,
+    DECAY,
+    SUSTAIN,
+    RELEASE,
+}
+
+impl EnvelopeState {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(*self as u8);
+    }
+
+    fn load_state(state: &mut StateReader) -> Result<Self, String> {
+        match state.read_u8()? {
+            0 => Ok(EnvelopeState::ATTACK),
+            1 => Ok(EnvelopeState::DECAY),
+            2 => Ok(EnvelopeState::SUSTAIN),
+            3 => Ok(EnvelopeState::RELEASE),
+            n => Err(format!("Invalid envelope state {} in save state", n)),
+        }
+    }
+}
+
+/// One operator's settings, taken from either the modulator or carrier half of a patch
+struct OperatorPatch {
+    tremolo: bool,
+    vibrato: bool,
+    // sustained tones hold at the sustain level, while percussive ones keep decaying
+    sustained: bool,
+    key_scale_rate: bool,
+    multiplier: u32,
+    key_scale_level: u32,
+    half_sine: bool,
+    attack: u32,
+    decay: u32,
+    sustain_level: u32,
+    release: u32,
+}
+
+impl OperatorPatch {
+    fn from_patch(patch: &[u8; 8], carrier: bool) -> Self {
+        let op = carrier as usize;
+
+        Self {
+            tremolo: (patch[op] & 0b10000000) != 0,
+            vibrato: (patch[op] & 0b01000000) != 0,
+            sustained: (patch[op] & 0b00100000) != 0,
+            key_scale_rate: (patch[op] & 0b00010000) != 0,
+            multiplier: MULTIPLIERS[(patch[op] & 0x0F) as usize],
+            key_scale_level: (patch[2 + op] >> 6) as u32,
+            half_sine: (patch[3] & if carrier { 0b00010000 } else { 0b00001000 }) != 0,
+            attack: (patch[4 + op] >> 4) as u32,
+            decay: (patch[4 + op] & 0x0F) as u32,
+            sustain_level: (patch[6 + op] >> 4) as u32,
+            release: (patch[6 + op] & 0x0F) as u32,
+        }
+    }
+}
+
+/// A sine oscillator with its own envelope. Each channel feeds a modulator's output into a carrier's phase
+struct Operator {
+    // 19-bit phase, the top 10 bits of which index the sine table
+    phase: u32,
+    state: EnvelopeState,
+    // attenuation with `ENV_BITS` fractional bits
+    envelope: u32,
+    // the last two outputs, used for the modulator's feedback
+    outputs: [i32; 2],
+}
+
+impl SystemControl for Operator {
+    fn reset(&mut self) {
+        self.phase = 0;
+        self.state = EnvelopeState::RELEASE;
+        self.envelope = ENV_MAX;
+        self.outputs = [0; 2];
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u32(self.phase);
+        self.state.save_state(state);
+        state.write_u32(self.envelope);
+        state.write_u16(self.outputs[0] as u16);
+        state.write_u16(self.outputs[1] as u16);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.phase = state.read_u32()? & 0x7FFFF;
+        self.state = EnvelopeState::load_state(state)?;
+        self.envelope = state.read_u32()?.min(ENV_MAX);
+        self.outputs[0] = state.read_u16()? as i16 as i32;
+        self.outputs[1] = state.read_u16()? as i16 as i32;
+        Ok(())
+    }
+}
+
+impl Operator {
+    fn new() -> Self {
+        Self {
+            phase: 0,
+            state: EnvelopeState::RELEASE,
+            envelope: ENV_MAX,
+            outputs: [0; 2],
+        }
+    }
+
+    fn key_on(&mut self) {
+        // the attack starts from wherever the envelope currently is
+        self.phase = 0;
+        self.state = EnvelopeState::ATTACK;
+    }
+
+    fn key_off(&mut self) {
+        self.state = EnvelopeState::RELEASE;
+    }
+
+    /// `rate` is the 4-bit rate from the patch, or 0 to hold the envelope where it is
+    fn clock_envelope(&mut self, patch: &OperatorPatch, rate: u32, key_scale: u32) {
+        if rate == 0 {
+            return;
+        }
+
+        let rate = (rate * 4 + (key_scale >> if patch.key_scale_rate { 0 } else { 2 })).min(63);
+        let step = (4 + (rate & 0x03)) << (rate >> 2);
+
+        match self.state {
+            EnvelopeState::ATTACK => {
+                // the attack is exponential, slowing down as it nears full volume
+                let decrease = ((self.envelope as u64 * step as u64) >> 18).max(1) as u32;
+
+                if rate >= 60 || self.envelope < decrease + (1 << ENV_BITS) {
+                    self.envelope = 0;
+                    self.state = EnvelopeState::DECAY;
+                } else {
+                    self.envelope -= decrease;
+                }
+            }
+            EnvelopeState::DECAY => {
+                let sustain_level = (patch.sustain_level * 8) << ENV_BITS;
+                self.envelope = (self.envelope + step).min(ENV_MAX);
+
+                if self.envelope >= sustain_level {
+                    self.envelope = self.envelope.max(sustain_level);
+                    self.state = EnvelopeState::SUSTAIN;
+                }
+            }
+            EnvelopeState::SUSTAIN | EnvelopeState::RELEASE => {
+                self.envelope = (self.envelope + step).min(ENV_MAX);
+            }
+        }
+    }
+
+    fn clock_phase(&mut self, patch: &OperatorPatch, f_number: u32, block: u32) {
+        let increment = ((f_number << block) * patch.multiplier) >> 1;
+        self.phase = (self.phase + increment) & 0x7FFFF;
+    }
+
+    fn output(&mut self, tables: &Tables, patch: &OperatorPatch, modulation: i32, attenuation: u32) -> i32 {
+        let index = (((self.phase >> 9) as i32 + modulation) as usize) & (SINE_STEPS - 1);
+        let attenuation = (self.envelope >> ENV_BITS) as usize + attenuation as usize;
+
+        let silent = self.envelope >= ENV_MAX || attenuation >= ATTENUATION_STEPS;
+
+        // the half sine waveform cuts off the negative half of the wave
+        let output = if silent || (patch.half_sine && index >= SINE_STEPS / 2) {
+            0
+        } else {
+            (tables.sine[index] * tables.attenuation[attenuation]) >> 12
+        };
+
+        self.outputs = [self.outputs[1], output];
+        output
+    }
+}
+
+struct Channel {
+    f_number: u32,
+    block: u32,
+    key_on: bool,
+    // releases notes slowly after key off, regardless of the patch's release rate
+    sustain: bool,
+    instrument: usize,
+    volume: u32,
+
+    modulator: Operator,
+    carrier: Operator,
+}
+
+impl SystemControl for Channel {
+    fn reset(&mut self) {
+        self.f_number = 0;
+        self.block = 0;
+        self.key_on = false;
+        self.sustain = false;
+        self.instrument = 0;
+        self.volume = 0;
+        self.modulator.reset();
+        self.carrier.reset();
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.f_number as u16);
+        state.write_u8(self.block as u8);
+        state.write_bool(self.key_on);
+        state.write_bool(self.sustain);
+        state.write_u8(self.instrument as u8);
+        state.write_u8(self.volume as u8);
+        self.modulator.save_state(state);
+        self.carrier.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.f_number = (state.read_u16()? & 0x1FF) as u32;
+        self.block = (state.read_u8()? & 0x07) as u32;
+        self.key_on = state.read_bool()?;
+        self.sustain = state.read_bool()?;
+        self.instrument = (state.read_u8()? & 0x0F) as usize;
+        self.volume = (state.read_u8()? & 0x0F) as u32;
+        self.modulator.load_state(state)?;
+        self.carrier.load_state(state)?;
+        Ok(())
+    }
+}
+
+impl Channel {
+    fn new() -> Self {
+        Self {
+            f_number: 0,
+            block: 0,
+            key_on: false,
+            sustain: false,
+            instrument: 0,
+            volume: 0,
+
+            modulator: Operator::new(),
+            carrier: Operator::new(),
+        }
+    }
+
+    fn set_key_on(&mut self, key_on: bool) {
+        if key_on && !self.key_on {
+            self.modulator.key_on();
+            self.carrier.key_on();
+        } else if !key_on && self.key_on {
+            self.modulator.key_off();
+            self.carrier.key_off();
+        }
+
+        self.key_on = key_on;
+    }
+
+    // The patch's own release rate is used for sustained tones, while percussive ones use it to decay while held
+    fn envelope_rate(&self, operator: &Operator, patch: &OperatorPatch) -> u32 {
+        match operator.state {
+            EnvelopeState::ATTACK => patch.attack,
+            EnvelopeState::DECAY => patch.decay,
+            EnvelopeState::SUSTAIN if patch.sustained => 0,
+            EnvelopeState::SUSTAIN => patch.release,
+            EnvelopeState::RELEASE if self.sustain => 5,
+            EnvelopeState::RELEASE if patch.sustained => patch.release,
+            EnvelopeState::RELEASE => 7,
+        }
+    }
+
+    fn key_scale_level(&self, patch: &OperatorPatch) -> u32 {
+        if patch.key_scale_level == 0 {
+            return 0;
+        }
+
+        let level = KEY_SCALE_LEVELS[(self.f_number >> 5) as usize].saturating_sub(16 * (7 - self.block));
+        level >> (3 - patch.key_scale_level)
+    }
+
+    fn clock(&mut self, tables: &Tables, patch: &[u8; 8], tremolo: u32, vibrato: i32) -> i32 {
+        let patches = [OperatorPatch::from_patch(patch, false), OperatorPatch::from_patch(patch, true)];
+        let key_scale = (self.block << 1) | (self.f_number >> 8);
+
+        let rate = self.envelope_rate(&self.modulator, &patches[0]);
+        self.modulator.clock_envelope(&patches[0], rate, key_scale);
+        let rate = self.envelope_rate(&self.carrier, &patches[1]);
+        self.carrier.clock_envelope(&patches[1], rate, key_scale);
+
+        for (operator, patch) in [&mut self.modulator, &mut self.carrier].into_iter().zip(&patches) {
+            let f_number = if patch.vibrato {
+                (self.f_number as i32 + ((self.f_number as i32 * vibrato) >> 11)) as u32
+            } else {
+                self.f_number
+            };
+
+            operator.clock_phase(patch, f_number, self.block);
+        }
+
+        let tremolo_of = |patch: &OperatorPatch| if patch.tremolo { tremolo } else { 0 };
+
+        // the modulator's level comes from the patch (in 0.75dB steps), and the carrier's from the channel volume (in 3dB steps)
+        let feedback = (patch[3] & 0x07) as u32;
+        let modulation = if feedback == 0 {
+            0
+        } else {
+            (self.modulator.outputs[0] + self.modulator.outputs[1]) >> (8 - feedback)
+        };
+        let attenuation = ((patch[2] & 0x3F) as u32) * 2 + self.key_scale_level(&patches[0]) + tremolo_of(&patches[0]);
+        let modulator = self.modulator.output(tables, &patches[0], modulation, attenuation);
+
+        let attenuation = self.volume * 8 + self.key_scale_level(&patches[1]) + tremolo_of(&patches[1]);
+        self.carrier.output(tables, &patches[1], modulator, attenuation)
+    }
+}
+
+/// Lookup tables built once, so the synth itself only does integer math
+struct Tables {
+    sine: [i32; SINE_STEPS],
+    // the linear gain (out of 4096) for each attenuation step
+    attenuation: [i32; ATTENUATION_STEPS],
+}
+
+impl Tables {
+    fn new() -> Self {
+        let mut sine = [0; SINE_STEPS];
+        for (i, value) in sine.iter_mut().enumerate() {
+            *value = ((2.0 * PI * (i as f64 + 0.5) / SINE_STEPS as f64).sin() * MAX_OUTPUT).round() as i32;
+        }
+
+        let mut attenuation = [0; ATTENUATION_STEPS];
+        for (i, value) in attenuation.iter_mut().enumerate() {
+            *value = (4096.0 * 10f64.powf(-(i as f64 * 0.375) / 20.0)).round() as i32;
+        }
+
+        Self { sine, attenuation }
+    }
+}
+
+/// VRC7's expansion audio: a cut-down YM2413 (OPLL) with six FM channels and no rhythm mode
+pub struct Vrc7Audio {
+    channels: [Channel; 6],
+    custom_patch: [u8; 8],
+    register_select: u8,
+    // held in reset by bit 6 of $E000, which silences and clears the synth
+    silenced: bool,
+
+    cycles: u8,
+    am_counter: u32,
+    pm_counter: u32,
+    outputs: [i32; 6],
+
+    tables: Tables,
+}
+
+impl SystemControl for Vrc7Audio {
+    fn reset(&mut self) {
+        for channel in &mut self.channels {
+            channel.reset();
+        }
+        self.custom_patch = [0; 8];
+        self.register_select = 0;
+        self.silenced = false;
+        self.cycles = 0;
+        self.am_counter = 0;
+        self.pm_counter = 0;
+        self.outputs = [0; 6];
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        for channel in &self.channels {
+            channel.save_state(state);
+        }
+        state.write_bytes(&self.custom_patch);
+        state.write_u8(self.register_select);
+        state.write_bool(self.silenced);
+        state.write_u8(self.cycles);
+        state.write_u32(self.am_counter);
+        state.write_u32(self.pm_counter);
+        for output in &self.outputs {
+            state.write_u16(*output as u16);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        for channel in &mut self.channels {
+            channel.load_state(state)?;
+        }
+        state.read_bytes(&mut self.custom_patch)?;
+        self.register_select = state.read_u8()? & 0x3F;
+        self.silenced = state.read_bool()?;
+        self.cycles = state.read_u8()? % CPU_CYCLES_PER_SAMPLE;
+        self.am_counter = state.read_u32()? % AM_PERIOD;
+        self.pm_counter = state.read_u32()? % PM_PERIOD;
+        for output in &mut self.outputs {
+            *output = state.read_u16()? as i16 as i32;
+        }
+        Ok(())
+    }
+}
+
+impl Vrc7Audio {
+    pub fn new() -> Self {
+        Self {
+            channels: std::array::from_fn(|_| Channel::new()),
+            custom_patch: [0; 8],
+            register_select: 0,
+            silenced: false,
+
+            cycles: 0,
+            am_counter: 0,
+            pm_counter: 0,
+            outputs: [0; 6],
+
+            tables: Tables::new(),
+        }
+    }
+
+    /// Clocked once per CPU cycle, writing the output level of each channel in `VRC7_CHANNELS` to `levels`
+    pub fn clock(&mut self, levels: &mut [f32]) {
+        self.cycles += 1;
+
+        if self.cycles >= CPU_CYCLES_PER_SAMPLE {
+            self.cycles = 0;
+
+            if !self.silenced {
+                self.clock_sample();
+            }
+        }
+
+        for (level, output) in levels.iter_mut().zip(&self.outputs) {
+            *level = *output as f32;
+        }
+    }
+
+    // A full volume channel swings about as far as a full volume APU pulse
+    pub fn mix(levels: &[f32]) -> f32 {
+        levels.iter().sum::<f32>() * PULSE_TABLE[15] / (2.0 * MAX_OUTPUT as f32)
+    }
+
+    /// Writes to $9010 select the register that writes to $9030 go to
+    pub fn select_register(&mut self, byte: u8) {
+        self.register_select = byte;
+    }
+
+    pub fn write_register(&mut self, byte: u8) {
+        if self.silenced {
+            return;
+        }
+
+        let reg = self.register_select as usize;
+        let channel = reg & 0x0F;
+
+        match reg {
+            0x00..=0x07 => self.custom_patch[reg] = byte,
+            0x10..=0x15 => {
+                let channel = &mut self.channels[channel];
+                channel.f_number = (channel.f_number & 0x100) | byte as u32;
+            }
+            0x20..=0x25 => {
+                let channel = &mut self.channels[channel];
+                channel.f_number = (channel.f_number & 0xFF) | ((byte & 0x01) as u32) << 8;
+                channel.block = ((byte >> 1) & 0x07) as u32;
+                channel.sustain = (byte & 0b00100000) != 0;
+                channel.set_key_on((byte & 0b00010000) != 0);
+            }
+            0x30..=0x35 => {
+                let channel = &mut self.channels[channel];
+                channel.instrument = (byte >> 4) as usize;
+                channel.volume = (byte & 0x0F) as u32;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn set_silenced(&mut self, silenced: bool) {
+        if silenced && !self.silenced {
+            self.reset();
+        }
+
+        self.silenced = silenced;
+    }
+
+    fn clock_sample(&mut self) {
+        self.am_counter = (self.am_counter + 1) % AM_PERIOD;
+        self.pm_counter = (self.pm_counter + 1) % PM_PERIOD;
+
+        // triangle waves, from 0 up to the tremolo's depth, and from -8 to 8 for the vibrato
+        let am_distance = self.am_counter.min(AM_PERIOD - self.am_counter);
+        let tremolo = am_distance * AM_DEPTH * 2 / AM_PERIOD;
+
+        let pm_step = (self.pm_counter * 32 / PM_PERIOD) as i32;
+        let vibrato = match pm_step {
+            0..=7 => pm_step,
+            8..=23 => 16 - pm_step,
+            _ => pm_step - 32,
+        };
+
+        for (channel, output) in self.channels.iter_mut().zip(&mut self.outputs) {
+            let patch = match channel.instrument {
+                0 => &self.custom_patch,
+                n => &BUILTIN_PATCHES[n - 1],
+            };
+
+            *output = channel.clock(&self.tables, patch, tremolo, vibrato);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Vrc7Audio, CPU_CYCLES_PER_SAMPLE};
+
+    const SAMPLE_RATE: f64 = 3579545.0 / 72.0;
+
+    fn write(audio: &mut Vrc7Audio, reg: u8, byte: u8) {
+        audio.select_register(reg);
+        audio.write_register(byte);
+    }
+
+    // Returns the first channel's output for each of the synth's samples
+    fn run(audio: &mut Vrc7Audio, samples: usize) -> Vec<f32> {
+        let mut levels = [0.0; 6];
+        let mut output = Vec::new();
+
+        for _ in 0..samples {
+            for _ in 0..CPU_CYCLES_PER_SAMPLE {
+                audio.clock(&mut levels);
+            }
+            output.push(levels[0]);
+        }
+
+        output
+    }
+
+    // A custom patch with a silent modulator, so the carrier plays a plain sine
+    fn sine_patch(audio: &mut Vrc7Audio) {
+        for (reg, byte) in [0x21, 0x21, 0x3F, 0x00, 0xF0, 0xF0, 0x0F, 0x0F].into_iter().enumerate() {
+            write(audio, reg as u8, byte);
+        }
+    }
+
+    #[test]
+    pub fn test_sine_frequency() {
+        let mut audio = Vrc7Audio::new();
+        sine_patch(&mut audio);
+
+        // F-Number 290 ($122) in block 4 is 440Hz
+        write(&mut audio, 0x10, 0x22);
+        write(&mut audio, 0x30, 0x00);
+        write(&mut audio, 0x20, 0b00011001);
+
+        let output = run(&mut audio, SAMPLE_RATE as usize);
+        let rising_edges = output.windows(2).filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0).count();
+        assert!((438..=441).contains(&rising_edges), "{} Hz", rising_edges);
+
+        let peak = output.iter().cloned().fold(0.0, f32::max);
+        assert!(peak > 2000.0 && peak <= 2047.0);
+
+        // each step of the channel volume attenuates by 3dB
+        write(&mut audio, 0x30, 0x02);
+        let peak = run(&mut audio, 1000).iter().cloned().fold(0.0, f32::max);
+        assert!((peak - 1024.0).abs() < 32.0, "{}", peak);
+    }
+
+    #[test]
+    pub fn test_key_off() {
+        let mut audio = Vrc7Audio::new();
+        assert!(run(&mut audio, 1000).iter().all(|&level| level == 0.0));
+
+        // the built-in flute, which releases quickly once keyed off
+        write(&mut audio, 0x10, 0xAC);
+        write(&mut audio, 0x30, 0x40);
+        write(&mut audio, 0x20, 0b00011000);
+        assert!(run(&mut audio, 5000).iter().any(|&level| level.abs() > 500.0));
+
+        write(&mut audio, 0x20, 0b00001000);
+        run(&mut audio, 20000);
+        assert!(run(&mut audio, 1000).iter().all(|&level| level == 0.0));
+    }
+
+    // Every channel's output after each 250 samples of built-in patches 1 to 6, keyed on together. These were
+    // recorded from this implementation, not from another OPLL core, so they only catch changes to the output
+    const SNAPSHOT_OUTPUT: [[i32; 6]; 20] = [
+        [-193, 1958, -1996, -46, 121, -1625],
+        [1311, 1777, -1407, -67, -573, 1618],
+        [-79, 1649, -1746, 103, 1118, -1174],
+        [-1578, -636, -1370, 296, -1571, 2022],
+        [-2004, -70, 1064, -676, 1784, 812],
+        [-2029, -795, -1943, 711, -1954, -1712],
+        [-1858, -1432, -552, 558, 2046, 2022],
+        [-1538, -951, -1951, -1358, -1877, 1025],
+        [-1068, -937, -156, 1440, 1792, -1796],
+        [-540, -120, -1754, 527, -1716, -2032],
+        [-82, 1437, -1498, -1738, 1578, -1504],
+        [269, 1326, -1512, 1856, -1498, -1230],
+        [-766, 1418, -1314, 364, 1132, -1927],
+        [528, -14, 1386, -1990, -86, -1633],
+        [1796, -191, -1775, 2031, -855, 1330],
+        [1925, -687, -357, 137, 1120, 1774],
+        [1820, -1320, -1723, -1951, -1071, -1754],
+        [1633, -1049, -290, 1960, 1021, 1753],
+        [1323, -1097, -1480, 144, -1019, 1503],
+        [870, -464, -1517, -1873, 1021, -1268],
+    ];
+
+    #[test]
+    pub fn test_output_snapshot() {
+        let mut audio = Vrc7Audio::new();
+
+        for (channel, instrument) in (0..6).zip(1..) {
+            write(&mut audio, 0x10 + channel, 0x80 + channel * 16);
+            write(&mut audio, 0x30 + channel, instrument << 4);
+            write(&mut audio, 0x20 + channel, 0b00011000);
+        }
+
+        let mut levels = [0.0; 6];
+        for expected in SNAPSHOT_OUTPUT {
+            for _ in 0..250 * CPU_CYCLES_PER_SAMPLE as usize {
+                audio.clock(&mut levels);
+            }
+            assert_eq!(levels.map(|level| level as i32), expected);
+        }
+    }
+}
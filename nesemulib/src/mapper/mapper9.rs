@@ -0,0 +1,249 @@
+use crate::{cartridge::Mirroring, SystemControl, StateReader, StateWriter};
+
+use super::{copy_trainer, Mapper, PRG_RAM_END, PRG_RAM_START, PRG_ROM_END, PRG_ROM_START};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x1000;
+
+const LATCH_FD: usize = 0;
+const LATCH_FE: usize = 1;
+
+/// MMC2 and MMC4 only differ in how their PRG-ROM is banked, and in MMC4 having PRG-RAM
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LatchChip {
+    /// One switchable 8KB bank at $8000, with the last three 8KB banks fixed (PxROM)
+    MMC2,
+    /// One switchable 16KB bank at $8000, with the last 16KB bank fixed (FxROM)
+    MMC4,
+}
+
+pub struct Mapper9 {
+    chip: LatchChip,
+    prg_ram: Vec<u8>,
+    prg_rom_banks: usize,
+
+    prg_bank: usize,
+    // 4KB banks for each pattern table, selected by whether tile $FD or $FE was last fetched from it
+    chr_banks: [[usize; 2]; 2],
+    latches: [usize; 2],
+    mirroring: Mirroring,
+}
+
+impl SystemControl for Mapper9 {
+    fn reset(&mut self) {
+        self.prg_bank = 0;
+        self.chr_banks = [[0; 2]; 2];
+        self.latches = [LATCH_FE; 2];
+        self.mirroring = Mirroring::VERTICAL;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_ram);
+
+        state.write_usize(self.prg_bank);
+        for banks in &self.chr_banks {
+            state.write_usize(banks[LATCH_FD]);
+            state.write_usize(banks[LATCH_FE]);
+        }
+        for latch in &self.latches {
+            state.write_usize(*latch);
+        }
+        self.mirroring.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.read_bytes(&mut self.prg_ram)?;
+
+        self.prg_bank = state.read_usize()? & 0x0F;
+        for banks in &mut self.chr_banks {
+            banks[LATCH_FD] = state.read_usize()? & 0x1F;
+            banks[LATCH_FE] = state.read_usize()? & 0x1F;
+        }
+        for latch in &mut self.latches {
+            *latch = state.read_usize()? & 0x01;
+        }
+        self.mirroring = Mirroring::load_state(state)?;
+
+        Ok(())
+    }
+}
+
+impl Mapper for Mapper9 {
+    fn mapped_cpu_read(&mut self, prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8> {
+        match addr {
+            PRG_RAM_START..=PRG_RAM_END if !self.prg_ram.is_empty() => {
+                Some(self.prg_ram[(addr - PRG_RAM_START) % self.prg_ram.len()])
+            }
+            PRG_ROM_START..=PRG_ROM_END => {
+                // Fixed banks count back from the last 8KB bank, wrapping around ROMs too small to hold them all
+                let bank_count = self.prg_rom_banks * 2;
+                let from_last_bank = |n: usize| bank_count - 1 - n % bank_count;
+
+                let bank = match (self.chip, addr) {
+                    (LatchChip::MMC2, 0x8000..=0x9FFF) => self.prg_bank,
+                    (LatchChip::MMC2, _) => from_last_bank(3 - ((addr - PRG_ROM_START) >> 13)),
+                    (LatchChip::MMC4, 0x8000..=0xBFFF) => self.prg_bank * 2 + ((addr >> 13) & 0x01),
+                    (LatchChip::MMC4, _) => from_last_bank(1 - ((addr >> 13) & 0x01)),
+                };
+
+                Some(prg_rom[(bank * PRG_BANK_SIZE + (addr & (PRG_BANK_SIZE - 1))) % prg_rom.len()])
+            }
+            _ => None
+        }
+    }
+
+    fn mapped_cpu_write(&mut self, _prg_rom: &mut Vec<u8>, addr: usize, byte: u8) -> bool {
+        match addr {
+            PRG_RAM_START..=PRG_RAM_END if !self.prg_ram.is_empty() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - PRG_RAM_START) % len] = byte;
+            }
+            0xA000..=0xAFFF => self.prg_bank = (byte & 0x0F) as usize,
+            0xB000..=0xBFFF => self.chr_banks[0][LATCH_FD] = (byte & 0x1F) as usize,
+            0xC000..=0xCFFF => self.chr_banks[0][LATCH_FE] = (byte & 0x1F) as usize,
+            0xD000..=0xDFFF => self.chr_banks[1][LATCH_FD] = (byte & 0x1F) as usize,
+            0xE000..=0xEFFF => self.chr_banks[1][LATCH_FE] = (byte & 0x1F) as usize,
+            0xF000..=0xFFFF => {
+                self.mirroring = if byte & 0x01 == 0 {
+                    Mirroring::VERTICAL
+                } else {
+                    Mirroring::HORIZONTAL
+                };
+            }
+            _ => return false
+        }
+
+        true
+    }
+
+    fn mapped_ppu_read(&self, chr_rom: &Vec<u8>, addr: usize) -> u8 {
+        let table = (addr >> 12) & 0x01;
+        let bank = self.chr_banks[table][self.latches[table]];
+
+        chr_rom[(bank * CHR_BANK_SIZE + (addr & (CHR_BANK_SIZE - 1))) % chr_rom.len()]
+    }
+
+    // The latches only switch after the tile's pattern has been fetched, so the tile itself is drawn from the old bank.
+    // MMC2 only watches a single address for the first pattern table.
+    fn notify_ppu_read(&mut self, addr: usize) {
+        match (self.chip, addr) {
+            (_, 0x0FD8) | (LatchChip::MMC4, 0x0FD9..=0x0FDF) => self.latches[0] = LATCH_FD,
+            (_, 0x0FE8) | (LatchChip::MMC4, 0x0FE9..=0x0FEF) => self.latches[0] = LATCH_FE,
+            (_, 0x1FD8..=0x1FDF) => self.latches[1] = LATCH_FD,
+            (_, 0x1FE8..=0x1FEF) => self.latches[1] = LATCH_FE,
+            _ => {}
+        }
+    }
+
+    fn get_updated_mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    fn load_trainer(&mut self, trainer: &[u8]) -> bool {
+        copy_trainer(&mut self.prg_ram, trainer)
+    }
+
+    fn get_save_ram(&self) -> Option<&[u8]> {
+        if self.prg_ram.is_empty() { None } else { Some(&self.prg_ram) }
+    }
+
+    fn load_save_ram(&mut self, save_ram: &[u8]) -> bool {
+        self.prg_ram.copy_from_slice(save_ram);
+        !self.prg_ram.is_empty()
+    }
+}
+
+impl Mapper9 {
+    /// MMC2 boards don't have any PRG-RAM, so `prg_ram_size` is only used for MMC4
+    pub fn new(chip: LatchChip, prg_rom_banks: usize, prg_ram_size: usize) -> Self {
+        let prg_ram_size = match chip {
+            LatchChip::MMC2 => 0,
+            LatchChip::MMC4 => prg_ram_size,
+        };
+
+        Self {
+            chip,
+            prg_ram: vec![0; prg_ram_size],
+            prg_rom_banks,
+
+            prg_bank: 0,
+            chr_banks: [[0; 2]; 2],
+            latches: [LATCH_FE; 2],
+            mirroring: Mirroring::VERTICAL,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mapper::TestRom;
+
+    #[test]
+    pub fn test_mmc2_latches() {
+        let mut cartridge = TestRom::ines(9, 0x20000, 0x8000).prg_banks(0x2000).chr_banks(0x1000).cartridge();
+
+        cartridge.cpu_write(0xB000, 1);
+        cartridge.cpu_write(0xC000, 2);
+        cartridge.cpu_write(0xD000, 3);
+        cartridge.cpu_write(0xE000, 4);
+
+        // both latches power on set to $FE
+        assert_eq!(cartridge.ppu_read(0x0000), 2);
+        assert_eq!(cartridge.ppu_read(0x1000), 4);
+
+        cartridge.notify_ppu_read(0x0FD8);
+        assert_eq!(cartridge.ppu_read(0x0000), 1);
+
+        // MMC2 only switches the first pattern table on exactly $0FD8 and $0FE8
+        cartridge.notify_ppu_read(0x0FE9);
+        assert_eq!(cartridge.ppu_read(0x0000), 1);
+        cartridge.notify_ppu_read(0x0FE8);
+        assert_eq!(cartridge.ppu_read(0x0000), 2);
+
+        cartridge.notify_ppu_read(0x1FDF);
+        assert_eq!(cartridge.ppu_read(0x1000), 3);
+        assert_eq!(cartridge.ppu_read(0x0000), 2);
+    }
+
+    #[test]
+    pub fn test_mmc2_prg_banking() {
+        let mut cartridge = TestRom::ines(9, 0x20000, 0x8000).prg_banks(0x2000).chr_banks(0x1000).cartridge();
+
+        cartridge.cpu_write(0xA000, 5);
+        assert_eq!(cartridge.cpu_read(0x8000), Some(5));
+        assert_eq!(cartridge.cpu_read(0xA000), Some(13));
+        assert_eq!(cartridge.cpu_read(0xC000), Some(14));
+        assert_eq!(cartridge.cpu_read(0xE000), Some(15));
+        assert_eq!(cartridge.get_save_ram(), None);
+
+        // with only 16KB of PRG-ROM, the fixed banks wrap around it
+        let mut cartridge = TestRom::ines(9, 0x4000, 0x8000).prg_banks(0x2000).chr_banks(0x1000).cartridge();
+        assert_eq!(cartridge.cpu_read(0xA000), Some(1));
+        assert_eq!(cartridge.cpu_read(0xC000), Some(0));
+        assert_eq!(cartridge.cpu_read(0xE000), Some(1));
+    }
+
+    #[test]
+    pub fn test_mmc4() {
+        let mut cartridge = TestRom::ines(10, 0x20000, 0x8000)
+            .prg_banks(0x2000)
+            .chr_banks(0x1000)
+            .flags_6(0x02)
+            .cartridge();
+
+        cartridge.cpu_write(0xA000, 3);
+        assert_eq!(cartridge.cpu_read(0x8000), Some(6));
+        assert_eq!(cartridge.cpu_read(0xA000), Some(7));
+        assert_eq!(cartridge.cpu_read(0xC000), Some(14));
+        assert_eq!(cartridge.cpu_read(0xE000), Some(15));
+
+        cartridge.cpu_write(0xB000, 1);
+        cartridge.notify_ppu_read(0x0FDB);
+        assert_eq!(cartridge.ppu_read(0x0000), 1);
+
+        cartridge.cpu_write(0x6000, 0x42);
+        assert_eq!(cartridge.cpu_read(0x6000), Some(0x42));
+        assert_eq!(cartridge.get_save_ram().unwrap().len(), 0x2000);
+    }
+}
@@ -1,18 +1,30 @@
-use crate::SystemControl;
+use crate::{SystemControl, StateReader, StateWriter};
 
 use super::Mapper;
 
 
 pub struct TestMapper {
     prg_rom: [u8; 0x10000],
+    // when set, the last nametable slot reads this byte and ignores writes, like MMC5's fill mode
+    pub fill_name_table: Option<u8>,
 }
 
 impl SystemControl for TestMapper {
     fn reset(&mut self) {}
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.prg_rom);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.read_bytes(&mut self.prg_rom)?;
+
+        Ok(())
+    }
 }
 
 impl Mapper for TestMapper {
-    fn mapped_cpu_read(&self, _prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8> {
+    fn mapped_cpu_read(&mut self, _prg_rom: &mut Vec<u8>, addr: usize) -> Option<u8> {
         Some(self.prg_rom[addr])
     }
     
@@ -28,6 +40,17 @@ impl Mapper for TestMapper {
     fn mapped_ppu_write(&mut self, chr_rom: &mut Vec<u8>, addr: usize, byte: u8) {
         chr_rom[addr] = byte;
     }
+
+    fn mapped_name_table_read(&self, _chr_rom: &Vec<u8>, addr: usize) -> Option<u8> {
+        match self.fill_name_table {
+            Some(byte) if addr & 0x0C00 == 0x0C00 => Some(byte),
+            _ => None,
+        }
+    }
+
+    fn mapped_name_table_write(&mut self, _chr_rom: &mut Vec<u8>, addr: usize, _byte: u8) -> bool {
+        self.fill_name_table.is_some() && addr & 0x0C00 == 0x0C00
+    }
 }
 
 #[cfg(test)]
@@ -35,6 +58,7 @@ impl TestMapper {
     pub fn new() -> Self {
         Self {
             prg_rom: [0; 0x10000],
+            fill_name_table: None,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,97 @@
+use crate::cartridge::{CartridgeNes, CHR_ROM_SIZE, PRG_ROM_SIZE};
+
+/// Builds iNES and NES 2.0 files for tests, so that each one only has to say how its board differs
+pub struct TestRom {
+    header: [u8; 0x10],
+    prg_rom: Vec<u8>,
+    chr_rom: Vec<u8>,
+}
+
+impl TestRom {
+    /// A plain iNES file, with sizes in bytes. No CHR-ROM means CHR-RAM
+    pub fn ines(mapper_num: u16, prg_rom_size: usize, chr_rom_size: usize) -> Self {
+        let mut rom = Self::new(mapper_num, prg_rom_size, chr_rom_size);
+        rom.header[7] &= 0xF0;
+        rom
+    }
+
+    /// An NES 2.0 file, with sizes in bytes. It has no PRG-RAM, and 8KB of CHR-RAM when there's no CHR-ROM
+    pub fn nes2(mapper_num: u16, submapper_num: u8, prg_rom_size: usize, chr_rom_size: usize) -> Self {
+        let mut rom = Self::new(mapper_num, prg_rom_size, chr_rom_size);
+        rom.header[8] = (submapper_num << 4) | (mapper_num >> 8) as u8;
+        if chr_rom_size == 0 {
+            rom.header[11] = 0x07;
+        }
+        rom
+    }
+
+    fn new(mapper_num: u16, prg_rom_size: usize, chr_rom_size: usize) -> Self {
+        assert!(prg_rom_size.is_multiple_of(PRG_ROM_SIZE) && chr_rom_size.is_multiple_of(CHR_ROM_SIZE));
+
+        let mut header = [0; 0x10];
+        header[0..4].copy_from_slice(&[0x4E, 0x45, 0x53, 0x1A]);
+        header[4] = (prg_rom_size / PRG_ROM_SIZE) as u8;
+        header[5] = (chr_rom_size / CHR_ROM_SIZE) as u8;
+        header[6] = (mapper_num as u8 & 0x0F) << 4;
+        header[7] = (mapper_num as u8 & 0xF0) | 0x08;
+
+        Self {
+            header,
+            prg_rom: vec![0; prg_rom_size],
+            chr_rom: vec![0; chr_rom_size],
+        }
+    }
+
+    /// Starts each `bank_size` bank of PRG-ROM with its own bank number
+    pub fn prg_banks(mut self, bank_size: usize) -> Self {
+        number_banks(&mut self.prg_rom, bank_size);
+        self
+    }
+
+    /// Starts each `bank_size` bank of CHR-ROM with its own bank number
+    pub fn chr_banks(mut self, bank_size: usize) -> Self {
+        number_banks(&mut self.chr_rom, bank_size);
+        self
+    }
+
+    /// Copies `bytes` into PRG-ROM, starting at `offset`
+    pub fn prg_rom_at(mut self, offset: usize, bytes: &[u8]) -> Self {
+        self.prg_rom[offset..offset + bytes.len()].copy_from_slice(bytes);
+        self
+    }
+
+    /// Sets the mirroring (0x01), battery (0x02) and four-screen (0x08) bits of flags 6
+    pub fn flags_6(mut self, flags: u8) -> Self {
+        self.header[6] |= flags & 0x0F;
+        self
+    }
+
+    /// NES 2.0 PRG-RAM sizes, as shift counts where the size is 64 << count
+    pub fn prg_ram(mut self, prg_ram_shift: u8, prg_nvram_shift: u8) -> Self {
+        self.header[10] = (prg_nvram_shift << 4) | prg_ram_shift;
+        self
+    }
+
+    /// NES 2.0 CHR-RAM size, as a shift count where the size is 64 << count
+    pub fn chr_ram(mut self, chr_ram_shift: u8) -> Self {
+        self.header[11] = chr_ram_shift;
+        self
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        let mut rom = self.header.to_vec();
+        rom.extend(self.prg_rom);
+        rom.extend(self.chr_rom);
+        rom
+    }
+
+    pub fn cartridge(self) -> CartridgeNes {
+        CartridgeNes::from_ines_bytes(&self.into_bytes()).unwrap()
+    }
+}
+
+fn number_banks(rom: &mut [u8], bank_size: usize) {
+    for (bank, data) in rom.chunks_mut(bank_size).enumerate() {
+        data[0] = bank as u8;
+    }
+}
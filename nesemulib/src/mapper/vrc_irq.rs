@@ -0,0 +1,115 @@
+use crate::{SystemControl, StateReader, StateWriter};
+
+// In scanline mode, the prescaler divides CPU cycles by 113.667 (341 / 3) to approximate scanlines
+const PRESCALER_PERIOD: i16 = 341;
+const PRESCALER_STEP: i16 = 3;
+
+/// The CPU cycle based IRQ counter shared by Konami's VRC4, VRC6 and VRC7
+pub struct VrcIrq {
+    pub latch: u8,
+    counter: u8,
+    prescaler: i16,
+
+    enabled: bool,
+    enable_after_ack: bool,
+    cycle_mode: bool,
+    pending: bool,
+}
+
+impl SystemControl for VrcIrq {
+    fn reset(&mut self) {
+        self.latch = 0;
+        self.counter = 0;
+        self.prescaler = 0;
+        self.enabled = false;
+        self.enable_after_ack = false;
+        self.cycle_mode = false;
+        self.pending = false;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.latch);
+        state.write_u8(self.counter);
+        state.write_u16(self.prescaler as u16);
+        state.write_bool(self.enabled);
+        state.write_bool(self.enable_after_ack);
+        state.write_bool(self.cycle_mode);
+        state.write_bool(self.pending);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.latch = state.read_u8()?;
+        self.counter = state.read_u8()?;
+        self.prescaler = (state.read_u16()? as i16).clamp(0, PRESCALER_PERIOD);
+        self.enabled = state.read_bool()?;
+        self.enable_after_ack = state.read_bool()?;
+        self.cycle_mode = state.read_bool()?;
+        self.pending = state.read_bool()?;
+        Ok(())
+    }
+}
+
+impl VrcIrq {
+    pub fn new() -> Self {
+        Self {
+            latch: 0,
+            counter: 0,
+            prescaler: 0,
+
+            enabled: false,
+            enable_after_ack: false,
+            cycle_mode: false,
+            pending: false,
+        }
+    }
+
+    /// Writing the control register acknowledges the IRQ, and reloads the counter if it's being enabled
+    pub fn write_control(&mut self, byte: u8) {
+        self.enable_after_ack = (byte & 0b001) != 0;
+        self.enabled = (byte & 0b010) != 0;
+        self.cycle_mode = (byte & 0b100) != 0;
+        self.pending = false;
+
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = PRESCALER_PERIOD;
+        }
+    }
+
+    pub fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    /// Called once per CPU cycle
+    pub fn clock(&mut self) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.cycle_mode {
+            self.tick();
+        } else {
+            self.prescaler -= PRESCALER_STEP;
+
+            if self.prescaler <= 0 {
+                self.prescaler += PRESCALER_PERIOD;
+                self.tick();
+            }
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.pending
+    }
+
+    // the counter counts up, and fires when it overflows
+    fn tick(&mut self) {
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
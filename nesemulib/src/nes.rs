@@ -0,0 +1,515 @@
+use std::vec::Drain;
+
+use crate::apu::Apu2A03;
+use crate::bus::SystemBus;
+use crate::cartridge::CartridgeNes;
+use crate::cpu::Cpu6502;
+use crate::ppu::Ppu2C03;
+use crate::savestate::{StateReader, StateWriter, STATE_MAGIC, STATE_VERSION};
+use crate::{Region, SystemControl};
+
+/// The whole console, which owns every chip and drives them all from a single clock.
+/// The APU lives inside the CPU, just like it does on the 2A03.
+pub struct Nes {
+    pub cpu: Cpu6502,
+    pub ppu: Ppu2C03,
+    pub bus: SystemBus,
+
+    /// Number of PPU dots elapsed since the last reset
+    pub total_cycles: u64,
+    /// Number of CPU cycles elapsed since the last reset
+    pub cpu_cycles: u64,
+    /// Number of frames completed since the last reset
+    pub frame_count: u64,
+    samples: Vec<f32>,
+
+    region: Region,
+    // Master clock cycles accumulated towards the next CPU cycle
+    master_clock: u32,
+}
+
+impl SystemControl for Nes {
+    fn reset(&mut self) {
+        self.bus.reset();
+        self.reset_chips();
+    }
+
+    /// Pressing the reset button, which the cartridge sees as a `soft_reset`,
+    /// so that multicarts that count presses can switch to another menu
+    fn soft_reset(&mut self) {
+        self.bus.soft_reset();
+        self.reset_chips();
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u64(self.total_cycles);
+        state.write_u64(self.cpu_cycles);
+        state.write_u64(self.frame_count);
+        self.region.save_state(state);
+        state.write_u32(self.master_clock);
+        self.cpu.save_state(state);
+        self.ppu.save_state(state);
+        self.bus.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.total_cycles = state.read_u64()?;
+        self.cpu_cycles = state.read_u64()?;
+        self.frame_count = state.read_u64()?;
+        self.set_region(Region::load_state(state)?);
+        self.master_clock = state.read_u32()?;
+        self.cpu.load_state(state)?;
+        self.ppu.load_state(state)?;
+        self.bus.load_state(state)
+    }
+}
+
+impl Nes {
+    /// Creates a NES of the region given by the cartridge's header
+    pub fn new(cartridge: CartridgeNes, sample_rate: u32) -> Self {
+        let region = Region::from_timing(cartridge.header.timing);
+
+        let mut nes = Self {
+            cpu: Cpu6502::new(Apu2A03::new(sample_rate)),
+            ppu: Ppu2C03::new(),
+            bus: SystemBus::new(cartridge),
+
+            total_cycles: 0,
+            cpu_cycles: 0,
+            frame_count: 0,
+            samples: Vec::new(),
+
+            region,
+            master_clock: 0,
+        };
+
+        nes.set_region(region);
+        nes.reset();
+        nes
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Switches the clock speeds and frame timing of every chip to match the given region.
+    /// Takes effect immediately, though games will usually only behave correctly after a reset.
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.ppu.set_region(region);
+        self.cpu.apu.set_region(region);
+    }
+
+    // Resets everything but the bus, which is reset first so that the CPU can read the reset vector
+    fn reset_chips(&mut self) {
+        self.cpu.reset(&mut self.bus);
+        self.ppu.reset();
+        self.total_cycles = 0;
+        self.cpu_cycles = 0;
+        self.frame_count = 0;
+        self.master_clock = self.first_master_clock();
+        self.samples.clear();
+    }
+
+    // Starts just short of a CPU cycle so that the CPU is clocked on the very first dot
+    fn first_master_clock(&self) -> u32 {
+        self.region.cpu_clock_divider() - self.region.ppu_clock_divider()
+    }
+
+    /// Advances the system by a single PPU dot. The CPU (or DMA) and APU are clocked whenever
+    /// enough master clock cycles have passed, which is every third dot on NTSC (3.2 dots on PAL).
+    /// Returns true if a CPU cycle took place during this dot.
+    pub fn step_cycle(&mut self) -> bool {
+        self.ppu.clock(&mut self.bus);
+
+        if self.ppu.scanline == -1 && self.ppu.cycles == 0 {
+            self.frame_count += 1;
+        }
+
+        self.master_clock += self.region.ppu_clock_divider();
+        let cpu_cycle = self.master_clock >= self.region.cpu_clock_divider();
+
+        if cpu_cycle {
+            self.master_clock -= self.region.cpu_clock_divider();
+
+            if self.bus.dma_transferring {
+                self.bus.dma_clock(self.cpu_cycles as u32);
+            } else if self.bus.dmc_read_stall > 0 {
+                self.bus.dmc_read_stall -= 1;
+            } else {
+                self.cpu.clock(&mut self.bus);
+            }
+
+            self.cpu.apu.cpu_clock(&mut self.bus);
+            self.bus.cartridge.cpu_clock();
+
+            if let Some(sample) = self.cpu.apu.cpu_try_clock_sample() {
+                self.samples.push(sample);
+            }
+
+            self.cpu_cycles += 1;
+        }
+
+        if self.ppu.nmi_requested() {
+            self.cpu.nmi(&mut self.bus);
+        }
+
+        if self.bus.irq_active() || self.cpu.apu.irq_active() {
+            self.cpu.irq(&mut self.bus);
+        }
+
+        self.total_cycles += 1;
+
+        cpu_cycle
+    }
+
+    /// Runs until the CPU has finished executing its current instruction
+    pub fn step_instruction(&mut self) {
+        loop {
+            let cpu_cycle = self.step_cycle();
+
+            if (cpu_cycle && self.cpu.instruction_complete()) || self.cpu.jammed {
+                break;
+            }
+        }
+    }
+
+    /// Runs until the PPU has finished rendering the current frame,
+    /// which can then be taken with `Ppu2C03::try_get_frame()`
+    pub fn run_frame(&mut self) {
+        let frame_count = self.frame_count;
+
+        while self.frame_count == frame_count {
+            self.step_cycle();
+        }
+    }
+
+    /// Runs for the given number of PPU dots
+    pub fn run_for_cycles(&mut self, cycles: u64) {
+        for _ in 0..cycles {
+            self.step_cycle();
+        }
+    }
+
+    /// Takes a snapshot of the entire machine, which can later be restored with `restore_snapshot`
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+
+        state.write_bytes(&STATE_MAGIC);
+        state.write_u16(STATE_VERSION);
+        state.write_u16(self.bus.cartridge.mapper_num);
+
+        self.save_state(&mut state);
+
+        state.into_bytes()
+    }
+
+    /// Restores a snapshot taken by `snapshot`. The machine is left untouched if the snapshot is invalid.
+    pub fn restore_snapshot(&mut self, data: &[u8]) -> Result<(), String> {
+        let backup = self.snapshot();
+
+        if let Err(e) = self.read_snapshot(data) {
+            self.read_snapshot(&backup).expect("Failed to restore state from backup");
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    fn read_snapshot(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut state = StateReader::new(data);
+
+        let mut magic = [0; 4];
+        state.read_bytes(&mut magic)?;
+        if magic != STATE_MAGIC {
+            return Err(String::from("Not a save state"));
+        }
+
+        let version = state.read_u16()?;
+        if version != STATE_VERSION {
+            return Err(format!("Unsupported save state version {} (expected {})", version, STATE_VERSION));
+        }
+
+        let mapper_num = state.read_u16()?;
+        if mapper_num != self.bus.cartridge.mapper_num {
+            return Err(format!("Save state is for mapper {}, but the cartridge uses mapper {}", 
+                mapper_num, self.bus.cartridge.mapper_num));
+        }
+
+        self.load_state(&mut state)?;
+
+        if !state.is_empty() {
+            return Err(String::from("Save state contains unexpected trailing data"));
+        }
+
+        Ok(())
+    }
+
+    /// Takes all audio samples output by the APU since the last call.
+    /// Frontends should call this regularly as samples are buffered until drained.
+    pub fn drain_samples(&mut self) -> Drain<'_, f32> {
+        self.samples.drain(..)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::CartridgeNes;
+    use crate::mapper::TestRom;
+    use crate::savestate::StateWriter;
+    use crate::{Region, SystemControl};
+
+    use super::Nes;
+
+    #[test]
+    pub fn test_nestest_automation() {
+        let cartridge = CartridgeNes::from_ines_file("../roms/nestest.nes").unwrap();
+        let mut nes = Nes::new(cartridge, 44100);
+
+        // automated mode starts at 0xC000 and ends by returning to an address of 0x0000
+        nes.cpu.program_counter = 0xC000;
+
+        for _ in 0..8991 {
+            nes.step_instruction();
+        }
+
+        assert!(!nes.cpu.jammed);
+        assert_eq!(nes.bus.cpu_read(0x0002, true), Some(0x00), "legal opcode tests failed");
+        assert_eq!(nes.bus.cpu_read(0x0003, true), Some(0x00), "illegal opcode tests failed");
+    }
+
+    #[test]
+    pub fn test_save_state_round_trip() {
+        let cartridge = CartridgeNes::from_ines_file("../roms/nestest.nes").unwrap();
+        let mut nes = Nes::new(cartridge, 44100);
+        nes.cpu.program_counter = 0xC000;
+
+        for _ in 0..2000 {
+            nes.step_instruction();
+        }
+
+        let state = nes.snapshot();
+
+        for _ in 0..3000 {
+            nes.step_instruction();
+        }
+        let expected = nes.snapshot();
+
+        nes.restore_snapshot(&state).unwrap();
+        assert_eq!(nes.snapshot(), state);
+
+        for _ in 0..3000 {
+            nes.step_instruction();
+        }
+        assert_eq!(nes.snapshot(), expected);
+    }
+
+    #[test]
+    pub fn test_invalid_save_state() {
+        let cartridge = CartridgeNes::from_ines_file("../roms/nestest.nes").unwrap();
+        let mut nes = Nes::new(cartridge, 44100);
+        let state = nes.snapshot();
+
+        assert!(nes.restore_snapshot(b"not a save state").is_err());
+        assert!(nes.restore_snapshot(&state[..state.len() - 1]).is_err());
+
+        let mut wrong_version = state.clone();
+        wrong_version[4] = wrong_version[4].wrapping_add(1);
+        assert!(nes.restore_snapshot(&wrong_version).is_err());
+
+        // failed loads should leave the machine untouched
+        assert_eq!(nes.snapshot(), state);
+    }
+
+    #[test]
+    pub fn test_corrupt_mapper_state() {
+        // (mapper, submapper) of every board, as NES 2.0 cartridges with CHR-ROM and no PRG-RAM
+        // so that the cartridge's state is mostly the mapper's registers
+        let boards = [
+            (0, 0), (1, 0), (2, 0), (3, 0), (4, 0), (4, 1), (4, 3), (4, 4), (5, 0), (7, 0), (9, 0), (10, 0),
+            (11, 0), (13, 0), (15, 0), (16, 4), (16, 5), (19, 0), (21, 0), (22, 0), (23, 0), (24, 0), (25, 0),
+            (26, 0), (30, 0), (34, 0), (34, 1), (38, 0), (57, 0), (58, 0), (66, 0), (69, 0), (70, 0), (71, 1),
+            (76, 0), (79, 0), (85, 0), (87, 0), (88, 0), (95, 0), (118, 0), (119, 0), (140, 0), (152, 0),
+            (153, 0), (154, 0), (157, 0), (159, 0), (180, 0), (206, 0), (225, 0), (226, 0), (227, 0),
+            (228, 0), (232, 0), (233, 0),
+        ];
+
+        for (mapper_num, submapper_num) in boards {
+            let mut nes = Nes::new(TestRom::nes2(mapper_num, submapper_num, 0x20000, 0x20000).cartridge(), 44100);
+
+            // $80 in every register enables the expansion audio channels, so that they get clocked from the corrupted state
+            for addr in 0x4020..=0xFFFF {
+                nes.bus.cpu_write(addr, 0x80);
+            }
+            nes.bus.cpu_write(0x2001, 0x18);
+            let state = nes.snapshot();
+
+            // the cartridge's state comes first in the bus's
+            let mut bus_state = StateWriter::new();
+            nes.bus.save_state(&mut bus_state);
+            let mut cartridge_state = StateWriter::new();
+            nes.bus.cartridge.save_state(&mut cartridge_state);
+            let start = state.len() - bus_state.into_bytes().len();
+            let end = start + cartridge_state.into_bytes().len();
+
+            // loading any one corrupted byte either fails, or leaves the mapper in a state it can run from
+            for i in start..end {
+                let mut corrupted = state.clone();
+                corrupted[i] = 0xFF;
+
+                if nes.restore_snapshot(&corrupted).is_err() {
+                    continue;
+                }
+
+                for _ in 0..341 {
+                    nes.step_cycle();
+                }
+                for addr in (0x4020..=0xFFFF).step_by(0x0101) {
+                    nes.bus.cpu_read(addr, false);
+                    nes.bus.cpu_write(addr, 0x00);
+                }
+
+                nes.restore_snapshot(&state).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_region_frame_timing() {
+        for (region, cpu_cycles_per_frame) in [(Region::PAL, 33247), (Region::DENDY, 35464)] {
+            let cartridge = CartridgeNes::from_ines_file("../roms/nestest.nes").unwrap();
+            let mut nes = Nes::new(cartridge, 44100);
+            nes.set_region(region);
+            nes.reset();
+
+            // PAL and Dendy frames are 312 scanlines long and never skip a dot
+            nes.run_frame();
+            let (dots, cpu_cycles) = (nes.total_cycles, nes.cpu_cycles);
+            nes.run_frame();
+
+            assert_eq!(nes.total_cycles - dots, 312 * 341, "{:?}", region);
+            assert!((nes.cpu_cycles - cpu_cycles).abs_diff(cpu_cycles_per_frame) <= 1, "{:?}", region);
+        }
+    }
+
+    // A mapper 4 ROM that runs `code` from $E000
+    fn mmc3_nes(code: &[u8]) -> Nes {
+        let cartridge = TestRom::ines(4, 0x8000, 0x2000)
+            .prg_rom_at(0x6000, code)
+            .prg_rom_at(0x7FFC, &[0x00, 0xE0])
+            .cartridge();
+
+        Nes::new(cartridge, 44100)
+    }
+
+    // Enables rendering and the IRQ, with the counter reloaded with 10, then loops with interrupts disabled
+    fn mmc3_irq_code(ppu_ctrl: u8) -> [u8; 25] {
+        [
+            0x78, 0xA9, ppu_ctrl, 0x8D, 0x00, 0x20, 0xA9, 0x18, 0x8D, 0x01, 0x20,
+            0xA9, 0x0A, 0x8D, 0x00, 0xC0, 0x8D, 0x01, 0xC0, 0x8D, 0x01, 0xE0,
+            0x4C, 0x16, 0xE0,
+        ]
+    }
+
+    #[test]
+    pub fn test_mmc3_irq_timing() {
+        // (PPUCTRL, scanline and dots the IRQ fires on). A12 rises when the sprite patterns are fetched from $1000,
+        // or when the next scanline's first tiles are fetched from $1000. 8x16 sprites pick their pattern table from
+        // their tile number, so the sprites at the top of the screen (OAM is all zero at power-on) come from $0000 and
+        // don't clock the counter, while the empty slots on the lines below fetch tile $FF from $1000
+        let cases = [(0x08, 9, 258..=264), (0x10, 9, 322..=328), (0x20, 25, 258..=264)];
+
+        for (ppu_ctrl, scanline, dots) in cases {
+            let mut nes = mmc3_nes(&mmc3_irq_code(ppu_ctrl));
+
+            while !nes.bus.irq_active() {
+                nes.step_cycle();
+            }
+
+            assert_eq!(nes.ppu.scanline, scanline, "PPUCTRL {:02X}", ppu_ctrl);
+            assert!(dots.contains(&nes.ppu.cycles), "PPUCTRL {:02X} fired on dot {}", ppu_ctrl, nes.ppu.cycles);
+        }
+    }
+
+    #[test]
+    #[ignore]
+    pub fn test_mmc3_test_roms() {
+        let test_rom_path = "../roms/mmc3_test";
+
+        // (ROM, submapper) where the last one tests the MMC3A's IRQ counter
+        let roms = [
+            ("1-clocking", 0), ("2-details", 0), ("3-A12_clocking", 0),
+            ("4-scanline_timing", 0), ("5-MMC3", 0), ("6-MMC3_alt", 4),
+        ];
+
+        for (name, submapper_num) in roms {
+            let mut data = std::fs::read(format!("{}/{}.nes", test_rom_path, name)).unwrap();
+
+            // rewritten as NES 2.0 to pick the submapper, keeping the 8KB of PRG-RAM the results are written to
+            data[7] = (data[7] & 0xF0) | 0x08;
+            data[8] = submapper_num << 4;
+            data[9] = 0;
+            data[10] = 0x07;
+
+            let cartridge = CartridgeNes::from_ines_bytes(&data).unwrap();
+            let mut nes = Nes::new(cartridge, 44100);
+
+            // results are valid once $6001-$6003 hold DE B0 61, and $6000 stops being $80 (running)
+            let read = |nes: &mut Nes, addr: usize| nes.bus.cpu_read(addr, true).unwrap_or(0);
+            for _ in 0..600 {
+                nes.run_frame();
+
+                let signature = [read(&mut nes, 0x6001), read(&mut nes, 0x6002), read(&mut nes, 0x6003)];
+                if signature == [0xDE, 0xB0, 0x61] && read(&mut nes, 0x6000) != 0x80 {
+                    break;
+                }
+            }
+
+            let message: String = (0x6004..0x6100)
+                .map(|addr| read(&mut nes, addr))
+                .take_while(|&byte| byte != 0)
+                .map(|byte| byte as char)
+                .collect();
+
+            assert_eq!(read(&mut nes, 0x6000), 0x00, "{}: {}", name, message);
+        }
+    }
+
+    #[test]
+    pub fn test_mmc3_irq_without_a12_rises() {
+        // with the background and sprites both at $0000, A12 never rises while rendering
+        let mut nes = mmc3_nes(&mmc3_irq_code(0x00));
+
+        for _ in 0..3 {
+            nes.run_frame();
+            assert!(!nes.bus.irq_active());
+        }
+    }
+
+    #[test]
+    pub fn test_mmc3_irq_from_ppuaddr() {
+        // with everything rendered from $0000, only setting PPUADDR to $1000 mid-frame makes A12 rise. The counter
+        // is reloaded with 1 by the first rise, and the second one takes it to 0. A delay loop after enabling
+        // rendering gets past the pre-render scanline
+        let code = [
+            0x78, 0xA9, 0x01, 0x8D, 0x00, 0xC0, 0x8D, 0x01, 0xC0, 0x8D, 0x01, 0xE0,
+            0xA9, 0x18, 0x8D, 0x01, 0x20, 0xA2, 0x00, 0xCA, 0xD0, 0xFD,
+            0xA9, 0x10, 0x8D, 0x06, 0x20, 0xA9, 0x00, 0x8D, 0x06, 0x20,
+            0x8D, 0x06, 0x20, 0x8D, 0x06, 0x20,
+            0xA9, 0x10, 0x8D, 0x06, 0x20, 0xA9, 0x00, 0x8D, 0x06, 0x20,
+            0x4C, 0x30, 0xE0,
+        ];
+        let mut nes = mmc3_nes(&code);
+
+        // after the first and second writes that set PPUADDR to $1000
+        for (pc, irq) in [(0xE020, false), (0xE030, true)] {
+            while nes.cpu.program_counter != pc {
+                assert!(!nes.bus.irq_active());
+                nes.step_instruction();
+            }
+
+            assert!((0..240).contains(&nes.ppu.scanline), "PPUADDR written on scanline {}", nes.ppu.scanline);
+            assert_eq!(nes.bus.irq_active(), irq, "PC {:04X}", pc);
+        }
+    }
+}
@@ -3,10 +3,10 @@ mod registers;
 mod palette;
 
 pub use ppubus::PpuBus;
-pub use self::{palette::{Colour, DISPLAY_PALETTE}, registers::*};
+pub use self::{palette::{Colour, DISPLAY_PALETTE, APPROX_PAL_DISPLAY_PALETTE}, registers::*};
 
 use crate::bus::SystemBus;
-use crate::{SystemControl, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use crate::{Region, SystemControl, StateReader, StateWriter, DISPLAY_HEIGHT, DISPLAY_WIDTH};
 
 use self::ppubus::{OAMEntry, ATTR_TABLE_START, NAME_TABLE_START, OAM_SIZE, PALETTE_TABLE_START};
 
@@ -34,11 +34,8 @@ const S_RENDER_END: i32 = 239;
 /// Idle scanline that occurs between rendering and VBLANK
 const S_POST_RENDER: i32 = 240;
 
-/// First scanline of the VBLANK Period
-const S_VBLANK_START: i32 = 241;
-
-/// Last scanline of VBLANK and final scanline of the frame
-const S_VBLANK_END: i32 = 260;
+// The first scanline of VBLANK and the final scanline of the frame depend on the region,
+// see `Region::vblank_start_scanline()` and `Region::last_scanline()`
 
 /// Final cycle of each scanline
 const C_HBLANK_END: u32 = 340;
@@ -67,6 +64,9 @@ pub struct Ppu2C03 {
     nmi: bool,
     odd_frame: bool,
     frame_complete: bool,
+
+    region: Region,
+    palette: &'static [Colour; 64],
 }
 
 impl SystemControl for Ppu2C03 {
@@ -93,6 +93,62 @@ impl SystemControl for Ppu2C03 {
         self.nmi = false;
         self.odd_frame = false;
     }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u32(self.cycles);
+        state.write_i32(self.scanline);
+
+        for sprite in &self.sprite_cache {
+            sprite.save_state(state);
+        }
+        state.write_usize(self.sprite_cache_count);
+        state.write_bytes(&self.spr_patt_lo_shifter);
+        state.write_bytes(&self.spr_patt_hi_shifter);
+        state.write_bool(self.contains_spr_0);
+        state.write_bool(self.spr_0_rendered);
+
+        state.write_u8(self.bg_next_tile_id);
+        state.write_u8(self.bg_next_tile_attr);
+        state.write_u8(self.bg_next_tile_lo);
+        state.write_u8(self.bg_next_tile_hi);
+        state.write_u16(self.bg_patt_lo_shifter);
+        state.write_u16(self.bg_patt_hi_shifter);
+        state.write_u16(self.bg_attr_lo_shifter);
+        state.write_u16(self.bg_attr_hi_shifter);
+
+        state.write_bool(self.nmi);
+        state.write_bool(self.odd_frame);
+        state.write_bool(self.frame_complete);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.cycles = state.read_u32()?;
+        self.scanline = state.read_i32()?;
+
+        for sprite in &mut self.sprite_cache {
+            sprite.load_state(state)?;
+        }
+        self.sprite_cache_count = state.read_usize()?.min(SPRITE_CACHE_SIZE);
+        state.read_bytes(&mut self.spr_patt_lo_shifter)?;
+        state.read_bytes(&mut self.spr_patt_hi_shifter)?;
+        self.contains_spr_0 = state.read_bool()?;
+        self.spr_0_rendered = state.read_bool()?;
+
+        self.bg_next_tile_id = state.read_u8()?;
+        self.bg_next_tile_attr = state.read_u8()?;
+        self.bg_next_tile_lo = state.read_u8()?;
+        self.bg_next_tile_hi = state.read_u8()?;
+        self.bg_patt_lo_shifter = state.read_u16()?;
+        self.bg_patt_hi_shifter = state.read_u16()?;
+        self.bg_attr_lo_shifter = state.read_u16()?;
+        self.bg_attr_hi_shifter = state.read_u16()?;
+
+        self.nmi = state.read_bool()?;
+        self.odd_frame = state.read_bool()?;
+        self.frame_complete = state.read_bool()?;
+
+        Ok(())
+    }
 }
 
 impl Ppu2C03 {
@@ -121,9 +177,20 @@ impl Ppu2C03 {
             nmi: false,
             odd_frame: false,
             frame_complete: false,
+
+            region: Region::NTSC,
+            palette: &DISPLAY_PALETTE,
         }
     }
 
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.palette = match region {
+            Region::PAL => &APPROX_PAL_DISPLAY_PALETTE,
+            Region::NTSC | Region::DENDY => &DISPLAY_PALETTE,
+        };
+    }
+
     pub fn clock(&mut self, bus: &mut SystemBus) {
 
         match self.scanline {
@@ -135,6 +202,8 @@ impl Ppu2C03 {
 
                     self.spr_patt_lo_shifter = [0; SPRITE_CACHE_SIZE];
                     self.spr_patt_hi_shifter = [0; SPRITE_CACHE_SIZE];
+                    // no sprites are evaluated on the pre-render scanline, so none are drawn on the first one
+                    self.sprite_cache_count = 0;
                 }
                 
                 // Background Graphics Processing 
@@ -147,7 +216,7 @@ impl Ppu2C03 {
 
                                 self.load_bg_shifters();
                                 let bg_next_tile_id_addr = NAME_TABLE_START | ((bus.ppu_bus.vram_addr.0 as usize) & 0x0FFF);
-                                self.bg_next_tile_id = bus.ppu_read(bg_next_tile_id_addr);
+                                self.bg_next_tile_id = bus.ppu_fetch(bg_next_tile_id_addr);
                             }
                             2 => { // fetch tile palette attribute
                                 let coarse_x = bus.ppu_bus.vram_addr.coarse_x() as usize;
@@ -159,7 +228,7 @@ impl Ppu2C03 {
                                     | ((coarse_y >> 2) << 3)
                                     | (coarse_x >> 2);
 
-                                self.bg_next_tile_attr = bus.ppu_read(bg_next_tile_attr_addr);
+                                self.bg_next_tile_attr = bus.ppu_fetch(bg_next_tile_attr_addr);
 
                                 if coarse_y & 0x02 != 0 { self.bg_next_tile_attr >>= 4; }
                                 if coarse_x & 0x02 != 0 { self.bg_next_tile_attr >>= 2; }
@@ -170,7 +239,7 @@ impl Ppu2C03 {
                                     + ((self.bg_next_tile_id as usize) * TILE_BYTES)
                                     + bus.ppu_bus.vram_addr.fine_y() as usize;
 
-                                self.bg_next_tile_lo = bus.ppu_read(bg_next_tile_lo_addr);
+                                self.bg_next_tile_lo = bus.ppu_fetch(bg_next_tile_lo_addr);
                             },
                             6 => { // fetch HIGH plane of tile pattern
                                 let bg_next_tile_hi_addr = bus.ppu_bus.ctrl.bg_pattern_addr()
@@ -178,7 +247,7 @@ impl Ppu2C03 {
                                     + bus.ppu_bus.vram_addr.fine_y() as usize
                                     + 8;
 
-                                self.bg_next_tile_hi = bus.ppu_read(bg_next_tile_hi_addr);
+                                self.bg_next_tile_hi = bus.ppu_fetch(bg_next_tile_hi_addr);
                             },
                             7 => { // increment vram horizontal scroll bits
                                 if Ppu2C03::rendering_enabled(&mut bus.ppu_bus) {
@@ -204,7 +273,7 @@ impl Ppu2C03 {
                     338 | 340 => {
                         if self.cycles & 0x01 == 0 {
                             let bg_next_tile_id_addr = NAME_TABLE_START | ((bus.ppu_bus.vram_addr.0 as usize) & 0x0FFF);
-                            self.bg_next_tile_id = bus.ppu_read(bg_next_tile_id_addr);
+                            self.bg_next_tile_id = bus.ppu_fetch(bg_next_tile_id_addr);
                         }
                     }
                     _ => {}
@@ -217,82 +286,47 @@ impl Ppu2C03 {
                 };
 
                 // Sprite / Foreground Graphics Processing 
-                match self.cycles {
-                    257 if self.scanline >= S_RENDER_START => { // fetch ALL sprites for next scanline and update SPR_OVERFLOW
-                        self.sprite_cache = [OAMEntry::default(); SPRITE_CACHE_SIZE];
-                        self.spr_patt_lo_shifter = [0; SPRITE_CACHE_SIZE];
-                        self.spr_patt_hi_shifter = [0; SPRITE_CACHE_SIZE];
-                        self.sprite_cache_count = 0;
+                if self.cycles == 257 && self.scanline >= S_RENDER_START { // fetch ALL sprites for next scanline and update SPR_OVERFLOW
+                    self.sprite_cache = [OAMEntry::default(); SPRITE_CACHE_SIZE];
+                    self.spr_patt_lo_shifter = [0; SPRITE_CACHE_SIZE];
+                    self.spr_patt_hi_shifter = [0; SPRITE_CACHE_SIZE];
+                    self.sprite_cache_count = 0;
+                    
+                    self.contains_spr_0 = false;
+
+                    let mut oam_pos = 0;
+
+                    let ppu_bus = &mut bus.ppu_bus;
+
+                    while oam_pos < OAM_SIZE && self.sprite_cache_count <= SPRITE_CACHE_SIZE {
+                        let sprite_dist = self.scanline as i32 - ppu_bus.read_oam(oam_pos + 0) as i32;
                         
-                        self.contains_spr_0 = false;
-    
-                        let mut oam_pos = 0;
-
-                        let ppu_bus = &mut bus.ppu_bus;
-
-                        while oam_pos < OAM_SIZE && self.sprite_cache_count <= SPRITE_CACHE_SIZE {
-                            let sprite_dist = self.scanline as i32 - ppu_bus.read_oam(oam_pos + 0) as i32;
-                            
-                            if sprite_dist >= 0 && sprite_dist < ppu_bus.ctrl.spr_height() as i32 {
-    
-                                if self.sprite_cache_count < SPRITE_CACHE_SIZE {
-
-                                    if oam_pos == 0 {
-                                        self.contains_spr_0 = true;
-                                    }
-
-                                    self.sprite_cache[self.sprite_cache_count] = ppu_bus.read_oam_entry(oam_pos);
-                                    self.sprite_cache_count += 1;
-                                } else {
-                                    ppu_bus.status.set(PpuStatus::SPR_OVERFLOW, true);
+                        if sprite_dist >= 0 && sprite_dist < ppu_bus.ctrl.spr_height() as i32 {
+
+                            if self.sprite_cache_count < SPRITE_CACHE_SIZE {
+
+                                if oam_pos == 0 {
+                                    self.contains_spr_0 = true;
                                 }
-                            }
-    
-                            oam_pos += OAM_ENTRY_BYTES;
-                        }
-                    }
-                    340 => { // load sprite shifters
-                        for i in 0..self.sprite_cache_count {
-                            let sprite = self.sprite_cache[i];
-                            let y_dist = (self.scanline as usize) - sprite.y;
-
-                            let mut y_offset = y_dist & 0x07;
-                            if sprite.y_flipped() { y_offset = 7 - y_offset; }
-
-                            let pattern_addr_lo = if bus.ppu_bus.ctrl.spr_height() == 8 {
-                                bus.ppu_bus.ctrl.spr_pattern_addr()
-                                    | (sprite.id * TILE_BYTES)
-                                    | y_offset
+
+                                self.sprite_cache[self.sprite_cache_count] = ppu_bus.read_oam_entry(oam_pos);
+                                self.sprite_cache_count += 1;
                             } else {
-                                let tile_offset = if (y_dist < 8) ^ sprite.y_flipped() {
-                                    (sprite.id & 0b11111110) * TILE_BYTES
-                                } else {
-                                    ((sprite.id & 0b11111110) + 1) * TILE_BYTES
-                                };
-
-                                ((sprite.id & 0x01) << 12) 
-                                    | tile_offset 
-                                    | y_offset
-                            };
-
-                            let mut sprite_pattern_lo = bus.ppu_read(pattern_addr_lo + 0);
-                            let mut sprite_pattern_hi = bus.ppu_read(pattern_addr_lo + 8);
-
-                            if sprite.x_flipped() {
-                                sprite_pattern_lo = REVERSED_BYTE[sprite_pattern_lo as usize];
-                                sprite_pattern_hi = REVERSED_BYTE[sprite_pattern_hi as usize];
+                                ppu_bus.status.set(PpuStatus::SPR_OVERFLOW, true);
                             }
-
-                            self.spr_patt_lo_shifter[i] = sprite_pattern_lo;
-                            self.spr_patt_hi_shifter[i] = sprite_pattern_hi;
                         }
+
+                        oam_pos += OAM_ENTRY_BYTES;
                     }
-                    _ => {}
+                }
+
+                if matches!(self.cycles, 257..=320) { // fetch patterns of sprites for next scanline, one sprite every 8 cycles
+                    self.fetch_sprite_pattern(bus);
                 }
             }
             S_POST_RENDER => {} // Idle Scanline
-            S_VBLANK_START..=S_VBLANK_END => { // In VBlank
-                if self.scanline == S_VBLANK_START && self.cycles == 1 {
+            _ => { // In VBlank, or idle before it on Dendy
+                if self.scanline == self.region.vblank_start_scanline() && self.cycles == 1 {
                     bus.ppu_bus.status.set(PpuStatus::IN_VBLANK, true);
 
                     if bus.ppu_bus.ctrl.nmi_enabled() {
@@ -300,7 +334,6 @@ impl Ppu2C03 {
                     }
                 }
             }
-            _ => {}
         }
 
         // Draw Final Pixel
@@ -382,7 +415,7 @@ impl Ppu2C03 {
                 }
             };
             
-            let colour = Ppu2C03::get_colour_from_palette(bus, palette, pixel);
+            let colour = self.get_colour_from_palette(bus, palette, pixel);
             self.frame[(self.scanline as usize) * DISPLAY_WIDTH + self.cycles as usize - 1] = colour;
         }
 
@@ -392,11 +425,8 @@ impl Ppu2C03 {
 
         if Ppu2C03::rendering_enabled(&mut bus.ppu_bus) {
 
-            if self.cycles == 260 && self.scanline < S_POST_RENDER {
-                bus.cartridge.notify_scanline();
-            }
-
-            if self.odd_frame && self.cycles == C_HBLANK_END && self.scanline == S_PRE_RENDER {
+            if self.odd_frame && self.cycles == C_HBLANK_END && self.scanline == S_PRE_RENDER 
+                && self.region.skips_odd_frame_dot() {
                 self.cycles += 1;
             }
         }
@@ -406,7 +436,7 @@ impl Ppu2C03 {
             self.cycles = 0;
             self.scanline += 1;
 
-            if self.scanline > S_VBLANK_END {
+            if self.scanline > self.region.last_scanline() {
 
                 self.scanline = -1;
 
@@ -436,9 +466,9 @@ impl Ppu2C03 {
     }
 
     #[inline]
-    fn get_colour_from_palette(bus: &SystemBus, palette: usize, pixel: usize) -> Colour {
+    fn get_colour_from_palette(&self, bus: &SystemBus, palette: usize, pixel: usize) -> Colour {
         let palette_index = bus.ppu_read(PALETTE_TABLE_START + (palette << 2) + pixel) as usize;
-        DISPLAY_PALETTE[palette_index]
+        self.palette[palette_index]
     }
 
     #[inline]
@@ -476,6 +506,67 @@ impl Ppu2C03 {
         }
     }
 
+    // Each of the 8 sprite slots takes 8 cycles: two garbage nametable fetches, then the low and high pattern planes.
+    // Empty slots still fetch the patterns of tile $FF, which mappers watching PPU A12 rely on
+    fn fetch_sprite_pattern(&mut self, bus: &mut SystemBus) {
+        let slot = ((self.cycles - 257) / 8) as usize;
+
+        match (self.cycles - 257) % 8 {
+            0 | 2 => {
+                bus.ppu_fetch(NAME_TABLE_START | ((bus.ppu_bus.vram_addr.0 as usize) & 0x0FFF));
+            }
+            plane @ (4 | 6) => {
+                let plane_offset = if plane == 4 { 0 } else { 8 };
+
+                if slot >= self.sprite_cache_count {
+                    let pattern_addr = if bus.ppu_bus.ctrl.spr_height() == 8 {
+                        bus.ppu_bus.ctrl.spr_pattern_addr() | (0xFF * TILE_BYTES)
+                    } else {
+                        0x1000 | (0xFE * TILE_BYTES)
+                    };
+
+                    bus.ppu_fetch(pattern_addr + plane_offset);
+                    return;
+                }
+
+                let sprite = self.sprite_cache[slot];
+                let y_dist = (self.scanline as usize) - sprite.y;
+
+                let mut y_offset = y_dist & 0x07;
+                if sprite.y_flipped() { y_offset = 7 - y_offset; }
+
+                let pattern_addr = if bus.ppu_bus.ctrl.spr_height() == 8 {
+                    bus.ppu_bus.ctrl.spr_pattern_addr()
+                        | (sprite.id * TILE_BYTES)
+                        | y_offset
+                } else {
+                    let tile_offset = if (y_dist < 8) ^ sprite.y_flipped() {
+                        (sprite.id & 0b11111110) * TILE_BYTES
+                    } else {
+                        ((sprite.id & 0b11111110) + 1) * TILE_BYTES
+                    };
+
+                    ((sprite.id & 0x01) << 12) 
+                        | tile_offset 
+                        | y_offset
+                };
+
+                let mut sprite_pattern = bus.ppu_fetch(pattern_addr + plane_offset);
+
+                if sprite.x_flipped() {
+                    sprite_pattern = REVERSED_BYTE[sprite_pattern as usize];
+                }
+
+                if plane == 4 {
+                    self.spr_patt_lo_shifter[slot] = sprite_pattern;
+                } else {
+                    self.spr_patt_hi_shifter[slot] = sprite_pattern;
+                }
+            }
+            _ => {}
+        }
+    }
+
     #[inline]
     pub fn nmi_requested(&mut self) -> bool {
         let ret = self.nmi;
@@ -485,7 +576,7 @@ impl Ppu2C03 {
 
     // Index: 0 or 1, palette: 0 to 7
     pub fn get_pattern_table(&self, bus: &SystemBus, index: usize, palette: usize) -> [Colour; PATTERN_TABLE_W_H * PATTERN_TABLE_W_H] {
-        let mut patt_table = [DISPLAY_PALETTE[0x01]; PATTERN_TABLE_W_H * PATTERN_TABLE_W_H];
+        let mut patt_table = [self.palette[0x01]; PATTERN_TABLE_W_H * PATTERN_TABLE_W_H];
 
         for tile_y in 0..16 {
 
@@ -501,7 +592,7 @@ impl Ppu2C03 {
                         let pixel = (((tile_hi & (1 << col)) >> col) << 1)
                             | ((tile_lo & (1 << col)) >> col);
 
-                        patt_table[((tile_y * 8 + row) << 7) | tile_x * 8 + (7 - col)] = Self::get_colour_from_palette(bus, palette, pixel as usize);
+                        patt_table[((tile_y * 8 + row) << 7) | tile_x * 8 + (7 - col)] = self.get_colour_from_palette(bus, palette, pixel as usize);
                     }
                 }
             }
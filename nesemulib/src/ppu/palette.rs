@@ -69,4 +69,39 @@ pub const DISPLAY_PALETTE: [Colour; 64] = [
 	Colour(160, 162, 160),
 	Colour(0, 0, 0),
 	Colour(0, 0, 0),
-];
\ No newline at end of file
+];
+
+// The 2C07 generates its colours with a different phase to the 2C02, so on PAL the
+// NTSC palette is approximated with each colour's hue rotated by 15 degrees
+const PAL_HUE_ROTATION: f32 = 15.0;
+
+lazy_static! {
+    /// Not measured from or generated for a 2C07: only the NTSC palette with its hues rotated, which is
+    /// close enough to look right but won't match real PAL hardware colour for colour
+    pub static ref APPROX_PAL_DISPLAY_PALETTE: [Colour; 64] = {
+        let (sin, cos) = PAL_HUE_ROTATION.to_radians().sin_cos();
+        let mut palette = DISPLAY_PALETTE;
+
+        for colour in palette.iter_mut() {
+            let (r, g, b) = (colour.0 as f32, colour.1 as f32, colour.2 as f32);
+
+            // rotate the chroma (U and V) of the colour, keeping its luma (Y) the same
+            let y = 0.299 * r + 0.587 * g + 0.114 * b;
+            let u = 0.492 * (b - y);
+            let v = 0.877 * (r - y);
+            let (u, v) = (u * cos - v * sin, u * sin + v * cos);
+
+            let r = y + v / 0.877;
+            let b = y + u / 0.492;
+            let g = (y - 0.299 * r - 0.114 * b) / 0.587;
+
+            *colour = Colour(
+                r.round().clamp(0.0, 255.0) as u8,
+                g.round().clamp(0.0, 255.0) as u8,
+                b.round().clamp(0.0, 255.0) as u8,
+            );
+        }
+
+        palette
+    };
+}
@@ -1,4 +1,4 @@
-use crate::{cartridge::{CartridgeNes, Mirroring}, SystemControl};
+use crate::{cartridge::{CartridgeNes, NameTableSource}, SystemControl, StateReader, StateWriter};
 
 use super::registers::{LoopyPpuReg, PpuCtrl, PpuMask, PpuStatus};
 
@@ -51,6 +51,22 @@ impl OAMEntry {
     pub fn palette(&self) -> usize {
         self.attributes & 0x03
     }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.y as u8);
+        state.write_u8(self.id as u8);
+        state.write_u8(self.attributes as u8);
+        state.write_u8(self.x as u8);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.y = state.read_u8()? as usize;
+        self.id = state.read_u8()? as usize;
+        self.attributes = state.read_u8()? as usize;
+        self.x = state.read_u8()? as usize;
+
+        Ok(())
+    }
 }
 
 pub struct PpuBus {
@@ -86,6 +102,48 @@ impl SystemControl for PpuBus {
         self.ppu_addr_latch = false;
         self.ppu_data_buffer = 0;
     }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        for name_table in &self.name_table {
+            state.write_bytes(name_table);
+        }
+        state.write_bytes(&self.palette_table);
+        state.write_bytes(&self.oam);
+
+        state.write_u8(self.ctrl.bits());
+        state.write_u8(self.mask.bits());
+        state.write_u8(self.status.bits());
+        state.write_u8(self.oam_addr_reg);
+
+        state.write_u16(self.vram_addr.0);
+        state.write_u16(self.tram_addr.0);
+        state.write_u8(self.fine_x);
+
+        state.write_bool(self.ppu_addr_latch);
+        state.write_u8(self.ppu_data_buffer);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        for name_table in &mut self.name_table {
+            state.read_bytes(name_table)?;
+        }
+        state.read_bytes(&mut self.palette_table)?;
+        state.read_bytes(&mut self.oam)?;
+
+        self.ctrl = PpuCtrl::from_bits_truncate(state.read_u8()?);
+        self.mask = PpuMask::from_bits_truncate(state.read_u8()?);
+        self.status = PpuStatus::from_bits_truncate(state.read_u8()?);
+        self.oam_addr_reg = state.read_u8()?;
+
+        self.vram_addr = LoopyPpuReg(state.read_u16()?);
+        self.tram_addr = LoopyPpuReg(state.read_u16()?);
+        self.fine_x = state.read_u8()?;
+
+        self.ppu_addr_latch = state.read_bool()?;
+        self.ppu_data_buffer = state.read_u8()?;
+
+        Ok(())
+    }
 }
 
 impl PpuBus {
@@ -152,11 +210,18 @@ impl PpuBus {
 
                 if !read_only {
 
+                    let read_addr = if (self.vram_addr.0 as usize & 0x3FFF) >= PALETTE_TABLE_START {
+                        // the buffer is filled from the nametable "underneath" the palettes
+                        (self.vram_addr.0 - 0x1000) as usize
+                    } else {
+                        self.vram_addr.0 as usize
+                    };
+
+                    self.ppu_data_buffer = self.ppu_read(read_addr, cartridge);
+                    cartridge.notify_ppu_read(read_addr & 0x3FFF);
+
                     if (self.vram_addr.0 as usize & 0x3FFF) >= PALETTE_TABLE_START {
-                        self.ppu_data_buffer = self.ppu_read((self.vram_addr.0 - 0x1000) as usize, cartridge);
                         ret = self.ppu_data_buffer;
-                    } else {
-                        self.ppu_data_buffer = self.ppu_read(self.vram_addr.0 as usize, cartridge);
                     }
     
                     self.vram_addr.0 += self.ctrl.vram_addr_inc();
@@ -211,12 +276,14 @@ impl PpuBus {
                 } else {
                     self.tram_addr.0 = (self.tram_addr.0 & 0x7F00) | (byte as u16);
                     self.vram_addr.0 = self.tram_addr.0;
+                    cartridge.notify_ppu_addr(self.vram_addr.0 as usize & 0x3FFF);
                 }
 
                 self.ppu_addr_latch = !self.ppu_addr_latch;
             }
             0x0007 => {
                 self.ppu_write(self.vram_addr.0 as usize, byte, cartridge);
+                cartridge.notify_ppu_addr(self.vram_addr.0 as usize & 0x3FFF);
                 self.vram_addr.0 += self.ctrl.vram_addr_inc();
             }
             _ => unreachable!()
@@ -229,32 +296,13 @@ impl PpuBus {
         match addr {
             PATTERN_TABLE_START..=PATTERN_TABLE_END => cartridge.ppu_read(addr),
             NAME_TABLE_START..=NAME_TABLE_END => {
-                match cartridge.mirroring() {
-                    Mirroring::HORIZONTAL => {
-                        // [        A        ]|[        a        ]
-                        // [ 0x2000 - 0x3FFF ]|[ 0x4000 - 0x7FFF ]
-                        // --------------------------------------
-                        // [        B        ]|[        b        ]
-                        // [ 0x8000 - 0xBFFF ]|[ 0xC000 - 0xFFFF ]
-
-                        self.name_table[(addr >> 11) & 0x01][addr & 0x3FF] 
-                    },
-                    Mirroring::VERTICAL => {
-                        // [        A        ]|[        B        ]
-                        // [ 0x2000 - 0x3FFF ]|[ 0x4000 - 0x7FFF ]
-                        // --------------------------------------
-                        // [        a        ]|[        b        ]
-                        // [ 0x8000 - 0xBFFF ]|[ 0xC000 - 0xFFFF ]
-
-                        self.name_table[(addr >> 10) & 0x01][addr & 0x3FF] 
-                    },
-                    Mirroring::ONESCREEN_LO => {
-                        self.name_table[0][addr & 0x3FF] 
-                    }
-                    Mirroring::ONESCREEN_HI => {
-                        self.name_table[1][addr & 0x3FF] 
-                    }
-                    _ => unimplemented!()
+                if let Some(byte) = cartridge.name_table_read(addr) {
+                    return byte;
+                }
+
+                match cartridge.mirroring().name_table_slots()[(addr >> 10) & 0x03] {
+                    NameTableSource::CIRAM(page) => self.name_table[page as usize][addr & 0x3FF],
+                    NameTableSource::CARTRIDGE(page) => cartridge.vram_read(page, addr),
                 }
             },
             PALETTE_TABLE_START..=PALETTE_TABLE_END => {
@@ -276,32 +324,13 @@ impl PpuBus {
         match addr {
             PATTERN_TABLE_START..=PATTERN_TABLE_END => cartridge.ppu_write(addr, byte),
             NAME_TABLE_START..=NAME_TABLE_END => {
-                match cartridge.mirroring() {
-                    Mirroring::HORIZONTAL => {
-                        // [        A        ]|[        a        ]
-                        // [ 0x2000 - 0x3FFF ]|[ 0x4000 - 0x7FFF ]
-                        // --------------------------------------
-                        // [        B        ]|[        b        ]
-                        // [ 0x8000 - 0xBFFF ]|[ 0xC000 - 0xFFFF ]
-
-                        self.name_table[(addr >> 11) & 0x01][addr & 0x3FF] = byte;
-                    },
-                    Mirroring::VERTICAL => {
-                        // [        A        ]|[        B        ]
-                        // [ 0x2000 - 0x3FFF ]|[ 0x4000 - 0x7FFF ]
-                        // --------------------------------------
-                        // [        a        ]|[        b        ]
-                        // [ 0x8000 - 0xBFFF ]|[ 0xC000 - 0xFFFF ]
-                        
-                        self.name_table[(addr >> 10) & 0x01][addr & 0x3FF] = byte;
-                    },
-                    Mirroring::ONESCREEN_LO => {
-                        self.name_table[0][addr & 0x3FF] = byte;
-                    }
-                    Mirroring::ONESCREEN_HI => {
-                        self.name_table[1][addr & 0x3FF] = byte;
-                    }
-                    _ => unimplemented!()
+                if cartridge.name_table_write(addr, byte) {
+                    return;
+                }
+
+                match cartridge.mirroring().name_table_slots()[(addr >> 10) & 0x03] {
+                    NameTableSource::CIRAM(page) => self.name_table[page as usize][addr & 0x3FF] = byte,
+                    NameTableSource::CARTRIDGE(page) => cartridge.vram_write(page, addr, byte),
                 }
             },
             PALETTE_TABLE_START..=PALETTE_TABLE_END => {
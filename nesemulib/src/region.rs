@@ -0,0 +1,99 @@
+use crate::cartridge::TimingMode;
+use crate::{StateReader, StateWriter};
+
+/// The console's video standard, which determines its clock speeds and frame timing
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Region {
+    NTSC,
+    PAL,
+    /// Famiclone with PAL video timing, but an NTSC-like CPU to PPU ratio
+    DENDY,
+}
+
+impl Region {
+    /// Multi-region games run best on NTSC, as that's what most of them were developed for
+    pub fn from_timing(timing: TimingMode) -> Self {
+        match timing {
+            TimingMode::NTSC | TimingMode::MULTI_REGION => Region::NTSC,
+            TimingMode::PAL => Region::PAL,
+            TimingMode::DENDY => Region::DENDY,
+        }
+    }
+
+    pub fn master_clock_frequency(&self) -> f32 {
+        match self {
+            Region::NTSC => 21_477_272.0,
+            Region::PAL | Region::DENDY => 26_601_712.0,
+        }
+    }
+
+    /// Number of master clock cycles in a single PPU dot
+    pub fn ppu_clock_divider(&self) -> u32 {
+        match self {
+            Region::NTSC => 4,
+            Region::PAL | Region::DENDY => 5,
+        }
+    }
+
+    /// Number of master clock cycles in a single CPU cycle
+    pub fn cpu_clock_divider(&self) -> u32 {
+        match self {
+            Region::NTSC => 12,
+            Region::PAL => 16,
+            Region::DENDY => 15,
+        }
+    }
+
+    pub fn cpu_frequency(&self) -> f32 {
+        self.master_clock_frequency() / self.cpu_clock_divider() as f32
+    }
+
+    pub fn ppu_frequency(&self) -> f32 {
+        self.master_clock_frequency() / self.ppu_clock_divider() as f32
+    }
+
+    /// The scanline at which VBLANK starts and an NMI may be sent.
+    /// Dendy idles for 50 extra scanlines after rendering, so that its VBLANK is as short as NTSC's.
+    pub fn vblank_start_scanline(&self) -> i32 {
+        match self {
+            Region::NTSC | Region::PAL => 241,
+            Region::DENDY => 291,
+        }
+    }
+
+    /// The final scanline of each frame, after which the pre-render scanline (-1) starts
+    pub fn last_scanline(&self) -> i32 {
+        match self {
+            Region::NTSC => 260,
+            Region::PAL | Region::DENDY => 310,
+        }
+    }
+
+    /// Only the NTSC PPU skips a dot on odd frames while rendering
+    pub fn skips_odd_frame_dot(&self) -> bool {
+        matches!(self, Region::NTSC)
+    }
+
+    /// Dendy's APU runs off the same tables as NTSC, only its CPU clock is different
+    pub fn uses_pal_apu(&self) -> bool {
+        matches!(self, Region::PAL)
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(match self {
+            Region::NTSC  => 0,
+            Region::PAL   => 1,
+            Region::DENDY => 2,
+        });
+    }
+
+    pub fn load_state(state: &mut StateReader) -> Result<Self, String> {
+        match state.read_u8()? {
+            0 => Ok(Region::NTSC),
+            1 => Ok(Region::PAL),
+            2 => Ok(Region::DENDY),
+            n => Err(format!("Invalid region {} in save state", n)),
+        }
+    }
+}
@@ -0,0 +1,254 @@
+use std::collections::VecDeque;
+
+use crate::nes::Nes;
+
+/// Keeps a history of recent snapshots of the NES so that it can be rewound.
+/// Only the most recent snapshot is stored whole; every older snapshot is stored as
+/// a run-length encoded XOR delta against the one taken after it, since most of
+/// the machine state (RAM, nametables, cartridge RAM) changes very little between frames.
+pub struct RewindBuffer {
+    /// Number of frames run between each snapshot
+    pub frames_per_snapshot: u64,
+    /// Maximum number of bytes used by the buffer before the oldest snapshots are dropped
+    pub memory_budget: usize,
+
+    latest: Option<Vec<u8>>,
+    // deltas.back() turns `latest` into the snapshot taken before it, and so on
+    deltas: VecDeque<Vec<u8>>,
+    delta_bytes: usize,
+    last_snapshot_frame: u64,
+}
+
+impl RewindBuffer {
+    pub fn new(frames_per_snapshot: u64, memory_budget: usize) -> Self {
+        Self {
+            frames_per_snapshot,
+            memory_budget,
+
+            latest: None,
+            deltas: VecDeque::new(),
+            delta_bytes: 0,
+            last_snapshot_frame: 0,
+        }
+    }
+
+    /// Drops every stored snapshot, which must be done whenever a different cartridge is loaded
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+        self.delta_bytes = 0;
+    }
+
+    /// Number of snapshots currently stored
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.latest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    /// Number of bytes currently used by stored snapshots
+    pub fn memory_used(&self) -> usize {
+        self.delta_bytes + self.latest.as_ref().map_or(0, |latest| latest.len())
+    }
+
+    /// Takes a snapshot of the NES if enough frames have run since the last one.
+    /// Should be called after every time the NES is run.
+    pub fn try_capture(&mut self, nes: &Nes) {
+        if nes.frame_count < self.last_snapshot_frame {
+            // the NES was reset or loaded from an older state
+            self.last_snapshot_frame = nes.frame_count;
+        }
+
+        if self.latest.is_some() && nes.frame_count - self.last_snapshot_frame < self.frames_per_snapshot.max(1) {
+            return;
+        }
+
+        self.push(nes.snapshot());
+        self.last_snapshot_frame = nes.frame_count;
+    }
+
+    /// Restores the most recent snapshot into the NES and removes it from the buffer.
+    /// Returns false if there is nothing left to rewind to.
+    pub fn rewind(&mut self, nes: &mut Nes) -> bool {
+        let snapshot = match self.pop() {
+            Some(snapshot) => snapshot,
+            None => return false,
+        };
+
+        if nes.restore_snapshot(&snapshot).is_err() {
+            // snapshots belong to a different cartridge, so none of them are usable
+            self.clear();
+            return false;
+        }
+
+        self.last_snapshot_frame = nes.frame_count;
+        true
+    }
+
+    fn push(&mut self, snapshot: Vec<u8>) {
+        if let Some(latest) = self.latest.take() {
+            let delta = encode_delta(&snapshot, &latest);
+            self.delta_bytes += delta.len();
+            self.deltas.push_back(delta);
+        }
+
+        self.latest = Some(snapshot);
+
+        while self.memory_used() > self.memory_budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.delta_bytes -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Option<Vec<u8>> {
+        let latest = self.latest.take()?;
+
+        if let Some(delta) = self.deltas.pop_back() {
+            self.delta_bytes -= delta.len();
+            self.latest = Some(decode_delta(&latest, &delta));
+        }
+
+        Some(latest)
+    }
+}
+
+// Deltas are a sequence of runs of the form [ unchanged bytes: varint ][ changed bytes: varint ][ XORed bytes ... ],
+// preceded by the length of the target snapshot.
+fn encode_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    write_varint(&mut delta, target.len());
+
+    let xor_at = |i: usize| target[i] ^ base.get(i).copied().unwrap_or(0);
+
+    let mut i = 0;
+    while i < target.len() {
+        let unchanged_start = i;
+        while i < target.len() && xor_at(i) == 0 {
+            i += 1;
+        }
+
+        let changed_start = i;
+        while i < target.len() && xor_at(i) != 0 {
+            i += 1;
+        }
+
+        write_varint(&mut delta, changed_start - unchanged_start);
+        write_varint(&mut delta, i - changed_start);
+        delta.extend((changed_start..i).map(xor_at));
+    }
+
+    delta
+}
+
+fn decode_delta(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut pos = 0;
+    let len = read_varint(delta, &mut pos);
+
+    let mut target: Vec<u8> = (0..len).map(|i| base.get(i).copied().unwrap_or(0)).collect();
+
+    let mut i = 0;
+    while pos < delta.len() {
+        i += read_varint(delta, &mut pos);
+        let changed = read_varint(delta, &mut pos);
+
+        for byte in &delta[pos..pos + changed] {
+            target[i] ^= byte;
+            i += 1;
+        }
+        pos += changed;
+    }
+
+    target
+}
+
+fn write_varint(data: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        data.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+
+        value |= ((byte & 0x7F) as usize) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            return value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::CartridgeNes;
+    use crate::nes::Nes;
+
+    use super::{decode_delta, encode_delta, RewindBuffer};
+
+    #[test]
+    pub fn test_delta_round_trip() {
+        let base: Vec<u8> = (0..1000).map(|i| (i * 7) as u8).collect();
+        let mut target = base.clone();
+        target[0] ^= 0xFF;
+        target[500..700].fill(0x42);
+        target.extend_from_slice(&[1, 2, 3]);
+
+        let delta = encode_delta(&base, &target);
+        assert!(delta.len() < target.len());
+        assert_eq!(decode_delta(&base, &delta), target);
+
+        let shorter = &base[..10];
+        assert_eq!(decode_delta(&base, &encode_delta(&base, shorter)), shorter);
+    }
+
+    #[test]
+    pub fn test_rewind() {
+        let cartridge = CartridgeNes::from_ines_file("../roms/nestest.nes").unwrap();
+        let mut nes = Nes::new(cartridge, 44100);
+        let mut rewind = RewindBuffer::new(2, usize::MAX);
+
+        let mut snapshots = Vec::new();
+        for _ in 0..10 {
+            rewind.try_capture(&nes);
+            if snapshots.len() < rewind.len() {
+                snapshots.push(nes.snapshot());
+            }
+            nes.run_frame();
+        }
+        assert_eq!(rewind.len(), 5);
+
+        while let Some(expected) = snapshots.pop() {
+            assert!(rewind.rewind(&mut nes));
+            assert_eq!(nes.snapshot(), expected);
+        }
+        assert!(!rewind.rewind(&mut nes));
+    }
+
+    #[test]
+    pub fn test_rewind_memory_budget() {
+        let cartridge = CartridgeNes::from_ines_file("../roms/nestest.nes").unwrap();
+        let mut nes = Nes::new(cartridge, 44100);
+        let snapshot_size = nes.snapshot().len();
+        let mut rewind = RewindBuffer::new(1, snapshot_size + 256);
+
+        for _ in 0..100 {
+            rewind.try_capture(&nes);
+            nes.run_frame();
+            assert!(rewind.memory_used() <= rewind.memory_budget);
+        }
+
+        assert!(rewind.len() > 1 && rewind.len() < 100);
+    }
+}